@@ -0,0 +1,46 @@
+//! Records the revision and the compiler the exporter is built from, for
+//! `p1_exporter_build_info`.
+
+use std::{env, path::Path, process::Command};
+
+fn main() {
+    let revision = output("git", &["rev-parse", "--short=12", "HEAD"]);
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    // "rustc 1.75.0 (82e1608df 2023-12-21)"
+    let rustc = output(&rustc, &["--version"])
+        .and_then(|version| Some(version.split_whitespace().nth(1)?.to_string()));
+    println!(
+        "cargo:rustc-env=P1_EXPORTER_REVISION={}",
+        revision.as_deref().unwrap_or("unknown")
+    );
+    println!(
+        "cargo:rustc-env=P1_EXPORTER_RUSTC={}",
+        rustc.as_deref().unwrap_or("unknown")
+    );
+
+    // Cargo reruns the script on every build for files that don't exist, so
+    // the revision is only tracked in a checkout.
+    let head = Path::new(".git/HEAD");
+    if head.exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        let branch = std::fs::read_to_string(head).unwrap_or_default();
+        if let Some(reference) = branch.trim().strip_prefix("ref: ") {
+            let reference = Path::new(".git").join(reference);
+            if reference.exists() {
+                println!("cargo:rerun-if-changed={}", reference.display());
+            }
+        }
+    } else {
+        println!("cargo:rerun-if-changed=build.rs");
+    }
+}
+
+/// Returns the trimmed output of a command, if it succeeds.
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_string())
+}
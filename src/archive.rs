@@ -0,0 +1,149 @@
+//! Archive of every reading in daily CSV files, independent of the retention
+//! of Prometheus.
+
+use crate::{
+    collector::{civil_date, unix_days},
+    sink::{Sample, Sink},
+};
+use std::{
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const HEADER: &str = "time,metric,labels,value\n";
+
+pub struct ArchiveConfig {
+    pub dir: PathBuf,
+    /// Days of files to keep, including today's. All are kept if unset.
+    pub retention: Option<u32>,
+}
+
+pub struct ArchiveSink {
+    config: ArchiveConfig,
+    /// The file of the current day, by the days since the Unix epoch.
+    file: Mutex<Option<(i64, File)>>,
+}
+
+impl ArchiveSink {
+    pub fn open(config: ArchiveConfig) -> Result<Self, io::Error> {
+        fs::create_dir_all(&config.dir).map_err(|err| {
+            io::Error::new(err.kind(), format!("{}: {err}", config.dir.display()))
+        })?;
+        Ok(ArchiveSink {
+            config,
+            file: Mutex::new(None),
+        })
+    }
+
+    fn write(&self, samples: &[Sample]) -> Result<(), io::Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let days = (now.as_secs() / 86400) as i64;
+        let (year, month, day) = civil_date(days);
+        let secs = now.as_secs() % 86400;
+        let time = format!(
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            now.subsec_millis()
+        );
+
+        let mut lines = String::new();
+        for sample in samples {
+            let labels = sample
+                .labels
+                .iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect::<Vec<_>>()
+                .join(";");
+            let _ = writeln!(
+                lines,
+                "{time},{},{},{}",
+                sample.name,
+                quote(&labels),
+                sample.value
+            );
+        }
+
+        let mut file = self.file.lock().unwrap();
+        let file = match &mut *file {
+            Some((current, file)) if *current == days => file,
+            file => {
+                let path = self
+                    .config
+                    .dir
+                    .join(format!("p1-{year:04}-{month:02}-{day:02}.csv"));
+                let mut opened = OpenOptions::new().create(true).append(true).open(&path)?;
+                if opened.metadata()?.len() == 0 {
+                    opened.write_all(HEADER.as_bytes())?;
+                }
+                if let Some(retention) = self.config.retention {
+                    self.expire(days - i64::from(retention));
+                }
+                &mut file.insert((days, opened)).1
+            }
+        };
+        file.write_all(lines.as_bytes())
+    }
+
+    /// Removes the files of the days before `before`.
+    fn expire(&self, before: i64) {
+        let entries = match fs::read_dir(&self.config.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                tracing::warn!("Failed to list {}: {err}", self.config.dir.display());
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let Some(days) = name.to_str().and_then(file_days) else {
+                continue;
+            };
+            if days < before {
+                let path = entry.path();
+                match fs::remove_file(&path) {
+                    Ok(()) => tracing::debug!("Removed {}", path.display()),
+                    Err(err) => tracing::warn!("Failed to remove {}: {err}", path.display()),
+                }
+            }
+        }
+    }
+}
+
+impl Sink for ArchiveSink {
+    fn publish(&self, samples: &[Sample]) {
+        if let Err(err) = self.write(samples) {
+            tracing::warn!(
+                "Failed to archive readings to {}: {err}",
+                self.config.dir.display()
+            );
+        }
+    }
+}
+
+/// Returns the day since the Unix epoch of an archive file named
+/// `p1-YYYY-MM-DD.csv`.
+fn file_days(name: &str) -> Option<i64> {
+    let date = name.strip_prefix("p1-")?.strip_suffix(".csv")?;
+    let mut date = date.split('-');
+    let year = date.next()?.parse().ok()?;
+    let month = date.next()?.parse().ok()?;
+    let day = date.next()?.parse().ok()?;
+    date.next().is_none().then(|| unix_days(year, month, day))
+}
+
+/// Quotes a CSV field if needed.
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,136 @@
+//! Authentication of HTTP requests with Basic auth or a bearer token.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    sync::Mutex,
+};
+use tiny_http::Header;
+
+/// Users and tokens allowed to access the protected endpoints.
+#[derive(Debug, Default)]
+pub struct Auth {
+    /// bcrypt hashes of the passwords by user name.
+    users: HashMap<String, String>,
+    token: Option<String>,
+    /// `Authorization` headers already verified against `users`, as bcrypt
+    /// is deliberately slow.
+    verified: Mutex<HashSet<String>>,
+}
+
+impl Auth {
+    /// Reads the users from an htpasswd file with bcrypt hashes (as written
+    /// by `htpasswd -B`) and the token from a file with the token only.
+    pub fn load(users: Option<&Path>, token: Option<&Path>) -> Result<Auth, String> {
+        let mut auth = Auth::default();
+        if let Some(path) = users {
+            let content =
+                fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+            for (i, line) in content.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let (user, hash) = line
+                    .split_once(':')
+                    .ok_or_else(|| format!("{}:{}: expected USER:HASH", path.display(), i + 1))?;
+                if !["$2a$", "$2b$", "$2y$"].iter().any(|p| hash.starts_with(p)) {
+                    return Err(format!(
+                        "{}:{}: the password of '{user}' isn't a bcrypt hash",
+                        path.display(),
+                        i + 1
+                    ));
+                }
+                auth.users.insert(user.to_string(), hash.to_string());
+            }
+        }
+        if let Some(path) = token {
+            let token =
+                fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+            let token = token.trim();
+            if token.is_empty() {
+                return Err(format!("{}: empty token", path.display()));
+            }
+            auth.token = Some(token.to_string());
+        }
+        Ok(auth)
+    }
+
+    /// Returns whether the request carries valid credentials.
+    pub fn authorized(&self, headers: &[Header]) -> bool {
+        let Some(authorization) = headers
+            .iter()
+            .find(|header| header.field.equiv("Authorization"))
+            .map(|header| header.value.as_str())
+        else {
+            return false;
+        };
+        if let (Some(token), Some(given)) = (&self.token, authorization.strip_prefix("Bearer ")) {
+            return constant_time_eq(token.as_bytes(), given.trim().as_bytes());
+        }
+        let Some(credentials) = authorization.strip_prefix("Basic ") else {
+            return false;
+        };
+        if self.verified.lock().unwrap().contains(authorization) {
+            return true;
+        }
+        let Some(credentials) = base64_decode(credentials.trim()) else {
+            return false;
+        };
+        let Some((user, password)) = String::from_utf8_lossy(&credentials)
+            .split_once(':')
+            .map(|(user, password)| (user.to_string(), password.to_string()))
+        else {
+            return false;
+        };
+        let valid = self
+            .users
+            .get(&user)
+            .is_some_and(|hash| bcrypt::verify(password, hash).unwrap_or(false));
+        if valid {
+            self.verified
+                .lock()
+                .unwrap()
+                .insert(authorization.to_string());
+        }
+        valid
+    }
+
+    /// Returns the `WWW-Authenticate` header asking for credentials.
+    pub fn challenge(&self) -> Header {
+        let scheme = if self.users.is_empty() {
+            "Bearer"
+        } else {
+            "Basic realm=\"p1-exporter\""
+        };
+        format!("WWW-Authenticate: {scheme}").parse().unwrap()
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let s = s.trim_end_matches('=').as_bytes();
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for &c in s {
+        bits = (bits << 6) | u32::from(value(c)?);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
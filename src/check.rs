@@ -0,0 +1,149 @@
+//! One-off validation of a P1 reader: connect, wait for a valid telegram and
+//! summarize it.
+
+use crate::{
+    collector, obis,
+    parse::{self, Value},
+};
+use std::{
+    io::{self, BufReader, Read},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+/// Top-level fields of a decoded telegram that are listed in the summary
+/// itself rather than as present or missing.
+const IDENTIFICATION: [&str; 6] = [
+    "header",
+    "checksum",
+    "dsmr_version",
+    "equipment_id",
+    "unknown_objects",
+    "invalid_objects",
+];
+
+/// Connects to the P1 reader at `addr` and prints a summary of the first
+/// valid telegram received within `timeout`.
+pub fn run(addr: &str, timeout: Duration) -> Result<(), String> {
+    let start = Instant::now();
+    let sock = collector::connect(addr, timeout)
+        .map_err(|err| format!("Failed to connect to {addr}: {err}"))?;
+    println!("Connected to {addr} in {:.1?}", start.elapsed());
+
+    let sock = DeadlineReader {
+        sock,
+        deadline: start + timeout,
+    };
+    let mut rejected = 0;
+    let mut last_err = None;
+    let bytes = BufReader::new(sock)
+        .bytes()
+        .map_while(|b| b.map_err(|err| last_err = Some(err)).ok());
+    for readout in dsmr5::Reader::new(bytes) {
+        let text = obis::readout_text(&readout);
+        let decoded = parse::decode(text);
+        if !decoded.valid {
+            rejected += 1;
+            tracing::warn!(
+                "Received a telegram the exporter would reject, see `p1-exporter parse` for details"
+            );
+            continue;
+        }
+        println!("Received a valid telegram after {:.1?}", start.elapsed());
+        summarize(&decoded.description);
+        return Ok(());
+    }
+    let rejected = match rejected {
+        0 => String::new(),
+        n => format!(", rejected {n} telegrams"),
+    };
+    Err(match last_err {
+        Some(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            format!("No valid telegram within {timeout:?}{rejected}")
+        }
+        Some(err) => format!("Failed to read from {addr}: {err}{rejected}"),
+        None => format!("{addr} closed the connection{rejected}"),
+    })
+}
+
+fn summarize(description: &Value) {
+    let Value::Object(fields) = description else {
+        return;
+    };
+    let mut present = Vec::new();
+    let mut missing = Vec::new();
+    for (key, value) in fields {
+        if IDENTIFICATION.contains(key) {
+            if !matches!(key, &"unknown_objects" | &"invalid_objects") {
+                println!("{key}: {value}");
+            }
+            continue;
+        }
+        // Phases and M-Bus channels are listed one by one.
+        let children = match value {
+            Value::Object(children)
+                if children
+                    .iter()
+                    .any(|(_, child)| matches!(child, Value::Object(_))) =>
+            {
+                children
+                    .iter()
+                    .map(|(child, value)| (format!("{key}.{child}"), value))
+                    .collect()
+            }
+            _ => vec![(key.to_string(), value)],
+        };
+        for (name, value) in children {
+            if has_data(value) {
+                present.push(name);
+            } else {
+                missing.push(name);
+            }
+        }
+    }
+    println!("present: {}", list(&present));
+    println!("missing: {}", list(&missing));
+    if let Some((_, unknown)) = fields.iter().find(|(key, _)| *key == "unknown_objects") {
+        println!("unknown objects: {unknown}");
+    }
+}
+
+/// Returns whether any value within `value` is present.
+fn has_data(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::List(items) => items.iter().any(has_data),
+        Value::Object(fields) => fields.iter().any(|(_, value)| has_data(value)),
+        _ => true,
+    }
+}
+
+fn list(names: &[String]) -> String {
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+/// A socket whose reads time out once the deadline has passed.
+struct DeadlineReader {
+    sock: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.sock.set_read_timeout(Some(remaining))?;
+        self.sock.read(buf)
+    }
+}
@@ -0,0 +1,46 @@
+//! Removal of the files created by the exporter, such as its Unix socket,
+//! when it terminates.
+
+use std::{ffi::CString, os::unix::ffi::OsStrExt, path::PathBuf, sync::OnceLock};
+
+static PATHS: OnceLock<Vec<CString>> = OnceLock::new();
+
+/// Removes `paths`, in order, when the process is terminated by SIGINT,
+/// SIGTERM or SIGHUP (unless that reloads the configuration). Directories
+/// are removed once empty.
+pub fn remove_on_exit(paths: Vec<PathBuf>) {
+    let paths = paths
+        .iter()
+        .filter_map(|path| CString::new(path.as_os_str().as_bytes()).ok())
+        .collect();
+    if PATHS.set(paths).is_err() {
+        return;
+    }
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        // SAFETY: terminate only makes async-signal-safe calls.
+        unsafe { libc::signal(signal, terminate as *const () as libc::sighandler_t) };
+    }
+}
+
+/// Removes the files registered with `remove_on_exit` right away, for a
+/// regular exit.
+pub fn remove_files() {
+    for path in PATHS.get().into_iter().flatten() {
+        // SAFETY: path is a valid NUL-terminated string.
+        unsafe {
+            if libc::unlink(path.as_ptr()) != 0 {
+                libc::rmdir(path.as_ptr());
+            }
+        }
+    }
+}
+
+extern "C" fn terminate(signal: libc::c_int) {
+    remove_files();
+    // Terminate the way the signal would have without the handler.
+    // SAFETY: signal and raise are async-signal-safe.
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
@@ -0,0 +1,2371 @@
+use crate::{
+    cleanup, compat,
+    cost::{Costs, Prices},
+    decrypt, dlms, homewizard, http,
+    metrics::{
+        ExtraSeries, InfoLabels, MbusDeviceLabels, MbusLabels, MeterInfoLabels, MeterMetrics,
+        MeterReadings, MetricGroup, PowerUnit, RawTelegram, READER_DOWN, READER_UP,
+    },
+    mqtt, obis,
+    proxy::Proxy,
+    record::Recorder,
+    replay::{self, ReplaySpeed},
+    serial::{self, SerialConfig},
+    sink::{Sample, Sink},
+    sml, tls, udp,
+};
+use dsmr5::{
+    state::{Slave, State},
+    types::TST,
+};
+use prometheus_client::metrics::counter::{Atomic, Counter};
+use std::{
+    any::Any,
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    fmt,
+    fs::File,
+    hash::{BuildHasher, Hasher},
+    io::{self, BufReader, Read},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    os::fd::AsRawFd,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    process,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Connections lasting this long reset the reconnection backoff.
+const STABLE_CONNECTION: Duration = Duration::from_secs(60);
+/// Telegrams a lower total must be reported in to be taken as a reset of the
+/// register, e.g. by a replaced meter, rather than as a glitch.
+const REGRESSION_TELEGRAMS: u32 = 10;
+/// Length in seconds of the blocks of the clock the energy is tracked in,
+/// those capacity tariffs are billed on.
+const QUARTER_HOUR: i64 = 900;
+/// Values of the `phase` label, in the order of `State::lines`.
+const PHASES: [&str; 3] = ["l1", "l2", "l3"];
+
+pub struct CollectorConfig {
+    pub mode: CollectionMode,
+    pub format: P1Format,
+    pub dsmr_version: DsmrVersion,
+    pub gas_scale: Option<f64>,
+    pub energy_scale: Option<f64>,
+    /// M-Bus channels expected to have a device attached. When empty,
+    /// presence is reported for channels that have been seen.
+    pub expected_mbus_channels: Vec<u8>,
+    /// M-Bus channels to export. When empty, all channels are exported.
+    pub mbus_channels: Vec<u8>,
+    pub tariff_names: TariffNames,
+    pub prices: Prices,
+    pub proxy: Option<Arc<Proxy>>,
+    pub recorder: Option<Arc<Recorder>>,
+    /// Power (in kW) above which consumption and production are considered
+    /// to happen at the same time.
+    pub power_conflict_threshold: f64,
+    /// Telegrams reporting more power (in kW) are skipped as corrupted.
+    pub max_power: Option<f64>,
+    /// Telegrams whose energy totals increased by more (in kWh) since the
+    /// last telegram are skipped as corrupted.
+    pub max_energy_step: Option<f64>,
+    /// Only one telegram is processed per interval, if set.
+    pub sample_interval: Option<Duration>,
+    /// Whether to export every numeric object of the telegrams.
+    pub export_raw_obis: bool,
+    pub power_unit: PowerUnit,
+    /// Clock of the days and months of the power peaks.
+    pub peak_timezone: PeakTimezone,
+    pub sinks: Arc<Vec<Box<dyn Sink>>>,
+    /// Groups of the samples published to the sinks.
+    pub metric_groups: Vec<MetricGroup>,
+    /// Keep the series of inactive tariffs at 0 instead of removing them.
+    pub pre_register: bool,
+    /// Drop the connection on a malformed telegram instead of skipping it.
+    pub strict_parsing: bool,
+    pub connect_timeout: Duration,
+    /// TLS settings of the connections to P1 readers, if encrypted.
+    pub tls: Option<tls::Client>,
+    /// Keys of the telegrams, if encrypted by the meter.
+    pub decryption: Option<decrypt::Keys>,
+    /// The connection is considered lost after no data for this long.
+    pub read_timeout: Duration,
+    /// Idle time after which TCP keepalive probes are sent to P1 readers, if
+    /// enabled.
+    pub tcp_keepalive: Option<Duration>,
+    /// Initial delay between reconnection attempts to the P1 reader.
+    pub reconnect_interval: Duration,
+    /// Upper bound of the exponential reconnection backoff.
+    pub max_reconnect_delay: Duration,
+    /// How long a backup reader is read before trying the primary again.
+    pub failback_interval: Duration,
+    /// The readings are removed after no telegram for this long, if set.
+    pub stale_after: Option<Duration>,
+    /// Connections in a row without a telegram after which the P1 reader is
+    /// considered failed.
+    pub max_consecutive_errors: u32,
+    /// Exit the process once a P1 reader failed.
+    pub exit_on_failure: bool,
+    /// Most recent long power failures of the event log to export.
+    pub power_failure_events: usize,
+}
+
+/// The defaults of the command line options.
+impl Default for CollectorConfig {
+    fn default() -> Self {
+        CollectorConfig {
+            mode: CollectionMode::Background,
+            format: P1Format::Dsmr,
+            dsmr_version: DsmrVersion::Auto,
+            gas_scale: None,
+            energy_scale: None,
+            expected_mbus_channels: vec![],
+            mbus_channels: vec![],
+            tariff_names: TariffNames::default(),
+            prices: Prices::default(),
+            proxy: None,
+            recorder: None,
+            power_conflict_threshold: 0.0,
+            max_power: None,
+            max_energy_step: None,
+            sample_interval: None,
+            export_raw_obis: false,
+            power_unit: PowerUnit::Kilowatts,
+            peak_timezone: PeakTimezone::Meter,
+            sinks: Arc::default(),
+            metric_groups: MetricGroup::ALL.to_vec(),
+            pre_register: false,
+            strict_parsing: false,
+            connect_timeout: Duration::from_secs(5),
+            tls: None,
+            decryption: None,
+            read_timeout: Duration::from_secs(2),
+            tcp_keepalive: None,
+            reconnect_interval: Duration::from_secs(5),
+            max_reconnect_delay: Duration::from_secs(300),
+            failback_interval: Duration::from_secs(300),
+            stale_after: None,
+            max_consecutive_errors: 10,
+            exit_on_failure: false,
+            power_failure_events: 10,
+        }
+    }
+}
+
+/// Clock the days and months of the power peaks are tracked in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeakTimezone {
+    /// The local time of the meter, from the telegrams.
+    Meter,
+    /// The timezone of the system, as set by `TZ`.
+    Local,
+    Utc,
+}
+
+impl PeakTimezone {
+    /// Returns the current date, in the system's timezone if the meter
+    /// doesn't report its time.
+    fn today(self, meter: Option<&TST>) -> Option<(i64, u8, u8)> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        match (self, meter) {
+            (PeakTimezone::Meter, Some(tst)) => Some((2000 + tst.year as i64, tst.month, tst.day)),
+            (PeakTimezone::Utc, _) => Some(civil_date(now.div_euclid(86400))),
+            (PeakTimezone::Meter | PeakTimezone::Local, _) => {
+                let now = now as libc::time_t;
+                // SAFETY: tm is plain data, which localtime_r only writes to.
+                let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+                if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+                    return None;
+                }
+                Some((
+                    tm.tm_year as i64 + 1900,
+                    tm.tm_mon as u8 + 1,
+                    tm.tm_mday as u8,
+                ))
+            }
+        }
+    }
+}
+
+impl FromStr for PeakTimezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "meter" => Ok(PeakTimezone::Meter),
+            "local" => Ok(PeakTimezone::Local),
+            "utc" => Ok(PeakTimezone::Utc),
+            _ => Err(format!(
+                "unknown peak timezone '{s}', expected meter, local or utc"
+            )),
+        }
+    }
+}
+
+/// When the telegrams are read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CollectionMode {
+    /// Continuously, by a collector thread per meter.
+    Background,
+    /// One telegram per meter on every scrape, with the reader connected
+    /// only meanwhile.
+    Scrape,
+}
+
+impl FromStr for CollectionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "background" => Ok(CollectionMode::Background),
+            "scrape" => Ok(CollectionMode::Scrape),
+            _ => Err(format!(
+                "unknown collection mode '{s}', expected background or scrape"
+            )),
+        }
+    }
+}
+
+/// What the meters send.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum P1Format {
+    /// DSMR telegrams, possibly encrypted.
+    Dsmr,
+    /// DLMS/COSEM push frames, converted into telegrams.
+    Dlms,
+    /// SML files, converted into telegrams.
+    Sml,
+}
+
+impl FromStr for P1Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dsmr" => Ok(P1Format::Dsmr),
+            "dlms" => Ok(P1Format::Dlms),
+            "sml" => Ok(P1Format::Sml),
+            _ => Err(format!(
+                "unknown P1 format '{s}', expected dsmr, dlms or sml"
+            )),
+        }
+    }
+}
+
+/// DSMR versions of the meters, as far as their telegrams are read
+/// differently.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DsmrVersion {
+    /// Any version, telegrams without checksum read as of DSMR 2.2 and 3,
+    /// and those of Swedish HAN ports upgraded as well.
+    Auto,
+    /// DSMR 2.2 and 3, whose telegrams end without checksum and whose
+    /// serial line runs at 9600 7E1.
+    Legacy,
+    /// DSMR 4 and 5, whose telegrams must end with a checksum.
+    Current,
+}
+
+impl FromStr for DsmrVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(DsmrVersion::Auto),
+            "2.2" | "3" => Ok(DsmrVersion::Legacy),
+            "4" | "5" => Ok(DsmrVersion::Current),
+            _ => Err(format!(
+                "unknown DSMR version '{s}', expected auto, 2.2, 3, 4 or 5"
+            )),
+        }
+    }
+}
+
+/// Mapping of tariff numbers, as reported by the meter, to label values.
+#[derive(Clone, Debug)]
+pub struct TariffNames(BTreeMap<u16, String>);
+
+/// The tariffs of the Netherlands, `1=low,2=high`.
+impl Default for TariffNames {
+    fn default() -> Self {
+        TariffNames(BTreeMap::from([
+            (1, "low".to_string()),
+            (2, "high".to_string()),
+        ]))
+    }
+}
+
+impl TariffNames {
+    /// Returns the name of the tariff, falling back to its number.
+    fn name(&self, tariff: u16) -> String {
+        self.0
+            .get(&tariff)
+            .cloned()
+            .unwrap_or_else(|| tariff.to_string())
+    }
+
+    fn contains(&self, tariff: u16) -> bool {
+        self.0.contains_key(&tariff)
+    }
+
+    fn names(&self) -> impl Iterator<Item = &String> {
+        self.0.values()
+    }
+}
+
+impl FromStr for TariffNames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut names = BTreeMap::new();
+        for entry in s.split(',') {
+            let (tariff, name) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected TARIFF=NAME, got '{entry}'"))?;
+            let tariff = tariff
+                .trim()
+                .parse::<u16>()
+                .map_err(|_| format!("invalid tariff number '{tariff}'"))?;
+            if name.is_empty() {
+                return Err(format!("empty name for tariff {tariff}"));
+            }
+            names.insert(tariff, name.to_string());
+        }
+        Ok(TariffNames(names))
+    }
+}
+
+#[derive(Default)]
+struct CollectorState {
+    dsmr_version: Option<String>,
+    seen_mbus_channels: [bool; 4],
+    reported_mbus_devices: [bool; 4],
+    unknown_tariff: Option<u16>,
+    /// Active tariff and when it was first seen.
+    tariff: Option<(u16, Instant)>,
+    /// Where the telegrams are read from, for `p1_info`.
+    source: String,
+    info: Option<InfoLabels>,
+    meter_info: Option<MeterInfoLabels>,
+    /// Text message of the meter, to log its changes.
+    message: Option<String>,
+    /// End of the most recent long power failure in the event log, and the
+    /// failures exported.
+    power_failures_logged: Option<f64>,
+    power_failure_events: Vec<(f64, f64)>,
+    /// Months of the peak history, to remove those that left it.
+    peak_months: Vec<String>,
+    /// Last gas reading per M-Bus channel, for the flow rate.
+    gas_readings: [Option<GasReading>; 4],
+    costs: Costs,
+    consumed_peak: Peak,
+    produced_peak: Peak,
+    quarter_hour: QuarterHour,
+    regressions: Regressions,
+    /// Set when the collector is to stop.
+    stop: Arc<AtomicBool>,
+    /// Stop after the first parsed telegram.
+    once: bool,
+    /// When to leave a backup reader for the primary one.
+    failback: Option<Instant>,
+    /// When the collector started, the readings being stale from then on
+    /// until the first telegram.
+    started: Option<Instant>,
+    /// Whether the readings have been removed for being stale.
+    expired: bool,
+}
+
+/// Highest power of the current day and month.
+#[derive(Default)]
+struct Peak {
+    day: Option<((i64, u8, u8), f64)>,
+    month: Option<((i64, u8), f64)>,
+}
+
+impl Peak {
+    /// Records the power on `date` and returns the peaks of its day and
+    /// month, starting over on a new one.
+    fn update(&mut self, date: (i64, u8, u8), power: f64) -> (f64, f64) {
+        let day = match self.day {
+            Some((day, peak)) if day == date => peak.max(power),
+            _ => power,
+        };
+        let month = match self.month {
+            Some((month, peak)) if month == (date.0, date.1) => peak.max(power),
+            _ => power,
+        };
+        self.day = Some((date, day));
+        self.month = Some(((date.0, date.1), month));
+        (day, month)
+    }
+}
+
+/// Energy totals at the start of the current quarter hour of the clock, and
+/// the energy of the last completed one.
+#[derive(Default)]
+struct QuarterHour {
+    /// The quarter hour, counted from the Unix epoch, and the consumed and
+    /// produced totals of its first telegram, unknown for the one the
+    /// exporter started in.
+    current: Option<(i64, Option<[Option<f64>; 2]>)>,
+    /// End of the last completed quarter hour, in Unix time, and the energy
+    /// consumed and produced in it.
+    last: Option<(i64, [Option<f64>; 2])>,
+}
+
+impl QuarterHour {
+    /// Records the totals at `time` and returns the last completed quarter
+    /// hour. A quarter hour without telegrams at its start or at its end,
+    /// as the meter or the exporter was down, is left out.
+    fn update(&mut self, time: i64, totals: [Option<f64>; 2]) -> Option<(i64, [Option<f64>; 2])> {
+        let block = time.div_euclid(QUARTER_HOUR);
+        match self.current {
+            Some((current, _)) if current == block => {}
+            Some((current, Some(start))) if current + 1 == block => {
+                let energy = |i: usize| Some(totals[i]? - start[i]?);
+                self.last = Some((block * QUARTER_HOUR, [energy(0), energy(1)]));
+                self.current = Some((block, Some(totals)));
+            }
+            Some(_) => self.current = Some((block, Some(totals))),
+            None => self.current = Some((block, None)),
+        }
+        self.last
+    }
+}
+
+/// Telegrams in a row that reported a lower total than the counter of a
+/// series, by the family and the labels of the series.
+#[derive(Default)]
+struct Regressions(HashMap<String, u32>);
+
+/// A total read from the meter: a reading, or a number of events.
+trait Total: Copy + PartialOrd + fmt::Display {
+    /// Bits of the total as stored in the counter.
+    fn to_bits(self) -> u64;
+}
+
+impl Total for f64 {
+    fn to_bits(self) -> u64 {
+        f64::to_bits(self)
+    }
+}
+
+impl Total for u64 {
+    fn to_bits(self) -> u64 {
+        self
+    }
+}
+
+impl Regressions {
+    /// Sets `counter` to the `total` read from the meter and returns its new
+    /// value. A lower total than the current one is ignored and counted, as a
+    /// counter going backwards reads as a reset to Prometheus, unless it's
+    /// reported for [`REGRESSION_TELEGRAMS`] telegrams.
+    fn store<N: Total>(
+        &mut self,
+        metrics: &MeterMetrics,
+        name: &str,
+        series: String,
+        counter: &Counter<N, AtomicU64>,
+        total: N,
+    ) -> N
+    where
+        AtomicU64: Atomic<N>,
+    {
+        let current = counter.get();
+        if total < current {
+            let telegrams = self.0.entry(series.clone()).or_default();
+            *telegrams += 1;
+            if *telegrams < REGRESSION_TELEGRAMS {
+                if *telegrams == 1 {
+                    tracing::warn!("Ignoring {name} going back from {current} to {total}");
+                }
+                metrics
+                    .counter_regressions
+                    .get_or_create(&[("metric", name.to_string())])
+                    .inc();
+                return current;
+            }
+            tracing::warn!("Taking {name} going back from {current} to {total} as a reset");
+        }
+        self.0.remove(&series);
+        counter
+            .inner()
+            .store(Total::to_bits(total), Ordering::SeqCst);
+        total
+    }
+}
+
+/// A gas reading as captured by the gas meter.
+struct GasReading {
+    equipment_id: String,
+    /// Capture time in seconds, see [`capture_seconds`].
+    captured: i64,
+    volume: f64,
+}
+
+/// Creates the gauge series known from the configuration with a value of 0,
+/// so they are present before the first telegram.
+///
+/// Counters are left alone: a 0 followed by the meter's total would show up
+/// as a spurious increase in `increase()` and `rate()`.
+pub fn pre_register(metrics: &MeterMetrics, config: &CollectorConfig) {
+    reset_active_tariff(metrics, config);
+    for &channel in &config.expected_mbus_channels {
+        metrics
+            .mbus_channel_present
+            .get_or_create(&[("channel", channel)])
+            .set(0);
+    }
+}
+
+fn reset_active_tariff(metrics: &MeterMetrics, config: &CollectorConfig) {
+    for name in config.tariff_names.names() {
+        metrics
+            .active_tariff
+            .get_or_create(&[("tariff", name.clone())])
+            .set(0);
+    }
+}
+
+/// Address of a P1 reader, optionally prefixed with the name of its meter as
+/// `NAME=HOST:PORT`.
+#[derive(Clone, Debug)]
+pub struct ReaderAddress {
+    pub meter: Option<String>,
+    /// `host:port` pair, resolved on every connection attempt.
+    pub addr: String,
+}
+
+impl FromStr for ReaderAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (meter, addr) = match s.split_once('=') {
+            Some(("", _)) => return Err(format!("empty meter name in '{s}'")),
+            Some((meter, addr)) => (Some(meter.to_string()), addr),
+            None => (None, s),
+        };
+        let valid = addr
+            .rsplit_once(':')
+            .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+        if !valid {
+            return Err(format!("invalid address '{addr}', expected host:port"));
+        }
+        Ok(ReaderAddress {
+            meter,
+            addr: addr.to_string(),
+        })
+    }
+}
+
+/// Inputs of the collectors with the names of their meters.
+pub type Inputs = Vec<(Option<String>, Input)>;
+
+/// Where the telegrams are read from.
+#[derive(Clone, Debug)]
+pub enum Input {
+    Tcp(String),
+    Serial(SerialConfig),
+    Udp(SocketAddr),
+    /// A topic P1 dongles publish the telegrams to.
+    Mqtt(mqtt::Broker, String),
+    /// A HomeWizard P1 meter, polled at the given interval.
+    HomeWizard(http::Url, Duration),
+    /// A file or FIFO, reopened on EOF.
+    File(PathBuf),
+    Stdin,
+    /// A capture file, replayed once.
+    Replay(PathBuf, ReplaySpeed),
+    /// The primary input followed by its backups, in the order they're
+    /// tried.
+    Failover(Vec<Input>),
+}
+
+impl Input {
+    fn open(&self, config: &CollectorConfig) -> Result<Box<dyn Read + Send>, io::Error> {
+        match self {
+            Input::Tcp(addr) => {
+                let sock = connect(addr, config.connect_timeout)?;
+                sock.set_read_timeout(Some(config.read_timeout))?;
+                if let Some(idle) = config.tcp_keepalive {
+                    keepalive(&sock, idle)?;
+                }
+                match &config.tls {
+                    Some(tls) => Ok(Box::new(tls.connect(sock, addr)?)),
+                    None => Ok(Box::new(sock)),
+                }
+            }
+            Input::Serial(serial) => Ok(Box::new(serial::open(serial, config.read_timeout)?)),
+            Input::Udp(addr) => Ok(Box::new(udp::bind(*addr, config.read_timeout)?)),
+            Input::Mqtt(broker, topic) => Ok(Box::new(mqtt::subscribe(
+                broker,
+                topic,
+                config.read_timeout,
+            )?)),
+            Input::HomeWizard(url, interval) => Ok(Box::new(homewizard::open(
+                url,
+                *interval,
+                config.read_timeout,
+            )?)),
+            Input::File(path) => Ok(Box::new(File::open(path)?)),
+            Input::Stdin => Ok(Box::new(io::stdin())),
+            Input::Replay(path, speed) => Ok(Box::new(replay::open(path, *speed)?)),
+            Input::Failover(inputs) => Ok(open_first(inputs, 0, config)?.1),
+        }
+    }
+
+    /// Whether the input can be opened again once closed.
+    pub fn reopenable(&self) -> bool {
+        match self {
+            Input::Stdin | Input::Replay(..) => false,
+            Input::Failover(inputs) => inputs.iter().all(Input::reopenable),
+            _ => true,
+        }
+    }
+}
+
+/// Opens the first of `inputs` that can be opened, trying them in order from
+/// the one at `start` on, and returns its index.
+fn open_first(
+    inputs: &[Input],
+    start: usize,
+    config: &CollectorConfig,
+) -> Result<(usize, Box<dyn Read + Send>), io::Error> {
+    let mut last_err = None;
+    for i in (start..inputs.len()).chain(0..start) {
+        match inputs[i].open(config) {
+            Ok(reader) => return Ok((i, reader)),
+            Err(err) => {
+                tracing::debug!("Failed to connect to {}: {err}", inputs[i]);
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::other("no P1 readers")))
+}
+
+/// Resolves `addr` and connects to the first address accepting the
+/// connection.
+pub fn connect(addr: &str, timeout: Duration) -> Result<TcpStream, io::Error> {
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(sock) => return Ok(sock),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("'{addr}' didn't resolve to any address"),
+        )
+    }))
+}
+
+/// Enables TCP keepalive on `sock`, probing after it's been idle for `idle`
+/// and dropping it after three unanswered probes at the same interval.
+fn keepalive(sock: &TcpStream, idle: Duration) -> Result<(), io::Error> {
+    let secs = idle.as_secs().clamp(1, i32::MAX as u64) as libc::c_int;
+    for (level, name, value) in [
+        (libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1),
+        (libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, secs),
+        (libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, secs),
+        (libc::IPPROTO_TCP, libc::TCP_KEEPCNT, 3),
+    ] {
+        let ret = unsafe {
+            libc::setsockopt(
+                sock.as_raw_fd(),
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Tcp(addr) => write!(f, "tcp://{addr}"),
+            Input::Serial(config) => write!(f, "serial:{}", config.device.display()),
+            Input::Udp(addr) => write!(f, "udp://{addr}"),
+            Input::Mqtt(broker, topic) => write!(f, "{}/{topic}", broker.address),
+            Input::HomeWizard(url, _) => write!(f, "{url}"),
+            Input::File(path) => write!(f, "file:{}", path.display()),
+            Input::Stdin => write!(f, "stdin"),
+            Input::Replay(path, _) => write!(f, "replay:{}", path.display()),
+            Input::Failover(inputs) => {
+                let inputs: Vec<_> = inputs.iter().map(Input::to_string).collect();
+                write!(f, "{}", inputs.join(" or "))
+            }
+        }
+    }
+}
+
+/// A running collector.
+pub struct Collector {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<MeterMetrics>,
+}
+
+impl Collector {
+    /// Asks the collector to stop, which it does after the telegram or the
+    /// connection attempt at hand.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread.thread().unpark();
+    }
+
+    /// Waits for the collector to stop and returns its meter's series.
+    pub fn join(self) -> MeterMetrics {
+        self.thread.join().expect("collector panics to be caught")
+    }
+}
+
+pub fn start_metrics_collector(
+    input: Input,
+    metrics: MeterMetrics,
+    config: Arc<CollectorConfig>,
+) -> Collector {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let thread = thread::spawn(move || {
+        let _span =
+            tracing::info_span!("collector", meter = metrics.meter.as_deref(), reader = %input)
+                .entered();
+        let mut errors = 0;
+        // Panics outside of the collection of telegrams, which are recovered
+        // from by reconnecting, restart the collector afresh.
+        while let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| {
+            run_collector(&input, &metrics, &config, &stopped, &mut errors)
+        })) {
+            metrics.collector_panics.inc();
+            tracing::error!("Collector panicked, restarting: {}", panic_message(&*panic));
+            count_error(&config, &mut errors);
+            thread::park_timeout(config.reconnect_interval);
+            if stopped.load(Ordering::SeqCst) {
+                break;
+            }
+        }
+        metrics
+    });
+    Collector { stop, thread }
+}
+
+/// Reads the telegrams of `input`, reconnecting as needed, until stopped or
+/// the input ends. `errors` counts the connections in a row that delivered
+/// no telegram.
+fn run_collector(
+    input: &Input,
+    metrics: &MeterMetrics,
+    config: &CollectorConfig,
+    stop: &Arc<AtomicBool>,
+    errors: &mut u32,
+) {
+    let mut state = CollectorState {
+        source: input.to_string(),
+        stop: stop.clone(),
+        started: Some(Instant::now()),
+        ..Default::default()
+    };
+    let mut connected_before = false;
+    let mut backoff = Backoff::new(config.reconnect_interval, config.max_reconnect_delay);
+    // Of a failover input, the index of the reader to try first and of
+    // the one connected to.
+    let (mut start, mut active) = (0, 0);
+    loop {
+        let opened = match input {
+            Input::Failover(inputs) => open_first(inputs, start, config).map(|(i, sock)| {
+                if i != active {
+                    tracing::info!("Switching to P1 reader {}", inputs[i]);
+                }
+                active = i;
+                state.source = inputs[i].to_string();
+                state.failback = (i > 0).then(|| Instant::now() + config.failback_interval);
+                for (j, input) in inputs.iter().enumerate() {
+                    metrics
+                        .reader_endpoint_active
+                        .get_or_create(&[("endpoint", input.to_string())])
+                        .set((j == i) as i64);
+                }
+                sock
+            }),
+            input => input.open(config),
+        };
+        match opened {
+            Ok(sock) => {
+                let connected = Instant::now();
+                let parsed = metrics.bytes_parsed.get();
+                if connected_before {
+                    metrics.reader_reconnects.inc();
+                }
+                connected_before = true;
+                metrics.reader_state.set(READER_UP);
+                metrics.reader_connection_up.set(1);
+                metrics.parser_synced.set(0);
+                metrics.reader_backoff.set(backoff.next.as_secs_f64());
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    collect_metrics(sock, metrics, config, &mut state)
+                }));
+                let clean = match result {
+                    Ok(Ok(())) => true,
+                    Ok(Err(err)) => {
+                        tracing::warn!(
+                            error_kind = ?err.kind(),
+                            "Failed to collect metrics: {err}"
+                        );
+                        false
+                    }
+                    Err(panic) => {
+                        metrics.collector_panics.inc();
+                        tracing::error!("Collector panicked: {}", panic_message(&*panic));
+                        false
+                    }
+                };
+                metrics.reader_state.set(READER_DOWN);
+                metrics.reader_connection_up.set(0);
+                metrics.parser_synced.set(0);
+                metrics
+                    .connection_duration
+                    .observe(connected.elapsed().as_secs_f64());
+                if let Input::Failover(inputs) = input {
+                    metrics
+                        .reader_endpoint_active
+                        .get_or_create(&[("endpoint", inputs[active].to_string())])
+                        .set(0);
+                    // Readers accepting connections without delivering
+                    // telegrams, like serial bridges with the cable
+                    // unplugged, are skipped the next time.
+                    start = if clean || metrics.bytes_parsed.get() != parsed {
+                        0
+                    } else {
+                        (active + 1) % inputs.len()
+                    };
+                }
+                if state.stop.load(Ordering::SeqCst) {
+                    return;
+                }
+                if metrics.bytes_parsed.get() != parsed {
+                    *errors = 0;
+                } else {
+                    count_error(config, errors);
+                }
+                if !input.reopenable() {
+                    tracing::warn!("Reached the end of {input}, no more telegrams to read");
+                    return;
+                }
+                if connected.elapsed() >= STABLE_CONNECTION {
+                    backoff.reset();
+                    if clean {
+                        tracing::info!("Connection to P1 reader closed, reconnecting");
+                        continue;
+                    }
+                }
+            }
+            Err(err) => {
+                tracing::warn!(
+                    error_kind = ?err.kind(),
+                    "Failed to connect to P1 reader: {err}"
+                );
+                count_error(config, errors);
+            }
+        };
+        if state.stop.load(Ordering::SeqCst) {
+            return;
+        }
+        let delay = backoff.next_delay();
+        metrics.reader_backoff.set(delay.as_secs_f64());
+        // Woken up early when stopped, and every second meanwhile to
+        // remove the readings once stale.
+        let resume = Instant::now() + delay;
+        while let Some(left) = resume.checked_duration_since(Instant::now()) {
+            expire_stale(metrics, config, &mut state);
+            thread::park_timeout(left.min(Duration::from_secs(1)));
+            if state.stop.load(Ordering::SeqCst) {
+                return;
+            }
+        }
+    }
+}
+
+/// A meter read on every scrape, in [`CollectionMode::Scrape`].
+pub struct OnDemand {
+    input: Input,
+    metrics: MeterMetrics,
+    config: Arc<CollectorConfig>,
+    state: CollectorState,
+    /// Scrapes in a row that read no telegram.
+    errors: u32,
+}
+
+impl OnDemand {
+    pub fn new(input: Input, metrics: MeterMetrics, config: Arc<CollectorConfig>) -> OnDemand {
+        let state = CollectorState {
+            source: input.to_string(),
+            once: true,
+            started: Some(Instant::now()),
+            ..Default::default()
+        };
+        OnDemand {
+            input,
+            metrics,
+            config,
+            state,
+            errors: 0,
+        }
+    }
+
+    /// Connects to the reader and collects a single telegram. Returns whether
+    /// one was read.
+    pub fn collect(&mut self) -> bool {
+        let meter = self.metrics.meter.as_deref();
+        let _span = tracing::info_span!("collector", meter, reader = %self.input).entered();
+        let parsed = self.metrics.bytes_parsed.get();
+        let mut result = self
+            .input
+            .open(&self.config)
+            .and_then(|sock| collect_metrics(sock, &self.metrics, &self.config, &mut self.state));
+        // The telegrams end when the reader is silent for the read timeout.
+        if result.is_ok() && self.metrics.bytes_parsed.get() == parsed {
+            result = Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no telegram received",
+            ));
+        }
+        self.metrics.parser_synced.set(0);
+        expire_stale(&self.metrics, &self.config, &mut self.state);
+        match result {
+            Ok(()) => {
+                self.metrics.reader_state.set(READER_UP);
+                self.errors = 0;
+                true
+            }
+            Err(err) => {
+                self.metrics.reader_state.set(READER_DOWN);
+                tracing::warn!(error_kind = ?err.kind(), "Failed to read a telegram: {err}");
+                count_error(&self.config, &mut self.errors);
+                false
+            }
+        }
+    }
+
+    /// Returns the meter's series.
+    pub fn into_metrics(self) -> MeterMetrics {
+        self.metrics
+    }
+}
+
+/// Exponential backoff between reconnection attempts.
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    /// Delay before the next attempt, without jitter.
+    next: Duration,
+}
+
+impl Backoff {
+    fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max,
+            next: initial.min(max),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.next = self.initial.min(self.max);
+    }
+
+    /// Returns the delay before the next attempt and doubles the one after.
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        jitter(delay)
+    }
+}
+
+/// Returns a random duration between half of `delay` and `delay`, so that
+/// readers failing together don't reconnect in lockstep.
+fn jitter(delay: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    delay.mul_f64(0.5 + 0.5 * (random as f64 / u64::MAX as f64))
+}
+
+fn collect_metrics(
+    sock: impl Read,
+    metrics: &MeterMetrics,
+    config: &CollectorConfig,
+    collector: &mut CollectorState,
+) -> Result<(), io::Error> {
+    let sock = CountingReader::new(sock, metrics.bytes_read.clone());
+    let sock = ProxyReader::new(sock, config.proxy.clone());
+    let sock: Box<dyn Read> = match (config.format, &config.decryption) {
+        (P1Format::Dlms, keys) => Box::new(dlms::reader(sock, keys.clone())),
+        (P1Format::Sml, _) => Box::new(sml::reader(sock)),
+        (P1Format::Dsmr, Some(keys)) => Box::new(decrypt::reader(sock, keys.clone())),
+        (P1Format::Dsmr, None) if config.dsmr_version == DsmrVersion::Current => Box::new(sock),
+        (P1Format::Dsmr, None) => Box::new(compat::reader(sock)),
+    };
+    // The reader ends on the first error, which is kept to tell a silent
+    // reader from a closed connection.
+    let mut read_error = None;
+    let reader = dsmr5::Reader::new(
+        BufReader::new(sock)
+            .bytes()
+            .map_while(|b| b.map_err(|err| read_error = Some(err)).ok()),
+    );
+
+    let mut previous = None;
+    // When the last telegram was processed, for --sample-interval.
+    let mut sampled: Option<Instant> = None;
+    // Energy totals of the last telegram, by register.
+    let mut energy_totals = BTreeMap::new();
+    for readout in reader {
+        if collector.stop.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        expire_stale(metrics, config, collector);
+        metrics.telegrams_received.inc();
+        let text = obis::readout_text(&readout);
+        if let Some(recorder) = &config.recorder {
+            recorder.record(text);
+        }
+        if let (Some(interval), Some(sampled)) = (config.sample_interval, sampled) {
+            // Telegrams in between only count as received, if intact.
+            if sampled.elapsed() < interval {
+                if readout.to_telegram().is_ok() {
+                    record_arrival(metrics, &mut previous);
+                }
+                continue;
+            }
+        }
+        // Only the checksum of the telegram is needed, the objects are
+        // parsed from the text.
+        let parsed = readout.to_telegram().and_then(|_| obis::state(text));
+        {
+            let telegram = RawTelegram {
+                text: text.to_string(),
+                received: SystemTime::now(),
+                error: parsed.as_ref().err().map(|err| format!("{err:?}")),
+            };
+            if parsed.is_ok() {
+                metrics.events.publish(&metrics.meter, &telegram);
+            }
+            let mut recent = metrics.recent_telegrams.lock().unwrap();
+            if parsed.is_ok() {
+                recent.last_parsed = Some(telegram.clone());
+            }
+            recent.last = Some(telegram);
+        }
+        let mut state = match parsed {
+            Ok(state) => state,
+            Err(err) => {
+                metrics.readouts_skipped.inc();
+                metrics.telegram_parse_errors.inc();
+                if config.strict_parsing {
+                    return Err(io::Error::other(format!("{err:?}")));
+                }
+                tracing::warn!("Skipping malformed telegram: {err:?}");
+                continue;
+            }
+        };
+        if let Some((reason, err)) = implausible(&state, text, config, &mut energy_totals) {
+            metrics.readouts_skipped.inc();
+            metrics
+                .readings_rejected
+                .get_or_create(&[("reason", reason)])
+                .inc();
+            tracing::warn!("Skipping telegram with implausible readings: {err}");
+            continue;
+        }
+        metrics.bytes_parsed.inc_by(text.len() as u64);
+        metrics.parser_synced.set(1);
+
+        let now = record_arrival(metrics, &mut previous);
+        sampled = Some(now);
+
+        let id = obis::identification(text);
+        tracing::debug!(
+            bytes = text.len(),
+            equipment_id = id.equipment_id.as_deref(),
+            power_consumed_kw = state.power_delivered,
+            power_produced_kw = state.power_received,
+            tariff = state.tariff_indicator.map(u16::from_be_bytes),
+            "Received telegram"
+        );
+        let mut samples = Vec::new();
+
+        if let Some(version) = &id.version {
+            if collector.dsmr_version.as_ref() != Some(version) {
+                if let Some(old) = &collector.dsmr_version {
+                    tracing::warn!("DSMR version changed from {old} to {version}");
+                    metrics.dsmr_version_changes.inc();
+                }
+                collector.dsmr_version = Some(version.clone());
+            }
+        }
+        if let Some(dsmr_version) = &collector.dsmr_version {
+            let gas_equipment_id = state
+                .slaves
+                .iter()
+                .zip(&id.slave_equipment_ids)
+                .enumerate()
+                .filter(|(i, _)| {
+                    config.mbus_channels.is_empty()
+                        || config.mbus_channels.contains(&(*i as u8 + 1))
+                })
+                .find(|(_, (slave, _))| slave.device_type == Some(0x03))
+                .and_then(|(_, (_, id))| id.clone());
+            let meter_info = MeterInfoLabels {
+                dsmr_version: dsmr_version.clone(),
+                equipment_id: id.equipment_id.clone().unwrap_or_default(),
+                gas_equipment_id: gas_equipment_id.unwrap_or_default(),
+            };
+            if collector.meter_info.as_ref() != Some(&meter_info) {
+                if let Some(old) = &collector.meter_info {
+                    if old.equipment_id != meter_info.equipment_id {
+                        tracing::warn!(
+                            "Equipment identifier changed from '{}' to '{}'",
+                            old.equipment_id,
+                            meter_info.equipment_id
+                        );
+                    }
+                }
+                metrics.meter_info.clear();
+                metrics.meter_info.get_or_create(&meter_info).set(1);
+                collector.meter_info = Some(meter_info);
+            }
+        }
+        let message = obis::text_message(text);
+        if message != collector.message {
+            match &message {
+                Some(message) => tracing::info!("Meter displays message '{message}'"),
+                None => tracing::info!("Meter message cleared"),
+            }
+            metrics.meter_message_info.clear();
+            if let Some(message) = &message {
+                metrics
+                    .meter_message_info
+                    .get_or_create(&[("message", message.clone())])
+                    .set(1);
+            }
+            collector.message = message;
+        }
+
+        if let (Some(dsmr_version), Some(equipment_id)) =
+            (&collector.dsmr_version, &id.equipment_id)
+        {
+            let info = InfoLabels {
+                version: env!("CARGO_PKG_VERSION"),
+                dsmr_version: dsmr_version.clone(),
+                equipment_id: equipment_id.clone(),
+                source: collector.source.clone(),
+            };
+            if collector.info.as_ref() != Some(&info) {
+                metrics.info.clear();
+                metrics.info.get_or_create(&info).set(1);
+                collector.info = Some(info);
+            }
+        }
+
+        if let (Some(delivered), Some(received)) = (state.power_delivered, state.power_received) {
+            let threshold = config.power_conflict_threshold;
+            if delivered > threshold && received > threshold {
+                tracing::debug!(
+                    "Telegram reports both consumed ({delivered} kW) and produced ({received} kW) power"
+                );
+                metrics.power_direction_conflicts.inc();
+            }
+        }
+
+        if let Some(timestamp) = state.datetime.as_ref().and_then(unix_time) {
+            metrics.meter_timestamp.get_or_create(&()).set(timestamp);
+            if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+                let drift = timestamp - now.as_secs_f64();
+                metrics.meter_clock_drift.get_or_create(&()).set(drift);
+            }
+        }
+
+        let unit = config.power_unit;
+        let mut readings = MeterReadings::default();
+        if let Some(pd) = state.power_delivered.map(|pd| unit.scale(pd)) {
+            metrics.power_consumed.get_or_create(&()).set(pd);
+            readings.power_consumed = Some(pd);
+            samples.push(Sample::gauge(unit.sample_name("power_consumed_kw"), pd));
+        }
+        if let Some(pd) = state.power_received.map(|pd| unit.scale(pd)) {
+            metrics.power_produced.get_or_create(&()).set(pd);
+            readings.power_produced = Some(pd);
+            samples.push(Sample::gauge(unit.sample_name("power_produced_kw"), pd));
+        }
+        if let (Some(consumed), Some(produced)) = (state.power_delivered, state.power_received) {
+            let net = unit.scale(consumed - produced);
+            metrics.power_net.get_or_create(&()).set(net);
+            samples.push(Sample::gauge(unit.sample_name("power_net_kw"), net));
+        }
+        if let Some(date) = config.peak_timezone.today(state.datetime.as_ref()) {
+            let peaks = [
+                (
+                    state.power_delivered,
+                    &mut collector.consumed_peak,
+                    &metrics.power_consumed_peak,
+                    "power_consumed_peak_kw",
+                ),
+                (
+                    state.power_received,
+                    &mut collector.produced_peak,
+                    &metrics.power_produced_peak,
+                    "power_produced_peak_kw",
+                ),
+            ];
+            for (power, peak, family, name) in peaks {
+                let Some(power) = power.map(|power| unit.scale(power)) else {
+                    continue;
+                };
+                let (day, month) = peak.update(date, power);
+                for (period, peak) in [("day", day), ("month", month)] {
+                    family.get_or_create(&[("period", period)]).set(peak);
+                    samples.push(
+                        Sample::gauge(unit.sample_name(name), peak).with_label("period", period),
+                    );
+                }
+            }
+        }
+
+        let average_demand =
+            obis::find(text, obis::AVERAGE_DEMAND).and_then(|groups| obis::power(groups.first()?));
+        if let Some(demand) = average_demand.map(|demand| unit.scale(demand)) {
+            metrics.average_demand.get_or_create(&()).set(demand);
+            samples.push(Sample::gauge(unit.sample_name("average_demand_kw"), demand));
+        }
+        let monthly_peak =
+            obis::find(text, obis::MONTHLY_PEAK).and_then(|groups| obis::power(groups.last()?));
+        if let Some(peak) = monthly_peak.map(|peak| unit.scale(peak)) {
+            metrics.monthly_peak.get_or_create(&()).set(peak);
+            samples.push(Sample::gauge(unit.sample_name("monthly_peak_kw"), peak));
+        }
+        if let Some(history) = obis::peak_history(text) {
+            let months: Vec<String> = history.iter().map(|(month, _)| month.clone()).collect();
+            if months != collector.peak_months {
+                metrics.peak_history.clear();
+                collector.peak_months = months;
+            }
+            for (month, peak) in history {
+                metrics
+                    .peak_history
+                    .get_or_create(&[("month", month)])
+                    .set(unit.scale(peak));
+            }
+        }
+
+        if let Some(failures) = state.power_failures {
+            let failures = collector.regressions.store(
+                metrics,
+                "power_failures",
+                "power_failures".to_string(),
+                &metrics.power_failures,
+                failures,
+            );
+            samples.push(Sample::counter("power_failures", failures as f64));
+        }
+        if let Some(failures) = state.long_power_failures {
+            let failures = collector.regressions.store(
+                metrics,
+                "long_power_failures",
+                "long_power_failures".to_string(),
+                &metrics.long_power_failures,
+                failures,
+            );
+            samples.push(Sample::counter("long_power_failures", failures as f64));
+        }
+        if let Some(log) = obis::power_failure_log(text) {
+            let mut events: Vec<(f64, f64)> = log
+                .iter()
+                .filter_map(|(end, length)| Some((unix_time(end)?, *length)))
+                .collect();
+            events.sort_by(|a, b| b.0.total_cmp(&a.0));
+            // Only the failures logged since the last telegram are added.
+            let logged = collector.power_failures_logged;
+            let added: f64 = events
+                .iter()
+                .filter(|(end, _)| logged.is_none_or(|logged| *end > logged))
+                .map(|(_, length)| length)
+                .sum();
+            metrics.long_power_failure_duration.inc_by(added);
+            if let Some(&(end, _)) = events.first() {
+                collector.power_failures_logged = Some(logged.map_or(end, |l| l.max(end)));
+            }
+            events.truncate(config.power_failure_events);
+            if events != collector.power_failure_events {
+                metrics.long_power_failure_end.clear();
+                metrics.long_power_failure_length.clear();
+                for (i, &(end, length)) in events.iter().enumerate() {
+                    let labels = [("event", i + 1)];
+                    metrics
+                        .long_power_failure_end
+                        .get_or_create(&labels)
+                        .set(end);
+                    metrics
+                        .long_power_failure_length
+                        .get_or_create(&labels)
+                        .set(length);
+                }
+                collector.power_failure_events = events;
+            }
+            samples.push(Sample::counter(
+                "long_power_failure_duration_seconds",
+                metrics.long_power_failure_duration.get(),
+            ));
+        }
+
+        for (i, (line, phase)) in state.lines.iter().zip(PHASES).enumerate() {
+            if let Some(voltage) = line.voltage {
+                metrics
+                    .voltage
+                    .get_or_create(&[("phase", phase)])
+                    .set(voltage);
+                samples.push(Sample::gauge("voltage_volts", voltage).with_label("phase", phase));
+            }
+            if let Some(current) = line.current {
+                metrics
+                    .current
+                    .get_or_create(&[("phase", phase)])
+                    .set(current as i64);
+                samples.push(
+                    Sample::gauge("current_amperes", current as f64).with_label("phase", phase),
+                );
+            }
+            if let Some(sags) = line.voltage_sags {
+                let sags = collector.regressions.store(
+                    metrics,
+                    "voltage_sags",
+                    format!("voltage_sags/{phase}"),
+                    &metrics.voltage_sags.get_or_create(&[("phase", phase)]),
+                    sags,
+                );
+                samples
+                    .push(Sample::counter("voltage_sags", sags as f64).with_label("phase", phase));
+            }
+            if let Some(swells) = line.voltage_swells {
+                let swells = collector.regressions.store(
+                    metrics,
+                    "voltage_swells",
+                    format!("voltage_swells/{phase}"),
+                    &metrics.voltage_swells.get_or_create(&[("phase", phase)]),
+                    swells,
+                );
+                samples.push(
+                    Sample::counter("voltage_swells", swells as f64).with_label("phase", phase),
+                );
+            }
+            if let Some(power) = line.active_power_plus.map(|power| unit.scale(power)) {
+                metrics
+                    .phase_power_consumed
+                    .get_or_create(&[("phase", phase)])
+                    .set(power);
+                samples.push(
+                    Sample::gauge(unit.sample_name("phase_power_consumed_kw"), power)
+                        .with_label("phase", phase),
+                );
+            }
+            if let Some(power) = line.active_power_neg.map(|power| unit.scale(power)) {
+                metrics
+                    .phase_power_produced
+                    .get_or_create(&[("phase", phase)])
+                    .set(power);
+                samples.push(
+                    Sample::gauge(unit.sample_name("phase_power_produced_kw"), power)
+                        .with_label("phase", phase),
+                );
+            }
+            let reactive = obis::reactive_power(text, i);
+            for (power, family, name) in [
+                (
+                    reactive[0],
+                    &metrics.phase_reactive_power_consumed,
+                    "phase_reactive_power_consumed_kvar",
+                ),
+                (
+                    reactive[1],
+                    &metrics.phase_reactive_power_produced,
+                    "phase_reactive_power_produced_kvar",
+                ),
+            ] {
+                if let Some(power) = power.map(|power| unit.scale(power)) {
+                    family.get_or_create(&[("phase", phase)]).set(power);
+                    samples.push(
+                        Sample::gauge(unit.sample_name(name), power).with_label("phase", phase),
+                    );
+                }
+            }
+            // Only meters reporting the reactive power tell the power factor
+            // apart from 1.
+            let active = line
+                .active_power_plus
+                .map(|plus| plus - line.active_power_neg.unwrap_or_default());
+            let reactive = (reactive != [None, None])
+                .then(|| reactive[0].unwrap_or_default() - reactive[1].unwrap_or_default());
+            if let (Some(active), Some(reactive)) = (active, reactive) {
+                let apparent = active.hypot(reactive);
+                if apparent > 0.0 {
+                    let factor = active.abs() / apparent;
+                    metrics
+                        .phase_power_factor
+                        .get_or_create(&[("phase", phase)])
+                        .set(factor);
+                    samples.push(
+                        Sample::gauge("phase_power_factor", factor).with_label("phase", phase),
+                    );
+                }
+            }
+        }
+
+        let (mut energy_cost, mut gas_cost) = collector.costs.daily_charges(&config.prices, now);
+        for tariff in obis::energy_tariffs(text) {
+            let [consumed, produced] = obis::energy_registers(tariff);
+            let name = config.tariff_names.name(tariff);
+            let consumed = energy(text, &consumed, config.energy_scale).map(|pd| {
+                let total = collector.regressions.store(
+                    metrics,
+                    unit.sample_name("power_consumed_kwh"),
+                    format!("power_consumed_kwh/{name}"),
+                    &metrics
+                        .power_consumed_total
+                        .get_or_create(&[("tariff", name.clone())]),
+                    unit.energy(pd),
+                );
+                unit.kwh(total)
+            });
+            let produced = energy(text, &produced, config.energy_scale).map(|pd| {
+                let total = collector.regressions.store(
+                    metrics,
+                    unit.sample_name("power_produced_kwh"),
+                    format!("power_produced_kwh/{name}"),
+                    &metrics
+                        .power_produced_total
+                        .get_or_create(&[("tariff", name.clone())]),
+                    unit.energy(pd),
+                );
+                unit.kwh(total)
+            });
+            if let Some(consumed) = consumed {
+                energy_cost += collector.costs.energy(&config.prices, tariff, consumed);
+                readings.energy_consumed =
+                    Some(readings.energy_consumed.unwrap_or_default() + consumed);
+            }
+            if let Some(produced) = produced {
+                readings.energy_produced =
+                    Some(readings.energy_produced.unwrap_or_default() + produced);
+            }
+            let tariff = name;
+            if let Some(pd) = consumed.map(|pd| unit.energy(pd)) {
+                let name = unit.sample_name("power_consumed_kwh");
+                samples.push(Sample::counter(name, pd).with_label("tariff", &tariff));
+            }
+            if let Some(pd) = produced.map(|pd| unit.energy(pd)) {
+                let name = unit.sample_name("power_produced_kwh");
+                samples.push(Sample::counter(name, pd).with_label("tariff", &tariff));
+            }
+            if let (Some(consumed), Some(produced)) = (consumed, produced) {
+                let net = unit.energy(consumed - produced);
+                metrics
+                    .power_net_energy
+                    .get_or_create(&[("tariff", tariff.clone())])
+                    .set(net);
+                let name = unit.sample_name("power_net_kwh");
+                samples.push(Sample::gauge(name, net).with_label("tariff", tariff));
+            }
+        }
+        let time = state.datetime.as_ref().and_then(unix_time).or_else(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+            Some(now.as_secs_f64())
+        });
+        let totals = [readings.energy_consumed, readings.energy_produced];
+        if let (Some(time), [Some(_), _] | [_, Some(_)]) = (time, totals) {
+            if let Some((end, energy)) = collector.quarter_hour.update(time as i64, totals) {
+                metrics.quarter_hour_end.get_or_create(&()).set(end as f64);
+                samples.push(Sample::gauge(
+                    "quarter_hour_end_timestamp_seconds",
+                    end as f64,
+                ));
+                let directions = [
+                    (
+                        energy[0],
+                        &metrics.quarter_hour_energy_consumed,
+                        "quarter_hour_energy_consumed_kwh",
+                    ),
+                    (
+                        energy[1],
+                        &metrics.quarter_hour_energy_produced,
+                        "quarter_hour_energy_produced_kwh",
+                    ),
+                ];
+                for (energy, family, name) in directions {
+                    if let Some(energy) = energy.map(|energy| unit.energy(energy)) {
+                        family.get_or_create(&()).set(energy);
+                        samples.push(Sample::gauge(unit.sample_name(name), energy));
+                    }
+                }
+            }
+        }
+        *metrics.readings.lock().unwrap() = readings;
+
+        if config.pre_register {
+            reset_active_tariff(metrics, config);
+        } else {
+            metrics.active_tariff.clear();
+        }
+        if let Some(ti) = state.tariff_indicator {
+            let tariff = u16::from_be_bytes(ti);
+            if !config.tariff_names.contains(tariff) && collector.unknown_tariff != Some(tariff) {
+                tracing::warn!("Meter reports tariff {tariff} missing from --tariff-names");
+                collector.unknown_tariff = Some(tariff);
+            }
+            let since = match collector.tariff {
+                Some((active, since)) if active == tariff => since,
+                _ => {
+                    collector.tariff = Some((tariff, now));
+                    now
+                }
+            };
+            metrics
+                .active_tariff_duration
+                .set((now - since).as_secs_f64());
+            let tariff = config.tariff_names.name(tariff);
+            metrics
+                .active_tariff
+                .get_or_create(&[("tariff", tariff.clone())])
+                .set(1);
+            samples.push(Sample::gauge("active_tariff", 1.0).with_label("tariff", tariff));
+        }
+
+        for (extra, series) in &metrics.extra_obis {
+            let value =
+                obis::find(text, &extra.code).and_then(|groups| obis::value(groups.last()?));
+            let Some((value, _)) = value else {
+                continue;
+            };
+            match series {
+                ExtraSeries::Gauge(gauge) => {
+                    gauge.set(value * extra.scale);
+                }
+                ExtraSeries::Counter(counter) => {
+                    collector.regressions.store(
+                        metrics,
+                        &extra.name,
+                        format!("{}/{}", extra.name, extra.code),
+                        counter,
+                        value * extra.scale,
+                    );
+                }
+            }
+        }
+        if config.export_raw_obis {
+            for (code, value) in obis::objects(text).filter_map(obis::numeric_value) {
+                metrics
+                    .obis_value
+                    .get_or_create(&[("code", code.to_string())])
+                    .set(value);
+            }
+        }
+
+        for (i, sl) in state.slaves.iter_mut().enumerate() {
+            let channel = i as u8 + 1;
+            if !config.mbus_channels.is_empty() && !config.mbus_channels.contains(&channel) {
+                if sl.device_type.is_some() {
+                    metrics.mbus_readings_ignored.inc();
+                }
+                *sl = Slave::default();
+            }
+        }
+
+        for (i, sl) in state.slaves.iter().enumerate() {
+            let channel = i as u8 + 1;
+            let present = sl.device_type.is_some();
+            collector.seen_mbus_channels[i] |= present;
+
+            let report = if config.expected_mbus_channels.is_empty() {
+                collector.seen_mbus_channels[i]
+            } else {
+                config.expected_mbus_channels.contains(&channel)
+            };
+            if report {
+                metrics
+                    .mbus_channel_present
+                    .get_or_create(&[("channel", channel)])
+                    .set(present as i64);
+            }
+
+            if let (Some(device_type), false) = (sl.device_type, collector.reported_mbus_devices[i])
+            {
+                let labels = MbusDeviceLabels {
+                    channel,
+                    device_type,
+                    medium: mbus_medium(device_type),
+                    equipment_id: id.slave_equipment_ids[i].clone().unwrap_or_default(),
+                };
+                metrics.mbus_device_info.get_or_create(&labels).set(1);
+                collector.reported_mbus_devices[i] = true;
+            }
+        }
+
+        for (i, sl) in state.slaves.into_iter().enumerate() {
+            let Slave {
+                device_type: Some(device_type),
+                meter_reading: Some((captured, reading)),
+            } = sl
+            else {
+                continue;
+            };
+            let channel = i as u8 + 1;
+            let labels = MbusLabels {
+                mbus_channel: channel,
+                equipment_id: id.slave_equipment_ids[i].clone().unwrap_or_default(),
+            };
+            let unit_scale = || {
+                obis::unit(text, &format!("0-{}:24.2.1", i + 1))
+                    .and_then(volume_unit_scale)
+                    .unwrap_or(1.0)
+            };
+            if let Some(timestamp) = unix_time(&captured) {
+                metrics.mbus_timestamp.get_or_create(&labels).set(timestamp);
+            }
+            match device_type {
+                3 => {
+                    let gd = collector.regressions.store(
+                        metrics,
+                        "gas_consumed_cubic_meters",
+                        format!(
+                            "gas_consumed_cubic_meters/{channel}/{}",
+                            labels.equipment_id
+                        ),
+                        &metrics.gas_consumed_total.get_or_create(&labels),
+                        reading * config.gas_scale.unwrap_or_else(unit_scale),
+                    );
+                    samples.push(slave_sample("gas_consumed_cubic_meters", gd, &labels));
+                    gas_cost += collector.costs.gas(&config.prices, channel, gd);
+
+                    // The gas meter captures its reading only every 5 minutes
+                    // (hourly before DSMR 5), so the flow is the average
+                    // between the last two captures rather than between
+                    // telegrams.
+                    let reading = GasReading {
+                        equipment_id: labels.equipment_id.clone(),
+                        captured: capture_seconds(&captured),
+                        volume: gd,
+                    };
+                    let previous = collector.gas_readings[i]
+                        .take()
+                        .filter(|previous| previous.equipment_id == reading.equipment_id);
+                    match previous {
+                        Some(previous) if previous.captured == reading.captured => {
+                            collector.gas_readings[i] = Some(previous);
+                        }
+                        Some(previous)
+                            if previous.captured < reading.captured
+                                && previous.volume <= reading.volume =>
+                        {
+                            let flow = (reading.volume - previous.volume) * 3600.0
+                                / (reading.captured - previous.captured) as f64;
+                            metrics.gas_flow.get_or_create(&labels).set(flow);
+                            samples.push(
+                                Sample::gauge("gas_flow_cubic_meters_per_hour", flow)
+                                    .with_label("mbus_channel", channel.to_string())
+                                    .with_label("equipment_id", &labels.equipment_id),
+                            );
+                            collector.gas_readings[i] = Some(reading);
+                        }
+                        _ => collector.gas_readings[i] = Some(reading),
+                    }
+                }
+                7 => {
+                    let wd = collector.regressions.store(
+                        metrics,
+                        "water_consumed_cubic_meters",
+                        format!(
+                            "water_consumed_cubic_meters/{channel}/{}",
+                            labels.equipment_id
+                        ),
+                        &metrics.water_consumed_total.get_or_create(&labels),
+                        reading * unit_scale(),
+                    );
+                    samples.push(slave_sample("water_consumed_cubic_meters", wd, &labels));
+                }
+                0x04 | 0x0A | 0x0C | 0x0D => {
+                    let unit = obis::unit(text, &format!("0-{}:24.2.1", i + 1));
+                    let (counter, name) = if device_type == 0x0A {
+                        (&metrics.cooling_consumed_total, "cooling_consumed_gj")
+                    } else {
+                        (&metrics.heat_consumed_total, "heat_consumed_gj")
+                    };
+                    let hd = collector.regressions.store(
+                        metrics,
+                        name,
+                        format!("{name}/{channel}/{}", labels.equipment_id),
+                        &counter.get_or_create(&labels),
+                        reading * unit.and_then(heat_unit_scale).unwrap_or(1.0),
+                    );
+                    samples.push(slave_sample(name, hd, &labels));
+                }
+                _ => {}
+            }
+        }
+
+        if config.prices.energy_configured() {
+            let cost = metrics.energy_cost.get_or_create(&());
+            cost.inc_by(energy_cost);
+            samples.push(Sample::counter("energy_cost", cost.get()));
+        }
+        if let Some(day_ahead) = &config.prices.day_ahead {
+            match day_ahead.current() {
+                Some(price) => {
+                    metrics.energy_price.get_or_create(&()).set(price);
+                    samples.push(Sample::gauge("energy_price", price));
+                }
+                None => metrics.energy_price.clear(),
+            }
+        }
+        if config.prices.gas_configured() {
+            let cost = metrics.gas_cost.get_or_create(&());
+            cost.inc_by(gas_cost);
+            samples.push(Sample::counter("gas_cost", cost.get()));
+        }
+
+        samples.retain(|sample| {
+            MetricGroup::of(sample.name).is_none_or(|group| config.metric_groups.contains(&group))
+        });
+        if let Some(meter) = &metrics.meter {
+            samples = samples
+                .into_iter()
+                .map(|sample| sample.with_label("meter", meter))
+                .collect();
+        }
+        for sink in config.sinks.iter() {
+            sink.publish_telegram(text);
+            sink.publish(&samples);
+        }
+        if collector.once {
+            break;
+        }
+        if collector
+            .failback
+            .is_some_and(|failback| failback <= Instant::now())
+        {
+            tracing::info!("Trying to return to the primary P1 reader");
+            break;
+        }
+    }
+
+    match read_error {
+        Some(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no data received for {:?}", config.read_timeout),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the seconds since the Unix epoch of a timestamp of the meter, as
+/// if it were UTC and in standard time, so that the time between readings
+/// holds across a DST change.
+fn capture_seconds(tst: &TST) -> i64 {
+    let days = unix_days(2000 + tst.year as i64, tst.month, tst.day);
+    let seconds = ((days * 24 + tst.hour as i64) * 60 + tst.minute as i64) * 60 + tst.second as i64;
+    if tst.dst {
+        seconds - 3600
+    } else {
+        seconds
+    }
+}
+
+/// Returns the Unix time of a timestamp of the meter, taking it to be in the
+/// system's timezone, as set by `TZ`.
+fn unix_time(tst: &TST) -> Option<f64> {
+    // SAFETY: tm is plain data, which mktime only reads and normalizes.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = 100 + tst.year as libc::c_int;
+    tm.tm_mon = tst.month as libc::c_int - 1;
+    tm.tm_mday = tst.day as libc::c_int;
+    tm.tm_hour = tst.hour as libc::c_int;
+    tm.tm_min = tst.minute as libc::c_int;
+    tm.tm_sec = tst.second as libc::c_int;
+    tm.tm_isdst = tst.dst as libc::c_int;
+    match unsafe { libc::mktime(&mut tm) } {
+        -1 => None,
+        time => Some(time as f64),
+    }
+}
+
+/// Returns the days since the Unix epoch of a date.
+pub fn unix_days(year: i64, month: u8, day: u8) -> i64 {
+    // Days since 0000-03-01, so that the leap day ends a year.
+    let (year, month) = if month > 2 {
+        (year, month as i64 - 3)
+    } else {
+        (year - 1, month as i64 + 9)
+    };
+    year * 365 + year.div_euclid(4) - year.div_euclid(100)
+        + year.div_euclid(400)
+        + (153 * month + 2) / 5
+        + day as i64
+        - 1
+        - 719468
+}
+
+/// Returns the date of a day since the Unix epoch.
+pub fn civil_date(days: i64) -> (i64, u8, u8) {
+    // Days since 0000-03-01, then the year and day of the year of a
+    // calendar starting in March.
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u8;
+    if month < 10 {
+        (era * 400 + year_of_era, month as u8 + 3, day)
+    } else {
+        (era * 400 + year_of_era + 1, month as u8 - 9, day)
+    }
+}
+
+/// Counts a connection to the P1 reader that delivered no telegram, giving
+/// up on the reader after `--max-consecutive-errors` in a row if so
+/// configured.
+fn count_error(config: &CollectorConfig, errors: &mut u32) {
+    *errors += 1;
+    if *errors != config.max_consecutive_errors {
+        return;
+    }
+    tracing::error!("No telegram from the P1 reader in {errors} attempts in a row");
+    if config.exit_on_failure {
+        cleanup::remove_files();
+        process::exit(1);
+    }
+}
+
+/// Removes the readings once no telegram has been received for
+/// `--stale-after`, until the next one.
+fn expire_stale(metrics: &MeterMetrics, config: &CollectorConfig, state: &mut CollectorState) {
+    let Some(stale_after) = config.stale_after else {
+        return;
+    };
+    let last = metrics
+        .telegram_timing
+        .lock()
+        .unwrap()
+        .last
+        .or(state.started);
+    if last.is_none_or(|last| last.elapsed() < stale_after) {
+        state.expired = false;
+    } else if !state.expired {
+        tracing::warn!("No telegram received for {stale_after:?}, removing the readings");
+        metrics.expire_readings();
+        state.expired = true;
+    }
+}
+
+/// Records the arrival of an intact telegram, after the `previous` one, and
+/// returns its time.
+fn record_arrival(metrics: &MeterMetrics, previous: &mut Option<Instant>) -> Instant {
+    let now = Instant::now();
+    {
+        let mut timing = metrics.telegram_timing.lock().unwrap();
+        timing.record(now, previous.map(|prev| now - prev));
+        if let Some(interval) = timing.interval {
+            metrics.telegram_interval.set(interval);
+        }
+    }
+    if let Ok(timestamp) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        metrics.last_telegram_timestamp.set(timestamp.as_secs_f64());
+    }
+    *previous = Some(now);
+    now
+}
+
+/// Returns why the readings of a telegram are implausible, if they are, as
+/// the `reason` label and a description. The energy totals are tracked in
+/// `totals`, so that a single corrupted telegram doesn't reject the ones
+/// following it.
+fn implausible(
+    state: &State,
+    text: &str,
+    config: &CollectorConfig,
+    totals: &mut BTreeMap<String, f64>,
+) -> Option<(&'static str, String)> {
+    let mut implausible = None;
+    if let Some(max) = config.max_power {
+        let power = [state.power_delivered, state.power_received]
+            .into_iter()
+            .chain(
+                state
+                    .lines
+                    .iter()
+                    .flat_map(|line| [line.active_power_plus, line.active_power_neg]),
+            )
+            .flatten()
+            .find(|&power| power > max);
+        if let Some(power) = power {
+            implausible = Some(("power", format!("{power} kW exceeds {max} kW")));
+        }
+    }
+    for tariff in obis::energy_tariffs(text) {
+        for register in obis::energy_registers(tariff) {
+            let Some(total) = energy(text, &register, config.energy_scale) else {
+                continue;
+            };
+            let last = totals.insert(register.clone(), total);
+            let (Some(max), Some(last)) = (config.max_energy_step, last) else {
+                continue;
+            };
+            if implausible.is_none() && total - last > max {
+                implausible = Some((
+                    "energy_step",
+                    format!(
+                        "{register} increased by {} kWh, more than {max} kWh",
+                        total - last
+                    ),
+                ));
+            }
+        }
+    }
+    implausible
+}
+
+/// Returns a sample of an M-Bus device reading.
+fn slave_sample(name: &'static str, value: f64, labels: &MbusLabels) -> Sample {
+    Sample::counter(name, value)
+        .with_label("mbus_channel", labels.mbus_channel.to_string())
+        .with_label("equipment_id", &labels.equipment_id)
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        msg
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Returns the name of an M-Bus device type as per EN 13757-3.
+pub fn mbus_medium(device_type: u64) -> &'static str {
+    match device_type {
+        0x02 => "electricity",
+        0x03 => "gas",
+        0x04 => "heat",
+        0x06 => "warm_water",
+        0x07 => "water",
+        0x0A => "cooling",
+        0x0C => "heat",
+        0x0D => "heat_cooling",
+        _ => "other",
+    }
+}
+
+/// Reads an energy register, normalized to kWh unless `scale` is given.
+pub fn energy(telegram: &str, reference: &str, scale: Option<f64>) -> Option<f64> {
+    let (value, unit) = obis::value(obis::find(telegram, reference)?.first()?)?;
+    let scale = scale.unwrap_or_else(|| unit.and_then(energy_unit_scale).unwrap_or(1.0));
+    Some(value * scale)
+}
+
+fn energy_unit_scale(unit: &str) -> Option<f64> {
+    match unit {
+        "kWh" => Some(1.0),
+        "Wh" => Some(0.001),
+        "MWh" => Some(1000.0),
+        _ => None,
+    }
+}
+
+/// Returns the factor converting thermal energy in `unit` to GJ.
+fn heat_unit_scale(unit: &str) -> Option<f64> {
+    match unit {
+        "GJ" => Some(1.0),
+        "MJ" => Some(0.001),
+        "kWh" => Some(0.0036),
+        "MWh" => Some(3.6),
+        _ => None,
+    }
+}
+
+/// Returns the factor converting a volume in `unit` to m³. Some M-Bus
+/// gateways keep the multiplier of the device in the unit, e.g. `0.01m3`
+/// for a meter counting in units of 0.01 m³.
+fn volume_unit_scale(unit: &str) -> Option<f64> {
+    let base = unit.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let multiplier = match &unit[..unit.len() - base.len()] {
+        "" => 1.0,
+        multiplier => multiplier.parse().ok()?,
+    };
+    let scale = match base {
+        "m3" => 1.0,
+        "dm3" | "l" | "L" => 0.001,
+        _ => return None,
+    };
+    Some(multiplier * scale)
+}
+
+/// A reader that counts the number of bytes read from the underlying reader.
+struct CountingReader<R> {
+    inner: R,
+    counter: Counter,
+}
+
+impl<R> CountingReader<R> {
+    fn new(inner: R, counter: Counter) -> Self {
+        CountingReader { inner, counter }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.counter.inc_by(n as u64);
+        Ok(n)
+    }
+}
+
+/// A reader that forwards everything read to the proxy clients.
+struct ProxyReader<R> {
+    inner: R,
+    proxy: Option<Arc<Proxy>>,
+}
+
+impl<R> ProxyReader<R> {
+    fn new(inner: R, proxy: Option<Arc<Proxy>>) -> Self {
+        ProxyReader { inner, proxy }
+    }
+}
+
+impl<R: Read> Read for ProxyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(proxy) = &self.proxy {
+            proxy.broadcast(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::P1Metrics;
+    use std::{fs, sync::atomic::AtomicUsize, sync::Mutex};
+
+    /// Returns a DSMR 5 telegram with the given objects and its CRC.
+    fn telegram(objects: &[&str]) -> String {
+        let mut text = String::from("/ISK5\\2M550T-1012\r\n\r\n1-3:0.2.8(50)\r\n");
+        text.push_str("0-0:1.0.0(261015034138S)\r\n");
+        for object in objects {
+            text.push_str(object);
+            text.push_str("\r\n");
+        }
+        text.push('!');
+        let crc = obis::crc16(text.as_bytes());
+        format!("{text}{crc:04X}\r\n")
+    }
+
+    /// Collects the telegrams and returns the meter's series.
+    fn collect(telegrams: &[String], config: &CollectorConfig) -> MeterMetrics {
+        let metrics = P1Metrics::new(&[], None).meter(None);
+        let mut state = CollectorState::default();
+        collect_metrics(telegrams.concat().as_bytes(), &metrics, config, &mut state).unwrap();
+        metrics
+    }
+
+    /// The example telegram of the DSMR 5.0.2 P1 companion standard, with
+    /// the CRC of its text.
+    const DSMR5_EXAMPLE: &str = "/ISk5\\2MT382-1000\r\n\
+        \r\n\
+        1-3:0.2.8(50)\r\n\
+        0-0:1.0.0(101209113020W)\r\n\
+        0-0:96.1.1(4B384547303034303436333935353037)\r\n\
+        1-0:1.8.1(123456.789*kWh)\r\n\
+        1-0:1.8.2(123456.789*kWh)\r\n\
+        1-0:2.8.1(123456.789*kWh)\r\n\
+        1-0:2.8.2(123456.789*kWh)\r\n\
+        0-0:96.14.0(0002)\r\n\
+        1-0:1.7.0(01.193*kW)\r\n\
+        1-0:2.7.0(00.000*kW)\r\n\
+        0-0:96.7.21(00004)\r\n\
+        0-0:96.7.9(00002)\r\n\
+        1-0:99.97.0(2)(0-0:96.7.19)(101208152415W)(0000000240*s)(101208151004W)(0000000301*s)\r\n\
+        1-0:32.32.0(00002)\r\n\
+        1-0:52.32.0(00001)\r\n\
+        1-0:72.32.0(00000)\r\n\
+        1-0:32.36.0(00000)\r\n\
+        1-0:52.36.0(00003)\r\n\
+        1-0:72.36.0(00000)\r\n\
+        0-0:96.13.0(303132333435363738393A3B3C3D3E3F303132333435363738393A3B3C3D3E3F\
+        303132333435363738393A3B3C3D3E3F303132333435363738393A3B3C3D3E3F\
+        303132333435363738393A3B3C3D3E3F)\r\n\
+        1-0:32.7.0(220.1*V)\r\n\
+        1-0:52.7.0(220.2*V)\r\n\
+        1-0:72.7.0(220.3*V)\r\n\
+        1-0:31.7.0(001*A)\r\n\
+        1-0:51.7.0(002*A)\r\n\
+        1-0:71.7.0(003*A)\r\n\
+        1-0:21.7.0(01.111*kW)\r\n\
+        1-0:41.7.0(02.222*kW)\r\n\
+        1-0:61.7.0(03.333*kW)\r\n\
+        1-0:22.7.0(04.444*kW)\r\n\
+        1-0:42.7.0(05.555*kW)\r\n\
+        1-0:62.7.0(06.666*kW)\r\n\
+        0-1:24.1.0(003)\r\n\
+        0-1:96.1.0(3232323241424344313233343536373839)\r\n\
+        0-1:24.2.1(101209112500W)(12785.123*m3)\r\n\
+        !E47C\r\n";
+
+    /// A sink recording the samples published to it, as
+    /// `name{label="value",...}` by their value.
+    #[derive(Default)]
+    struct RecordingSink {
+        samples: Arc<Mutex<HashMap<String, f64>>>,
+    }
+
+    impl Sink for RecordingSink {
+        fn publish(&self, samples: &[Sample]) {
+            let mut recorded = self.samples.lock().unwrap();
+            for sample in samples {
+                let labels: Vec<_> = sample
+                    .labels
+                    .iter()
+                    .map(|(name, value)| format!("{name}=\"{value}\""))
+                    .collect();
+                let series = if labels.is_empty() {
+                    sample.name.to_string()
+                } else {
+                    format!("{}{{{}}}", sample.name, labels.join(","))
+                };
+                recorded.insert(series, sample.value);
+            }
+        }
+    }
+
+    #[test]
+    fn dsmr5_example_state() {
+        let state = obis::state(DSMR5_EXAMPLE).unwrap();
+        assert_eq!(state.power_delivered, Some(1.193));
+        assert_eq!(state.power_received, Some(0.0));
+        assert_eq!(state.power_failures, Some(4));
+        assert_eq!(state.lines[0].voltage, Some(220.1));
+        assert_eq!(state.lines[2].active_power_neg, Some(6.666));
+        assert!(state.datetime.is_some());
+        assert!(obis::state("1-0:1.7.0(01.x93*kW)\r\n").is_err());
+    }
+
+    #[test]
+    fn dsmr5_example_collected() {
+        let sink = RecordingSink::default();
+        let samples = sink.samples.clone();
+        let config = CollectorConfig {
+            sinks: Arc::new(vec![Box::new(sink)]),
+            ..Default::default()
+        };
+        let metrics = collect(&[DSMR5_EXAMPLE.to_string()], &config);
+        assert_eq!(metrics.telegrams_received.get(), 1);
+        assert_eq!(metrics.telegram_parse_errors.get(), 0);
+
+        let samples = samples.lock().unwrap();
+        for (series, value) in [
+            ("power_consumed_kw", 1.193),
+            ("power_produced_kw", 0.0),
+            ("power_net_kw", 1.193),
+            ("power_consumed_kwh{tariff=\"low\"}", 123456.789),
+            ("power_produced_kwh{tariff=\"high\"}", 123456.789),
+            ("power_net_kwh{tariff=\"low\"}", 0.0),
+            ("voltage_volts{phase=\"l2\"}", 220.2),
+            ("current_amperes{phase=\"l3\"}", 3.0),
+            ("phase_power_consumed_kw{phase=\"l1\"}", 1.111),
+            ("phase_power_produced_kw{phase=\"l3\"}", 6.666),
+            ("power_failures", 4.0),
+            ("long_power_failures", 2.0),
+            ("voltage_sags{phase=\"l1\"}", 2.0),
+            ("voltage_swells{phase=\"l2\"}", 3.0),
+            (
+                "gas_consumed_cubic_meters{mbus_channel=\"1\",equipment_id=\"2222ABCD123456789\"}",
+                12785.123,
+            ),
+        ] {
+            assert_eq!(samples.get(series), Some(&value), "{series} in {samples:?}");
+        }
+    }
+
+    /// A sink panicking on the first telegram published to it.
+    struct PanickingSink {
+        published: Arc<AtomicUsize>,
+    }
+
+    impl Sink for PanickingSink {
+        fn publish(&self, _samples: &[Sample]) {
+            if self.published.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("injected panic");
+            }
+        }
+    }
+
+    #[test]
+    fn collector_recovers_from_panic() {
+        let path = std::env::temp_dir().join(format!("p1-exporter-panic-{}", process::id()));
+        fs::write(&path, telegram(&["1-0:1.7.0(00.310*kW)"])).unwrap();
+        let published = Arc::new(AtomicUsize::new(0));
+        let sink = PanickingSink {
+            published: published.clone(),
+        };
+        let config = CollectorConfig {
+            sinks: Arc::new(vec![Box::new(sink)]),
+            reconnect_interval: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let metrics = P1Metrics::new(&[], None).meter(None);
+        let collector =
+            start_metrics_collector(Input::File(path.clone()), metrics, Arc::new(config));
+        // The file is read again after the panic, its telegram collected.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while published.load(Ordering::SeqCst) < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        collector.stop();
+        let metrics = collector.join();
+        fs::remove_file(&path).unwrap();
+
+        assert!(published.load(Ordering::SeqCst) >= 2);
+        assert_eq!(metrics.collector_panics.get(), 1);
+        assert!(metrics.telegrams_received.get() >= 2);
+    }
+
+    /// A gas meter counting in units of 0.01 m³, reported in `unit`.
+    fn centi_gas_telegram(unit: &str) -> String {
+        telegram(&[
+            "0-1:24.1.0(003)",
+            "0-1:96.1.0(4730303339303031363532303530323136)",
+            &format!("0-1:24.2.1(261015034000S)(12345.678*{unit})"),
+        ])
+    }
+
+    fn gas(metrics: &MeterMetrics) -> f64 {
+        let labels = MbusLabels {
+            mbus_channel: 1,
+            equipment_id: "G0039001652050216".to_string(),
+        };
+        metrics.gas_consumed_total.get_or_create(&labels).get()
+    }
+
+    #[test]
+    fn gas_scale_from_unit() {
+        let metrics = collect(&[centi_gas_telegram("0.01m3")], &CollectorConfig::default());
+        assert!((gas(&metrics) - 123.45678).abs() < 1e-9);
+    }
+
+    #[test]
+    fn gas_scale_overrides_unit() {
+        // The unit doesn't tell the multiplier, --gas-scale does.
+        let config = CollectorConfig {
+            gas_scale: Some(0.01),
+            ..Default::default()
+        };
+        let metrics = collect(&[centi_gas_telegram("m3")], &config);
+        assert!((gas(&metrics) - 123.45678).abs() < 1e-9);
+    }
+
+    /// A meter reporting its energy registers in Wh.
+    fn wh_telegram() -> String {
+        telegram(&[
+            "1-0:1.8.1(001234567*Wh)",
+            "1-0:1.8.2(000765432*Wh)",
+            "1-0:2.8.1(000001500*Wh)",
+            "1-0:2.8.2(000000000*Wh)",
+            "0-0:96.14.0(0002)",
+        ])
+    }
+
+    fn energy_consumed(metrics: &MeterMetrics, tariff: &str) -> f64 {
+        metrics
+            .power_consumed_total
+            .get_or_create(&[("tariff", tariff.to_string())])
+            .get()
+    }
+
+    #[test]
+    fn energy_in_wh_normalized_to_kwh() {
+        let metrics = collect(&[wh_telegram()], &CollectorConfig::default());
+        assert!((energy_consumed(&metrics, "low") - 1234.567).abs() < 1e-9);
+        assert!((energy_consumed(&metrics, "high") - 765.432).abs() < 1e-9);
+        let produced = metrics
+            .power_produced_total
+            .get_or_create(&[("tariff", "low".to_string())])
+            .get();
+        assert!((produced - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn energy_scale_overrides_unit() {
+        let config = CollectorConfig {
+            energy_scale: Some(1.0),
+            ..Default::default()
+        };
+        let metrics = collect(&[wh_telegram()], &config);
+        assert!((energy_consumed(&metrics, "low") - 1234567.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn base_units() {
+        let config = CollectorConfig {
+            power_unit: PowerUnit::Watts,
+            ..Default::default()
+        };
+        let telegrams = [wh_telegram(), telegram(&["1-0:1.7.0(00.310*kW)"])];
+        let metrics = collect(&telegrams, &config);
+        assert!((energy_consumed(&metrics, "low") - 4_444_441_200.0).abs() < 1e-3);
+        let net = metrics
+            .power_net_energy
+            .get_or_create(&[("tariff", "low".to_string())])
+            .get();
+        assert!((net - 4_439_041_200.0).abs() < 1e-3);
+        assert!((metrics.power_consumed.get_or_create(&()).get() - 310.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn lower_event_counts_ignored() {
+        let telegrams = [
+            telegram(&["0-0:96.7.21(00005)", "1-0:32.32.0(00002)"]),
+            // A corrupted telegram that still passed the CRC check.
+            telegram(&["0-0:96.7.21(00003)", "1-0:32.32.0(00000)"]),
+        ];
+        let metrics = collect(&telegrams, &CollectorConfig::default());
+        assert_eq!(metrics.power_failures.get(), 5);
+        let sags = metrics.voltage_sags.get_or_create(&[("phase", "l1")]).get();
+        assert_eq!(sags, 2);
+        for metric in ["power_failures", "voltage_sags"] {
+            let regressions = metrics
+                .counter_regressions
+                .get_or_create(&[("metric", metric.to_string())])
+                .get();
+            assert_eq!(regressions, 1, "{metric}");
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Support of the telegrams of meters deviating from DSMR 5: those of DSMR
+//! 2.2 and 3 meters, which end without a checksum and format some objects
+//! differently, and the ESMR 5 based ones of Swedish HAN ports, which report
+//! the energy totals and other widths. They're upgraded to DSMR 5 telegrams
+//! for the rest of the exporter.
+
+use crate::{
+    obis::{self, crc16},
+    telegram::TelegramReader,
+};
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Read},
+};
+
+/// Telegrams are well below that.
+const MAX_TELEGRAM: u64 = 16 * 1024;
+const TARIFF: &str = "0-0:96.14.0";
+/// Power, in total and per phase, consumed and produced.
+const POWER: [&str; 8] = [
+    "1-0:1.7.0",
+    "1-0:2.7.0",
+    "1-0:21.7.0",
+    "1-0:22.7.0",
+    "1-0:41.7.0",
+    "1-0:42.7.0",
+    "1-0:61.7.0",
+    "1-0:62.7.0",
+];
+const CURRENT: [&str; 3] = ["1-0:31.7.0", "1-0:51.7.0", "1-0:71.7.0"];
+
+/// Reads telegrams of any DSMR version, upgrading them. Those failing the
+/// checksum are passed on as is, for the parser to reject them.
+pub fn reader<R: Read>(inner: R) -> TelegramReader<impl FnMut() -> io::Result<Vec<u8>>> {
+    let mut telegrams = Telegrams {
+        inner: BufReader::new(inner),
+    };
+    TelegramReader::new(move || telegrams.next_telegram())
+}
+
+struct Telegrams<R> {
+    inner: BufReader<R>,
+}
+
+impl<R: Read> Telegrams<R> {
+    /// Reads the next telegram, failing with `UnexpectedEof` at the end of
+    /// the stream.
+    fn next_telegram(&mut self) -> Result<Vec<u8>, io::Error> {
+        loop {
+            let mut skipped = Vec::new();
+            while !self.read_until(b'/', &mut skipped)? {
+                skipped.clear();
+            }
+            let mut text = vec![b'/'];
+            // The checksum if any follows, up to the end of the line.
+            let mut rest = Vec::new();
+            if !self.read_until(b'!', &mut text)? || !self.read_until(b'\n', &mut rest)? {
+                continue;
+            }
+            let checksum = rest.trim_ascii();
+            let valid = match checksum {
+                [] => true,
+                [_, _, _, _] => std::str::from_utf8(checksum)
+                    .ok()
+                    .and_then(|checksum| u16::from_str_radix(checksum, 16).ok())
+                    .is_some_and(|checksum| checksum == crc16(&text)),
+                _ => continue,
+            };
+            return Ok(match std::str::from_utf8(&text) {
+                Ok(upgraded) if valid => upgrade(upgraded).into_bytes(),
+                _ => {
+                    text.extend_from_slice(&rest);
+                    text
+                }
+            });
+        }
+    }
+
+    /// Reads up to and including `delimiter`, returning whether it was found
+    /// before giving up on runaway data.
+    fn read_until(&mut self, delimiter: u8, buf: &mut Vec<u8>) -> Result<bool, io::Error> {
+        let n = (&mut self.inner)
+            .take(MAX_TELEGRAM)
+            .read_until(delimiter, buf)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(buf.last() == Some(&delimiter))
+    }
+}
+
+/// Returns the DSMR 5 form of a telegram given up to and including its `!`,
+/// which is the same for DSMR 4 and 5 telegrams.
+///
+/// The power, current and energy readings get the widths of DSMR 5, energy
+/// totals become the registers of tariff 1, and the gas readings
+/// (`0-n:24.3.0`, with the value on the next line) become `0-n:24.2.1` ones.
+fn upgrade(text: &str) -> String {
+    let by_tariff = text.lines().any(obis::is_energy_register);
+    let has_tariff = text.lines().any(|line| line.starts_with(TARIFF));
+    // Whether energy totals became registers, of the tariff to be added.
+    let mut totals = false;
+    let mut t = String::new();
+    let mut lines = text.lines().peekable();
+    while let Some(line) = lines.next() {
+        let (reference, groups) = match line.find('(') {
+            Some(i) => line.split_at(i),
+            None => (line, ""),
+        };
+        let value = obis::find(line, reference)
+            .and_then(|groups| obis::value(groups.last()?))
+            .map(|(value, unit)| (value, unit.unwrap_or_default()));
+        match (reference, value) {
+            (reference, Some((value, "kW"))) if POWER.contains(&reference) => {
+                let _ = write!(t, "{reference}({value:06.3}*kW)\r\n");
+            }
+            (reference, Some((value, _))) if CURRENT.contains(&reference) => {
+                let _ = write!(t, "{reference}({:03.0}*A)\r\n", value.round());
+            }
+            // The unit is kept for the energy to be normalized to kWh.
+            (reference, Some((value, unit))) if obis::is_energy_register(reference) => {
+                let unit = if unit.is_empty() { "kWh" } else { unit };
+                let _ = write!(t, "{reference}({value:010.3}*{unit})\r\n");
+            }
+            ("1-0:1.8.0" | "1-0:2.8.0", Some((value, "kWh"))) if !by_tariff => {
+                totals = true;
+                let register = reference.replace(".8.0", ".8.1");
+                let _ = write!(t, "{register}({value:010.3}*kWh)\r\n");
+            }
+            (reference, Some((value, _))) if reference.ends_with(":24.1.0") => {
+                let _ = write!(t, "{reference}({value:03})\r\n");
+            }
+            (reference, _) if reference.ends_with(":24.3.0") => {
+                let groups = obis::find(line, reference).unwrap_or_default();
+                let value = lines
+                    .next_if(|next| next.starts_with('('))
+                    .and_then(|next| obis::value(next.trim().trim_matches(['(', ')'])));
+                if let (Some(time), Some(unit), Some((value, _))) =
+                    (groups.first(), groups.last(), value)
+                {
+                    let channel = reference.trim_end_matches("24.3.0");
+                    let _ = write!(t, "{channel}24.2.1({time}W)({value:09.3}*{unit})\r\n");
+                }
+            }
+            _ if line == "!" => {
+                if totals && !has_tariff {
+                    let _ = write!(t, "{TARIFF}(0001)\r\n");
+                }
+                t.push('!');
+            }
+            _ => {
+                let _ = write!(t, "{reference}{groups}\r\n");
+            }
+        }
+    }
+    let _ = write!(t, "{:04X}\r\n", crc16(t.as_bytes()));
+    t
+}
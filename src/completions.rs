@@ -0,0 +1,324 @@
+//! Shell completion scripts, generated from the definition of the command
+//! line so that they follow its options.
+
+use clap::{builder::ValueHint, Arg, Command};
+use std::{fmt::Write as _, str::FromStr};
+
+/// Shells completion scripts can be generated for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!("unknown shell '{s}', expected bash, zsh or fish")),
+        }
+    }
+}
+
+/// Returns the completion script of `cmd` for `shell`.
+pub fn generate(cmd: &mut Command, shell: Shell) -> String {
+    cmd.build();
+    match shell {
+        Shell::Bash => bash(cmd),
+        Shell::Zsh => zsh(cmd),
+        Shell::Fish => fish(cmd),
+    }
+}
+
+/// Returns the visible subcommands, leaving out `help`, which only repeats
+/// `--help`.
+fn subcommands(cmd: &Command) -> impl Iterator<Item = &Command> {
+    cmd.get_subcommands()
+        .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+}
+
+/// Returns the visible options of a command, without the positional
+/// arguments.
+fn options(cmd: &Command) -> impl Iterator<Item = &Arg> {
+    cmd.get_arguments()
+        .filter(|arg| !arg.is_hide_set() && !arg.is_positional())
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_num_args().is_some_and(|num| num.takes_values())
+}
+
+fn possible_values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+fn help(arg: &Arg) -> String {
+    arg.get_help()
+        .map_or(String::new(), |help| help.to_string())
+}
+
+fn flags(arg: &Arg) -> Vec<String> {
+    let short = arg.get_short().map(|short| format!("-{short}"));
+    let long = arg.get_long().map(|long| format!("--{long}"));
+    short.into_iter().chain(long).collect()
+}
+
+fn bash(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let function = format!("_{}", name.replace('-', "_"));
+    let commands: Vec<&str> = subcommands(cmd).map(Command::get_name).collect();
+
+    let mut script = String::new();
+    let _ = writeln!(script, "{function}() {{");
+    let _ = writeln!(script, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"");
+    let _ = writeln!(script, "    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"");
+    let _ = writeln!(script, "    local command=\"\" word");
+    let _ = writeln!(
+        script,
+        "    for word in \"${{COMP_WORDS[@]:1:COMP_CWORD-1}}\"; do"
+    );
+    let _ = writeln!(script, "        case \"$word\" in");
+    let _ = writeln!(
+        script,
+        "            {}) command=\"$word\"; break ;;",
+        commands.join("|")
+    );
+    let _ = writeln!(script, "        esac");
+    let _ = writeln!(script, "    done");
+    let _ = writeln!(script, "    local opts");
+    let _ = writeln!(script, "    case \"$command\" in");
+    let sections = subcommands(cmd)
+        .map(|sub| (sub.get_name(), sub))
+        .chain([("*", cmd)]);
+    for (pattern, sub) in sections {
+        let mut words: Vec<String> = options(sub).flat_map(flags).collect();
+        if pattern == "*" {
+            words.extend(commands.iter().map(|command| command.to_string()));
+        }
+        let _ = writeln!(script, "        {pattern})");
+        let _ = writeln!(script, "            opts=\"{}\"", words.join(" "));
+        let _ = writeln!(script, "            case \"$prev\" in");
+        for arg in options(sub).filter(|arg| takes_value(arg)) {
+            let values = possible_values(arg);
+            let reply = if !values.is_empty() {
+                format!("$(compgen -W \"{}\" -- \"$cur\")", values.join(" "))
+            } else if arg.get_value_hint() == ValueHint::DirPath {
+                "$(compgen -d -- \"$cur\")".to_string()
+            } else if arg.get_value_hint() == ValueHint::AnyPath {
+                "$(compgen -f -- \"$cur\")".to_string()
+            } else {
+                String::new()
+            };
+            let _ = writeln!(
+                script,
+                "                {}) COMPREPLY=({reply}); return ;;",
+                flags(arg).join("|")
+            );
+        }
+        let _ = writeln!(script, "            esac");
+        let _ = writeln!(script, "            ;;");
+    }
+    let _ = writeln!(script, "    esac");
+    let _ = writeln!(
+        script,
+        "    if [[ \"$cur\" == -* || -z \"$command\" ]]; then"
+    );
+    let _ = writeln!(
+        script,
+        "        COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))"
+    );
+    let _ = writeln!(script, "    else");
+    let _ = writeln!(script, "        COMPREPLY=($(compgen -f -- \"$cur\"))");
+    let _ = writeln!(script, "    fi");
+    let _ = writeln!(script, "}}");
+    let _ = writeln!(script, "complete -F {function} -o filenames {name}");
+    script
+}
+
+fn zsh(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let mut script = format!("#compdef {name}\n\n");
+    let _ = writeln!(script, "_{name}() {{");
+    let _ = writeln!(script, "    local context state state_descr line");
+    let _ = writeln!(script, "    typeset -A opt_args");
+    let _ = writeln!(script, "    _arguments -s -C \\");
+    for spec in options(cmd).map(zsh_option) {
+        let _ = writeln!(script, "        {spec} \\");
+    }
+    let _ = writeln!(script, "        '1: :->command' \\");
+    let _ = writeln!(script, "        '*:: :->argument'");
+    let _ = writeln!(script, "    case $state in");
+    let _ = writeln!(script, "        command)");
+    let _ = writeln!(script, "            local commands=(");
+    for sub in subcommands(cmd) {
+        let about = sub
+            .get_about()
+            .map_or(String::new(), |about| about.to_string());
+        let _ = writeln!(
+            script,
+            "                '{}:{}'",
+            sub.get_name(),
+            zsh_quote(&about.replace(':', "\\:"))
+        );
+    }
+    let _ = writeln!(script, "            )");
+    let _ = writeln!(script, "            _describe -t commands command commands");
+    let _ = writeln!(script, "            ;;");
+    let _ = writeln!(script, "        argument)");
+    let _ = writeln!(script, "            case $line[1] in");
+    for sub in subcommands(cmd) {
+        let _ = writeln!(script, "                {})", sub.get_name());
+        let _ = write!(script, "                    _arguments -s");
+        let positionals = sub
+            .get_positionals()
+            .filter(|arg| !arg.is_hide_set())
+            .map(|arg| {
+                let required = if arg.is_required_set() { ":" } else { "::" };
+                format!("'{required}{}:{}'", zsh_escape(&help(arg)), zsh_action(arg))
+            });
+        for spec in options(sub).map(zsh_option).chain(positionals) {
+            let _ = write!(script, " \\\n                        {spec}");
+        }
+        let _ = writeln!(script, "\n                    ;;");
+    }
+    let _ = writeln!(script, "            esac");
+    let _ = writeln!(script, "            ;;");
+    let _ = writeln!(script, "    esac");
+    let _ = writeln!(script, "}}");
+    let _ = writeln!(script, "\n_{name} \"$@\"");
+    script
+}
+
+/// Returns the `_arguments` spec of an option.
+fn zsh_option(arg: &Arg) -> String {
+    let flags = flags(arg);
+    let repeated = matches!(
+        arg.get_action(),
+        clap::ArgAction::Append | clap::ArgAction::Count
+    );
+    let help = zsh_escape(&help(arg));
+    let value = if takes_value(arg) {
+        let name = arg
+            .get_value_names()
+            .and_then(|names| names.first())
+            .map_or("VALUE".to_string(), |name| name.to_string());
+        format!(":{}:{}", zsh_escape(&name), zsh_action(arg))
+    } else {
+        String::new()
+    };
+    let suffix = |flag: &String| match (takes_value(arg), flag.starts_with("--")) {
+        (false, _) => flag.clone(),
+        (true, true) => format!("{flag}="),
+        (true, false) => format!("{flag}+"),
+    };
+    match (&flags[..], repeated) {
+        ([flag], false) => format!("'{}[{help}]{value}'", suffix(flag)),
+        ([flag], true) => format!("'*{}[{help}]{value}'", suffix(flag)),
+        (flags, repeated) => format!(
+            "'{}'{{{}}}'[{help}]{value}'",
+            if repeated {
+                "*".to_string()
+            } else {
+                format!("({})", flags.join(" "))
+            },
+            flags.iter().map(suffix).collect::<Vec<_>>().join(",")
+        ),
+    }
+}
+
+fn zsh_action(arg: &Arg) -> String {
+    let values = possible_values(arg);
+    if !values.is_empty() {
+        return format!("({})", values.join(" "));
+    }
+    match arg.get_value_hint() {
+        ValueHint::AnyPath | ValueHint::FilePath => "_files".to_string(),
+        ValueHint::DirPath => "_files -/".to_string(),
+        _ => " ".to_string(),
+    }
+}
+
+/// Escapes the text of an `_arguments` spec, which is single-quoted.
+fn zsh_escape(text: &str) -> String {
+    zsh_quote(
+        &text
+            .replace('\\', "\\\\")
+            .replace('[', "\\[")
+            .replace(']', "\\]")
+            .replace(':', "\\:"),
+    )
+}
+
+fn zsh_quote(text: &str) -> String {
+    text.replace('\'', "'\\''")
+}
+
+fn fish(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let commands: Vec<&str> = subcommands(cmd).map(Command::get_name).collect();
+    let mut script = String::new();
+    let top = format!("not __fish_seen_subcommand_from {}", commands.join(" "));
+    for arg in options(cmd) {
+        let _ = writeln!(script, "{}", fish_option(name, &top, arg));
+    }
+    for sub in subcommands(cmd) {
+        let about = sub
+            .get_about()
+            .map_or(String::new(), |about| about.to_string());
+        let _ = writeln!(
+            script,
+            "complete -c {name} -n '{top}' -f -a {} -d '{}'",
+            sub.get_name(),
+            fish_quote(&about)
+        );
+    }
+    for sub in subcommands(cmd) {
+        let condition = format!("__fish_seen_subcommand_from {}", sub.get_name());
+        for arg in options(sub) {
+            let _ = writeln!(script, "{}", fish_option(name, &condition, arg));
+        }
+    }
+    script
+}
+
+fn fish_option(name: &str, condition: &str, arg: &Arg) -> String {
+    let mut line = format!("complete -c {name} -n '{condition}'");
+    if let Some(short) = arg.get_short() {
+        let _ = write!(line, " -s {short}");
+    }
+    if let Some(long) = arg.get_long() {
+        let _ = write!(line, " -l {long}");
+    }
+    if takes_value(arg) {
+        line.push_str(" -r");
+        let values = possible_values(arg);
+        if !values.is_empty() {
+            let _ = write!(line, " -f -a '{}'", values.join(" "));
+        } else if matches!(
+            arg.get_value_hint(),
+            ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath
+        ) {
+            line.push_str(" -F");
+        } else {
+            line.push_str(" -f");
+        }
+    }
+    let help = help(arg);
+    if !help.is_empty() {
+        let _ = write!(line, " -d '{}'", fish_quote(&help));
+    }
+    line
+}
+
+fn fish_quote(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
@@ -0,0 +1,372 @@
+//! Configuration file support.
+//!
+//! The file is a subset of TOML: tables, strings, numbers, booleans and
+//! single-line arrays. Every key names a command line option, e.g.
+//! `p1_address = "192.168.1.10:2000"` or `device = "/dev/ttyUSB0"` in a
+//! `[serial]` table for `--serial-device`. The options are passed to clap
+//! ahead of the actual command line, so flags given there take precedence.
+//! Options given on the command line or in `P1_EXPORTER_*` environment
+//! variables replace the file's values entirely, which matters for options
+//! accepting multiple values.
+
+use clap::{parser::ValueSource, ArgAction, ArgMatches, Command};
+use std::{ffi::OsString, fmt::Write as _, fs, path::Path};
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    /// Strings and numbers, which are passed on as is.
+    Scalar(String),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+/// Returns the command line of `cmd` with the options of the configuration
+/// file given with `--config` or `P1_EXPORTER_CONFIG` (if any) inserted
+/// after the program name.
+pub fn args(cmd: Command) -> Result<Vec<OsString>, String> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    // Subcommands don't take the exporter's options.
+    let subcommand = args
+        .get(1)
+        .is_some_and(|arg| !arg.to_string_lossy().starts_with('-'));
+    let Some(path) = config_path(&args).filter(|_| !subcommand) else {
+        return Ok(args);
+    };
+    let from_file =
+        read(Path::new(&path)).map_err(|err| format!("{}: {err}", Path::new(&path).display()))?;
+    // Resolved by clap, so that short flags and aliases count too. The
+    // options missing from the command line are left to the file, hence the
+    // errors are ignored.
+    let mut cmd = cmd.ignore_errors(true);
+    cmd.build();
+    let matches = cmd
+        .clone()
+        .try_get_matches_from(&args)
+        .map_err(|err| err.to_string())?;
+    let from_file: Vec<_> = from_file
+        .into_iter()
+        .filter(|(key, _)| !given(&cmd, &matches, key))
+        .map(|(_, arg)| arg)
+        .collect();
+    args.splice(1..1, from_file);
+    Ok(args)
+}
+
+/// Renders the options in `matches` in the format of the configuration
+/// file, with the defaults marked as such. Values of options hiding their
+/// environment values, the secrets, are left out.
+pub fn render(cmd: &mut Command, matches: &ArgMatches) -> String {
+    cmd.build();
+    let mut out = String::new();
+    for arg in cmd.get_arguments() {
+        let (Some(long), Some(values)) = (arg.get_long(), matches.get_raw(arg.get_id().as_str()))
+        else {
+            continue;
+        };
+        if matches!(
+            long,
+            "config" | "check-config" | "print-config" | "help" | "version"
+        ) {
+            continue;
+        }
+        let key = long.replace('-', "_");
+        if arg.is_hide_env_values_set() {
+            let _ = writeln!(out, "# {key} is set, not shown");
+            continue;
+        }
+        let flag = !arg.get_num_args().is_some_and(|num| num.takes_values());
+        let values: Vec<String> = values
+            .map(|value| {
+                let value = value.to_string_lossy();
+                if flag {
+                    value.into_owned()
+                } else {
+                    quote(&value)
+                }
+            })
+            .collect();
+        // Options that can be repeated are arrays, even with a single value.
+        let value = if matches!(arg.get_action(), ArgAction::Append) {
+            format!("[{}]", values.join(", "))
+        } else {
+            values.join(", ")
+        };
+        let _ = write!(out, "{key} = {value}");
+        match matches.value_source(arg.get_id().as_str()) {
+            Some(ValueSource::DefaultValue) => out.push_str(" # default"),
+            Some(ValueSource::EnvVariable) => {
+                let _ = write!(out, " # {}", env_var(&key));
+            }
+            _ => {}
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Quotes a string for the configuration file.
+fn quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns whether the options are read from a configuration file.
+pub fn in_use() -> bool {
+    config_path(&std::env::args_os().collect::<Vec<_>>()).is_some()
+}
+
+fn config_path(args: &[OsString]) -> Option<OsString> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().cloned();
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(path.into());
+        }
+    }
+    std::env::var_os(env_var("config"))
+}
+
+/// Returns the name of the environment variable setting the option `key`.
+fn env_var(key: &str) -> String {
+    format!("P1_EXPORTER_{}", key.to_uppercase().replace('-', "_"))
+}
+
+/// Returns whether the option with the long name `key` is given on the
+/// command line or in its environment variable.
+fn given(cmd: &Command, matches: &ArgMatches, key: &str) -> bool {
+    let Some(arg) = cmd.get_arguments().find(|arg| arg.get_long() == Some(key)) else {
+        // Left for clap to reject.
+        return false;
+    };
+    matches!(
+        matches.value_source(arg.get_id().as_str()),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+/// Reads the file into option names and the arguments to pass to clap.
+fn read(path: &Path) -> Result<Vec<(String, OsString)>, String> {
+    let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let mut args = vec![];
+    for (key, value) in parse(&text)? {
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                Value::Scalar(value) => args.push((key.clone(), format!("--{key}={value}").into())),
+                Value::Bool(true) => args.push((key.clone(), format!("--{key}").into())),
+                Value::Bool(false) => {}
+                Value::Array(_) => return Err(format!("{key}: nested arrays aren't supported")),
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Parses the file into option names and their values.
+fn parse(text: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut table = String::new();
+    let mut entries = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let err = |msg: String| format!("line {}: {msg}", n + 1);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let (name, rest) = header
+                .split_once(']')
+                .ok_or_else(|| err("unterminated table header".into()))?;
+            expect_end(rest).map_err(err)?;
+            table = name.trim().replace('.', "-");
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| err("expected key = value".into()))?;
+        let key = key.trim().trim_matches('"');
+        if key.is_empty() {
+            return Err(err("empty key".into()));
+        }
+        let (value, rest) = parse_value(value.trim()).map_err(err)?;
+        expect_end(rest).map_err(err)?;
+        let key = match table.as_str() {
+            "" => key.to_string(),
+            table => format!("{table}-{key}"),
+        };
+        entries.push((key.replace('_', "-"), value));
+    }
+    Ok(entries)
+}
+
+fn expect_end(rest: &str) -> Result<(), String> {
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected '{rest}'"))
+    }
+}
+
+/// Parses the value at the start of `s`, returning it and the rest of `s`.
+fn parse_value(s: &str) -> Result<(Value, &str), String> {
+    if let Some(s) = s.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = s.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::Scalar(value), &s[i + 1..])),
+                '\\' => value.push(match chars.next() {
+                    Some((_, 'n')) => '\n',
+                    Some((_, 't')) => '\t',
+                    Some((_, 'r')) => '\r',
+                    Some((_, c @ ('"' | '\\'))) => c,
+                    _ => return Err("invalid escape sequence".into()),
+                }),
+                c => value.push(c),
+            }
+        }
+        return Err("unterminated string".into());
+    }
+    if let Some(s) = s.strip_prefix('\'') {
+        let (value, rest) = s.split_once('\'').ok_or("unterminated string")?;
+        return Ok((Value::Scalar(value.to_string()), rest));
+    }
+    if let Some(mut s) = s.strip_prefix('[') {
+        let mut values = vec![];
+        loop {
+            s = s.trim_start();
+            if let Some(rest) = s.strip_prefix(']') {
+                return Ok((Value::Array(values), rest));
+            }
+            let (value, rest) = parse_value(s)?;
+            values.push(value);
+            s = rest.trim_start();
+            if let Some(rest) = s.strip_prefix(',') {
+                s = rest;
+            } else if !s.starts_with(']') {
+                return Err("expected ',' or ']' in array".into());
+            }
+        }
+    }
+
+    let end = s
+        .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+        .unwrap_or(s.len());
+    let (token, rest) = s.split_at(end);
+    match token {
+        "true" => Ok((Value::Bool(true), rest)),
+        "false" => Ok((Value::Bool(false), rest)),
+        _ => {
+            let number = token.replace('_', "");
+            if number.parse::<f64>().is_ok() {
+                Ok((Value::Scalar(number), rest))
+            } else if token.is_empty() {
+                Err("missing value".into())
+            } else {
+                Err(format!(
+                    "unsupported value '{token}', strings must be quoted"
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scalar(value: &str) -> Value {
+        Value::Scalar(value.to_string())
+    }
+
+    #[test]
+    fn parse_tables_and_values() {
+        let text = r#"
+# The meter.
+p1_address = "192.168.1.10:2000"
+energy_prices = ['1=0.25', "2=0.3"] # by tariff
+sample_timestamps = true
+pre_register = false
+
+[serial]
+device = "/dev/ttyUSB0"
+baud_rate = 115_200
+"#;
+        let entries = parse(text).unwrap();
+        assert_eq!(
+            entries,
+            [
+                ("p1-address".to_string(), scalar("192.168.1.10:2000")),
+                (
+                    "energy-prices".to_string(),
+                    Value::Array(vec![scalar("1=0.25"), scalar("2=0.3")])
+                ),
+                ("sample-timestamps".to_string(), Value::Bool(true)),
+                ("pre-register".to_string(), Value::Bool(false)),
+                ("serial-device".to_string(), scalar("/dev/ttyUSB0")),
+                ("serial-baud-rate".to_string(), scalar("115200")),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_escapes() {
+        let entries = parse(r#"label = "a\"b\\c\td""#).unwrap();
+        assert_eq!(entries[0].1, scalar("a\"b\\c\td"));
+        let quoted = quote("a\"b\\c\td");
+        assert_eq!(parse_value(&quoted).unwrap(), (scalar("a\"b\\c\td"), ""));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for (text, err) in [
+            ("[serial", "line 1: unterminated table header"),
+            ("p1_address", "line 1: expected key = value"),
+            ("= 1", "line 1: empty key"),
+            ("a = \"b", "line 1: unterminated string"),
+            ("a = \"\\x\"", "line 1: invalid escape sequence"),
+            ("a = [1 2]", "line 1: expected ',' or ']' in array"),
+            ("a = ", "line 1: missing value"),
+            (
+                "\na = b",
+                "line 2: unsupported value 'b', strings must be quoted",
+            ),
+            ("a = 1 2", "line 1: unexpected '2'"),
+        ] {
+            assert_eq!(parse(text).unwrap_err(), err, "{text}");
+        }
+    }
+
+    #[test]
+    fn read_into_arguments() {
+        let path = std::env::temp_dir().join(format!("p1-exporter-config-{}", std::process::id()));
+        fs::write(
+            &path,
+            "label = [\"site=home\", \"floor=1\"]\nno_eof = true\nonce = false\n",
+        )
+        .unwrap();
+        let args = read(&path);
+        fs::remove_file(&path).unwrap();
+        let args: Vec<_> = args.unwrap().into_iter().map(|(_, arg)| arg).collect();
+        assert_eq!(args, ["--label=site=home", "--label=floor=1", "--no-eof"]);
+    }
+}
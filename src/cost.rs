@@ -0,0 +1,115 @@
+//! Costs of the consumed energy and gas, accrued from the increases of the
+//! meter readings and the time passed, in whatever currency the prices are.
+
+use crate::prices::DayAheadPrices;
+use std::{collections::BTreeMap, str::FromStr, time::Instant};
+
+/// Prices of energy and gas.
+#[derive(Clone, Debug, Default)]
+pub struct Prices {
+    pub energy: EnergyPrices,
+    /// Prices of a dynamic contract, instead of `energy`.
+    pub day_ahead: Option<DayAheadPrices>,
+    /// Per m³ of gas.
+    pub gas: Option<f64>,
+    /// Fixed charges per day, accrued continuously.
+    pub energy_daily_charge: f64,
+    pub gas_daily_charge: f64,
+}
+
+impl Prices {
+    pub fn energy_configured(&self) -> bool {
+        !self.energy.0.is_empty() || self.day_ahead.is_some() || self.energy_daily_charge > 0.0
+    }
+
+    pub fn gas_configured(&self) -> bool {
+        self.gas.is_some() || self.gas_daily_charge > 0.0
+    }
+}
+
+/// Prices per consumed kWh by tariff number, as reported by the meter.
+#[derive(Clone, Debug, Default)]
+pub struct EnergyPrices(BTreeMap<u16, f64>);
+
+impl FromStr for EnergyPrices {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut prices = BTreeMap::new();
+        for entry in s.split(',') {
+            let (tariff, price) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected TARIFF=PRICE, got '{entry}'"))?;
+            let tariff = tariff
+                .trim()
+                .parse::<u16>()
+                .map_err(|_| format!("invalid tariff number '{tariff}'"))?;
+            let price = parse_price(price.trim())?;
+            prices.insert(tariff, price);
+        }
+        Ok(EnergyPrices(prices))
+    }
+}
+
+/// Parses a price or charge, which can't be negative as the costs are
+/// counters.
+pub fn parse_price(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|price| price.is_finite() && *price >= 0.0)
+        .ok_or_else(|| format!("invalid price '{s}'"))
+}
+
+/// Readings and time the costs were last accrued at.
+#[derive(Default)]
+pub struct Costs {
+    energy: BTreeMap<u16, f64>,
+    /// By M-Bus channel.
+    gas: [Option<f64>; 4],
+    accrued: Option<Instant>,
+}
+
+impl Costs {
+    /// Returns the cost of the energy consumed in `tariff` since its last
+    /// reading, at the current price of a dynamic contract if any. The first
+    /// reading only sets the baseline.
+    pub fn energy(&mut self, prices: &Prices, tariff: u16, reading: f64) -> f64 {
+        let last = self.energy.insert(tariff, reading);
+        let price = match &prices.day_ahead {
+            Some(day_ahead) => day_ahead.current(),
+            None => prices.energy.0.get(&tariff).copied(),
+        };
+        price.map_or(0.0, |price| price * increase(last, reading))
+    }
+
+    /// Returns the cost of the gas consumed on the M-Bus `channel` since its
+    /// last reading.
+    pub fn gas(&mut self, prices: &Prices, channel: u8, reading: f64) -> f64 {
+        let Some(price) = prices.gas else {
+            return 0.0;
+        };
+        let last = self.gas[channel as usize - 1].replace(reading);
+        price * increase(last, reading)
+    }
+
+    /// Returns the energy and gas daily charges accrued since the last call.
+    pub fn daily_charges(&mut self, prices: &Prices, now: Instant) -> (f64, f64) {
+        let days = self
+            .accrued
+            .replace(now)
+            .map_or(0.0, |accrued| (now - accrued).as_secs_f64() / 86400.0);
+        (
+            prices.energy_daily_charge * days,
+            prices.gas_daily_charge * days,
+        )
+    }
+}
+
+/// Returns the increase of a register, none if it went backwards as when the
+/// meter is replaced.
+fn increase(last: Option<f64>, reading: f64) -> f64 {
+    match last {
+        Some(last) if last <= reading => reading - last,
+        _ => 0.0,
+    }
+}
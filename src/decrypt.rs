@@ -0,0 +1,263 @@
+//! Decryption of the AES-128-GCM encrypted telegrams of Luxembourgish
+//! ("Smarty") and some Austrian meters, which wrap every telegram in a DLMS
+//! general-glo-ciphering frame.
+
+use crate::telegram::TelegramReader;
+use aes_gcm::{
+    aead::{consts::U12, AeadInPlace, KeyInit},
+    aes::{cipher::BlockEncrypt, Aes128},
+    AesGcm,
+};
+use std::{
+    fmt,
+    io::{self, BufReader, Read},
+    str::FromStr,
+};
+
+/// Tag of a general-glo-ciphering frame.
+const GENERAL_GLO_CIPHERING: u8 = 0xdb;
+/// Security control byte of frames both authenticated and encrypted.
+const AUTHENTICATED_ENCRYPTED: u8 = 0x30;
+/// Security control byte of frames only encrypted, without a tag.
+const ENCRYPTED: u8 = 0x20;
+/// Length of the truncated GCM tag ending the frames.
+const TAG_LEN: usize = 12;
+/// Authentication key of the Smarty meters, the same for all of them.
+pub const SMARTY_AUTHENTICATION_KEY: &str = "00112233445566778899AABBCCDDEEFF";
+
+type Cipher = AesGcm<Aes128, U12, U12>;
+
+/// An AES-128 key, given as 32 hexadecimal digits.
+#[derive(Clone)]
+pub struct Key([u8; 16]);
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.len() != 32 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err("expected 32 hexadecimal digits".to_string());
+        }
+        let mut key = [0; 16];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).expect("checked above");
+        }
+        Ok(Key(key))
+    }
+}
+
+// Keeps the keys out of the logs.
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Keys {
+    /// The key the telegrams are encrypted with, provided by the grid
+    /// operator.
+    pub encryption: Key,
+    /// The key authenticating the frames along with their header.
+    pub authentication: Key,
+}
+
+/// Decrypts the payload of a general-glo-ciphering frame: the security
+/// control byte, the frame counter, the ciphertext and, unless only
+/// encrypted, the tag.
+pub fn decrypt(keys: &Keys, system_title: &[u8; 8], payload: &[u8]) -> Result<Vec<u8>, String> {
+    let tag_len = match payload.first() {
+        Some(&AUTHENTICATED_ENCRYPTED) => TAG_LEN,
+        Some(&ENCRYPTED) => 0,
+        Some(other) => return Err(format!("unsupported security control byte {other:#04x}")),
+        None => return Err("empty frame".to_string()),
+    };
+    if payload.len() <= 5 + tag_len {
+        return Err(format!("frame too short ({} bytes)", payload.len()));
+    }
+    let (header, rest) = payload.split_at(5);
+    let mut nonce = [0; 12];
+    nonce[..8].copy_from_slice(system_title);
+    nonce[8..].copy_from_slice(&header[1..]);
+    let (ciphertext, tag) = rest.split_at(rest.len() - tag_len);
+    let mut plaintext = ciphertext.to_vec();
+    if tag_len == 0 {
+        decrypt_ctr(&keys.encryption, &nonce, &mut plaintext);
+        return Ok(plaintext);
+    }
+    let mut aad = vec![header[0]];
+    aad.extend_from_slice(&keys.authentication.0);
+    Cipher::new(&keys.encryption.0.into())
+        .decrypt_in_place_detached(&nonce.into(), &aad, &mut plaintext, tag.into())
+        .map_err(|_| "authentication failed, is the key right?".to_string())?;
+    Ok(plaintext)
+}
+
+/// Decrypts the ciphertext of a frame without a tag, which AES-GCM
+/// encrypts as AES-CTR from the second counter block.
+fn decrypt_ctr(key: &Key, nonce: &[u8; 12], data: &mut [u8]) {
+    let cipher = Aes128::new(&key.0.into());
+    for (i, chunk) in data.chunks_mut(16).enumerate() {
+        let mut block = [0; 16];
+        block[..12].copy_from_slice(nonce);
+        block[12..].copy_from_slice(&(i as u32 + 2).to_be_bytes());
+        let mut block = block.into();
+        cipher.encrypt_block(&mut block);
+        chunk.iter_mut().zip(block).for_each(|(b, k)| *b ^= k);
+    }
+}
+
+/// Reads the decrypted telegrams of a stream of encrypted frames, skipping
+/// those that fail to decrypt.
+pub fn reader<R: Read>(
+    inner: R,
+    keys: Keys,
+) -> TelegramReader<impl FnMut() -> io::Result<Vec<u8>>> {
+    let mut decrypter = Decrypter {
+        inner: BufReader::new(inner),
+        keys,
+    };
+    TelegramReader::new(move || decrypter.next_frame())
+}
+
+struct Decrypter<R> {
+    inner: BufReader<R>,
+    keys: Keys,
+}
+
+impl<R: Read> Decrypter<R> {
+    /// Decrypts the next frame, failing with `UnexpectedEof` at the end of
+    /// the stream.
+    fn next_frame(&mut self) -> Result<Vec<u8>, io::Error> {
+        loop {
+            // Frames start with the tag and the length of the system title.
+            if self.read_u8()? != GENERAL_GLO_CIPHERING || self.read_u8()? != 8 {
+                continue;
+            }
+            let mut system_title = [0; 8];
+            self.inner.read_exact(&mut system_title)?;
+            let Some(len) = self.read_length()? else {
+                continue;
+            };
+            let mut payload = vec![0; len];
+            self.inner.read_exact(&mut payload)?;
+            match decrypt(&self.keys, &system_title, &payload) {
+                Ok(telegram) => return Ok(telegram),
+                Err(err) => tracing::warn!("Skipping encrypted frame: {err}"),
+            }
+        }
+    }
+
+    /// Reads the BER-encoded length of the payload, if valid.
+    fn read_length(&mut self) -> Result<Option<usize>, io::Error> {
+        let octets = match self.read_u8()? {
+            len @ 0..=0x7f => return Ok(Some(len.into())),
+            0x81 => 1,
+            0x82 => 2,
+            // Frames are well below 64 KiB.
+            _ => return Ok(None),
+        };
+        let mut len = [0; 2];
+        self.inner.read_exact(&mut len[2 - octets..])?;
+        Ok(Some(u16::from_be_bytes(len).into()))
+    }
+
+    fn read_u8(&mut self) -> Result<u8, io::Error> {
+        let mut byte = [0];
+        self.inner.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYSTEM_TITLE: [u8; 8] = *b"SAG12345";
+    const TELEGRAM: &[u8] = b"/Lux5\\253833635_A\r\n\r\n1-0:1.7.0(00.310*kW)\r\n!0000\r\n";
+
+    fn keys() -> Keys {
+        Keys {
+            encryption: "000102030405060708090A0B0C0D0E0F".parse().unwrap(),
+            authentication: SMARTY_AUTHENTICATION_KEY.parse().unwrap(),
+        }
+    }
+
+    /// Returns the payload of a frame with the telegram encrypted, and
+    /// authenticated unless `control` is [`ENCRYPTED`].
+    fn payload(keys: &Keys, control: u8, counter: u32) -> Vec<u8> {
+        let mut nonce = [0; 12];
+        nonce[..8].copy_from_slice(&SYSTEM_TITLE);
+        nonce[8..].copy_from_slice(&counter.to_be_bytes());
+        let mut aad = vec![control];
+        aad.extend_from_slice(&keys.authentication.0);
+        let mut ciphertext = TELEGRAM.to_vec();
+        let tag = Cipher::new(&keys.encryption.0.into())
+            .encrypt_in_place_detached(&nonce.into(), &aad, &mut ciphertext)
+            .unwrap();
+        let mut payload = vec![control];
+        payload.extend_from_slice(&counter.to_be_bytes());
+        payload.extend_from_slice(&ciphertext);
+        if control == AUTHENTICATED_ENCRYPTED {
+            payload.extend_from_slice(&tag);
+        }
+        payload
+    }
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![GENERAL_GLO_CIPHERING, 8];
+        frame.extend_from_slice(&SYSTEM_TITLE);
+        frame.extend_from_slice(&[0x81, payload.len() as u8]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn parse_key() {
+        assert!(" 000102030405060708090a0b0c0d0e0f\n".parse::<Key>().is_ok());
+        assert!("0001".parse::<Key>().is_err());
+        assert!("000102030405060708090A0B0C0D0E0G".parse::<Key>().is_err());
+        assert_eq!(format!("{:?}", keys().encryption), "Key(..)");
+    }
+
+    #[test]
+    fn decrypt_frames() {
+        let keys = keys();
+        let payload = payload(&keys, AUTHENTICATED_ENCRYPTED, 1);
+        assert_eq!(decrypt(&keys, &SYSTEM_TITLE, &payload).unwrap(), TELEGRAM);
+        // Without the tag, the ciphertext is the same.
+        let payload = self::payload(&keys, ENCRYPTED, 2);
+        assert_eq!(decrypt(&keys, &SYSTEM_TITLE, &payload).unwrap(), TELEGRAM);
+
+        let wrong = Keys {
+            authentication: "FFEEDDCCBBAA99887766554433221100".parse().unwrap(),
+            ..keys.clone()
+        };
+        let payload = self::payload(&keys, AUTHENTICATED_ENCRYPTED, 3);
+        assert_eq!(
+            decrypt(&wrong, &SYSTEM_TITLE, &payload).unwrap_err(),
+            "authentication failed, is the key right?"
+        );
+        assert!(decrypt(&keys, &SYSTEM_TITLE, &[0x10, 0, 0, 0, 1, 0]).is_err());
+        assert!(decrypt(&keys, &SYSTEM_TITLE, &payload[..10]).is_err());
+    }
+
+    #[test]
+    fn read_frames() {
+        let keys = keys();
+        let mut corrupted = payload(&keys, AUTHENTICATED_ENCRYPTED, 1);
+        corrupted[7] ^= 1;
+        let stream = [
+            b"noise".to_vec(),
+            frame(&corrupted),
+            frame(&payload(&keys, AUTHENTICATED_ENCRYPTED, 2)),
+        ]
+        .concat();
+        let mut telegrams = vec![];
+        reader(&stream[..], keys)
+            .read_to_end(&mut telegrams)
+            .unwrap();
+        assert_eq!(telegrams, TELEGRAM);
+    }
+}
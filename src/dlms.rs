@@ -0,0 +1,746 @@
+//! Reading of the DLMS/COSEM push frames of Austrian meters (EVN, Wiener
+//! Netze, ...) and of the HAN ports of Norwegian meters (Aidon, Kamstrup,
+//! Kaifa), which are converted into DSMR telegrams for the rest of the
+//! exporter.
+//!
+//! The meters push a data-notification every few seconds, mostly encrypted
+//! with the key provided by the grid operator in Austria, in HDLC frames or
+//! in M-Bus long frames.
+
+use crate::{
+    decrypt,
+    telegram::{self, scale, Reading, TelegramReader},
+};
+use std::io::{self, BufReader, Read};
+
+const HDLC_FLAG: u8 = 0x7e;
+const MBUS_START: u8 = 0x68;
+const MBUS_STOP: u8 = 0x16;
+/// The LLC header starting the information field of the first HDLC segment.
+const LLC: [u8; 3] = [0xe6, 0xe7, 0x00];
+const DATA_NOTIFICATION: u8 = 0x0f;
+const GENERAL_GLO_CIPHERING: u8 = 0xdb;
+
+/// The values pushed, in order, by meters that send them without their OBIS
+/// codes, like those of Wiener Netze.
+const POSITIONAL: [&str; 8] = [
+    "1-0:1.8.0",
+    "1-0:2.8.0",
+    "1-0:3.8.0",
+    "1-0:4.8.0",
+    "1-0:1.7.0",
+    "1-0:2.7.0",
+    "1-0:3.7.0",
+    "1-0:4.7.0",
+];
+
+/// The values pushed by Kaifa meters after their list version identifier,
+/// meter ID and meter type, with their scalers: power and the current and
+/// voltage of the phases, of L1 only for single-phase meters.
+const KAIFA_THREE_PHASE: [(&str, i32); 10] = [
+    ("1-0:1.7.0", 0),
+    ("1-0:2.7.0", 0),
+    ("1-0:3.7.0", 0),
+    ("1-0:4.7.0", 0),
+    ("1-0:31.7.0", -3),
+    ("1-0:51.7.0", -3),
+    ("1-0:71.7.0", -3),
+    ("1-0:32.7.0", -1),
+    ("1-0:52.7.0", -1),
+    ("1-0:72.7.0", -1),
+];
+const KAIFA_SINGLE_PHASE: [(&str, i32); 6] = [
+    ("1-0:1.7.0", 0),
+    ("1-0:2.7.0", 0),
+    ("1-0:3.7.0", 0),
+    ("1-0:4.7.0", 0),
+    ("1-0:31.7.0", -3),
+    ("1-0:32.7.0", -1),
+];
+/// The energy totals Kaifa meters push hourly, after the clock.
+const KAIFA_ENERGY: [&str; 4] = ["1-0:1.8.0", "1-0:2.8.0", "1-0:3.8.0", "1-0:4.8.0"];
+
+/// Reads the telegrams converted from a stream of push frames, decrypted
+/// with `keys` if they're encrypted, skipping the frames that fail to
+/// decrypt or decode.
+pub fn reader<R: Read>(
+    inner: R,
+    keys: Option<decrypt::Keys>,
+) -> TelegramReader<impl FnMut() -> io::Result<Vec<u8>>> {
+    let mut frames = PushFrames {
+        inner: BufReader::new(inner),
+        keys,
+        apdu: Vec::new(),
+    };
+    TelegramReader::new(move || frames.next_notification())
+}
+
+struct PushFrames<R> {
+    inner: BufReader<R>,
+    keys: Option<decrypt::Keys>,
+    /// The APDU being reassembled from the segments received so far.
+    apdu: Vec<u8>,
+}
+
+impl<R: Read> PushFrames<R> {
+    /// Converts the next data-notification into a telegram, failing with
+    /// `UnexpectedEof` at the end of the stream.
+    fn next_notification(&mut self) -> Result<Vec<u8>, io::Error> {
+        loop {
+            let apdu = self.next_apdu()?;
+            match notification(&apdu, self.keys.as_ref()).and_then(|n| to_telegram(&n)) {
+                Ok(telegram) => return Ok(telegram.into_bytes()),
+                Err(err) => tracing::warn!("Skipping push frame: {err}"),
+            }
+        }
+    }
+
+    /// Reads frames until an APDU is complete.
+    fn next_apdu(&mut self) -> Result<Vec<u8>, io::Error> {
+        let mut byte = self.read_u8()?;
+        loop {
+            match byte {
+                HDLC_FLAG => {
+                    let format = self.read_u8()?;
+                    if format & 0xf0 != 0xa0 {
+                        // Consecutive flags, or garbage.
+                        byte = format;
+                        continue;
+                    }
+                    if self.hdlc_frame(format)? {
+                        return Ok(std::mem::take(&mut self.apdu));
+                    }
+                    // The closing flag may open the next frame as well.
+                    byte = HDLC_FLAG;
+                }
+                MBUS_START => {
+                    if self.mbus_frame()? {
+                        return Ok(std::mem::take(&mut self.apdu));
+                    }
+                    byte = self.read_u8()?;
+                }
+                _ => byte = self.read_u8()?,
+            }
+        }
+    }
+
+    /// Reads an HDLC frame of type 3 following its opening flag and first
+    /// format byte. Returns whether it completed the APDU.
+    fn hdlc_frame(&mut self, format: u8) -> Result<bool, io::Error> {
+        let format = [format, self.read_u8()?];
+        let len = usize::from(format[0] & 0x07) << 8 | usize::from(format[1]);
+        // The format bytes, the addresses, the control byte and the checks.
+        if len < 9 {
+            return Ok(false);
+        }
+        let mut frame = vec![0; len];
+        frame[..2].copy_from_slice(&format);
+        self.inner.read_exact(&mut frame[2..])?;
+        if self.read_u8()? != HDLC_FLAG {
+            return Ok(false);
+        }
+        let (content, fcs) = frame.split_at(len - 2);
+        if crc16_x25(content) != u16::from_le_bytes([fcs[0], fcs[1]]) {
+            tracing::warn!("Skipping HDLC frame with a wrong checksum");
+            self.apdu.clear();
+            return Ok(false);
+        }
+        // The destination and source addresses end with a byte with the
+        // lowest bit set, the control byte and the header check follow.
+        let mut header = 2;
+        for _ in 0..2 {
+            while content.get(header).is_some_and(|b| b & 1 == 0) {
+                header += 1;
+            }
+            header += 1;
+        }
+        let Some(information) = content.get(header + 3..) else {
+            return Ok(false);
+        };
+        if let Some(first) = information.strip_prefix(&LLC[..]) {
+            self.apdu.clear();
+            self.apdu.extend_from_slice(first);
+        } else {
+            self.apdu.extend_from_slice(information);
+        }
+        let segmented = format[0] & 0x08 != 0;
+        Ok(!segmented && !self.apdu.is_empty())
+    }
+
+    /// Reads an M-Bus long frame following its start byte. Returns whether
+    /// it completed the APDU.
+    fn mbus_frame(&mut self) -> Result<bool, io::Error> {
+        let mut header = [0; 3];
+        self.inner.read_exact(&mut header)?;
+        let len = usize::from(header[0]);
+        if header[1] != header[0] || header[2] != MBUS_START || len < 5 {
+            return Ok(false);
+        }
+        // C, A and CI fields, the source and destination access points,
+        // then the APDU segment, the checksum and the stop byte.
+        let mut frame = vec![0; len + 2];
+        self.inner.read_exact(&mut frame)?;
+        let (data, trailer) = frame.split_at(len);
+        let checksum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if trailer != [checksum, MBUS_STOP] {
+            tracing::warn!("Skipping M-Bus frame with a wrong checksum");
+            self.apdu.clear();
+            return Ok(false);
+        }
+        // The CI field numbers the segments, with the last one flagged.
+        let ci = data[2];
+        if ci & 0x0f == 0 {
+            self.apdu.clear();
+        }
+        self.apdu.extend_from_slice(&data[5..]);
+        Ok(ci & 0x10 != 0)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, io::Error> {
+        let mut byte = [0];
+        self.inner.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+/// CRC16/X-25, the frame check sequence of HDLC.
+pub fn crc16_x25(data: &[u8]) -> u16 {
+    let crc = data.iter().fold(0xffff, |crc, &b| {
+        (0..8).fold(crc ^ u16::from(b), |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            }
+        })
+    });
+    !crc
+}
+
+/// A decoded data-notification.
+struct Notification {
+    /// The system title of the meter, if the APDU was encrypted.
+    system_title: Option<[u8; 8]>,
+    /// When the data was pushed, as a DSMR timestamp.
+    timestamp: Option<String>,
+    body: Data,
+}
+
+/// Decrypts the APDU if needed and decodes the data-notification in it.
+fn notification(apdu: &[u8], keys: Option<&decrypt::Keys>) -> Result<Notification, String> {
+    let mut cursor = Cursor(apdu);
+    let (system_title, plaintext) = match cursor.u8()? {
+        DATA_NOTIFICATION => (None, apdu.to_vec()),
+        GENERAL_GLO_CIPHERING => {
+            let keys = keys.ok_or("the frame is encrypted, but no --decryption-key is given")?;
+            if cursor.u8()? != 8 {
+                return Err("invalid system title".to_string());
+            }
+            let system_title: [u8; 8] = cursor.take(8)?.try_into().expect("8 bytes taken");
+            let len = cursor.length()?;
+            let plaintext = decrypt::decrypt(keys, &system_title, cursor.take(len)?)?;
+            (Some(system_title), plaintext)
+        }
+        tag => return Err(format!("unsupported APDU {tag:#04x}")),
+    };
+    let mut cursor = Cursor(&plaintext);
+    if cursor.u8()? != DATA_NOTIFICATION {
+        return Err("not a data-notification".to_string());
+    }
+    // The invoke id and priority.
+    cursor.take(4)?;
+    // The date and time, an octet string whose tag is left out but by Kaifa
+    // meters.
+    let timestamp = match cursor.u8()? {
+        0 => None,
+        0x09 => {
+            let len = cursor.length()?;
+            timestamp(cursor.take(len)?)
+        }
+        len => timestamp(cursor.take(len.into())?),
+    };
+    let body = cursor.data()?;
+    Ok(Notification {
+        system_title,
+        timestamp,
+        body,
+    })
+}
+
+/// A DLMS data value, as far as needed here.
+enum Data {
+    /// An array or a structure.
+    Items(Vec<Data>),
+    Number(f64),
+    /// Octet and visible strings.
+    Bytes(Vec<u8>),
+    Other,
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < n {
+            return Err("truncated APDU".to_string());
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    /// Reads a BER-encoded length.
+    fn length(&mut self) -> Result<usize, String> {
+        match self.u8()? {
+            len @ 0..=0x7f => Ok(len.into()),
+            len @ 0x81..=0x84 => Ok(self
+                .take(usize::from(len & 0x7f))?
+                .iter()
+                .fold(0, |len, b| len << 8 | usize::from(*b))),
+            _ => Err("invalid length".to_string()),
+        }
+    }
+
+    fn number<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("N bytes taken"))
+    }
+
+    /// Reads a value encoded in A-XDR.
+    fn data(&mut self) -> Result<Data, String> {
+        let data = match self.u8()? {
+            0x00 => Data::Other,
+            0x01 | 0x02 => {
+                let len = self.length()?;
+                let items = (0..len).map(|_| self.data()).collect::<Result<_, _>>()?;
+                Data::Items(items)
+            }
+            0x03 | 0x0d | 0x11 | 0x16 => Data::Number(self.u8()?.into()),
+            0x04 => {
+                let bits = self.length()?;
+                self.take(bits.div_ceil(8))?;
+                Data::Other
+            }
+            0x05 => Data::Number(i32::from_be_bytes(self.number()?).into()),
+            0x06 => Data::Number(u32::from_be_bytes(self.number()?).into()),
+            0x09 | 0x0a | 0x0c => {
+                let len = self.length()?;
+                Data::Bytes(self.take(len)?.to_vec())
+            }
+            0x0f => Data::Number((self.u8()? as i8).into()),
+            0x10 => Data::Number(i16::from_be_bytes(self.number()?).into()),
+            0x12 => Data::Number(u16::from_be_bytes(self.number()?).into()),
+            0x14 => Data::Number(i64::from_be_bytes(self.number()?) as f64),
+            0x15 => Data::Number(u64::from_be_bytes(self.number()?) as f64),
+            0x17 => Data::Number(f32::from_be_bytes(self.number()?).into()),
+            0x18 => Data::Number(f64::from_be_bytes(self.number()?)),
+            0x19 => Data::Bytes(self.take(12)?.to_vec()),
+            0x1a => Data::Bytes(self.take(5)?.to_vec()),
+            0x1b => Data::Bytes(self.take(4)?.to_vec()),
+            tag => return Err(format!("unsupported data type {tag:#04x}")),
+        };
+        Ok(data)
+    }
+}
+
+/// Formats a DLMS date-time as a DSMR timestamp (`YYMMDDhhmmssX`).
+fn timestamp(datetime: &[u8]) -> Option<String> {
+    let [year_hi, year_lo, month, day, _, hour, minute, second, _, _, _, status] =
+        *<&[u8; 12]>::try_from(datetime).ok()?;
+    let year = u16::from_be_bytes([year_hi, year_lo]);
+    if year == 0xffff || month > 12 || day > 31 || hour > 23 {
+        return None;
+    }
+    // The clock status flags daylight saving time.
+    let dst = if status & 0x80 != 0 { 'S' } else { 'W' };
+    Some(format!(
+        "{:02}{month:02}{day:02}{hour:02}{minute:02}{second:02}{dst}",
+        year % 100
+    ))
+}
+
+/// Collects the values following OBIS codes, with their scaler-unit if
+/// any, like the meters of EVN push them.
+fn readings(items: &[Data], readings: &mut Vec<(String, Reading)>) {
+    let mut i = 0;
+    while i < items.len() {
+        match (&items[i], items.get(i + 1)) {
+            (Data::Bytes(code), Some(value)) if code.len() == 6 => {
+                let code = telegram::reference(code);
+                let scaler_unit = match items.get(i + 2) {
+                    Some(Data::Items(items)) => match items[..] {
+                        [Data::Number(scaler), Data::Number(unit)] => {
+                            Some((scaler as i32, unit as u8))
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let reading = match (value, scaler_unit) {
+                    (Data::Number(value), Some((scaler, unit))) => {
+                        i += 1;
+                        Reading::Number(scale(*value, scaler), Some(unit))
+                    }
+                    (Data::Number(value), None) => Reading::Number(*value, None),
+                    (Data::Bytes(bytes), _) => Reading::Bytes(bytes.clone()),
+                    _ => {
+                        i += 2;
+                        continue;
+                    }
+                };
+                readings.push((code, reading));
+                i += 2;
+            }
+            (Data::Items(items), _) => {
+                self::readings(items, readings);
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+}
+
+/// Returns the list version identifier starting the notifications of the
+/// meters of Norwegian HAN ports, e.g. `Kamstrup_V0001`, on its own or with
+/// its OBIS code.
+fn list_version(items: &[Data]) -> Option<&[u8]> {
+    match items.first()? {
+        Data::Bytes(version) => Some(version),
+        Data::Items(items) => match &items[..] {
+            [Data::Bytes(code), Data::Bytes(version)] if code.len() == 6 => Some(version),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Collects the values of Kaifa meters, which push them without their OBIS
+/// codes.
+fn kaifa(items: &[Data], readings: &mut Vec<(String, Reading)>) {
+    let [_, Data::Bytes(id), _, rest @ ..] = items else {
+        return;
+    };
+    readings.push(("0-0:96.1.0".to_string(), Reading::Bytes(id.clone())));
+    let phases: &[_] = match rest.len() {
+        6 | 11 => &KAIFA_SINGLE_PHASE,
+        _ => &KAIFA_THREE_PHASE,
+    };
+    let (instant, rest) = rest.split_at(phases.len().min(rest.len()));
+    for ((code, scaler), item) in phases.iter().zip(instant) {
+        if let Data::Number(value) = item {
+            readings.push((
+                code.to_string(),
+                Reading::Number(scale(*value, *scaler), None),
+            ));
+        }
+    }
+    if let [Data::Bytes(clock), energy @ ..] = rest {
+        readings.push(("0-0:1.0.0".to_string(), Reading::Bytes(clock.clone())));
+        for (code, item) in KAIFA_ENERGY.iter().zip(energy) {
+            if let Data::Number(value) = item {
+                readings.push((code.to_string(), Reading::Number(*value, None)));
+            }
+        }
+    }
+}
+
+/// Moves the values of Kamstrup meters, which report them on channel 1 and
+/// without their scalers, to the usual OBIS codes and units.
+fn kamstrup(readings: &mut [(String, Reading)]) {
+    for (code, reading) in readings {
+        if let Some(rest) = code.strip_prefix("1-1:") {
+            *code = format!("1-0:{rest}");
+        } else if code == "0-1:1.0.0" {
+            *code = "0-0:1.0.0".to_string();
+        }
+        let Reading::Number(value, None) = reading else {
+            continue;
+        };
+        // Currents are in cA, energy in 10 Wh.
+        match code.as_str() {
+            "1-0:31.7.0" | "1-0:51.7.0" | "1-0:71.7.0" => *value = scale(*value, -2),
+            _ if code.ends_with(".8.0") => *value = scale(*value, 1),
+            _ => {}
+        }
+    }
+}
+
+/// Converts a data-notification into a DSMR telegram.
+fn to_telegram(notification: &Notification) -> Result<String, String> {
+    let items = match &notification.body {
+        Data::Items(items) => &items[..],
+        // Kaifa meters push their power alone every other second.
+        number @ Data::Number(_) => std::slice::from_ref(number),
+        _ => return Err("unexpected notification body".to_string()),
+    };
+    let version = list_version(items);
+    let mut values = Vec::new();
+    match version {
+        Some(version) if version.starts_with(b"KFM") => kaifa(items, &mut values),
+        Some(version) if version.starts_with(b"Kamstrup") => {
+            readings(items, &mut values);
+            kamstrup(&mut values);
+        }
+        _ => readings(items, &mut values),
+    }
+    if values.is_empty() {
+        let numbers: Vec<_> = items
+            .iter()
+            .filter_map(|item| match item {
+                Data::Number(value) => Some(*value),
+                _ => None,
+            })
+            .collect();
+        // A single value is the power of Kaifa meters.
+        let codes: &[_] = match numbers.len() {
+            1 => &["1-0:1.7.0"],
+            _ => &POSITIONAL,
+        };
+        values = codes
+            .iter()
+            .zip(numbers)
+            .map(|(code, value)| (code.to_string(), Reading::Number(value, None)))
+            .collect();
+    }
+    if values.is_empty() {
+        return Err("no values in the notification".to_string());
+    }
+    // The clock object is more precise than the time of the notification.
+    let timestamp = values
+        .iter()
+        .find_map(|(code, reading)| match reading {
+            Reading::Bytes(bytes) if code == "0-0:1.0.0" => timestamp(bytes),
+            _ => None,
+        })
+        .or_else(|| notification.timestamp.clone());
+    let manufacturer = notification
+        .system_title
+        .and_then(|title| String::from_utf8(title[..3].to_vec()).ok())
+        .or_else(|| Some(String::from_utf8_lossy(version?.get(..3)?).to_uppercase()))
+        .filter(|m| m.chars().all(|c| c.is_ascii_alphanumeric()))
+        .unwrap_or_else(|| "XXX".to_string());
+    Ok(telegram::build(
+        &format!("{manufacturer}5\\DLMS"),
+        timestamp.as_deref(),
+        &values,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::{
+        aead::{consts::U12, AeadInPlace, KeyInit},
+        aes::Aes128,
+        AesGcm,
+    };
+
+    /// 2026-10-15 03:41:38, in daylight saving time.
+    const DATETIME: [u8; 12] = [0x07, 0xea, 10, 15, 4, 3, 41, 38, 0, 0xff, 0x88, 0x80];
+
+    fn obis(code: [u8; 6]) -> Vec<u8> {
+        [&[0x09, 0x06][..], &code].concat()
+    }
+
+    fn double_long_unsigned(value: u32) -> Vec<u8> {
+        [&[0x06][..], &value.to_be_bytes()].concat()
+    }
+
+    fn scaler_unit(scaler: i8, unit: u8) -> Vec<u8> {
+        vec![0x02, 0x02, 0x0f, scaler as u8, 0x16, unit]
+    }
+
+    fn structure(items: &[Vec<u8>]) -> Vec<u8> {
+        [vec![0x02, items.len() as u8], items.concat()].concat()
+    }
+
+    /// A data-notification with the time and body given.
+    fn apdu(datetime: Option<[u8; 12]>, body: Vec<u8>) -> Vec<u8> {
+        let mut apdu = vec![DATA_NOTIFICATION, 0x00, 0x00, 0x00, 0x01];
+        match datetime {
+            Some(datetime) => {
+                apdu.push(12);
+                apdu.extend_from_slice(&datetime);
+            }
+            None => apdu.push(0),
+        }
+        apdu.extend(body);
+        apdu
+    }
+
+    /// The values of an EVN meter, with their OBIS codes and scalers.
+    fn evn() -> Vec<u8> {
+        apdu(
+            Some(DATETIME),
+            structure(&[
+                obis([1, 0, 1, 8, 0, 255]),
+                double_long_unsigned(12_345_678),
+                scaler_unit(0, 30),
+                obis([1, 0, 1, 7, 0, 255]),
+                double_long_unsigned(3_100),
+                scaler_unit(-1, 27),
+                obis([1, 0, 32, 7, 0, 255]),
+                vec![0x12, 0x09, 0x0b],
+                scaler_unit(-1, 35),
+            ]),
+        )
+    }
+
+    /// An HDLC frame of type 3, segmented unless `last`.
+    fn hdlc(information: &[u8], last: bool) -> Vec<u8> {
+        let len = information.len() + 9;
+        let segmented = if last { 0 } else { 0x08 };
+        let mut content = vec![0xa0 | segmented | (len >> 8) as u8, len as u8, 0x41, 0x03];
+        content.extend_from_slice(&[0x13, 0x00, 0x00]);
+        content.extend_from_slice(information);
+        let fcs = crc16_x25(&content);
+        [&[HDLC_FLAG][..], &content, &fcs.to_le_bytes(), &[HDLC_FLAG]].concat()
+    }
+
+    /// An M-Bus long frame with the segment numbered `n`.
+    fn mbus(segment: &[u8], n: u8, last: bool) -> Vec<u8> {
+        let ci = n | if last { 0x10 } else { 0 };
+        let data = [&[0x53, 0xff, ci, 0x67, 0xdb][..], segment].concat();
+        let checksum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        let len = data.len() as u8;
+        [
+            &[MBUS_START, len, len, MBUS_START][..],
+            &data,
+            &[checksum, MBUS_STOP],
+        ]
+        .concat()
+    }
+
+    fn read(stream: &[u8], keys: Option<decrypt::Keys>) -> String {
+        let mut telegrams = String::new();
+        reader(stream, keys).read_to_string(&mut telegrams).unwrap();
+        telegrams
+    }
+
+    fn assert_lines(telegram: &str, lines: &[&str]) {
+        for line in lines {
+            assert!(telegram.contains(line), "{line} in {telegram}");
+        }
+    }
+
+    #[test]
+    fn read_hdlc_frames() {
+        let apdu = evn();
+        let (first, second) = apdu.split_at(20);
+        let mut corrupted = hdlc(&[&LLC[..], &apdu].concat(), true);
+        corrupted[10] ^= 1;
+        let stream = [
+            corrupted,
+            hdlc(&[&LLC[..], first].concat(), false),
+            // Sharing its opening flag with the closing one of the segment
+            // before.
+            hdlc(second, true)[1..].to_vec(),
+        ]
+        .concat();
+        let telegram = read(&stream, None);
+        assert!(telegram.starts_with("/XXX5\\DLMS"), "{telegram}");
+        assert_lines(
+            &telegram,
+            &[
+                "0-0:1.0.0(261015034138S)",
+                "1-0:1.8.1(012345.678*kWh)",
+                "1-0:1.7.0(00.310*kW)",
+                "1-0:32.7.0(231.5*V)",
+            ],
+        );
+        assert_eq!(telegram.matches("/XXX5").count(), 1);
+    }
+
+    #[test]
+    fn read_encrypted_mbus_frames() {
+        let keys = decrypt::Keys {
+            encryption: "000102030405060708090A0B0C0D0E0F".parse().unwrap(),
+            authentication: decrypt::SMARTY_AUTHENTICATION_KEY.parse().unwrap(),
+        };
+        let system_title = *b"EVN12345";
+        let counter = 7u32.to_be_bytes();
+        let nonce = [&system_title[..], &counter].concat();
+        // The security control byte and the authentication key.
+        let aad: Vec<u8> = [0x30]
+            .into_iter()
+            .chain((0..16).map(|i| i * 0x11))
+            .collect();
+        let mut ciphertext = evn();
+        let key: [u8; 16] = std::array::from_fn(|i| i as u8);
+        let tag = AesGcm::<Aes128, U12, U12>::new(&key.into())
+            .encrypt_in_place_detached(nonce[..].into(), &aad, &mut ciphertext)
+            .unwrap();
+        let payload = [&[0x30][..], &counter, &ciphertext, &tag].concat();
+        let mut apdu = vec![GENERAL_GLO_CIPHERING, 8];
+        apdu.extend_from_slice(&system_title);
+        apdu.extend_from_slice(&[0x81, payload.len() as u8]);
+        apdu.extend(payload);
+
+        let (first, second) = apdu.split_at(40);
+        let stream = [mbus(first, 0, false), mbus(second, 1, true)].concat();
+        let telegram = read(&stream, Some(keys));
+        assert!(telegram.starts_with("/EVN5\\DLMS"), "{telegram}");
+        assert_lines(&telegram, &["1-0:1.8.1(012345.678*kWh)"]);
+
+        let err = notification(&apdu, None).err().unwrap();
+        assert_eq!(
+            err,
+            "the frame is encrypted, but no --decryption-key is given"
+        );
+    }
+
+    #[test]
+    fn positional_values() {
+        // Wiener Netze: the energy totals in Wh, then the power in W.
+        let values: Vec<_> = [1_000_000, 0, 0, 0, 420, 0, 0, 0]
+            .into_iter()
+            .map(double_long_unsigned)
+            .collect();
+        let apdu = apdu(None, structure(&values));
+        let telegram = to_telegram(&notification(&apdu, None).unwrap()).unwrap();
+        assert!(!telegram.contains("0-0:1.0.0"), "{telegram}");
+        assert_lines(&telegram, &["1-0:1.8.1", "1-0:1.7.0"]);
+    }
+
+    #[test]
+    fn kaifa_values() {
+        // The power alone, pushed every other second.
+        let apdu = self::apdu(None, double_long_unsigned(1_500));
+        let telegram = to_telegram(&notification(&apdu, None).unwrap()).unwrap();
+        assert_lines(&telegram, &["1-0:1.7.0(01.500*kW)"]);
+
+        let mut items = vec![vec![0x09, 0x0b], vec![0x09, 0x10], vec![0x09, 0x08]];
+        items[0].extend_from_slice(b"KFM_001.000");
+        items[1].extend_from_slice(b"6970631400000000");
+        items[2].extend_from_slice(b"MA304H3E");
+        let instant = [2_500, 0, 100, 0, 5_000, 4_000, 3_000, 2_300, 2_310, 2_320];
+        items.extend(instant.into_iter().map(double_long_unsigned));
+        let body = [vec![0x02, items.len() as u8], items.concat()].concat();
+        let apdu = self::apdu(None, body);
+        let telegram = to_telegram(&notification(&apdu, None).unwrap()).unwrap();
+        assert!(telegram.starts_with("/KFM5\\DLMS"), "{telegram}");
+        assert_lines(
+            &telegram,
+            &[
+                "1-0:1.7.0(02.500*kW)",
+                "1-0:31.7.0(005*A)",
+                "1-0:72.7.0(232.0*V)",
+            ],
+        );
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp(&DATETIME).unwrap(), "261015034138S");
+        let mut winter = DATETIME;
+        winter[11] = 0;
+        assert_eq!(timestamp(&winter).unwrap(), "261015034138W");
+        let mut unset = DATETIME;
+        unset[..2].copy_from_slice(&[0xff, 0xff]);
+        assert_eq!(timestamp(&unset), None);
+        assert_eq!(timestamp(&DATETIME[..8]), None);
+    }
+}
@@ -0,0 +1,112 @@
+//! Forwarding of the telegrams to a dsmr-reader instance, with the API of its
+//! remote datalogger.
+
+use crate::{
+    http::{self, Client, Url},
+    sink::{Sample, Sink},
+};
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Timeout for connecting to dsmr-reader and for every read and write.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Telegrams kept while dsmr-reader is unreachable, the oldest are dropped
+/// beyond that. About an hour of telegrams every second.
+const MAX_PENDING: usize = 3600;
+/// Delay before retrying a telegram dsmr-reader failed to take.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+pub struct DsmrReaderConfig {
+    /// Base URL of the dsmr-reader instance.
+    pub url: Url,
+    pub api_key: String,
+}
+
+/// Telegrams waiting to be sent, the oldest first.
+#[derive(Default)]
+struct Pending {
+    telegrams: Mutex<VecDeque<String>>,
+    received: Condvar,
+}
+
+pub struct DsmrReaderSink {
+    pending: Arc<Pending>,
+}
+
+impl DsmrReaderSink {
+    pub fn start(config: DsmrReaderConfig) -> Result<Self, io::Error> {
+        let client = Client::new(&config.url, TIMEOUT)?;
+        let url = config.url.join("api/v1/datalogger/dsmrreading");
+        let pending = Arc::new(Pending::default());
+        let queue = pending.clone();
+        thread::spawn(move || loop {
+            let telegram = {
+                let telegrams = queue.telegrams.lock().unwrap();
+                let mut telegrams = queue
+                    .received
+                    .wait_while(telegrams, |telegrams| telegrams.is_empty())
+                    .unwrap();
+                telegrams.pop_front().expect("waited for a telegram")
+            };
+            match send(&client, &url, &config.api_key, &telegram) {
+                Ok(()) => {}
+                Err(Failure::Retry(err)) => {
+                    tracing::warn!("Failed to send telegram to {url}, retrying: {err}");
+                    let mut telegrams = queue.telegrams.lock().unwrap();
+                    if telegrams.len() < MAX_PENDING {
+                        telegrams.push_front(telegram);
+                    }
+                    drop(telegrams);
+                    thread::sleep(RETRY_DELAY);
+                }
+                Err(Failure::Drop(err)) => {
+                    tracing::warn!("Failed to send telegram to {url}: {err}");
+                }
+            }
+        });
+        Ok(DsmrReaderSink { pending })
+    }
+}
+
+impl Sink for DsmrReaderSink {
+    fn publish(&self, _samples: &[Sample]) {}
+
+    fn publish_telegram(&self, text: &str) {
+        let mut telegrams = self.pending.telegrams.lock().unwrap();
+        // The text ends with the CRC, without the line end dsmr-reader
+        // expects after it.
+        telegrams.push_back(format!("{}\r\n", text.trim_end()));
+        let excess = telegrams.len().saturating_sub(MAX_PENDING);
+        telegrams.drain(..excess);
+        self.pending.received.notify_one();
+    }
+}
+
+enum Failure {
+    /// The request may succeed later, e.g. dsmr-reader is down.
+    Retry(io::Error),
+    /// dsmr-reader rejected the telegram.
+    Drop(io::Error),
+}
+
+fn send(client: &Client, url: &Url, api_key: &str, telegram: &str) -> Result<(), Failure> {
+    let body = format!("telegram={}", http::escape(telegram));
+    let headers = [
+        ("Content-Type", "application/x-www-form-urlencoded"),
+        ("X-AUTHKEY", api_key),
+    ];
+    let response = client
+        .send("POST", url, &headers, body.as_bytes())
+        .map_err(Failure::Retry)?;
+    let err = || io::Error::other(format!("status {}: {}", response.status, response.body));
+    match response.status {
+        200..=299 => Ok(()),
+        429 | 500..=599 => Err(Failure::Retry(err())),
+        _ => Err(Failure::Drop(err())),
+    }
+}
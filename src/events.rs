@@ -0,0 +1,80 @@
+//! Live readings streamed to HTTP clients as Server-Sent Events.
+
+use crate::{
+    metrics::RawTelegram,
+    parse::{self, Format, Value},
+};
+use std::{
+    io::{self, Write},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Interval of the comments keeping idle streams open, which also detect
+/// clients gone away.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The clients subscribed to the readings of all meters.
+#[derive(Debug, Default)]
+pub struct Events {
+    subscribers: Mutex<Vec<Sender<Arc<str>>>>,
+}
+
+impl Events {
+    pub fn subscribe(&self) -> Receiver<Arc<str>> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Sends the readings of a parsed telegram to every subscriber.
+    pub fn publish(&self, meter: &Option<String>, telegram: &RawTelegram) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let data = Value::Object(vec![
+            ("meter", meter.clone().into()),
+            ("received", Value::Number(telegram.received_unix())),
+            ("readings", parse::readings(&telegram.text)),
+        ])
+        .render(Format::Json);
+        let event: String = data
+            .lines()
+            .map(|line| format!("data: {line}\n"))
+            .chain(["\n".to_string()])
+            .collect();
+        let event: Arc<str> = event.into();
+        subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+/// Writes the response headers and then every event received until the
+/// client goes away. Returns right after the headers for HEAD requests.
+pub fn stream(
+    mut writer: impl Write,
+    events: Receiver<Arc<str>>,
+    head: bool,
+) -> Result<(), io::Error> {
+    writer.write_all(
+        b"HTTP/1.1 200 OK\r\n\
+          Content-Type: text/event-stream\r\n\
+          Cache-Control: no-cache\r\n\
+          Connection: close\r\n\r\n",
+    )?;
+    writer.flush()?;
+    if head {
+        return Ok(());
+    }
+    loop {
+        match events.recv_timeout(KEEPALIVE_INTERVAL) {
+            Ok(event) => writer.write_all(event.as_bytes())?,
+            Err(RecvTimeoutError::Timeout) => writer.write_all(b": keepalive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        writer.flush()?;
+    }
+}
@@ -0,0 +1,629 @@
+//! Exposition formats of the metrics and their negotiation.
+//!
+//! `prometheus_client` encodes OpenMetrics only, the classic Prometheus text
+//! format and the protobuf one are derived from that.
+
+use crate::{
+    metrics::MetricGroup,
+    protobuf::{field_bytes, field_double, field_varint, varint},
+};
+use prometheus_client::{encoding::text, registry::Registry};
+use std::{collections::HashMap, fmt};
+
+/// Encodes the registry in OpenMetrics.
+///
+/// With constant labels, `prometheus_client` ends the label sets of series
+/// without labels of their own with a comma, which strict parsers reject.
+pub fn encode(registry: &Registry) -> Result<String, fmt::Error> {
+    let mut encoded = String::new();
+    text::encode(&mut encoded, registry)?;
+    if !encoded.contains("\",}") {
+        return Ok(encoded);
+    }
+    let mut openmetrics = String::with_capacity(encoded.len());
+    for line in encoded.lines() {
+        // Label values may contain braces, but not the value of the sample.
+        match line.rfind('}') {
+            Some(end) if !line.starts_with('#') && line[..end].ends_with("\",") => {
+                openmetrics.push_str(&line[..end - 1]);
+                openmetrics.push_str(&line[end..]);
+            }
+            _ => openmetrics.push_str(line),
+        }
+        openmetrics.push('\n');
+    }
+    Ok(openmetrics)
+}
+
+/// Attaches the time the meter took its readings to their samples: the
+/// timestamp of the telegram, from `p1_meter_timestamp_seconds`, or for the
+/// M-Bus devices their capture time, from `p1_mbus_timestamp_seconds`.
+pub fn with_timestamps(openmetrics: &str, prefix: &str) -> String {
+    let meter_timestamp = format!("{prefix}_meter_timestamp_seconds");
+    let mbus_timestamp = format!("{prefix}_mbus_timestamp_seconds");
+    let mut meters = HashMap::new();
+    let mut devices = HashMap::new();
+    for (series, value, _) in openmetrics.lines().filter_map(split_sample) {
+        let name = metric_name(series);
+        if name == meter_timestamp {
+            meters.insert(label(series, "meter"), value);
+        } else if name == mbus_timestamp {
+            let device = (label(series, "meter"), label(series, "mbus_channel"));
+            devices.insert(device, value);
+        }
+    }
+    if meters.is_empty() && devices.is_empty() {
+        return openmetrics.to_string();
+    }
+
+    let mut timestamped = String::with_capacity(openmetrics.len() * 2);
+    for line in openmetrics.lines() {
+        timestamped.push_str(line);
+        let timestamp = split_sample(line)
+            .filter(|(_, _, timestamp)| timestamp.is_none())
+            .and_then(|(series, _, _)| {
+                let family = metric_name(series)
+                    .strip_prefix(prefix)?
+                    .strip_prefix('_')?;
+                let family = family.strip_suffix("_total").unwrap_or(family);
+                match family {
+                    "gas_consumed_cubic_meters"
+                    | "gas_flow_cubic_meters_per_hour"
+                    | "water_consumed_cubic_meters"
+                    | "heat_consumed_gj"
+                    | "cooling_consumed_gj" => {
+                        let device = (label(series, "meter"), label(series, "mbus_channel"));
+                        devices.get(&device)
+                    }
+                    _ => match MetricGroup::of(family)? {
+                        MetricGroup::Power
+                        | MetricGroup::Phases
+                        | MetricGroup::Energy
+                        | MetricGroup::Tariff
+                        | MetricGroup::Demand
+                        | MetricGroup::Quality => meters.get(&label(series, "meter")),
+                        _ => None,
+                    },
+                }
+            });
+        if let Some(timestamp) = timestamp {
+            timestamped.push(' ');
+            timestamped.push_str(timestamp);
+        }
+        timestamped.push('\n');
+    }
+    timestamped
+}
+
+/// Splits a sample line into the series, the value and the timestamp, if
+/// any.
+fn split_sample(line: &str) -> Option<(&str, &str, Option<&str>)> {
+    if line.starts_with('#') || line.is_empty() {
+        return None;
+    }
+    // Label values may contain spaces and braces, but not the value.
+    let end = match line.find(['{', ' ']) {
+        Some(i) if line[i..].starts_with('{') => line.rfind('}')? + 1,
+        Some(i) => i,
+        None => return None,
+    };
+    let (series, rest) = line.split_at(end);
+    let mut rest = rest.split_whitespace();
+    Some((series, rest.next()?, rest.next()))
+}
+
+fn metric_name(series: &str) -> &str {
+    series.split('{').next().unwrap_or(series)
+}
+
+/// Returns the value of a label of a series, as escaped in the exposition.
+fn label<'a>(series: &'a str, name: &str) -> Option<&'a str> {
+    let (_, mut labels) = series.split_once('{')?;
+    loop {
+        let (label, rest) = labels.split_once("=\"")?;
+        // The value ends at the first quote that isn't escaped.
+        let bytes = rest.as_bytes();
+        let mut end = 0;
+        while *bytes.get(end)? != b'"' {
+            end += if bytes[end] == b'\\' { 2 } else { 1 };
+        }
+        if label.trim_start_matches(',') == name {
+            return Some(&rest[..end]);
+        }
+        labels = &rest[end + 1..];
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    OpenMetrics,
+    /// The Prometheus text format, version 0.0.4.
+    Text,
+    /// Delimited `io.prometheus.client.MetricFamily` protobuf messages.
+    Protobuf,
+}
+
+impl Format {
+    /// Picks the format for the `Accept` header of a scrape, falling back to
+    /// the Prometheus text format if none is acceptable or the header is
+    /// missing, as older scrapers expect.
+    pub fn negotiate(accept: Option<&str>) -> Format {
+        let mut best = (Format::Text, 0.0);
+        for range in accept.unwrap_or_default().split(',') {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            let params: Vec<&str> = params.collect();
+            let param = |name: &str| {
+                params.iter().find_map(|param| {
+                    let (key, value) = param.split_once('=')?;
+                    (key.trim() == name).then(|| value.trim())
+                })
+            };
+            let q = param("q")
+                .and_then(|q| q.parse::<f64>().ok())
+                .unwrap_or(1.0);
+            let format = match media_type {
+                "application/openmetrics-text" => Format::OpenMetrics,
+                "text/plain" => Format::Text,
+                "application/vnd.google.protobuf"
+                    if param("proto") == Some("io.prometheus.client.MetricFamily")
+                        && param("encoding") == Some("delimited") =>
+                {
+                    Format::Protobuf
+                }
+                _ => continue,
+            };
+            if q > best.1 {
+                best = (format, q);
+            }
+        }
+        best.0
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            Format::Text => "text/plain; version=0.0.4; charset=utf-8",
+            Format::Protobuf => {
+                "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited"
+            }
+        }
+    }
+}
+
+/// Converts OpenMetrics text into the Prometheus text format.
+///
+/// The formats differ in the metadata: the Prometheus format has no `UNIT`
+/// lines, no `# EOF` and no info type, and names counter families after
+/// their samples, with the `_total` suffix. Timestamps are in milliseconds
+/// rather than seconds.
+pub fn to_text(openmetrics: &str) -> String {
+    let mut types = HashMap::new();
+    for line in openmetrics.lines() {
+        if let Some((name, kind)) = line
+            .strip_prefix("# TYPE ")
+            .and_then(|typ| typ.split_once(' '))
+        {
+            types.insert(name, kind);
+        }
+    }
+    let rename = |name: &str| match types.get(name) {
+        Some(&"counter") => (format!("{name}_total"), "counter"),
+        Some(&"info") => (format!("{name}_info"), "gauge"),
+        Some(&"unknown") => (name.to_string(), "untyped"),
+        Some(kind) => (name.to_string(), *kind),
+        None => (name.to_string(), "untyped"),
+    };
+
+    let mut text = String::with_capacity(openmetrics.len());
+    for line in openmetrics.lines() {
+        if line == "# EOF" || line.starts_with("# UNIT ") {
+            continue;
+        }
+        if let Some((name, help)) = line
+            .strip_prefix("# HELP ")
+            .and_then(|help| help.split_once(' '))
+        {
+            text.push_str(&format!("# HELP {} {help}\n", rename(name).0));
+        } else if let Some((name, _)) = line
+            .strip_prefix("# TYPE ")
+            .and_then(|typ| typ.split_once(' '))
+        {
+            let (name, kind) = rename(name);
+            text.push_str(&format!("# TYPE {name} {kind}\n"));
+        } else if let Some((series, value, Some(timestamp))) = split_sample(line) {
+            let millis = timestamp
+                .parse::<f64>()
+                .map_or(0, |t| (t * 1000.0).round() as i64);
+            text.push_str(&format!("{series} {value} {millis}\n"));
+        } else {
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+    text
+}
+
+pub type Labels = Vec<(String, String)>;
+
+/// A sample of the OpenMetrics encoding.
+pub struct Sample<'a> {
+    pub name: &'a str,
+    pub labels: Labels,
+    pub value: f64,
+    /// In seconds since the Unix epoch.
+    pub timestamp: Option<f64>,
+}
+
+/// A metric family of the OpenMetrics encoding.
+pub struct Family<'a> {
+    pub name: &'a str,
+    pub kind: &'a str,
+    pub help: &'a str,
+    pub unit: &'a str,
+    pub samples: Vec<Sample<'a>>,
+}
+
+/// Parses the families of OpenMetrics text.
+pub fn families(openmetrics: &str) -> Vec<Family<'_>> {
+    let mut families: Vec<Family> = Vec::new();
+    for line in openmetrics.lines() {
+        if let Some(meta) = line.strip_prefix("# ") {
+            let mut parts = meta.splitn(3, ' ');
+            let (Some(keyword), Some(name)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let value = parts.next().unwrap_or_default();
+            if families.last().is_none_or(|family| family.name != name) {
+                families.push(Family {
+                    name,
+                    kind: "unknown",
+                    help: "",
+                    unit: "",
+                    samples: Vec::new(),
+                });
+            }
+            let family = families.last_mut().expect("pushed above");
+            match keyword {
+                "TYPE" => family.kind = value,
+                "HELP" => family.help = value,
+                "UNIT" => family.unit = value,
+                _ => {}
+            }
+        } else if let Some(family) = families.last_mut() {
+            if let Some(sample) = sample(line) {
+                family.samples.push(sample);
+            }
+        }
+    }
+    families
+}
+
+/// Parses a sample line, ignoring its exemplar if any.
+fn sample(line: &str) -> Option<Sample<'_>> {
+    let (name, rest) = match line.find(['{', ' ']) {
+        Some(i) => line.split_at(i),
+        None => return None,
+    };
+    let (labels, rest) = match rest.strip_prefix('{') {
+        Some(rest) => labels(rest)?,
+        None => (Vec::new(), rest),
+    };
+    let mut rest = rest.split_whitespace();
+    let value = sample_value(rest.next()?)?;
+    let timestamp = rest.next().and_then(|timestamp| timestamp.parse().ok());
+    Some(Sample {
+        name,
+        labels,
+        value,
+        timestamp,
+    })
+}
+
+/// Parses the labels following the opening brace, returning them with the
+/// rest of the line after the closing one.
+fn labels(mut s: &str) -> Option<(Labels, &str)> {
+    let mut labels = Vec::new();
+    loop {
+        s = s.trim_start_matches(',');
+        if let Some(rest) = s.strip_prefix('}') {
+            return Some((labels, rest));
+        }
+        let (name, rest) = s.split_once("=\"")?;
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        loop {
+            match chars.next()? {
+                (i, '"') => {
+                    s = &rest[i + 1..];
+                    break;
+                }
+                (_, '\\') => match chars.next()?.1 {
+                    'n' => value.push('\n'),
+                    c => value.push(c),
+                },
+                (_, c) => value.push(c),
+            }
+        }
+        labels.push((name.to_string(), value));
+    }
+}
+
+pub fn sample_value(value: &str) -> Option<f64> {
+    match value {
+        "+Inf" => Some(f64::INFINITY),
+        "-Inf" => Some(f64::NEG_INFINITY),
+        value => value.parse().ok(),
+    }
+}
+
+/// Converts OpenMetrics text into delimited `MetricFamily` messages of the
+/// Prometheus protobuf format.
+///
+/// As in the Prometheus text format, counter families are named after their
+/// samples and info families are gauges. Families without samples, and those
+/// of other types than counters, gauges, info, histograms and unknown ones,
+/// are left out.
+pub fn to_protobuf(openmetrics: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for family in families(openmetrics) {
+        // The MetricType enum of the message.
+        let (name, kind, suffix) = match family.kind {
+            "counter" => (format!("{}_total", family.name), 0, "_total"),
+            "gauge" => (family.name.to_string(), 1, ""),
+            "info" => (format!("{}_info", family.name), 1, "_info"),
+            "unknown" => (family.name.to_string(), 3, ""),
+            "histogram" => (family.name.to_string(), 4, ""),
+            _ => continue,
+        };
+        let metrics: Vec<Vec<u8>> = if kind == 4 {
+            histogram_metrics(&family)
+        } else {
+            family
+                .samples
+                .iter()
+                .filter(|sample| sample.name.strip_prefix(family.name) == Some(suffix))
+                .map(|sample| {
+                    let mut value = Vec::new();
+                    field_double(&mut value, 1, sample.value);
+                    let mut metric = label_pairs(&sample.labels);
+                    // The gauge, counter or untyped field of the Metric.
+                    let field = match kind {
+                        0 => 3,
+                        1 => 2,
+                        _ => 5,
+                    };
+                    field_bytes(&mut metric, field, &value);
+                    timestamp_ms(&mut metric, sample.timestamp);
+                    metric
+                })
+                .collect()
+        };
+        if metrics.is_empty() {
+            continue;
+        }
+        let mut message = Vec::new();
+        field_bytes(&mut message, 1, name.as_bytes());
+        if !family.help.is_empty() {
+            field_bytes(&mut message, 2, unescape_help(family.help).as_bytes());
+        }
+        field_varint(&mut message, 3, kind);
+        for metric in metrics {
+            field_bytes(&mut message, 4, &metric);
+        }
+        if !family.unit.is_empty() {
+            field_bytes(&mut message, 5, family.unit.as_bytes());
+        }
+        varint(&mut encoded, message.len() as u64);
+        encoded.extend_from_slice(&message);
+    }
+    encoded
+}
+
+/// Returns the `Metric` messages of the series of a histogram family.
+fn histogram_metrics(family: &Family) -> Vec<Vec<u8>> {
+    /// The series of a histogram: its labels, count, sum, cumulative buckets
+    /// and timestamp.
+    type Series = (Labels, f64, f64, Vec<(f64, f64)>, Option<f64>);
+    let mut series: Vec<Series> = Vec::new();
+    for sample in &family.samples {
+        let mut labels = sample.labels.clone();
+        let le = labels
+            .iter()
+            .position(|(name, _)| name == "le")
+            .map(|i| labels.remove(i).1);
+        let i = match series.iter().position(|series| series.0 == labels) {
+            Some(i) => i,
+            None => {
+                series.push((labels, 0.0, 0.0, Vec::new(), sample.timestamp));
+                series.len() - 1
+            }
+        };
+        match sample.name.strip_prefix(family.name) {
+            Some("_count") => series[i].1 = sample.value,
+            Some("_sum") => series[i].2 = sample.value,
+            Some("_bucket") => {
+                if let Some(le) = le.and_then(|le| sample_value(&le)) {
+                    series[i].3.push((le, sample.value));
+                }
+            }
+            _ => {}
+        }
+    }
+    series
+        .into_iter()
+        .map(|(labels, count, sum, buckets, timestamp)| {
+            let mut histogram = Vec::new();
+            field_varint(&mut histogram, 1, count as u64);
+            field_double(&mut histogram, 2, sum);
+            // The +Inf bucket is implied by the count.
+            for (le, cumulative) in buckets.into_iter().filter(|(le, _)| le.is_finite()) {
+                let mut bucket = Vec::new();
+                field_varint(&mut bucket, 1, cumulative as u64);
+                field_double(&mut bucket, 2, le);
+                field_bytes(&mut histogram, 3, &bucket);
+            }
+            let mut metric = label_pairs(&labels);
+            field_bytes(&mut metric, 7, &histogram);
+            timestamp_ms(&mut metric, timestamp);
+            metric
+        })
+        .collect()
+}
+
+/// Returns the `LabelPair` fields of a `Metric`.
+fn label_pairs(labels: &[(String, String)]) -> Vec<u8> {
+    let mut metric = Vec::new();
+    for (name, value) in labels {
+        let mut pair = Vec::new();
+        field_bytes(&mut pair, 1, name.as_bytes());
+        field_bytes(&mut pair, 2, value.as_bytes());
+        field_bytes(&mut metric, 1, &pair);
+    }
+    metric
+}
+
+/// Appends the `timestamp_ms` field of a `Metric`, from seconds.
+fn timestamp_ms(metric: &mut Vec<u8>, timestamp: Option<f64>) {
+    if let Some(timestamp) = timestamp {
+        field_varint(metric, 6, (timestamp * 1000.0).round() as i64 as u64);
+    }
+}
+
+/// Unescapes the backslashes, quotes and line ends of a `HELP` text.
+fn unescape_help(help: &str) -> String {
+    let mut unescaped = String::with_capacity(help.len());
+    let mut chars = help.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROTOBUF: &str =
+        "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=delimited";
+
+    #[test]
+    fn negotiate_without_accept() {
+        assert_eq!(Format::negotiate(None), Format::Text);
+        assert_eq!(Format::negotiate(Some("")), Format::Text);
+        assert_eq!(Format::negotiate(Some("*/*")), Format::Text);
+        assert_eq!(Format::negotiate(Some("application/json")), Format::Text);
+    }
+
+    #[test]
+    fn negotiate_by_q_value() {
+        // The header of Prometheus 2.x.
+        let accept = "application/openmetrics-text;version=1.0.0,application/openmetrics-text;\
+                      version=0.0.1;q=0.75,text/plain;version=0.0.4;q=0.5,*/*;q=0.1";
+        assert_eq!(Format::negotiate(Some(accept)), Format::OpenMetrics);
+        let accept = "application/openmetrics-text;q=0.5, text/plain;q=0.9, */*;q=0.1";
+        assert_eq!(Format::negotiate(Some(accept)), Format::Text);
+        // With native histograms enabled.
+        let accept = format!("{PROTOBUF};q=0.8,application/openmetrics-text;version=1.0.0;q=0.7");
+        assert_eq!(Format::negotiate(Some(&accept)), Format::Protobuf);
+        // The first of equally preferred ones.
+        let accept = format!("text/plain, {PROTOBUF}");
+        assert_eq!(Format::negotiate(Some(&accept)), Format::Text);
+    }
+
+    #[test]
+    fn negotiate_protobuf_needs_delimited_families() {
+        let accept = "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily";
+        assert_eq!(Format::negotiate(Some(accept)), Format::Text);
+        assert_eq!(Format::negotiate(Some(PROTOBUF)), Format::Protobuf);
+    }
+
+    #[test]
+    fn text_renames_counters_and_info() {
+        let openmetrics = "# HELP p1_power_consumed_kwh Total consumed power.\n\
+                           # TYPE p1_power_consumed_kwh counter\n\
+                           # UNIT p1_power_consumed_kwh kwh\n\
+                           p1_power_consumed_kwh_total{tariff=\"low\"} 1.5 1700000000.25\n\
+                           # TYPE p1_meter info\n\
+                           p1_meter_info{id=\"x\"} 1\n\
+                           # TYPE p1_x unknown\n\
+                           p1_x 2\n\
+                           # EOF\n";
+        assert_eq!(
+            to_text(openmetrics),
+            "# HELP p1_power_consumed_kwh_total Total consumed power.\n\
+             # TYPE p1_power_consumed_kwh_total counter\n\
+             p1_power_consumed_kwh_total{tariff=\"low\"} 1.5 1700000000250\n\
+             # TYPE p1_meter_info gauge\n\
+             p1_meter_info{id=\"x\"} 1\n\
+             # TYPE p1_x untyped\n\
+             p1_x 2\n"
+        );
+    }
+
+    #[test]
+    fn timestamps_of_meter_and_mbus_readings() {
+        let openmetrics = "p1_meter_timestamp_seconds{meter=\"a\"} 100\n\
+                           p1_mbus_timestamp_seconds{meter=\"a\",mbus_channel=\"1\"} 50\n\
+                           p1_power_consumed_kw{meter=\"a\"} 0.3\n\
+                           p1_power_consumed_kwh_total{meter=\"a\",tariff=\"low\"} 10\n\
+                           p1_gas_consumed_cubic_meters_total{meter=\"a\",mbus_channel=\"1\"} 5\n\
+                           p1_power_consumed_kw{meter=\"b\"} 0.1\n\
+                           p1_telegrams_received_total{meter=\"a\"} 3\n\
+                           p1_power_net_kw{meter=\"a\"} 0.3 90\n";
+        assert_eq!(
+            with_timestamps(openmetrics, "p1"),
+            "p1_meter_timestamp_seconds{meter=\"a\"} 100\n\
+             p1_mbus_timestamp_seconds{meter=\"a\",mbus_channel=\"1\"} 50\n\
+             p1_power_consumed_kw{meter=\"a\"} 0.3 100\n\
+             p1_power_consumed_kwh_total{meter=\"a\",tariff=\"low\"} 10 100\n\
+             p1_gas_consumed_cubic_meters_total{meter=\"a\",mbus_channel=\"1\"} 5 50\n\
+             p1_power_consumed_kw{meter=\"b\"} 0.1\n\
+             p1_telegrams_received_total{meter=\"a\"} 3\n\
+             p1_power_net_kw{meter=\"a\"} 0.3 90\n"
+        );
+        let untouched = "p1_power_consumed_kw 0.3\n";
+        assert_eq!(with_timestamps(untouched, "p1"), untouched);
+    }
+
+    #[test]
+    fn protobuf_gauge() {
+        let openmetrics = "# HELP a_b Help.\n# TYPE a_b gauge\na_b{x=\"y\"} 1.5\n# EOF\n";
+        let mut expected = vec![35, 0x0a, 3, b'a', b'_', b'b', 0x12, 5];
+        expected.extend_from_slice(b"Help.");
+        // type GAUGE, then the metric with its label pair and gauge
+        expected.extend_from_slice(&[0x18, 1, 0x22, 19, 0x0a, 6, 0x0a, 1, b'x', 0x12, 1, b'y']);
+        expected.extend_from_slice(&[0x12, 9, 0x09]);
+        expected.extend_from_slice(&1.5f64.to_le_bytes());
+        assert_eq!(to_protobuf(openmetrics), expected);
+    }
+
+    #[test]
+    fn protobuf_names_counters_after_samples() {
+        let openmetrics = "# TYPE c counter\nc_total 2.0 1700000000.5\n\
+                           # TYPE i info\ni_info{v=\"1\"} 1\n# TYPE e gauge\n# EOF\n";
+        let encoded = to_protobuf(openmetrics);
+        // c_total, a COUNTER with a timestamp in milliseconds
+        let mut expected = vec![31, 0x0a, 7];
+        expected.extend_from_slice(b"c_total");
+        expected.extend_from_slice(&[0x18, 0, 0x22, 18, 0x1a, 9, 0x09]);
+        expected.extend_from_slice(&2.0f64.to_le_bytes());
+        expected.extend_from_slice(&[0x30]);
+        varint(&mut expected, 1_700_000_000_500);
+        // i_info, a GAUGE, and nothing for e without samples
+        expected.extend_from_slice(&[31, 0x0a, 6]);
+        expected.extend_from_slice(b"i_info");
+        expected.extend_from_slice(&[0x18, 1, 0x22, 19, 0x0a, 6, 0x0a, 1, b'v', 0x12, 1, b'1']);
+        expected.extend_from_slice(&[0x12, 9, 0x09]);
+        expected.extend_from_slice(&1.0f64.to_le_bytes());
+        assert_eq!(encoded, expected);
+    }
+}
@@ -0,0 +1,727 @@
+//! Public listeners of the HTTP server.
+//!
+//! The HTTP server reads requests of any size without timeouts, from as many
+//! connections as clients open, so a single misbehaving client could hold up
+//! the scrapes. Connections are accepted here instead, up to a limit, and
+//! only complete requests without a body are relayed to the HTTP server,
+//! which listens on a Unix socket in a directory only the exporter can
+//! access. HTTPS is terminated here as well, as the HTTP server can't verify
+//! client certificates.
+
+use crate::{
+    privileges::{self, User},
+    server::{self, ListenAddress, SocketPermissions},
+    systemd,
+};
+use prometheus_client::metrics::{counter::Counter, family::Family};
+use rustls::{ServerConfig, ServerConnection};
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    os::unix::{
+        ffi::OsStringExt,
+        io::{AsRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Time after which a connection without any traffic in either direction is
+/// closed. Longer than the keepalive interval of the event stream.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest request accepted, its request line and headers.
+const MAX_REQUEST_HEAD: usize = 16 * 1024;
+
+/// How long the rest of a refused request is read before the connection is
+/// closed, so that the client gets the response rather than a reset.
+const LINGER: Duration = Duration::from_secs(1);
+
+/// Header telling the HTTP server the address of the client, as
+/// `for="ip:port"`.
+pub const FORWARDED: &str = "Forwarded";
+
+pub struct Limits {
+    pub max_connections: usize,
+    /// Time a client has to send a request, from connecting for the first
+    /// one and from its first byte for the following ones.
+    pub read_timeout: Duration,
+    /// Time a client has to accept more of a response.
+    pub write_timeout: Duration,
+}
+
+/// Counter of the refused requests, by the reason.
+pub type Rejected = Family<[(&'static str, &'static str); 1], Counter>;
+
+/// The public listeners of the HTTP server.
+pub struct Front {
+    listeners: Vec<Listener>,
+    /// Set to serve HTTPS.
+    tls: Option<Arc<ServerConfig>>,
+    limits: Limits,
+    /// The private directory of the socket of the HTTP server.
+    dir: PathBuf,
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Front {
+    /// Binds the public addresses and creates the private directory for the
+    /// socket of the HTTP server.
+    pub fn bind(
+        addrs: &[ListenAddress],
+        permissions: &SocketPermissions,
+        tls: Option<Arc<ServerConfig>>,
+        limits: Limits,
+    ) -> Result<Front, io::Error> {
+        let listeners = addrs
+            .iter()
+            .map(|addr| {
+                listen(addr, permissions)
+                    .map_err(|err| io::Error::new(err.kind(), format!("{addr}: {err}")))
+            })
+            .collect::<Result<_, _>>()?;
+        let dir = private_dir().map_err(|err| {
+            io::Error::new(err.kind(), format!("{}: {err}", env::temp_dir().display()))
+        })?;
+        Ok(Front {
+            listeners,
+            tls,
+            limits,
+            dir,
+        })
+    }
+
+    /// The address for the HTTP server to listen on.
+    pub fn backend(&self) -> ListenAddress {
+        ListenAddress::Unix(self.dir.join("http.sock"))
+    }
+
+    /// The files created for the HTTP server, to be removed on exit.
+    pub fn files(&self) -> Vec<PathBuf> {
+        vec![self.dir.join("http.sock"), self.dir.clone()]
+    }
+
+    /// Hands the private directory and the socket to the user the process
+    /// is about to switch to.
+    pub fn chown(&self, user: &User) -> Result<(), io::Error> {
+        privileges::chown(&self.dir, user)?;
+        if let ListenAddress::Unix(path) = self.backend() {
+            privileges::chown(&path, user)?;
+        }
+        Ok(())
+    }
+
+    /// Accepts connections in the background, relaying each one to the HTTP
+    /// server from its own thread, and counts the refused requests in
+    /// `rejected`.
+    pub fn start(self, rejected: Rejected) {
+        let relay = Arc::new(Relay {
+            tls: self.tls,
+            limits: self.limits,
+            backend: self.dir.join("http.sock"),
+            rejected,
+            connections: AtomicUsize::new(0),
+        });
+        for listener in self.listeners {
+            let relay = relay.clone();
+            thread::spawn(move || accept(listener, relay));
+        }
+    }
+}
+
+/// Creates a directory with a random name under the temporary directory,
+/// which only the exporter can access, like `mkdtemp(3)` does: other users
+/// can neither take the name beforehand nor replace the directory.
+fn private_dir() -> Result<PathBuf, io::Error> {
+    let mut template = env::temp_dir()
+        .join("p1-exporter-XXXXXX")
+        .into_os_string()
+        .into_vec();
+    if template.contains(&0) {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    template.push(0);
+    let created = unsafe { libc::mkdtemp(template.as_mut_ptr() as *mut libc::c_char) };
+    if created.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    template.pop();
+    Ok(PathBuf::from(OsString::from_vec(template)))
+}
+
+fn listen(addr: &ListenAddress, permissions: &SocketPermissions) -> Result<Listener, io::Error> {
+    Ok(match addr {
+        ListenAddress::Tcp(sock) => TcpListener::bind(sock)
+            .map(Listener::Tcp)
+            .map_err(|err| server::bind_error(addr, err))?,
+        ListenAddress::Unix(path) => {
+            server::remove_stale_socket(path)?;
+            let listener = UnixListener::bind(path)?;
+            permissions.apply(path)?;
+            Listener::Unix(listener)
+        }
+        ListenAddress::Systemd(fd) => match systemd::listener(*fd)? {
+            systemd::Listener::Tcp(listener) => Listener::Tcp(listener),
+            systemd::Listener::Unix(listener) => Listener::Unix(listener),
+        },
+    })
+}
+
+/// Accepts the connections of a public listener.
+fn accept(listener: Listener, relay: Arc<Relay>) {
+    loop {
+        let accepted = match &listener {
+            Listener::Tcp(listener) => listener
+                .accept()
+                .map(|(sock, peer)| (Stream::Tcp(sock), Some(peer))),
+            Listener::Unix(listener) => listener
+                .accept()
+                .map(|(sock, _)| (Stream::Unix(sock), None)),
+        };
+        let (mut client, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                tracing::warn!("Failed to accept a connection: {err}");
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+        let name = peer.map_or("unix socket".to_string(), |peer| peer.to_string());
+        if relay.connections.fetch_add(1, Ordering::SeqCst) >= relay.limits.max_connections {
+            relay.connections.fetch_sub(1, Ordering::SeqCst);
+            tracing::debug!("Refusing the connection from {name}: too many connections");
+            relay.count(Rejection::Connections);
+            // Answering over TLS would take a handshake. The response is
+            // written without waiting, so as not to hold up the accepting of
+            // the other connections: it fits in the socket buffer unless the
+            // client stopped reading, which then goes without.
+            if relay.tls.is_none() {
+                let _ = client.set_nonblocking(true);
+                client.drain(Duration::ZERO);
+                let _ = client.write(Rejection::Connections.response().as_bytes());
+                let _ = client.shutdown(Shutdown::Write);
+            }
+            continue;
+        }
+        let relay = relay.clone();
+        thread::spawn(move || {
+            if let Err(err) = relay.relay(client, peer) {
+                tracing::debug!("Connection from {name} failed: {err}");
+            }
+            relay.connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Why a request was refused.
+#[derive(Clone, Copy, Debug)]
+enum Rejection {
+    /// The connection was over [`Limits::max_connections`].
+    Connections,
+    /// The request took longer than [`Limits::read_timeout`].
+    Timeout,
+    /// The request was larger than [`MAX_REQUEST_HEAD`].
+    Size,
+    /// The request had a body, which none of the endpoints takes.
+    Body,
+}
+
+impl Rejection {
+    /// The `reason` label of the rejection.
+    fn reason(self) -> &'static str {
+        match self {
+            Rejection::Connections => "connections",
+            Rejection::Timeout => "timeout",
+            Rejection::Size => "size",
+            Rejection::Body => "body",
+        }
+    }
+
+    /// The response closing the connection.
+    fn response(self) -> String {
+        let (status, body) = match self {
+            Rejection::Connections => ("503 Service Unavailable", "too many connections\n"),
+            Rejection::Timeout => ("408 Request Timeout", "request timeout\n"),
+            Rejection::Size => ("431 Request Header Fields Too Large", "request too large\n"),
+            Rejection::Body => ("413 Content Too Large", "request body not accepted\n"),
+        };
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+}
+
+/// Settings of the connections, shared by the listeners.
+struct Relay {
+    tls: Option<Arc<ServerConfig>>,
+    limits: Limits,
+    /// The socket of the HTTP server.
+    backend: PathBuf,
+    rejected: Rejected,
+    /// Connections being relayed.
+    connections: AtomicUsize,
+}
+
+impl Relay {
+    fn count(&self, rejection: Rejection) {
+        self.rejected
+            .get_or_create(&[("reason", rejection.reason())])
+            .inc();
+    }
+
+    /// Relays a connection to the HTTP server until the server closes its
+    /// end. The HTTP server is only connected to once the first request has
+    /// been read, for HTTPS after the handshake, including the verification
+    /// of the client certificate.
+    fn relay(&self, mut client: Stream, peer: Option<SocketAddr>) -> Result<(), io::Error> {
+        let mut tls = match &self.tls {
+            Some(config) => Some(ServerConnection::new(config.clone()).map_err(io::Error::other)?),
+            None => None,
+        };
+        let mut requests = Requests::new(peer);
+        let mut http: Option<UnixStream> = None;
+        // Requests not yet taken by the HTTP server, which only reads the
+        // next one once it responded to the previous one.
+        let mut pending = Vec::new();
+        let mut client_closed = false;
+        let mut buf = [0; 16 * 1024];
+        loop {
+            self.send(&mut client, tls.as_mut(), &[])?;
+
+            let deadline = requests.deadline(self.limits.read_timeout);
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| deadline <= now) {
+                // Clients that never sent anything are closed silently, like
+                // browsers connecting ahead of a request.
+                if requests.head.is_empty() {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                return self.refuse(client, tls, Rejection::Timeout);
+            }
+            let timeout =
+                deadline.map_or(IDLE_TIMEOUT, |deadline| (deadline - now).min(IDLE_TIMEOUT));
+            let mut fds = [
+                libc::pollfd {
+                    // Clients sending requests faster than they're served
+                    // wait for them.
+                    fd: if client_closed || pending.len() > MAX_REQUEST_HEAD {
+                        -1
+                    } else {
+                        client.as_raw_fd()
+                    },
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: http.as_ref().map_or(-1, |http| http.as_raw_fd()),
+                    events: if pending.is_empty() {
+                        libc::POLLIN
+                    } else {
+                        libc::POLLIN | libc::POLLOUT
+                    },
+                    revents: 0,
+                },
+            ];
+            // SAFETY: fds is a valid array of pollfd for the duration of the
+            // call.
+            let ready = unsafe {
+                libc::poll(
+                    fds.as_mut_ptr(),
+                    fds.len() as libc::nfds_t,
+                    // Rounded up, so that the deadline has passed on waking.
+                    timeout.as_millis() as libc::c_int + 1,
+                )
+            };
+            match ready {
+                -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+                -1 => return Err(io::Error::last_os_error()),
+                0 if deadline.is_some() => continue,
+                0 => return Err(io::ErrorKind::TimedOut.into()),
+                _ => {}
+            }
+
+            if fds[0].revents != 0 {
+                let mut received = Vec::new();
+                match &mut tls {
+                    Some(tls) => {
+                        tls.read_tls(&mut client)?;
+                        if let Err(err) = tls.process_new_packets() {
+                            // Send the alert explaining why the connection is
+                            // closed.
+                            let _ = tls.write_tls(&mut client);
+                            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+                        }
+                        loop {
+                            match tls.reader().read(&mut buf) {
+                                Ok(0) => client_closed = true,
+                                Ok(n) => {
+                                    received.extend_from_slice(&buf[..n]);
+                                    continue;
+                                }
+                                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                                    client_closed = true
+                                }
+                                Err(err) => return Err(err),
+                            }
+                            break;
+                        }
+                    }
+                    None => match client.read(&mut buf)? {
+                        0 => client_closed = true,
+                        n => received.extend_from_slice(&buf[..n]),
+                    },
+                }
+                if let Err(rejection) = requests.read(&received, &mut pending) {
+                    return self.refuse(client, tls, rejection);
+                }
+                if !pending.is_empty() && http.is_none() {
+                    let conn = UnixStream::connect(&self.backend)?;
+                    // Written to as it takes the requests, so that its
+                    // responses are relayed meanwhile.
+                    conn.set_nonblocking(true)?;
+                    http = Some(conn);
+                }
+            }
+
+            if let Some(conn) = &mut http {
+                while !pending.is_empty() {
+                    match conn.write(&pending) {
+                        Ok(n) => drop(pending.drain(..n)),
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+            if client_closed && pending.is_empty() {
+                match &http {
+                    // Let the HTTP server finish its response.
+                    Some(http) => {
+                        let _ = http.shutdown(Shutdown::Write);
+                    }
+                    None => return Ok(()),
+                }
+            }
+
+            if fds[1].revents & !libc::POLLOUT != 0 {
+                if let Some(conn) = &mut http {
+                    let n = match conn.read(&mut buf) {
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                        n => n?,
+                    };
+                    if n == 0 {
+                        if let Some(tls) = &mut tls {
+                            tls.send_close_notify();
+                            self.send(&mut client, Some(tls), &[])?;
+                        }
+                        return Ok(());
+                    }
+                    self.send(&mut client, tls.as_mut(), &buf[..n])?;
+                }
+            }
+        }
+    }
+
+    /// Writes `data` to the client, or with TLS encrypts it and writes all
+    /// that is to be sent. Fails if that takes longer than the write timeout,
+    /// however little the client takes at a time.
+    fn send(
+        &self,
+        client: &mut Stream,
+        tls: Option<&mut ServerConnection>,
+        mut data: &[u8],
+    ) -> Result<(), io::Error> {
+        let deadline = Instant::now() + self.limits.write_timeout;
+        let timed_out = |err: io::Error| match err.kind() {
+            io::ErrorKind::WouldBlock => io::ErrorKind::TimedOut.into(),
+            _ => err,
+        };
+        match tls {
+            Some(tls) => {
+                tls.writer().write_all(data)?;
+                while tls.wants_write() {
+                    client.write_until(deadline)?;
+                    tls.write_tls(client).map_err(timed_out)?;
+                }
+            }
+            None => {
+                while !data.is_empty() {
+                    client.write_until(deadline)?;
+                    match client.write(data).map_err(timed_out)? {
+                        0 => return Err(io::ErrorKind::WriteZero.into()),
+                        n => data = &data[n..],
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers a request that broke the limits and closes the connection.
+    fn refuse(
+        &self,
+        mut client: Stream,
+        tls: Option<ServerConnection>,
+        rejection: Rejection,
+    ) -> Result<(), io::Error> {
+        self.count(rejection);
+        let response = rejection.response();
+        match tls {
+            Some(mut tls) => {
+                tls.writer().write_all(response.as_bytes())?;
+                tls.send_close_notify();
+                self.send(&mut client, Some(&mut tls), &[])?;
+            }
+            None => self.send(&mut client, None, response.as_bytes())?,
+        }
+        let _ = client.shutdown(Shutdown::Write);
+        client.drain(LINGER);
+        Err(io::Error::other(format!(
+            "refused: {}",
+            response.lines().next().unwrap_or_default()
+        )))
+    }
+}
+
+/// The requests read from a client, relayed to the HTTP server once
+/// complete. As requests with a body are refused, all a client sends are
+/// request lines and headers.
+struct Requests {
+    /// Value of the `Forwarded` header added to every request, unless the
+    /// client is connected over a Unix socket.
+    forwarded: Option<String>,
+    /// What has been read of the current request.
+    head: Vec<u8>,
+    /// When the current request started, if one did: on connecting for the
+    /// first one, on its first byte for the following ones.
+    started: Option<Instant>,
+}
+
+impl Requests {
+    fn new(peer: Option<SocketAddr>) -> Requests {
+        Requests {
+            forwarded: peer.map(|peer| format!("for=\"{peer}\"")),
+            head: Vec::new(),
+            started: Some(Instant::now()),
+        }
+    }
+
+    /// Returns when the current request has to be complete.
+    fn deadline(&self, timeout: Duration) -> Option<Instant> {
+        self.started.map(|started| started + timeout)
+    }
+
+    /// Reads `data` from the client and appends the requests it completes to
+    /// `complete`.
+    fn read(&mut self, data: &[u8], complete: &mut Vec<u8>) -> Result<(), Rejection> {
+        if !data.is_empty() && self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+        self.head.extend_from_slice(data);
+        while let Some(end) = self.head.windows(4).position(|end| end == b"\r\n\r\n") {
+            let rest = self.head.split_off(end + 4);
+            let head = std::mem::replace(&mut self.head, rest);
+            if head.len() > MAX_REQUEST_HEAD {
+                return Err(Rejection::Size);
+            }
+            self.forward(&head, complete)?;
+            self.started = (!self.head.is_empty()).then(Instant::now);
+        }
+        if self.head.len() > MAX_REQUEST_HEAD {
+            return Err(Rejection::Size);
+        }
+        Ok(())
+    }
+
+    /// Appends a complete request to `complete`, with the `Forwarded` header
+    /// in place of any the client sent.
+    fn forward(&self, head: &[u8], complete: &mut Vec<u8>) -> Result<(), Rejection> {
+        let head = String::from_utf8_lossy(&head[..head.len() - 4]);
+        let mut lines = head.split("\r\n");
+        let mut request = lines.next().unwrap_or_default().to_string();
+        request.push_str("\r\n");
+        for line in lines {
+            let (name, value) = line.split_once(':').unwrap_or((line, ""));
+            let name = name.trim();
+            let body = match name.to_ascii_lowercase().as_str() {
+                "transfer-encoding" => true,
+                "content-length" => value.trim().parse::<u64>() != Ok(0),
+                _ => false,
+            };
+            if body {
+                return Err(Rejection::Body);
+            }
+            if !name.eq_ignore_ascii_case(FORWARDED) {
+                request.push_str(line);
+                request.push_str("\r\n");
+            }
+        }
+        if let Some(forwarded) = &self.forwarded {
+            request.push_str(&format!("{FORWARDED}: {forwarded}\r\n"));
+        }
+        request.push_str("\r\n");
+        complete.extend_from_slice(request.as_bytes());
+        Ok(())
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(sock) => sock.set_write_timeout(timeout),
+            Stream::Unix(sock) => sock.set_write_timeout(timeout),
+        }
+    }
+
+    /// Sets the write timeout to what is left until `deadline`.
+    fn write_until(&self, deadline: Instant) -> io::Result<()> {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.set_write_timeout(Some(left))
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(sock) => sock.set_nonblocking(nonblocking),
+            Stream::Unix(sock) => sock.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(sock) => sock.shutdown(how),
+            Stream::Unix(sock) => sock.shutdown(how),
+        }
+    }
+
+    /// Reads and discards what the client sends for up to `linger`, or what
+    /// has already arrived if zero, as closing a connection with unread data
+    /// resets it.
+    fn drain(&mut self, linger: Duration) {
+        let timeout = (!linger.is_zero()).then_some(linger);
+        let set = match self {
+            Stream::Tcp(sock) => sock.set_read_timeout(timeout),
+            Stream::Unix(sock) => sock.set_read_timeout(timeout),
+        };
+        if set.is_err() {
+            return;
+        }
+        let started = Instant::now();
+        let mut buf = [0; 4096];
+        let mut drained = 0;
+        while drained < MAX_REQUEST_HEAD && (linger.is_zero() || started.elapsed() <= linger) {
+            match self.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => drained += n,
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(sock) => sock.read(buf),
+            Stream::Unix(sock) => sock.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(sock) => sock.write(buf),
+            Stream::Unix(sock) => sock.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(sock) => sock.as_raw_fd(),
+            Stream::Unix(sock) => sock.as_raw_fd(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_forwarded_once_complete() {
+        let mut requests = Requests::new(Some("192.0.2.1:5000".parse().unwrap()));
+        let mut complete = vec![];
+        requests
+            .read(b"GET /metrics HTTP/1.1\r\nHost: x\r\n", &mut complete)
+            .unwrap();
+        assert!(complete.is_empty());
+        // The rest of the first request, spoofing its origin, and half of
+        // the next one.
+        requests
+            .read(
+                b"Forwarded: for=\"10.0.0.1\"\r\n\r\nGET / HTTP/1.1\r\n",
+                &mut complete,
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(complete.clone()).unwrap(),
+            "GET /metrics HTTP/1.1\r\nHost: x\r\nForwarded: for=\"192.0.2.1:5000\"\r\n\r\n"
+        );
+        assert!(requests.started.is_some());
+        complete.clear();
+        requests.read(b"\r\n", &mut complete).unwrap();
+        assert_eq!(
+            String::from_utf8(complete).unwrap(),
+            "GET / HTTP/1.1\r\nForwarded: for=\"192.0.2.1:5000\"\r\n\r\n"
+        );
+        assert!(requests.started.is_none());
+    }
+
+    #[test]
+    fn requests_over_unix_socket_not_forwarded() {
+        let mut requests = Requests::new(None);
+        let mut complete = vec![];
+        let request = b"GET /metrics HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+        requests.read(request, &mut complete).unwrap();
+        assert_eq!(complete, request);
+    }
+
+    #[test]
+    fn requests_with_body_or_too_large_rejected() {
+        for header in ["Content-Length: 5", "transfer-encoding: chunked"] {
+            let request = format!("POST / HTTP/1.1\r\n{header}\r\n\r\n");
+            let result = Requests::new(None).read(request.as_bytes(), &mut vec![]);
+            assert!(matches!(result, Err(Rejection::Body)), "{header}");
+        }
+        let header = vec![b'a'; MAX_REQUEST_HEAD + 1];
+        let result = Requests::new(None).read(&header, &mut vec![]);
+        assert!(matches!(result, Err(Rejection::Size)));
+    }
+}
@@ -0,0 +1,130 @@
+//! Periodic push of the latest readings to Graphite, with the carbon
+//! plaintext protocol.
+
+use crate::{
+    collector,
+    sink::{Sample, Sink},
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Timeout for connecting to carbon and for every write.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct GraphiteConfig {
+    /// `host:port` of the carbon plaintext receiver, resolved on every
+    /// connection.
+    pub address: String,
+    /// First component of the metric paths.
+    pub prefix: String,
+    pub interval: Duration,
+}
+
+pub struct GraphiteSink {
+    prefix: String,
+    /// The latest value of every metric path.
+    latest: Arc<Mutex<BTreeMap<String, f64>>>,
+}
+
+impl GraphiteSink {
+    pub fn start(config: GraphiteConfig) -> GraphiteSink {
+        let latest = Arc::new(Mutex::new(BTreeMap::new()));
+        let readings = latest.clone();
+        let prefix = config.prefix.clone();
+        thread::spawn(move || {
+            let mut conn: Option<TcpStream> = None;
+            loop {
+                thread::sleep(config.interval);
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let mut lines = String::new();
+                for (path, value) in readings.lock().unwrap().iter() {
+                    let _ = writeln!(lines, "{path} {value} {timestamp}");
+                }
+                if lines.is_empty() {
+                    continue;
+                }
+                if let Err(err) = send(&mut conn, &config.address, &lines) {
+                    tracing::warn!("Failed to send metrics to {}: {err}", config.address);
+                    conn = None;
+                }
+            }
+        });
+        GraphiteSink { prefix, latest }
+    }
+}
+
+impl Sink for GraphiteSink {
+    fn publish(&self, samples: &[Sample]) {
+        let mut latest = self.latest.lock().unwrap();
+        for sample in samples {
+            let path = path(&self.prefix, sample);
+            // Only the active tariff is sampled, the others become inactive.
+            if sample.name == "active_tariff" {
+                if let Some((tariffs, _)) = path.rsplit_once('.') {
+                    let tariffs = format!("{tariffs}.");
+                    for (other, value) in latest.range_mut(tariffs.clone()..) {
+                        if !other.starts_with(&tariffs) {
+                            break;
+                        }
+                        *value = 0.0;
+                    }
+                }
+            }
+            latest.insert(path, sample.value);
+        }
+    }
+}
+
+/// Writes `lines` to carbon, connecting first if not connected yet.
+fn send(conn: &mut Option<TcpStream>, address: &str, lines: &str) -> Result<(), io::Error> {
+    let sock = match conn {
+        Some(sock) => sock,
+        None => {
+            let sock = collector::connect(address, TIMEOUT)?;
+            sock.set_write_timeout(Some(TIMEOUT))?;
+            conn.insert(sock)
+        }
+    };
+    sock.write_all(lines.as_bytes())?;
+    sock.flush()
+}
+
+/// Returns the metric path of a sample: `prefix.[meter.]name[.label values]`,
+/// leaving out the equipment identifier of M-Bus devices.
+fn path(prefix: &str, sample: &Sample) -> String {
+    let mut path = prefix.to_string();
+    if let Some((_, meter)) = sample.labels.iter().find(|(name, _)| *name == "meter") {
+        path.push('.');
+        path.push_str(&sanitize(meter));
+    }
+    path.push('.');
+    path.push_str(sample.name);
+    for (name, value) in &sample.labels {
+        if !matches!(*name, "meter" | "equipment_id") {
+            path.push('.');
+            path.push_str(&sanitize(value));
+        }
+    }
+    path
+}
+
+/// Replaces the characters that separate path components or lines.
+fn sanitize(component: &str) -> String {
+    component
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
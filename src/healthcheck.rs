@@ -0,0 +1,106 @@
+//! Health check of a running exporter, for container HEALTHCHECKs without
+//! curl in the image.
+
+use crate::server::ListenAddress;
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    os::unix::net::UnixStream,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Timeout for connecting to the exporter and for every read and write.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Scrapes the exporter listening on `addr`, with the bearer `token` if any,
+/// and checks that every meter has received a telegram within `max_age`.
+/// `prefix` is that of the exporter's metric names.
+pub fn run(
+    addr: &ListenAddress,
+    prefix: &str,
+    max_age: Duration,
+    token: Option<&str>,
+) -> Result<(), String> {
+    let metric = format!("{prefix}_last_telegram_timestamp_seconds");
+    let body = scrape(addr, token).map_err(|err| format!("Failed to scrape {addr}: {err}"))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+
+    let mut meters = 0;
+    for line in body.lines() {
+        let Some(series) = line.strip_prefix(&metric) else {
+            continue;
+        };
+        let Some((labels, value)) = series.rsplit_once(' ') else {
+            continue;
+        };
+        if !(labels.is_empty() || labels.starts_with('{')) {
+            continue;
+        }
+        let meter = match labels {
+            "" | "{}" => "the meter".to_string(),
+            labels => format!("meter {labels}"),
+        };
+        let timestamp: f64 = value
+            .parse()
+            .map_err(|_| format!("Invalid timestamp in '{line}'"))?;
+        if timestamp == 0.0 {
+            return Err(format!("No telegram received yet from {meter}"));
+        }
+        let age = Duration::from_secs_f64((now - timestamp).max(0.0));
+        if age > max_age {
+            return Err(format!("Last telegram from {meter} is {age:.0?} old"));
+        }
+        meters += 1;
+    }
+    if meters == 0 {
+        return Err(format!("{addr} doesn't export {metric}"));
+    }
+    Ok(())
+}
+
+fn scrape(addr: &ListenAddress, token: Option<&str>) -> Result<String, io::Error> {
+    let response = match addr {
+        ListenAddress::Tcp(addr) => {
+            let sock = TcpStream::connect_timeout(addr, TIMEOUT)?;
+            sock.set_read_timeout(Some(TIMEOUT))?;
+            sock.set_write_timeout(Some(TIMEOUT))?;
+            get(sock, token)?
+        }
+        ListenAddress::Unix(path) => {
+            let sock = UnixStream::connect(path)?;
+            sock.set_read_timeout(Some(TIMEOUT))?;
+            sock.set_write_timeout(Some(TIMEOUT))?;
+            get(sock, token)?
+        }
+        ListenAddress::Systemd(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't connect to a socket passed by systemd",
+            ))
+        }
+    };
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split(' ').nth(1) != Some("200") {
+        return Err(io::Error::other(format!("unexpected response '{status}'")));
+    }
+    Ok(body.to_string())
+}
+
+fn get(mut sock: impl Read + Write, token: Option<&str>) -> Result<String, io::Error> {
+    let mut request =
+        "GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n".to_string();
+    if let Some(token) = token {
+        request.push_str(&format!("Authorization: Bearer {token}\r\n"));
+    }
+    request.push_str("\r\n");
+    sock.write_all(request.as_bytes())?;
+    let mut response = String::new();
+    sock.read_to_string(&mut response)?;
+    Ok(response)
+}
@@ -0,0 +1,75 @@
+//! Polling of the telegrams from the local API of HomeWizard Wi-Fi P1 meters,
+//! which don't serve them over TCP.
+
+use crate::{
+    http::{Client, Url},
+    telegram::TelegramReader,
+};
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+/// Polls the meter at `url` every `interval`, reading the telegrams it
+/// returns as a continuous stream. Reads fail once the meter hasn't responded
+/// or returned a new telegram for `timeout`.
+pub fn open(
+    url: &Url,
+    interval: Duration,
+    timeout: Duration,
+) -> Result<TelegramReader<impl FnMut() -> io::Result<Vec<u8>>>, io::Error> {
+    let mut poller = Poller {
+        client: Client::new(url, timeout)?,
+        url: url.join("api/v1/telegram"),
+        interval,
+        timeout,
+        polled: None,
+        last: Vec::new(),
+    };
+    Ok(TelegramReader::new(move || poller.poll()))
+}
+
+struct Poller {
+    client: Client,
+    url: Url,
+    interval: Duration,
+    timeout: Duration,
+    /// When the telegram was last requested.
+    polled: Option<Instant>,
+    /// The telegram last returned.
+    last: Vec<u8>,
+}
+
+impl Poller {
+    /// Polls until the meter returns a telegram other than the last one,
+    /// which it keeps returning until it receives the next.
+    fn poll(&mut self) -> Result<Vec<u8>, io::Error> {
+        let started = Instant::now();
+        loop {
+            if let Some(polled) = self.polled {
+                thread::sleep(self.interval.saturating_sub(polled.elapsed()));
+            }
+            self.polled = Some(Instant::now());
+            let response = self.client.send("GET", &self.url, &[], &[])?;
+            if response.status != 200 {
+                return Err(io::Error::other(format!(
+                    "unexpected status {} from {}: {}",
+                    response.status, self.url, response.body
+                )));
+            }
+            // The body is trimmed, the line end after the CRC included.
+            let mut telegram = response.body.into_bytes();
+            telegram.extend_from_slice(b"\r\n");
+            if telegram != self.last {
+                self.last.clone_from(&telegram);
+                return Ok(telegram);
+            }
+            if started.elapsed() >= self.timeout {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the meter didn't return a new telegram",
+                ));
+            }
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! A minimal HTTP/1.1 client for pushing metrics and fetching prices.
+
+use crate::{collector, tls};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    str::FromStr,
+    time::Duration,
+};
+
+/// URL of an HTTP or HTTPS endpoint.
+#[derive(Clone, Debug)]
+pub struct Url {
+    https: bool,
+    /// The host with the port, if given.
+    authority: String,
+    path: String,
+}
+
+impl Url {
+    /// Returns the URL with `path` appended to its path.
+    pub fn join(&self, path: &str) -> Url {
+        Url {
+            path: format!("{}/{}", self.path.trim_end_matches('/'), path),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the `host:port` to connect to.
+    fn addr(&self) -> String {
+        let port = self.authority.rsplit_once(':').map(|(_, port)| port);
+        match port {
+            Some(port) if !port.contains(']') => self.authority.clone(),
+            _ if self.https => format!("{}:443", self.authority),
+            _ => format!("{}:80", self.authority),
+        }
+    }
+}
+
+impl FromStr for Url {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (https, rest) = if let Some(rest) = s.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = s.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!("invalid URL '{s}', expected http:// or https://"));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(format!("invalid URL '{s}', missing host"));
+        }
+        Ok(Url {
+            https,
+            authority: authority.to_string(),
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.https { "https" } else { "http" };
+        write!(f, "{scheme}://{}{}", self.authority, self.path)
+    }
+}
+
+/// A response, with the body as sent by the server.
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+pub struct Client {
+    /// Set for HTTPS URLs.
+    tls: Option<tls::Client>,
+    timeout: Duration,
+}
+
+impl Client {
+    /// Creates a client for `url`, trusting the system's CAs if it's an
+    /// HTTPS one. `timeout` applies to connecting and to every read and
+    /// write.
+    pub fn new(url: &Url, timeout: Duration) -> Result<Client, io::Error> {
+        let tls = if url.https {
+            Some(tls::Client::load(None, None, None)?)
+        } else {
+            None
+        };
+        Ok(Client { tls, timeout })
+    }
+
+    /// Sends a request with the given headers and body, over a new
+    /// connection.
+    pub fn send(
+        &self,
+        method: &str,
+        url: &Url,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Response, io::Error> {
+        let addr = url.addr();
+        let sock = collector::connect(&addr, self.timeout)?;
+        sock.set_read_timeout(Some(self.timeout))?;
+        sock.set_write_timeout(Some(self.timeout))?;
+        let mut request = format!(
+            "{method} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            url.path,
+            url.authority,
+            body.len()
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str("\r\n");
+        let mut request = request.into_bytes();
+        request.extend_from_slice(body);
+        match &self.tls {
+            Some(tls) => exchange(tls.connect(sock, &addr)?, &request),
+            None => exchange(sock, &request),
+        }
+    }
+}
+
+fn exchange(mut sock: impl Read + Write, request: &[u8]) -> Result<Response, io::Error> {
+    sock.write_all(request)?;
+    sock.flush()?;
+    let mut response = Vec::new();
+    match sock.read_to_end(&mut response) {
+        Ok(_) => {}
+        // Servers closing TLS connections without close_notify are common.
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && !response.is_empty() => {}
+        Err(err) => return Err(err),
+    }
+    let (head, body) = match response.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(i) => (&response[..i], &response[i + 4..]),
+        None => (&response[..], &[][..]),
+    };
+    let head = String::from_utf8_lossy(head);
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response");
+    let status = head
+        .lines()
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(malformed)?;
+    let chunked = head.lines().any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.eq_ignore_ascii_case("transfer-encoding") && value.trim() == "chunked"
+        })
+    });
+    let body = if chunked {
+        dechunk(body).ok_or_else(malformed)?
+    } else {
+        body.to_vec()
+    };
+    Ok(Response {
+        status,
+        body: String::from_utf8_lossy(&body).trim().to_string(),
+    })
+}
+
+/// Decodes a body sent with the chunked transfer encoding.
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        // Chunk extensions follow the size after a semicolon.
+        let size = size.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+/// Percent-encodes `s` for use as a path segment.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                escaped.push(b as char)
+            }
+            _ => escaped.push_str(&format!("%{b:02X}")),
+        }
+    }
+    escaped
+}
+
+/// Encodes `data` in base64, with the URL and filename safe alphabet if
+/// `url_safe`.
+pub fn base64_encode(data: &[u8], url_safe: bool) -> String {
+    let alphabet: &[u8] = if url_safe {
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_"
+    } else {
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+    };
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | u32::from(b) << (16 - 8 * i));
+        for i in 0..chunk.len() + 1 {
+            out.push(alphabet[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+        for _ in chunk.len()..3 {
+            out.push('=');
+        }
+    }
+    out
+}
@@ -0,0 +1,194 @@
+//! Writing of the readings of every telegram to InfluxDB 2.x, in its line
+//! protocol.
+
+use crate::{
+    http::{self, Client, Url},
+    metrics::ConstLabel,
+    sink::{Kind, Sample, Sink},
+};
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Timeout for connecting to InfluxDB and for every read and write.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Telegrams kept while InfluxDB is unreachable, the oldest are dropped
+/// beyond that. About an hour of telegrams every second.
+const MAX_PENDING: usize = 3600;
+/// Delay before retrying the telegrams InfluxDB failed to take.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+pub struct InfluxDbConfig {
+    /// Base URL of the InfluxDB instance.
+    pub url: Url,
+    pub org: String,
+    pub bucket: String,
+    /// API token with write access to the bucket.
+    pub token: String,
+    /// Prefix of the measurement names.
+    pub prefix: String,
+    /// Constant labels of all series, written as tags.
+    pub labels: Vec<ConstLabel>,
+}
+
+/// Lines of the telegrams waiting to be written, the oldest first.
+#[derive(Default)]
+struct Pending {
+    telegrams: Mutex<VecDeque<String>>,
+    received: Condvar,
+}
+
+pub struct InfluxDbSink {
+    pending: Arc<Pending>,
+    prefix: String,
+    labels: Vec<ConstLabel>,
+}
+
+impl InfluxDbSink {
+    pub fn start(config: InfluxDbConfig) -> Result<Self, io::Error> {
+        let client = Client::new(&config.url, TIMEOUT)?;
+        let url = config.url.join(&format!(
+            "api/v2/write?org={}&bucket={}&precision=ms",
+            http::escape(&config.org),
+            http::escape(&config.bucket)
+        ));
+        let authorization = format!("Token {}", config.token);
+        let pending = Arc::new(Pending::default());
+        let queue = pending.clone();
+        thread::spawn(move || loop {
+            // Everything received meanwhile is written in one request.
+            let batch: Vec<String> = {
+                let telegrams = queue.telegrams.lock().unwrap();
+                let mut telegrams = queue
+                    .received
+                    .wait_while(telegrams, |telegrams| telegrams.is_empty())
+                    .unwrap();
+                telegrams.drain(..).collect()
+            };
+            match send(&client, &url, &authorization, &batch.concat()) {
+                Ok(()) => {}
+                Err(Failure::Retry(err)) => {
+                    tracing::warn!(
+                        "Failed to write readings to {}, retrying: {err}",
+                        config.url
+                    );
+                    let mut telegrams = queue.telegrams.lock().unwrap();
+                    for telegram in batch.into_iter().rev() {
+                        telegrams.push_front(telegram);
+                    }
+                    let excess = telegrams.len().saturating_sub(MAX_PENDING);
+                    telegrams.drain(..excess);
+                    drop(telegrams);
+                    thread::sleep(RETRY_DELAY);
+                }
+                Err(Failure::Drop(err)) => {
+                    tracing::warn!("Failed to write readings to {}: {err}", config.url);
+                }
+            }
+        });
+        Ok(InfluxDbSink {
+            pending,
+            prefix: config.prefix,
+            labels: config.labels,
+        })
+    }
+}
+
+impl Sink for InfluxDbSink {
+    fn publish(&self, samples: &[Sample]) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let lines = lines(samples, &self.prefix, &self.labels, timestamp);
+        if lines.is_empty() {
+            return;
+        }
+        let mut telegrams = self.pending.telegrams.lock().unwrap();
+        telegrams.push_back(lines);
+        let excess = telegrams.len().saturating_sub(MAX_PENDING);
+        telegrams.drain(..excess);
+        self.pending.received.notify_one();
+    }
+}
+
+/// Returns the line protocol of the samples of a telegram: a measurement
+/// named like the exported metric per sample, with its labels and the
+/// constant ones as tags and the reading in the `value` field.
+fn lines(samples: &[Sample], prefix: &str, labels: &[ConstLabel], timestamp: u128) -> String {
+    let mut lines = String::new();
+    for sample in samples {
+        // InfluxDB rejects the whole request for a non-finite value.
+        if !sample.value.is_finite() {
+            continue;
+        }
+        let name = match sample.kind {
+            Kind::Gauge => format!("{prefix}_{}", sample.name),
+            Kind::Counter => format!("{prefix}_{}_total", sample.name),
+        };
+        lines.push_str(&escape(&name, &[',', ' ']));
+        let mut tags: Vec<(&str, &str)> = sample
+            .labels
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .chain(
+                labels
+                    .iter()
+                    .map(|label| (label.name.as_str(), label.value.as_str())),
+            )
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+        // Sorted as InfluxDB recommends for its performance.
+        tags.sort();
+        for (name, value) in tags {
+            lines.push(',');
+            lines.push_str(&escape(name, &[',', '=', ' ']));
+            lines.push('=');
+            lines.push_str(&escape(value, &[',', '=', ' ']));
+        }
+        lines.push_str(&format!(" value={} {timestamp}\n", sample.value));
+    }
+    lines
+}
+
+/// Escapes the characters of a measurement or tag that end it, and the
+/// backslash.
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        // Line ends can't be escaped, they end the line.
+        let c = if c == '\n' { ' ' } else { c };
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+enum Failure {
+    /// The request may succeed later, e.g. InfluxDB is down.
+    Retry(io::Error),
+    /// InfluxDB rejected the lines.
+    Drop(io::Error),
+}
+
+fn send(client: &Client, url: &Url, authorization: &str, body: &str) -> Result<(), Failure> {
+    let headers = [
+        ("Content-Type", "text/plain; charset=utf-8"),
+        ("Authorization", authorization),
+    ];
+    let response = client
+        .send("POST", url, &headers, body.as_bytes())
+        .map_err(Failure::Retry)?;
+    let err = || io::Error::other(format!("status {}: {}", response.status, response.body));
+    match response.status {
+        200..=299 => Ok(()),
+        429 | 500..=599 => Err(Failure::Retry(err())),
+        _ => Err(Failure::Drop(err())),
+    }
+}
@@ -0,0 +1,82 @@
+//! Collection of the readings of DSMR (P1) smart meters into Prometheus
+//! metrics.
+//!
+//! The `p1-exporter` binary is built on this library, which other programs
+//! can use to embed the collection into their own registry and HTTP server:
+//! [`metrics::P1Metrics`] holds the metric families and registers them,
+//! [`collector::start_metrics_collector`] reads the telegrams of a meter
+//! from one of the [`collector::Input`]s in the background, and
+//! [`reload::Collectors`] manages the collectors of several meters.
+//!
+//! ```no_run
+//! use p1_exporter::{
+//!     collector::{start_metrics_collector, CollectorConfig, Input},
+//!     metrics::{MetricGroup, P1Metrics, PowerUnit},
+//! };
+//! use prometheus_client::registry::Registry;
+//! use std::sync::Arc;
+//!
+//! let mut registry = Registry::default();
+//! let metrics = P1Metrics::new(&[], None);
+//! metrics.register(&mut registry, "p1", &[], &MetricGroup::ALL, PowerUnit::Kilowatts);
+//! let collector = start_metrics_collector(
+//!     Input::Tcp("192.168.1.10:2000".to_string()),
+//!     metrics.meter(None),
+//!     Arc::new(CollectorConfig::default()),
+//! );
+//! ```
+//!
+//! The remaining modules implement the exporter's outputs, its HTTP server
+//! and the helpers of its command line.
+
+pub mod archive;
+pub mod auth;
+pub mod check;
+pub mod cleanup;
+pub mod collector;
+pub mod compat;
+pub mod completions;
+pub mod config;
+pub mod cost;
+pub mod decrypt;
+pub mod dlms;
+pub mod dsmr_reader;
+pub mod events;
+pub mod exposition;
+pub mod front;
+pub mod graphite;
+pub mod healthcheck;
+pub mod homewizard;
+pub mod http;
+pub mod influxdb;
+pub mod logging;
+pub mod man;
+pub mod metrics;
+pub mod mqtt;
+pub mod obis;
+pub mod otlp;
+pub mod parse;
+pub mod prices;
+pub mod privileges;
+pub mod procfs;
+pub mod protobuf;
+pub mod proxy;
+pub mod push;
+pub mod record;
+pub mod reload;
+pub mod remote_write;
+pub mod replay;
+pub mod serial;
+pub mod server;
+pub mod simulate;
+pub mod sink;
+pub mod sml;
+#[cfg(feature = "statsd")]
+pub mod statsd;
+pub mod systemd;
+pub mod telegram;
+pub mod textfile;
+pub mod tls;
+pub mod tui;
+pub mod udp;
+pub mod zabbix;
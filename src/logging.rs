@@ -0,0 +1,55 @@
+//! Logging to stderr with `tracing`.
+
+use std::{env, io, str::FromStr};
+use tracing::{level_filters::LevelFilter, Level};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Format {
+    Text,
+    /// One JSON object per line, with the fields of the event and its span.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown log format '{s}', expected text or json")),
+        }
+    }
+}
+
+pub fn parse_level(s: &str) -> Result<LevelFilter, String> {
+    s.parse().map_err(|_| format!("invalid log level '{s}'"))
+}
+
+pub fn parse_access_level(s: &str) -> Result<Level, String> {
+    s.parse().map_err(|_| format!("invalid log level '{s}'"))
+}
+
+/// Logs at `level`, unless `RUST_LOG` is set, which then takes precedence.
+///
+/// Dependencies are only heard at debug level, rustls warns about every TLS
+/// client it rejects.
+pub fn init(level: LevelFilter, format: Format) {
+    let filter = match env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) if !directives.is_empty() => EnvFilter::new(directives),
+        _ if level >= LevelFilter::DEBUG => EnvFilter::new(level.to_string()),
+        _ => EnvFilter::new(format!("off,{}={level}", env!("CARGO_CRATE_NAME"))),
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr);
+    match format {
+        Format::Text => subscriber.without_time().with_target(false).init(),
+        Format::Json => subscriber
+            .json()
+            .flatten_event(true)
+            .with_span_list(false)
+            .init(),
+    }
+}
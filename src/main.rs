@@ -1,202 +1,2032 @@
-use clap::Parser;
-use dsmr5::state::Slave;
-use prometheus_client::{
-    encoding::text::encode,
-    metrics::{counter::Counter, family::Family, gauge::Gauge},
-    registry::Registry,
+use auth::Auth;
+use clap::{CommandFactory, Parser};
+use collector::{
+    CollectionMode, CollectorConfig, DsmrVersion, Input, Inputs, OnDemand, P1Format, PeakTimezone,
+    ReaderAddress, TariffNames,
 };
+use metrics::{ConstLabel, MetricGroup, P1Metrics, PowerUnit};
+use obis::ExtraObis;
+#[cfg(feature = "statsd")]
+use p1_exporter::statsd;
+use p1_exporter::{
+    archive, auth, check, cleanup, collector, completions, config, cost, decrypt, dsmr_reader,
+    front, graphite, healthcheck, http, influxdb, logging, man, metrics, mqtt, obis, otlp, parse,
+    prices, privileges, proxy, push, record, reload, remote_write, replay, serial, server,
+    simulate, sink, systemd, textfile, tls, tui, zabbix,
+};
+use privileges::{Group, User};
+use prometheus_client::registry::Registry;
+use reload::{Collectors, Reload};
+use replay::ReplaySpeed;
+use serial::{FlowControl, Parity, SerialConfig};
+use server::{run_metrics_server, ListenAddress, ServerConfig, SocketPermissions};
 use std::{
-    io::{self, Read},
-    net::{SocketAddr, TcpStream},
-    sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
-    },
-    thread,
+    collections::HashSet,
+    fs, io,
+    net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    process,
+    sync::Arc,
     time::Duration,
 };
-use tiny_http::{Response, Server};
+use tracing::level_filters::LevelFilter;
+
+#[derive(Parser)]
+#[clap(
+    author,
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+#[clap(group(clap::ArgGroup::new("input").required(true).args(["p1_address", "serial_device", "p1_udp_listen", "p1_mqtt_url", "p1_homewizard_url", "p1_source", "replay"])))]
+struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_CONFIG",
+        value_name = "FILE",
+        help = "Read options from a TOML file; command line options take precedence"
+    )]
+    config: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_CHECK_CONFIG",
+        help = "Validate the options, read the files they refer to and resolve the P1 readers, then print the effective options and exit without starting"
+    )]
+    check_config: bool,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PRINT_CONFIG",
+        help = "Print the effective options in the format of the configuration file, secrets left out, then exit"
+    )]
+    print_config: bool,
+    #[clap(
+        short,
+        long,
+        env = "P1_EXPORTER_ADDRESS",
+        value_delimiter = ',',
+        help = "Address to listen on, either host:port or a Unix socket path (absolute or prefixed with unix:); repeated to listen on several addresses",
+        default_value = "127.0.0.1:4545"
+    )]
+    address: Vec<ListenAddress>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_SOCKET_MODE",
+        value_name = "MODE",
+        value_parser = server::parse_mode,
+        help = "Octal permissions of the Unix socket to listen on, e.g. 660"
+    )]
+    web_socket_mode: Option<u32>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_SOCKET_GROUP",
+        value_name = "GROUP",
+        help = "Group to own the Unix socket to listen on, e.g. that of a reverse proxy"
+    )]
+    web_socket_group: Option<Group>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_SYSTEMD_SOCKET",
+        conflicts_with = "address",
+        help = "Listen on the socket passed by systemd socket activation instead of --address"
+    )]
+    web_systemd_socket: bool,
+    #[clap(
+        short,
+        long,
+        env = "P1_EXPORTER_P1_ADDRESS",
+        value_name = "[METER=]ADDRESS",
+        value_delimiter = ',',
+        help = "P1 reader host:port, resolved on every connection; repeated for several meters, METER sets the meter label"
+    )]
+    p1_address: Vec<ReaderAddress>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_BACKUP_ADDRESS",
+        value_name = "[METER=]ADDRESS",
+        value_delimiter = ',',
+        help = "Backup P1 reader host:port of the meter, read while the primary reader fails; repeated for several backups, tried in order"
+    )]
+    p1_backup_address: Vec<ReaderAddress>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_FAILBACK_INTERVAL",
+        help = "How long a backup P1 reader is read before trying the primary one again",
+        default_value = "5m",
+        value_parser = parse_nonzero_duration
+    )]
+    p1_failback_interval: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MAX_CONSECUTIVE_ERRORS",
+        help = "Consider a P1 reader failed after this many connections in a row without a telegram",
+        default_value = "10",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    max_consecutive_errors: u32,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_EXIT_ON_COLLECTOR_FAILURE",
+        help = "Exit with an error once a P1 reader failed, for the service manager to restart the exporter"
+    )]
+    exit_on_collector_failure: bool,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_TLS",
+        help = "Connect to the P1 readers over TLS, e.g. to ser2net behind stunnel"
+    )]
+    p1_tls: bool,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_TLS_CA",
+        value_name = "FILE",
+        requires = "p1_tls",
+        help = "Trust the CAs in the PEM-encoded bundle instead of the system's for --p1-tls"
+    )]
+    p1_tls_ca: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_TLS_SERVER_NAME",
+        value_name = "NAME",
+        requires = "p1_tls",
+        help = "Name to send as SNI and to verify the P1 reader's certificate against, instead of its host"
+    )]
+    p1_tls_server_name: Option<String>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_TLS_CERT",
+        value_name = "FILE",
+        requires_all = ["p1_tls", "p1_tls_key"],
+        help = "Authenticate to the P1 readers with the PEM-encoded client certificate chain"
+    )]
+    p1_tls_cert: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_TLS_KEY",
+        value_name = "FILE",
+        requires = "p1_tls_cert",
+        help = "PEM-encoded private key of the --p1-tls-cert certificate"
+    )]
+    p1_tls_key: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_UDP_LISTEN",
+        help = "Receive telegrams sent as UDP datagrams on the given address instead of connecting to a P1 reader"
+    )]
+    p1_udp_listen: Option<SocketAddr>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_MQTT_URL",
+        requires = "p1_mqtt_topic",
+        help = "Receive the telegrams published by a P1 dongle to the MQTT broker at mqtt://HOST[:PORT] or, with TLS, mqtts://HOST[:PORT]"
+    )]
+    p1_mqtt_url: Option<mqtt::BrokerAddress>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_MQTT_TOPIC",
+        requires = "p1_mqtt_url",
+        help = "MQTT topic the P1 dongle publishes the raw telegrams to"
+    )]
+    p1_mqtt_topic: Option<String>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_MQTT_USERNAME",
+        requires = "p1_mqtt_password_file",
+        help = "Authenticate to the MQTT broker of --p1-mqtt-url as this user"
+    )]
+    p1_mqtt_username: Option<String>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_MQTT_PASSWORD_FILE",
+        value_name = "FILE",
+        requires = "p1_mqtt_username",
+        help = "File with the password of --p1-mqtt-username"
+    )]
+    p1_mqtt_password_file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_HOMEWIZARD_URL",
+        help = "Poll the telegrams from the local API of the HomeWizard Wi-Fi P1 meter at the given URL, e.g. http://192.168.1.50"
+    )]
+    p1_homewizard_url: Option<http::Url>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_HOMEWIZARD_INTERVAL",
+        help = "Interval between polls of the HomeWizard P1 meter",
+        default_value = "1s",
+        value_parser = parse_nonzero_duration
+    )]
+    p1_homewizard_interval: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_SOURCE",
+        value_name = "stdin|PATH",
+        help = "Read telegrams from stdin or a file or FIFO, which is reopened on EOF"
+    )]
+    p1_source: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_REPLAY",
+        value_name = "CAPTURE",
+        help = "Replay the raw telegrams of a capture file instead of reading from a P1 reader"
+    )]
+    replay: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_REPLAY_SPEED",
+        help = "Speed of --replay relative to the telegram timestamps, e.g. 10x, or as-fast-as-possible",
+        default_value = "1x"
+    )]
+    replay_speed: ReplaySpeed,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_SERIAL_DEVICE",
+        help = "Serial port of a P1 cable to read from instead of a P1 reader"
+    )]
+    serial_device: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_SERIAL_BAUD_RATE",
+        help = "Baud rate of the serial port [default: 115200, or 9600 with --dsmr-version 2.2 or 3]",
+        value_parser = serial::parse_baud_rate
+    )]
+    serial_baud_rate: Option<u32>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_SERIAL_PARITY",
+        help = "Parity of the serial port (none, even, odd) [default: none, or even with --dsmr-version 2.2 or 3]"
+    )]
+    serial_parity: Option<Parity>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_SERIAL_DATA_BITS",
+        help = "Data bits of the serial port (7 or 8), e.g. 8 for the 8E1 of Norwegian HAN ports [default: 8 without parity, 7 with]",
+        value_parser = clap::value_parser!(u8).range(7..=8)
+    )]
+    serial_data_bits: Option<u8>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_SERIAL_FLOW_CONTROL",
+        help = "Flow control of the serial port (none, rtscts, xonxoff)",
+        default_value = "none"
+    )]
+    serial_flow_control: FlowControl,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_P1_FORMAT",
+        alias = "protocol",
+        help = "What the meters send: DSMR telegrams (dsmr), DLMS/COSEM push frames (dlms) like Austrian and Norwegian meters, or SML (sml) like German meters",
+        default_value = "dsmr"
+    )]
+    p1_format: P1Format,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_DSMR_VERSION",
+        help = "DSMR version of the meters (auto, 2.2, 3, 4, 5): with auto, telegrams without checksum are read as of DSMR 2.2 and 3 and those of Swedish HAN ports upgraded, while 4 and 5 require DSMR 4 or 5 telegrams",
+        default_value = "auto"
+    )]
+    dsmr_version: DsmrVersion,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_DECRYPTION_KEY",
+        value_name = "KEY",
+        hide_env_values = true,
+        help = "Decrypt the telegrams or push frames of meters encrypting them (e.g. Luxembourg's Smarty, Austrian meters) with the given key, as 32 hexadecimal digits"
+    )]
+    decryption_key: Option<decrypt::Key>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_AUTHENTICATION_KEY",
+        value_name = "KEY",
+        hide_env_values = true,
+        help = "Authentication key of the encrypted telegrams or push frames",
+        default_value = decrypt::SMARTY_AUTHENTICATION_KEY
+    )]
+    authentication_key: decrypt::Key,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_GAS_SCALE",
+        help = "Scale factor applied to gas readings [default: derived from the reported unit, or 1.0]"
+    )]
+    gas_scale: Option<f64>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_ENERGY_SCALE",
+        help = "Scale factor converting energy readings to kWh [default: derived from the reported unit, or 1.0]"
+    )]
+    energy_scale: Option<f64>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_EXPECTED_MBUS_CHANNELS",
+        value_name = "CHANNELS",
+        value_delimiter = ',',
+        value_parser = clap::value_parser!(u8).range(1..=4),
+        help = "Comma-separated list of M-Bus channels expected to have a device attached"
+    )]
+    expected_mbus_channels: Vec<u8>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MBUS_CHANNELS",
+        value_name = "CHANNELS",
+        value_delimiter = ',',
+        value_parser = clap::value_parser!(u8).range(1..=4),
+        help = "Comma-separated list of M-Bus channels to export [default: all]"
+    )]
+    mbus_channels: Vec<u8>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_TARIFF_NAMES",
+        value_name = "TARIFF=NAME,...",
+        help = "Names of the tariffs used for the tariff label",
+        default_value = "1=low,2=high"
+    )]
+    tariff_names: TariffNames,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_ENERGY_PRICES",
+        value_name = "TARIFF=PRICE,...",
+        conflicts_with = "price_source",
+        help = "Prices per consumed kWh by tariff number, for p1_energy_cost_total"
+    )]
+    energy_prices: Option<cost::EnergyPrices>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_GAS_PRICE",
+        value_name = "PRICE",
+        help = "Price per consumed m³ of gas, for p1_gas_cost_total",
+        value_parser = cost::parse_price
+    )]
+    gas_price: Option<f64>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_ENERGY_DAILY_CHARGE",
+        value_name = "PRICE",
+        help = "Fixed daily charge added to p1_energy_cost_total",
+        default_value = "0",
+        value_parser = cost::parse_price
+    )]
+    energy_daily_charge: f64,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_GAS_DAILY_CHARGE",
+        value_name = "PRICE",
+        help = "Fixed daily charge added to p1_gas_cost_total",
+        default_value = "0",
+        value_parser = cost::parse_price
+    )]
+    gas_daily_charge: f64,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PRICE_SOURCE",
+        value_name = "entsoe|tibber",
+        requires = "price_api_token_file",
+        help = "Fetch the prices per kWh of a dynamic contract for p1_energy_cost_total"
+    )]
+    price_source: Option<prices::PriceSource>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PRICE_API_TOKEN_FILE",
+        value_name = "FILE",
+        requires = "price_source",
+        help = "File with the API token of --price-source"
+    )]
+    price_api_token_file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PRICE_AREA",
+        value_name = "EIC",
+        requires = "price_source",
+        help = "EIC code of the bidding zone of --price-source entsoe, e.g. 10YNL----------L"
+    )]
+    price_area: Option<String>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PRICE_MARKUP",
+        value_name = "PRICE",
+        allow_negative_numbers = true,
+        help = "Added to the prices of --price-source per kWh, e.g. taxes and the supplier's margin",
+        default_value = "0"
+    )]
+    price_markup: f64,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PRICE_REFRESH_INTERVAL",
+        help = "Interval of fetching the prices of --price-source",
+        default_value = "1h",
+        value_parser = parse_nonzero_duration
+    )]
+    price_refresh_interval: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_METRIC_PREFIX",
+        help = "Prefix of the names of the exported metrics, but for --extra-obis ones",
+        default_value = "p1",
+        value_parser = parse_metric_prefix
+    )]
+    metric_prefix: String,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_POWER_UNIT",
+        value_name = "watts|kilowatts",
+        help = "Units of the power and energy metrics: watts and joules, or kW and kWh",
+        default_value = "kilowatts"
+    )]
+    power_unit: PowerUnit,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PEAK_TIMEZONE",
+        value_name = "meter|local|utc",
+        help = "Clock whose days and months the power peaks start over with",
+        default_value = "meter"
+    )]
+    peak_timezone: PeakTimezone,
+    #[clap(
+        long = "label",
+        env = "P1_EXPORTER_LABEL",
+        value_name = "NAME=VALUE",
+        help = "Add a constant label to every exported metric, e.g. site=garage (repeatable)"
+    )]
+    labels: Vec<ConstLabel>,
+    #[clap(
+        long = "collector.enable",
+        alias = "collector-enable",
+        env = "P1_EXPORTER_COLLECTOR_ENABLE",
+        value_name = "GROUPS",
+        value_delimiter = ',',
+        help = "Comma-separated list of metric groups to export in addition to the default ones"
+    )]
+    enabled_groups: Vec<MetricGroup>,
+    #[clap(
+        long = "collector.disable",
+        alias = "collector-disable",
+        env = "P1_EXPORTER_COLLECTOR_DISABLE",
+        value_name = "GROUPS",
+        value_delimiter = ',',
+        help = "Comma-separated list of metric groups not to export"
+    )]
+    disabled_groups: Vec<MetricGroup>,
+    #[clap(
+        long = "collector.disable-defaults",
+        alias = "collector-disable-defaults",
+        env = "P1_EXPORTER_COLLECTOR_DISABLE_DEFAULTS",
+        help = "Export only the metric groups given with --collector.enable"
+    )]
+    disable_default_groups: bool,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_EXTRA_OBIS",
+        value_name = "CODE=METRIC[,OPTION=VALUE...]",
+        help = "Export the numeric value of an OBIS object under the given name, with optional type=gauge|counter, scale=FACTOR and labels, e.g. 1-0:3.8.0=reactive_energy_kvarh,type=counter,scale=0.001"
+    )]
+    extra_obis: Vec<ExtraObis>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_EXPORT_RAW_OBIS",
+        help = "Export the value of every numeric OBIS object of the telegrams as p1_obis_value, labeled with its code"
+    )]
+    export_raw_obis: bool,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_AGGREGATE_METERS",
+        help = "Also export the power and energy summed over all meters, as p1_site_power_consumed and the like"
+    )]
+    aggregate_meters: bool,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_POWER_FAILURE_EVENTS",
+        help = "Number of the most recent long power failures of the meter's event log to export",
+        default_value = "10"
+    )]
+    power_failure_events: usize,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_NO_EOF",
+        help = "Strip the trailing '# EOF' line from the metrics output"
+    )]
+    no_eof: bool,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_SAMPLE_TIMESTAMPS",
+        help = "Attach the time the meter took its readings to their samples, e.g. the capture time to the gas readings"
+    )]
+    sample_timestamps: bool,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_METRICS_CACHE_TTL",
+        help = "Serve the encoded metrics to scrapes for this long before encoding them again [default: no caching]",
+        value_parser = parse_duration
+    )]
+    metrics_cache_ttl: Option<Duration>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_DEBUG_ENDPOINT",
+        help = "Serve the last raw telegram of every meter and whether it was parsed on /debug/telegram"
+    )]
+    debug_endpoint: bool,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_ACCESS_LOG_LEVEL",
+        help = "Level every served HTTP request is logged at (error, warn, info, debug, trace)",
+        default_value = "debug",
+        value_parser = logging::parse_access_level
+    )]
+    web_access_log_level: tracing::Level,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_MAX_CONCURRENT_REQUESTS",
+        help = "Number of HTTP requests served concurrently",
+        default_value = "4",
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    web_max_concurrent_requests: u16,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_REQUEST_TIMEOUT",
+        help = "Answer HTTP requests that waited this long for a free worker with 503",
+        default_value = "10s",
+        value_parser = parse_duration
+    )]
+    web_request_timeout: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_MAX_CONNECTIONS",
+        help = "Number of HTTP connections accepted at once, further ones are answered with 503",
+        default_value = "32",
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    web_max_connections: u16,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_READ_TIMEOUT",
+        help = "Answer HTTP requests not received completely within this long with 408 and close the connection",
+        default_value = "10s",
+        value_parser = parse_nonzero_duration
+    )]
+    web_read_timeout: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_WRITE_TIMEOUT",
+        help = "Close HTTP connections whose client accepts nothing of a response for this long",
+        default_value = "10s",
+        value_parser = parse_nonzero_duration
+    )]
+    web_write_timeout: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_AUTH_USERS",
+        value_name = "FILE",
+        help = "Require Basic auth for the metrics, API and debug endpoints, with users from an htpasswd file with bcrypt hashes"
+    )]
+    web_auth_users: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_AUTH_TOKEN_FILE",
+        value_name = "FILE",
+        help = "Require the bearer token in the file for the metrics, API and debug endpoints"
+    )]
+    web_auth_token_file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_TLS_CERT",
+        value_name = "FILE",
+        requires = "web_tls_key",
+        help = "Serve HTTPS with the PEM-encoded certificate chain in the file"
+    )]
+    web_tls_cert: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_TLS_KEY",
+        value_name = "FILE",
+        requires = "web_tls_cert",
+        help = "PEM-encoded private key of the --web-tls-cert certificate"
+    )]
+    web_tls_key: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_TLS_CLIENT_CA",
+        value_name = "FILE",
+        requires = "web_tls_cert",
+        help = "Require HTTPS clients to present a certificate issued by a CA in the PEM-encoded bundle"
+    )]
+    web_tls_client_ca: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_DROP_PRIVILEGES",
+        value_name = "USER[:GROUP]",
+        num_args = 0..=1,
+        help = "Drop all capabilities after binding the listen address, optionally switching to the given user"
+    )]
+    drop_privileges: Option<Option<User>>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_TEXTFILE_OUTPUT",
+        visible_alias = "output",
+        help = "Periodically write the metrics to the given file"
+    )]
+    textfile_output: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_TEXTFILE_INTERVAL",
+        help = "Interval between writes of the metrics file",
+        default_value = "15s",
+        value_parser = parse_duration
+    )]
+    textfile_interval: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_ONCE",
+        requires = "textfile_output",
+        help = "Read a single telegram from every meter, write the metrics to --textfile-output and exit"
+    )]
+    once: bool,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PUSH_URL",
+        help = "Periodically push the metrics to the Pushgateway at the given URL"
+    )]
+    push_url: Option<http::Url>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PUSH_INTERVAL",
+        help = "Interval between pushes to the Pushgateway",
+        default_value = "15s",
+        value_parser = parse_nonzero_duration
+    )]
+    push_interval: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PUSH_JOB",
+        help = "Value of the job label of the pushed metrics",
+        default_value = "p1_exporter"
+    )]
+    push_job: String,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PUSH_INSTANCE",
+        help = "Value of the instance label of the pushed metrics [default: no instance label]"
+    )]
+    push_instance: Option<String>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_OTLP_URL",
+        help = "Periodically export the metrics to the OpenTelemetry collector's OTLP/HTTP receiver at the given URL, e.g. http://localhost:4318"
+    )]
+    otlp_url: Option<http::Url>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_OTLP_INTERVAL",
+        help = "Interval between exports to the OpenTelemetry collector",
+        default_value = "60s",
+        value_parser = parse_nonzero_duration
+    )]
+    otlp_interval: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_REMOTE_WRITE_URL",
+        help = "Send the readings of every telegram to the Prometheus remote write endpoint at the given URL"
+    )]
+    remote_write_url: Option<http::Url>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_REMOTE_WRITE_INTERVAL",
+        help = "Interval between remote write requests, each sending the readings received meanwhile",
+        default_value = "10s",
+        value_parser = parse_nonzero_duration
+    )]
+    remote_write_interval: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_REMOTE_WRITE_USERNAME",
+        requires = "remote_write_password_file",
+        help = "Authenticate to the remote write endpoint with Basic auth as this user"
+    )]
+    remote_write_username: Option<String>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_REMOTE_WRITE_PASSWORD_FILE",
+        value_name = "FILE",
+        requires = "remote_write_username",
+        help = "File with the password of --remote-write-username"
+    )]
+    remote_write_password_file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_REMOTE_WRITE_BEARER_TOKEN_FILE",
+        value_name = "FILE",
+        conflicts_with = "remote_write_username",
+        help = "Authenticate to the remote write endpoint with the bearer token in the file"
+    )]
+    remote_write_bearer_token_file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_INFLUXDB_URL",
+        requires_all = ["influxdb_org", "influxdb_bucket", "influxdb_token_file"],
+        help = "Write the readings of every telegram to the InfluxDB 2.x instance at the given URL, in its line protocol"
+    )]
+    influxdb_url: Option<http::Url>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_INFLUXDB_ORG",
+        requires = "influxdb_url",
+        help = "InfluxDB organization the bucket belongs to"
+    )]
+    influxdb_org: Option<String>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_INFLUXDB_BUCKET",
+        requires = "influxdb_url",
+        help = "InfluxDB bucket to write the readings to"
+    )]
+    influxdb_bucket: Option<String>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_INFLUXDB_TOKEN_FILE",
+        value_name = "FILE",
+        requires = "influxdb_url",
+        help = "File with the InfluxDB API token with write access to the bucket"
+    )]
+    influxdb_token_file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MQTT_URL",
+        help = "Publish the readings of every telegram as retained messages to the MQTT broker at mqtt://HOST[:PORT] or, with TLS, mqtts://HOST[:PORT]"
+    )]
+    mqtt_url: Option<mqtt::BrokerAddress>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MQTT_TOPIC_PREFIX",
+        help = "Prefix of the MQTT topics",
+        default_value = "p1"
+    )]
+    mqtt_topic_prefix: String,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MQTT_QOS",
+        help = "QoS of the MQTT messages (0 or 1)",
+        default_value = "0",
+        value_parser = clap::value_parser!(u8).range(0..=1)
+    )]
+    mqtt_qos: u8,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MQTT_CLIENT_ID",
+        help = "Client identifier to connect to the MQTT broker with",
+        default_value = "p1-exporter"
+    )]
+    mqtt_client_id: String,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MQTT_USERNAME",
+        requires = "mqtt_password_file",
+        help = "Authenticate to the MQTT broker as this user"
+    )]
+    mqtt_username: Option<String>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MQTT_PASSWORD_FILE",
+        value_name = "FILE",
+        requires = "mqtt_username",
+        help = "File with the password of --mqtt-username"
+    )]
+    mqtt_password_file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MQTT_TLS_CA",
+        value_name = "FILE",
+        help = "Verify the certificate of the MQTT broker against the CAs in the PEM file instead of the system's trusted CAs"
+    )]
+    mqtt_tls_ca: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MQTT_HOMEASSISTANT_DISCOVERY",
+        requires = "mqtt_url",
+        help = "Announce the sensors to Home Assistant with MQTT discovery messages"
+    )]
+    mqtt_homeassistant_discovery: bool,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MQTT_HOMEASSISTANT_PREFIX",
+        help = "Prefix of the Home Assistant discovery topics",
+        default_value = "homeassistant"
+    )]
+    mqtt_homeassistant_prefix: String,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_GRAPHITE_ADDRESS",
+        help = "Periodically send the latest readings to the Graphite carbon plaintext receiver at host:port"
+    )]
+    graphite_address: Option<String>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_GRAPHITE_PREFIX",
+        help = "Prefix of the Graphite metric paths",
+        default_value = "p1"
+    )]
+    graphite_prefix: String,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_GRAPHITE_INTERVAL",
+        help = "Interval between sends to Graphite",
+        default_value = "60s",
+        value_parser = parse_nonzero_duration
+    )]
+    graphite_interval: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_ZABBIX_ADDRESS",
+        requires = "zabbix_host",
+        help = "Periodically send the latest readings to the Zabbix server or proxy at host:port, for trapper items"
+    )]
+    zabbix_address: Option<String>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_ZABBIX_HOST",
+        requires = "zabbix_address",
+        help = "Name of the host in Zabbix the items belong to"
+    )]
+    zabbix_host: Option<String>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_ZABBIX_KEY_PREFIX",
+        help = "Prefix of the Zabbix item keys",
+        default_value = "p1"
+    )]
+    zabbix_key_prefix: String,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_ZABBIX_INTERVAL",
+        help = "Interval between sends to Zabbix",
+        default_value = "60s",
+        value_parser = parse_nonzero_duration
+    )]
+    zabbix_interval: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_DSMR_READER_URL",
+        requires = "dsmr_reader_api_key_file",
+        help = "Forward the telegrams to the dsmr-reader instance at the given URL, as its remote datalogger would"
+    )]
+    dsmr_reader_url: Option<http::Url>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_DSMR_READER_API_KEY_FILE",
+        value_name = "FILE",
+        requires = "dsmr_reader_url",
+        help = "File with the API key of the dsmr-reader instance"
+    )]
+    dsmr_reader_api_key_file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_ARCHIVE_DIR",
+        value_name = "DIR",
+        help = "Append every reading to daily CSV files in the given directory"
+    )]
+    archive_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_ARCHIVE_RETENTION",
+        value_name = "DAYS",
+        requires = "archive_dir",
+        help = "Remove the --archive-dir files older than this many days [default: keep all]",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    archive_retention: Option<u32>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PROXY_LISTEN",
+        help = "Re-serve the raw P1 stream to TCP clients on the given address"
+    )]
+    proxy_listen: Option<SocketAddr>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_RECORD",
+        help = "Append every raw telegram to the given file, e.g. for --replay"
+    )]
+    record: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_RECORD_MAX_SIZE",
+        value_name = "SIZE",
+        help = "Rotate the --record file to <file>.1 once it reaches this size, e.g. 10M [default: no rotation]",
+        value_parser = record::parse_size
+    )]
+    record_max_size: Option<u64>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_POWER_CONFLICT_THRESHOLD",
+        help = "Power in kW above which consumed and produced power at the same time is reported as a conflict",
+        default_value = "0.0"
+    )]
+    power_conflict_threshold: f64,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MAX_POWER",
+        value_name = "KW",
+        help = "Skip telegrams reporting more power in kW, as corrupted"
+    )]
+    max_power: Option<f64>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MAX_ENERGY_STEP",
+        value_name = "KWH",
+        help = "Skip telegrams whose energy totals increased by more kWh since the last telegram, as corrupted"
+    )]
+    max_energy_step: Option<f64>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_SAMPLE_INTERVAL",
+        help = "Process only one telegram per interval, e.g. 10s, to save CPU [default: every telegram]",
+        value_parser = parse_nonzero_duration
+    )]
+    sample_interval: Option<Duration>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_COLLECTION_MODE",
+        help = "Read telegrams continuously (background) or one per meter on every scrape (scrape)",
+        default_value = "background"
+    )]
+    collection_mode: CollectionMode,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_CONNECT_TIMEOUT",
+        help = "Timeout of connecting to the P1 reader",
+        default_value = "5s",
+        value_parser = parse_nonzero_duration
+    )]
+    connect_timeout: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_READ_TIMEOUT",
+        help = "Reconnect to the P1 reader after receiving no data for this long",
+        default_value = "2s",
+        value_parser = parse_nonzero_duration
+    )]
+    read_timeout: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_TCP_KEEPALIVE",
+        help = "Enable TCP keepalive on the connections to P1 readers, probing after they've been idle for this long, e.g. 30s [default: disabled]",
+        value_parser = parse_nonzero_duration
+    )]
+    tcp_keepalive: Option<Duration>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_RECONNECT_INTERVAL",
+        help = "Initial delay between reconnection attempts to the P1 reader",
+        default_value = "5s",
+        value_parser = parse_duration
+    )]
+    reconnect_interval: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MAX_RECONNECT_DELAY",
+        help = "Maximum delay between reconnection attempts to the P1 reader, which back off exponentially",
+        default_value = "5m",
+        value_parser = parse_duration
+    )]
+    max_reconnect_delay: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_STRICT_PARSING",
+        help = "Reconnect to the P1 reader on a malformed telegram instead of skipping it"
+    )]
+    strict_parsing: bool,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_MAX_TELEGRAM_AGE",
+        help = "Report telegrams older than this as stale in p1_telegram_fresh [default: no p1_telegram_fresh]",
+        value_parser = parse_duration
+    )]
+    max_telegram_age: Option<Duration>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_STALE_AFTER",
+        help = "Remove the power, voltage, current and other gauges of a meter after receiving no telegram for this long, until the next one [default: never]",
+        value_parser = parse_nonzero_duration
+    )]
+    stale_after: Option<Duration>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_LOG_LEVEL",
+        help = "Log level (off, error, warn, info, debug, trace), RUST_LOG overrides it with per-target directives",
+        default_value = "info",
+        value_parser = logging::parse_level
+    )]
+    log_level: LevelFilter,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_LOG_FORMAT",
+        help = "Log format (text, json)",
+        default_value = "text"
+    )]
+    log_format: logging::Format,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PRE_REGISTER",
+        help = "Create the tariff and expected M-Bus channel series with a value of 0 at startup"
+    )]
+    pre_register: bool,
+    #[cfg(feature = "statsd")]
+    #[clap(
+        long,
+        env = "P1_EXPORTER_STATSD_ADDRESS",
+        help = "Push the readings to a StatsD server (DogStatsD tags) at the given address"
+    )]
+    statsd_address: Option<SocketAddr>,
+    #[cfg(feature = "statsd")]
+    #[clap(
+        long,
+        env = "P1_EXPORTER_STATSD_PREFIX",
+        help = "Prefix of the StatsD metric names",
+        default_value = "p1"
+    )]
+    statsd_prefix: String,
+    #[cfg(feature = "statsd")]
+    #[clap(
+        long,
+        env = "P1_EXPORTER_STATSD_TAGS",
+        value_name = "NAME:VALUE,...",
+        value_delimiter = ',',
+        help = "DogStatsD tags added to every StatsD metric"
+    )]
+    statsd_tags: Vec<String>,
+}
 
-#[derive(Parser)]
-#[clap(author, version, about)]
-struct Args {
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Serve simulated DSMR5 telegrams over TCP, as a P1 reader would
+    Simulate(SimulateArgs),
+    /// Decode raw telegrams from a file or stdin and print what the exporter makes of them
+    Parse(ParseArgs),
+    /// Connect to a P1 reader, wait for a valid telegram and summarize it
+    Check(CheckArgs),
+    /// Check that a running exporter has received a telegram recently, for container health checks
+    Healthcheck(HealthcheckArgs),
+    /// Connect to a P1 reader and show its readings live in the terminal
+    Tui(TuiArgs),
+    /// Print the completion script of a shell
+    Completions(CompletionsArgs),
+    /// Print the manual page, in roff
+    Man,
+}
+
+#[derive(clap::Args)]
+struct SimulateArgs {
+    #[clap(
+        long,
+        env = "P1_EXPORTER_SIMULATE_LISTEN",
+        help = "Address to serve the telegrams on",
+        default_value = "127.0.0.1:2000"
+    )]
+    listen: SocketAddr,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_SIMULATE_INTERVAL",
+        help = "Interval between telegrams",
+        default_value = "1s",
+        value_parser = parse_nonzero_duration
+    )]
+    interval: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_SIMULATE_CONSUMPTION",
+        value_name = "KW",
+        help = "Average power consumption",
+        default_value = "0.5"
+    )]
+    consumption: f64,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_SIMULATE_PRODUCTION",
+        value_name = "KW",
+        help = "Power production at noon, e.g. of solar panels",
+        default_value = "0.0"
+    )]
+    production: f64,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_SIMULATE_GAS",
+        value_name = "M3_PER_HOUR",
+        help = "Gas consumption",
+        default_value = "0.1"
+    )]
+    gas: f64,
+}
+
+#[derive(clap::Args)]
+struct ParseArgs {
+    #[clap(help = "File with the raw telegrams, stdin if omitted or -")]
+    file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_PARSE_FORMAT",
+        help = "Output format (text, json)",
+        default_value = "text"
+    )]
+    format: parse::Format,
+}
+
+#[derive(clap::Args)]
+struct CheckArgs {
+    #[clap(
+        short,
+        long,
+        env = "P1_EXPORTER_CHECK_P1_ADDRESS",
+        help = "P1 reader host:port"
+    )]
+    p1_address: ReaderAddress,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_CHECK_TIMEOUT",
+        help = "How long to wait for a valid telegram, including connecting",
+        default_value = "30s",
+        value_parser = parse_nonzero_duration
+    )]
+    timeout: Duration,
+}
+
+#[derive(clap::Args)]
+struct TuiArgs {
+    #[clap(
+        short,
+        long,
+        env = "P1_EXPORTER_TUI_P1_ADDRESS",
+        help = "P1 reader host:port"
+    )]
+    p1_address: ReaderAddress,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_TUI_TIMEOUT",
+        help = "Reconnect to the P1 reader after receiving no data for this long",
+        default_value = "10s",
+        value_parser = parse_nonzero_duration
+    )]
+    timeout: Duration,
+}
+
+#[derive(clap::Args)]
+struct CompletionsArgs {
+    #[clap(help = "Shell to complete in (bash, zsh, fish)")]
+    shell: completions::Shell,
+}
+
+#[derive(clap::Args)]
+struct HealthcheckArgs {
     #[clap(
         short,
         long,
-        help = "Address to listen on",
+        env = "P1_EXPORTER_ADDRESS",
+        value_delimiter = ',',
+        help = "Address the exporter listens on, either host:port or a Unix socket path; the first one is checked if several are given",
         default_value = "127.0.0.1:4545"
     )]
-    address: SocketAddr,
-    #[clap(short, long, help = "P1 reader address")]
-    p1_address: SocketAddr,
+    address: Vec<ListenAddress>,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_HEALTHCHECK_MAX_TELEGRAM_AGE",
+        help = "Report unhealthy if any meter's last telegram is older than this",
+        default_value = "1m",
+        value_parser = parse_nonzero_duration
+    )]
+    max_telegram_age: Duration,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_METRIC_PREFIX",
+        help = "Prefix of the names of the exporter's metrics",
+        default_value = "p1",
+        value_parser = parse_metric_prefix
+    )]
+    metric_prefix: String,
+    #[clap(
+        long,
+        env = "P1_EXPORTER_WEB_AUTH_TOKEN_FILE",
+        value_name = "FILE",
+        help = "Authenticate with the bearer token in the file"
+    )]
+    web_auth_token_file: Option<PathBuf>,
 }
 
-#[derive(Default)]
-struct P1Metrics {
-    power_consumed: Gauge<f64, AtomicU64>,
-    power_produced: Gauge<f64, AtomicU64>,
+/// Parses a duration like `500ms`, `15s`, `5m` or `1h`. Plain numbers are
+/// interpreted as seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration '{s}'"))?;
+    let secs = match unit {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(format!("invalid duration unit '{unit}' in '{s}'")),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|err| format!("invalid duration '{s}': {err}"))
+}
 
-    power_consumed_total: Family<[(&'static str, &'static str); 1], Counter<f64, AtomicU64>>,
-    power_produced_total: Family<[(&'static str, &'static str); 1], Counter<f64, AtomicU64>>,
+fn parse_nonzero_duration(s: &str) -> Result<Duration, String> {
+    match parse_duration(s)? {
+        Duration::ZERO => Err("duration must not be zero".to_string()),
+        duration => Ok(duration),
+    }
+}
 
-    active_tariff: Family<[(&'static str, &'static str); 1], Gauge>,
+fn parse_metric_prefix(s: &str) -> Result<String, String> {
+    let valid = s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("invalid metric prefix '{s}'"));
+    }
+    Ok(s.to_string())
+}
 
-    gas_consumed_total: Counter<f64, AtomicU64>,
+/// Returns the metric groups to export: the default ones, all of them unless
+/// disabled, with those enabled added and those disabled removed.
+fn metric_groups(args: &Args) -> Vec<MetricGroup> {
+    let defaults: &[MetricGroup] = if args.disable_default_groups {
+        &[]
+    } else {
+        &MetricGroup::ALL
+    };
+    MetricGroup::ALL
+        .into_iter()
+        .filter(|group| defaults.contains(group) || args.enabled_groups.contains(group))
+        .filter(|group| !args.disabled_groups.contains(group))
+        .collect()
 }
 
-fn main() {
-    let args = Args::parse();
+/// Returns the `Authorization` header for the remote write endpoint, reading
+/// the password or token from its file.
+fn remote_write_authorization(args: &Args) -> Result<Option<String>, io::Error> {
+    let read = |path: &PathBuf| {
+        fs::read_to_string(path)
+            .map(|secret| secret.trim().to_string())
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+    };
+    if let (Some(user), Some(path)) = (
+        &args.remote_write_username,
+        &args.remote_write_password_file,
+    ) {
+        let credentials = format!("{user}:{}", read(path)?);
+        return Ok(Some(format!(
+            "Basic {}",
+            http::base64_encode(credentials.as_bytes(), false)
+        )));
+    }
+    if let Some(path) = &args.remote_write_bearer_token_file {
+        return Ok(Some(format!("Bearer {}", read(path)?)));
+    }
+    Ok(None)
+}
 
-    let mut registry = <Registry>::default();
-    let metrics = <P1Metrics>::default();
+/// Returns the configuration of the prices of `--price-source`, if given,
+/// reading the API token from its file.
+fn price_config(args: &Args) -> Result<Option<prices::PriceConfig>, String> {
+    let (Some(source), Some(path)) = (args.price_source, &args.price_api_token_file) else {
+        return Ok(None);
+    };
+    if source == prices::PriceSource::Entsoe && args.price_area.is_none() {
+        return Err("--price-source entsoe needs --price-area".to_string());
+    }
+    let token = fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    Ok(Some(prices::PriceConfig {
+        source,
+        token: token.trim().to_string(),
+        area: args.price_area.clone(),
+        markup: args.price_markup,
+        refresh_interval: args.price_refresh_interval,
+    }))
+}
 
-    registry.register(
-        "p1_power_consumed_kw",
-        "Power consumed",
-        metrics.power_consumed.clone(),
-    );
-    registry.register(
-        "p1_power_produced_kw",
-        "Power produced",
-        metrics.power_produced.clone(),
-    );
-    registry.register(
-        "p1_power_consumed_kwh",
-        "Total consumed power",
-        metrics.power_consumed_total.clone(),
-    );
-    registry.register(
-        "p1_power_produced_kwh",
-        "Total produced power",
-        metrics.power_produced_total.clone(),
-    );
-    registry.register(
-        "p1_active_tariff",
-        "Currently active tariff",
-        metrics.active_tariff.clone(),
-    );
-    registry.register(
-        "p1_gas_consumed_cubic_meters",
-        "Total consumed natural gas",
-        metrics.gas_consumed_total.clone(),
-    );
+/// Starts fetching the prices of `--price-source`, if given.
+fn day_ahead_prices(args: &Args) -> Result<Option<prices::DayAheadPrices>, String> {
+    price_config(args)?
+        .map(prices::DayAheadPrices::start)
+        .transpose()
+        .map_err(|err| err.to_string())
+}
+
+/// Validates the options as far as possible without starting anything: reads
+/// the files they refer to and resolves the P1 readers, which are only
+/// reported if they don't resolve since they are resolved on every
+/// connection.
+fn check_config(args: &Args) -> Result<(), String> {
+    obis::check_extra_obis(&args.extra_obis)
+        .map_err(|err| format!("Invalid --extra-obis: {err}"))?;
+    collector_config(args, None, None, Arc::default(), None)?;
+    price_config(args).map_err(|err| format!("Invalid prices of the dynamic contract: {err}"))?;
+    if args.remote_write_url.is_some() {
+        remote_write_authorization(args)
+            .map_err(|err| format!("Invalid remote write credentials: {err}"))?;
+    }
+    if args.mqtt_url.is_some() {
+        mqtt_credentials(
+            args.mqtt_username.as_ref(),
+            args.mqtt_password_file.as_ref(),
+        )?;
+    }
+    if let Some(path) = &args.influxdb_token_file {
+        fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    }
+    if let Some(path) = &args.dsmr_reader_api_key_file {
+        fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    }
+    if args.web_auth_users.is_some() || args.web_auth_token_file.is_some() {
+        Auth::load(
+            args.web_auth_users.as_deref(),
+            args.web_auth_token_file.as_deref(),
+        )
+        .map_err(|err| format!("Failed to read credentials: {err}"))?;
+    }
+    if let (Some(cert), Some(key)) = (&args.web_tls_cert, &args.web_tls_key) {
+        tls::load(cert, key, args.web_tls_client_ca.as_deref())
+            .map_err(|err| format!("Failed to read the TLS certificate: {err}"))?;
+    }
+    for reader in args.p1_address.iter().chain(&args.p1_backup_address) {
+        if let Err(err) = reader.addr.to_socket_addrs() {
+            tracing::warn!("P1 reader {} doesn't resolve: {err}", reader.addr);
+        }
+    }
+    Ok(())
+}
 
-    start_metrics_collector(args.p1_address, Arc::new(metrics));
-    if let Err(err) = run_metrics_server(args.address, registry) {
-        eprintln!("terminating: {err}")
+/// Returns the MQTT user name and the password read from its file.
+fn mqtt_credentials(
+    user: Option<&String>,
+    password_file: Option<&PathBuf>,
+) -> Result<Option<(String, String)>, String> {
+    let (Some(user), Some(path)) = (user, password_file) else {
+        return Ok(None);
+    };
+    match fs::read_to_string(path) {
+        Ok(password) => Ok(Some((user.clone(), password.trim().to_string()))),
+        Err(err) => Err(format!("Failed to read {}: {err}", path.display())),
     }
 }
 
-fn start_metrics_collector(addr: SocketAddr, metrics: Arc<P1Metrics>) {
-    thread::spawn(move || loop {
-        match TcpStream::connect(addr) {
-            Ok(sock) => {
-                if let Err(err) = collect_metrics(sock, metrics.clone()) {
-                    eprintln!("Failed to collect metrics: {err}");
+/// Derives the inputs of the collectors and their configuration from the
+/// options, sharing the proxy, the recorder and the sinks created at startup.
+fn collector_config(
+    args: &Args,
+    proxy: Option<Arc<proxy::Proxy>>,
+    recorder: Option<Arc<record::Recorder>>,
+    sinks: Arc<Vec<Box<dyn sink::Sink>>>,
+    day_ahead: Option<prices::DayAheadPrices>,
+) -> Result<(Inputs, CollectorConfig), String> {
+    if args.p1_address.len() > 1 {
+        let mut names = HashSet::new();
+        for reader in &args.p1_address {
+            match &reader.meter {
+                Some(name) if !names.insert(name) => {
+                    return Err(format!(
+                        "Meter name '{name}' is used for several P1 readers"
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    return Err(format!(
+                        "P1 reader {} needs a meter name, e.g. METER={}",
+                        reader.addr, reader.addr
+                    ));
                 }
             }
-            Err(err) => {
-                eprintln!("Failed to connect to P1 reader: {err}")
+        }
+        if args.proxy_listen.is_some() {
+            return Err("--proxy-listen supports a single P1 reader only".to_string());
+        }
+    }
+    let legacy = args.dsmr_version == DsmrVersion::Legacy;
+    let mut inputs: Inputs = if let Some(device) = args.serial_device.clone() {
+        let parity = args
+            .serial_parity
+            .unwrap_or(if legacy { Parity::Even } else { Parity::None });
+        vec![(
+            None,
+            Input::Serial(SerialConfig {
+                device,
+                baud_rate: args
+                    .serial_baud_rate
+                    .unwrap_or(if legacy { 9600 } else { 115200 }),
+                parity,
+                data_bits: args.serial_data_bits.unwrap_or(match parity {
+                    Parity::None => 8,
+                    Parity::Even | Parity::Odd => 7,
+                }),
+                flow_control: args.serial_flow_control,
+            }),
+        )]
+    } else if let Some(path) = args.replay.clone() {
+        vec![(None, Input::Replay(path, args.replay_speed))]
+    } else if let Some(addr) = args.p1_udp_listen {
+        vec![(None, Input::Udp(addr))]
+    } else if let (Some(address), Some(topic)) = (&args.p1_mqtt_url, &args.p1_mqtt_topic) {
+        let credentials = mqtt_credentials(
+            args.p1_mqtt_username.as_ref(),
+            args.p1_mqtt_password_file.as_ref(),
+        )?;
+        let broker = mqtt::Broker {
+            address: address.clone(),
+            tls_ca: None,
+            client_id: format!("p1-exporter-{}", process::id()),
+            credentials,
+        };
+        vec![(None, Input::Mqtt(broker, topic.clone()))]
+    } else if let Some(url) = args.p1_homewizard_url.clone() {
+        vec![(None, Input::HomeWizard(url, args.p1_homewizard_interval))]
+    } else if let Some(path) = args.p1_source.clone() {
+        let input = if path.as_os_str() == "stdin" || path.as_os_str() == "-" {
+            Input::Stdin
+        } else {
+            Input::File(path)
+        };
+        vec![(None, input)]
+    } else {
+        args.p1_address
+            .iter()
+            .map(|reader| (reader.meter.clone(), Input::Tcp(reader.addr.clone())))
+            .collect()
+    };
+    for backup in &args.p1_backup_address {
+        let input = match &backup.meter {
+            Some(meter) => inputs
+                .iter_mut()
+                .find(|(name, _)| name.as_ref() == Some(meter))
+                .map(|(_, input)| input)
+                .ok_or_else(|| {
+                    format!(
+                        "Backup P1 reader {} is of an unknown meter '{meter}'",
+                        backup.addr
+                    )
+                })?,
+            None if inputs.len() == 1 => &mut inputs[0].1,
+            None => {
+                return Err(format!(
+                    "Backup P1 reader {} needs the name of its meter, e.g. METER={}",
+                    backup.addr, backup.addr
+                ))
             }
         };
-        thread::sleep(Duration::from_secs(5));
+        let backup = Input::Tcp(backup.addr.clone());
+        match input {
+            Input::Failover(inputs) => inputs.push(backup),
+            primary => *primary = Input::Failover(vec![primary.clone(), backup]),
+        }
+    }
+    if args.collection_mode == CollectionMode::Scrape {
+        if let Some((_, input)) = inputs.iter().find(|(_, input)| !input.reopenable()) {
+            return Err(format!("Can't read {input} on every scrape"));
+        }
+    }
+
+    let tls = if args.p1_tls {
+        let cert = args.p1_tls_cert.as_deref().zip(args.p1_tls_key.as_deref());
+        tls::Client::load(
+            args.p1_tls_ca.as_deref(),
+            args.p1_tls_server_name.as_deref(),
+            cert,
+        )
+        .map(Some)
+        .map_err(|err| format!("Failed to set up TLS to the P1 reader: {err}"))?
+    } else {
+        None
+    };
+
+    let decryption = args.decryption_key.clone().map(|encryption| decrypt::Keys {
+        encryption,
+        authentication: args.authentication_key.clone(),
     });
+
+    let config = CollectorConfig {
+        mode: args.collection_mode,
+        format: args.p1_format,
+        dsmr_version: args.dsmr_version,
+        gas_scale: args.gas_scale,
+        energy_scale: args.energy_scale,
+        expected_mbus_channels: args.expected_mbus_channels.clone(),
+        mbus_channels: args.mbus_channels.clone(),
+        tariff_names: args.tariff_names.clone(),
+        prices: cost::Prices {
+            energy: args.energy_prices.clone().unwrap_or_default(),
+            day_ahead,
+            gas: args.gas_price,
+            energy_daily_charge: args.energy_daily_charge,
+            gas_daily_charge: args.gas_daily_charge,
+        },
+        proxy,
+        recorder,
+        power_conflict_threshold: args.power_conflict_threshold,
+        max_power: args.max_power,
+        max_energy_step: args.max_energy_step,
+        sample_interval: args.sample_interval,
+        export_raw_obis: args.export_raw_obis,
+        power_unit: args.power_unit,
+        peak_timezone: args.peak_timezone,
+        sinks,
+        pre_register: args.pre_register,
+        strict_parsing: args.strict_parsing,
+        connect_timeout: args.connect_timeout,
+        tls,
+        decryption,
+        read_timeout: args.read_timeout,
+        tcp_keepalive: args.tcp_keepalive,
+        reconnect_interval: args.reconnect_interval,
+        max_reconnect_delay: args.max_reconnect_delay,
+        failback_interval: args.p1_failback_interval,
+        stale_after: args.stale_after,
+        max_consecutive_errors: args.max_consecutive_errors,
+        exit_on_failure: args.exit_on_collector_failure,
+        power_failure_events: args.power_failure_events,
+        metric_groups: metric_groups(args),
+    };
+    Ok((inputs, config))
 }
 
-fn collect_metrics(sock: TcpStream, metrics: Arc<P1Metrics>) -> Result<(), io::Error> {
-    sock.set_read_timeout(Some(Duration::from_secs(2)))?;
-    let reader = dsmr5::Reader::new(sock.bytes().map_while(|b| b.ok()));
-
-    for readout in reader {
-        let telegram = readout
-            .to_telegram()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
-        let state = dsmr5::Result::<dsmr5::state::State>::from(&telegram)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
-
-        if let Some(pd) = state.power_delivered {
-            metrics.power_consumed.set(pd);
-        }
-        if let Some(pd) = state.power_received {
-            metrics.power_produced.set(pd);
-        }
-
-        if let Some(pd) = state.meterreadings[0].to {
-            metrics
-                .power_consumed_total
-                .get_or_create(&[("tariff", "low")])
-                .inner()
-                .store(pd.to_bits(), Ordering::SeqCst);
-        }
-        if let Some(pd) = state.meterreadings[1].to {
-            metrics
-                .power_consumed_total
-                .get_or_create(&[("tariff", "high")])
-                .inner()
-                .store(pd.to_bits(), Ordering::SeqCst);
-        }
-
-        if let Some(pd) = state.meterreadings[0].by {
-            metrics
-                .power_produced_total
-                .get_or_create(&[("tariff", "low")])
-                .inner()
-                .store(pd.to_bits(), Ordering::SeqCst);
-        }
-        if let Some(pd) = state.meterreadings[1].by {
-            metrics
-                .power_produced_total
-                .get_or_create(&[("tariff", "high")])
-                .inner()
-                .store(pd.to_bits(), Ordering::SeqCst);
-        }
-
-        metrics.active_tariff.clear();
-        match state.tariff_indicator {
-            Some([0, 1]) => metrics
-                .active_tariff
-                .get_or_create(&[("tariff", "low")])
-                .set(1),
-            Some([0, 2]) => metrics
-                .active_tariff
-                .get_or_create(&[("tariff", "high")])
-                .set(1),
-            _ => 0,
+fn main() {
+    let argv = match config::args(Args::command()) {
+        Ok(argv) => argv,
+        Err(err) => {
+            eprintln!("Failed to read the configuration file: {err}");
+            process::exit(1);
+        }
+    };
+    let args = Args::parse_from(&argv);
+    logging::init(args.log_level, args.log_format);
+
+    if let Some(Command::Healthcheck(args)) = &args.command {
+        let token = match &args.web_auth_token_file {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(token) => Some(token.trim().to_string()),
+                Err(err) => {
+                    tracing::error!("Failed to read {}: {err}", path.display());
+                    process::exit(1);
+                }
+            },
+            None => None,
+        };
+        // There's always one, the default if not given.
+        if let Err(err) = healthcheck::run(
+            &args.address[0],
+            &args.metric_prefix,
+            args.max_telegram_age,
+            token.as_deref(),
+        ) {
+            tracing::error!("{err}");
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Check(args)) = &args.command {
+        if let Err(err) = check::run(&args.p1_address.addr, args.timeout) {
+            tracing::error!("{err}");
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Tui(args)) = &args.command {
+        if let Err(err) = tui::run(&args.p1_address.addr, args.timeout) {
+            tracing::error!("{err}");
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Completions(args)) = &args.command {
+        print!(
+            "{}",
+            completions::generate(&mut Args::command(), args.shell)
+        );
+        return;
+    }
+    if let Some(Command::Man) = &args.command {
+        print!("{}", man::render(&mut Args::command()));
+        return;
+    }
+    if let Some(Command::Parse(args)) = &args.command {
+        if let Err(err) = parse::run(args.file.as_deref(), args.format) {
+            tracing::error!("{err}");
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Simulate(args)) = args.command {
+        let config = simulate::SimulatorConfig {
+            listen: args.listen,
+            interval: args.interval,
+            consumption: args.consumption,
+            production: args.production,
+            gas: args.gas,
         };
+        if let Err(err) = simulate::run(config) {
+            tracing::error!("Failed to run the simulator: {err}");
+            process::exit(1);
+        }
+        return;
+    }
 
-        for sl in state.slaves {
-            if let Slave {
-                device_type: Some(3),
-                meter_reading: Some((_, gd)),
-            } = sl
-            {
-                metrics
-                    .gas_consumed_total
-                    .inner()
-                    .store(gd.to_bits(), Ordering::SeqCst);
+    if args.print_config || args.check_config {
+        if args.check_config {
+            if let Err(err) = check_config(&args) {
+                tracing::error!("{err}");
+                process::exit(1);
             }
         }
+        let matches = Args::command().get_matches_from(&argv);
+        print!("{}", config::render(&mut Args::command(), &matches));
+        if args.check_config {
+            eprintln!("The configuration is valid");
+        }
+        return;
     }
 
-    Ok(())
-}
+    if let Err(err) = obis::check_extra_obis(&args.extra_obis) {
+        tracing::error!("Invalid --extra-obis: {err}");
+        process::exit(1);
+    }
+    let mut registry = <Registry>::default();
+    let metrics = P1Metrics {
+        aggregate_meters: args.aggregate_meters,
+        ..P1Metrics::new(&args.extra_obis, args.max_telegram_age)
+    };
+    let groups = metric_groups(&args);
+    metrics.register(
+        &mut registry,
+        &args.metric_prefix,
+        &args.labels,
+        &groups,
+        args.power_unit,
+    );
+
+    let proxy = match args.proxy_listen.map(proxy::Proxy::start).transpose() {
+        Ok(proxy) => proxy,
+        Err(err) => {
+            tracing::error!("Failed to start the P1 proxy: {err}");
+            process::exit(1);
+        }
+    };
+
+    let recorder = match args
+        .record
+        .clone()
+        .map(|path| record::Recorder::open(path, args.record_max_size))
+        .transpose()
+    {
+        Ok(recorder) => recorder.map(Arc::new),
+        Err(err) => {
+            tracing::error!("Failed to open the telegram recording: {err}");
+            process::exit(1);
+        }
+    };
 
-fn run_metrics_server(addr: SocketAddr, registry: Registry) -> Result<(), io::Error> {
-    let content_type = "Content-Type: application/openmetrics-text; version=1.0.0; charset=utf-8"
-        .parse::<tiny_http::Header>()
-        .unwrap();
-    let server = Server::http(addr).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let day_ahead = match day_ahead_prices(&args) {
+        Ok(day_ahead) => day_ahead,
+        Err(err) => {
+            tracing::error!("Failed to set up the prices of the dynamic contract: {err}");
+            process::exit(1);
+        }
+    };
 
-    for req in server.incoming_requests() {
-        let mut body = String::new();
-        let response = match encode(&mut body, &registry) {
-            Ok(()) => Response::from_string(body).with_header(content_type.clone()),
-            Err(err) => Response::from_string(format!("{}", err)).with_status_code(500),
+    let mut sinks: Vec<Box<dyn sink::Sink>> = Vec::new();
+    #[cfg(feature = "statsd")]
+    if let Some(addr) = args.statsd_address {
+        let config = statsd::StatsdConfig {
+            address: addr,
+            prefix: args.statsd_prefix.clone(),
+            tags: args.statsd_tags.clone(),
         };
-        if let Err(err) = req.respond(response) {
-            eprintln!("failed to respond: {err}");
+        match statsd::StatsdSink::new(config) {
+            Ok(sink) => sinks.push(Box::new(sink)),
+            Err(err) => {
+                tracing::error!("Failed to set up StatsD output: {err}");
+                process::exit(1);
+            }
         }
     }
 
-    Ok(())
+    if let Some(url) = args.remote_write_url.clone() {
+        let sink = remote_write_authorization(&args).and_then(|authorization| {
+            remote_write::RemoteWriteSink::start(remote_write::RemoteWriteConfig {
+                url,
+                interval: args.remote_write_interval,
+                authorization,
+                prefix: args.metric_prefix.clone(),
+                labels: args.labels.clone(),
+            })
+        });
+        match sink {
+            Ok(sink) => sinks.push(Box::new(sink)),
+            Err(err) => {
+                tracing::error!("Failed to set up remote write: {err}");
+                process::exit(1);
+            }
+        }
+    }
+
+    if let (Some(url), Some(org), Some(bucket), Some(path)) = (
+        &args.influxdb_url,
+        &args.influxdb_org,
+        &args.influxdb_bucket,
+        &args.influxdb_token_file,
+    ) {
+        let sink = fs::read_to_string(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+            .and_then(|token| {
+                influxdb::InfluxDbSink::start(influxdb::InfluxDbConfig {
+                    url: url.clone(),
+                    org: org.clone(),
+                    bucket: bucket.clone(),
+                    token: token.trim().to_string(),
+                    prefix: args.metric_prefix.clone(),
+                    labels: args.labels.clone(),
+                })
+            });
+        match sink {
+            Ok(sink) => sinks.push(Box::new(sink)),
+            Err(err) => {
+                tracing::error!("Failed to set up writing to InfluxDB: {err}");
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(broker) = args.mqtt_url.clone() {
+        let credentials = match mqtt_credentials(
+            args.mqtt_username.as_ref(),
+            args.mqtt_password_file.as_ref(),
+        ) {
+            Ok(credentials) => credentials,
+            Err(err) => {
+                tracing::error!("{err}");
+                process::exit(1);
+            }
+        };
+        let config = mqtt::MqttConfig {
+            broker: mqtt::Broker {
+                address: broker,
+                tls_ca: args.mqtt_tls_ca.clone(),
+                client_id: args.mqtt_client_id.clone(),
+                credentials,
+            },
+            topic_prefix: args.mqtt_topic_prefix.trim_end_matches('/').to_string(),
+            qos: args.mqtt_qos,
+            discovery_prefix: args
+                .mqtt_homeassistant_discovery
+                .then(|| args.mqtt_homeassistant_prefix.clone()),
+        };
+        match mqtt::MqttSink::start(config) {
+            Ok(sink) => sinks.push(Box::new(sink)),
+            Err(err) => {
+                tracing::error!("Failed to set up MQTT publishing: {err}");
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(address) = args.graphite_address.clone() {
+        sinks.push(Box::new(graphite::GraphiteSink::start(
+            graphite::GraphiteConfig {
+                address,
+                prefix: args.graphite_prefix.trim_end_matches('.').to_string(),
+                interval: args.graphite_interval,
+            },
+        )));
+    }
+
+    if let (Some(address), Some(host)) = (&args.zabbix_address, &args.zabbix_host) {
+        sinks.push(Box::new(zabbix::ZabbixSink::start(zabbix::ZabbixConfig {
+            address: address.clone(),
+            host: host.clone(),
+            key_prefix: args.zabbix_key_prefix.trim_end_matches('.').to_string(),
+            interval: args.zabbix_interval,
+        })));
+    }
+
+    if let (Some(url), Some(path)) = (&args.dsmr_reader_url, &args.dsmr_reader_api_key_file) {
+        let sink = fs::read_to_string(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+            .and_then(|api_key| {
+                dsmr_reader::DsmrReaderSink::start(dsmr_reader::DsmrReaderConfig {
+                    url: url.clone(),
+                    api_key: api_key.trim().to_string(),
+                })
+            });
+        match sink {
+            Ok(sink) => sinks.push(Box::new(sink)),
+            Err(err) => {
+                tracing::error!("Failed to set up forwarding to dsmr-reader: {err}");
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(dir) = args.archive_dir.clone() {
+        match archive::ArchiveSink::open(archive::ArchiveConfig {
+            dir,
+            retention: args.archive_retention,
+        }) {
+            Ok(sink) => sinks.push(Box::new(sink)),
+            Err(err) => {
+                tracing::error!("Failed to set up the archive: {err}");
+                process::exit(1);
+            }
+        }
+    }
+
+    let sinks = Arc::new(sinks);
+    let (inputs, config) = match collector_config(
+        &args,
+        proxy.clone(),
+        recorder.clone(),
+        sinks.clone(),
+        day_ahead.clone(),
+    ) {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("{err}");
+            process::exit(1);
+        }
+    };
+
+    if let (true, Some(path)) = (args.once, &args.textfile_output) {
+        let config = Arc::new(config);
+        let mut read = true;
+        for (meter, input) in inputs {
+            read &= OnDemand::new(input, metrics.meter(meter), config.clone()).collect();
+        }
+        if let Err(err) = textfile::write_textfile(path, &registry) {
+            tracing::error!("Failed to write metrics to {}: {err}", path.display());
+            process::exit(1);
+        }
+        process::exit(if read { 0 } else { 1 });
+    }
+
+    // Read before dropping privileges, the files may be readable by root
+    // only.
+    let auth = match (&args.web_auth_users, &args.web_auth_token_file) {
+        (None, None) => None,
+        (users, token) => match Auth::load(users.as_deref(), token.as_deref()) {
+            Ok(auth) => Some(auth),
+            Err(err) => {
+                tracing::error!("Failed to read credentials: {err}");
+                process::exit(1);
+            }
+        },
+    };
+    let tls = match (&args.web_tls_cert, &args.web_tls_key) {
+        (Some(cert), Some(key)) => match tls::load(cert, key, args.web_tls_client_ca.as_deref()) {
+            Ok(tls) => Some(tls),
+            Err(err) => {
+                tracing::error!("Failed to read the TLS certificate: {err}");
+                process::exit(1);
+            }
+        },
+        _ => None,
+    };
+
+    // The HTTP server listens on a private socket behind the public
+    // listeners, which enforce the limits and terminate TLS.
+    let listen = if args.web_systemd_socket {
+        match systemd::listen_fd() {
+            Ok(fd) => vec![ListenAddress::Systemd(fd)],
+            Err(err) => {
+                tracing::error!("Failed to get the socket from systemd: {err}");
+                process::exit(1);
+            }
+        }
+    } else {
+        args.address.clone()
+    };
+    let permissions = SocketPermissions {
+        mode: args.web_socket_mode,
+        group: args.web_socket_group,
+    };
+    let limits = front::Limits {
+        max_connections: args.web_max_connections.into(),
+        read_timeout: args.web_read_timeout,
+        write_timeout: args.web_write_timeout,
+    };
+    let front = match front::Front::bind(&listen, &permissions, tls, limits) {
+        Ok(front) => front,
+        Err(err) => {
+            tracing::error!("Failed to listen on {err}");
+            process::exit(1);
+        }
+    };
+    // The permissions apply to the public sockets only.
+    let (address, permissions) = (front.backend(), SocketPermissions::default());
+    let servers = match server::bind(&address, &permissions) {
+        Ok(server) => vec![(server, address)],
+        Err(err) => {
+            tracing::error!("Failed to listen on {address}: {err}");
+            process::exit(1);
+        }
+    };
+    let mut files = front.files();
+    for address in &listen {
+        if let ListenAddress::Unix(path) = address {
+            files.push(path.clone());
+        }
+    }
+    cleanup::remove_on_exit(files);
+    if let Some(user) = &args.drop_privileges {
+        if let Some(user) = user {
+            if let Err(err) = front.chown(user) {
+                tracing::error!("Failed to hand the HTTP socket to the user: {err}");
+                process::exit(1);
+            }
+        }
+        if let Err(err) = privileges::drop_privileges(user.as_ref()) {
+            tracing::error!("Failed to drop privileges: {err}");
+            process::exit(1);
+        }
+    }
+    front.start(metrics.http.requests_rejected.clone());
+
+    let metrics = Arc::new(metrics);
+    let registry = Arc::new(registry);
+    let collectors = Arc::new(Collectors::start(metrics.clone(), inputs, Arc::new(config)));
+    let reload = config::in_use().then(|| {
+        let collectors = collectors.clone();
+        let power_unit = args.power_unit;
+        let labels = args.labels.clone();
+        Arc::new(move || {
+            let args = config::args(Args::command())?;
+            let args = Args::try_parse_from(args).map_err(|err| {
+                let err = err.to_string();
+                err.lines()
+                    .next()
+                    .unwrap_or_default()
+                    .trim_start_matches("error: ")
+                    .to_string()
+            })?;
+            // The families are only registered at startup, with their unit,
+            // groups and constant labels, which the sinks got as well.
+            if args.labels != labels {
+                return Err("--label can't be changed by reloading, restart to apply it".into());
+            }
+            let (inputs, mut config) = collector_config(
+                &args,
+                proxy.clone(),
+                recorder.clone(),
+                sinks.clone(),
+                day_ahead.clone(),
+            )?;
+            config.power_unit = power_unit;
+            config.metric_groups = groups.clone();
+            collectors.replace(inputs, Arc::new(config));
+            Ok(())
+        }) as Reload
+    });
+    if let Some(reload) = &reload {
+        reload::on_sighup(reload.clone());
+    }
+    systemd::start_notifier(
+        metrics.clone(),
+        args.max_telegram_age.unwrap_or(server::DEFAULT_READY_AGE),
+    );
+    if let Some(path) = args.textfile_output {
+        textfile::start_textfile_exporter(path, args.textfile_interval, registry.clone());
+    }
+    if let Some(url) = args.push_url.clone() {
+        let config = push::PushConfig {
+            url,
+            interval: args.push_interval,
+            job: args.push_job.clone(),
+            instance: args.push_instance.clone(),
+        };
+        if let Err(err) = push::start_pusher(config, registry.clone()) {
+            tracing::error!("Failed to set up pushing to the Pushgateway: {err}");
+            process::exit(1);
+        }
+    }
+    if let Some(url) = args.otlp_url.clone() {
+        let config = otlp::OtlpConfig {
+            url,
+            interval: args.otlp_interval,
+        };
+        if let Err(err) = otlp::start_exporter(config, registry.clone()) {
+            tracing::error!("Failed to set up exporting to the OpenTelemetry collector: {err}");
+            process::exit(1);
+        }
+    }
+    let server_config = ServerConfig {
+        no_eof: args.no_eof,
+        sample_timestamps: args.sample_timestamps,
+        metric_prefix: args.metric_prefix.clone(),
+        cache_ttl: args.metrics_cache_ttl,
+        debug_endpoint: args.debug_endpoint,
+        auth,
+        socket_permissions: permissions,
+        reload,
+        collectors,
+        access_log: args.web_access_log_level,
+        workers: args.web_max_concurrent_requests.into(),
+        request_timeout: args.web_request_timeout,
+    };
+    if let Err(err) = run_metrics_server(servers, registry, metrics, server_config) {
+        tracing::error!("Terminating: {err}")
+    }
+    cleanup::remove_files();
 }
@@ -0,0 +1,147 @@
+//! Manual page in roff, generated from the definition of the command line
+//! so that it documents every option.
+
+use clap::{Arg, Command};
+use std::fmt::Write as _;
+
+/// Returns the manual page of `cmd`, for section 1.
+pub fn render(cmd: &mut Command) -> String {
+    cmd.build();
+    let name = cmd.get_name().to_string();
+    let version = cmd.get_version().unwrap_or_default();
+
+    let mut page = String::new();
+    let _ = writeln!(
+        page,
+        ".TH {} 1 \"\" \"{} {}\"",
+        escape(&name.to_uppercase()),
+        escape(&name),
+        escape(version)
+    );
+    let _ = writeln!(page, ".SH NAME");
+    let about = cmd
+        .get_about()
+        .map_or(String::new(), |about| about.to_string());
+    let _ = writeln!(page, "{} \\- {}", escape(&name), escape(&about));
+    let _ = writeln!(page, ".SH SYNOPSIS");
+    let _ = writeln!(
+        page,
+        "\\fB{}\\fR [\\fIOPTIONS\\fR]\n.br\n\\fB{}\\fR \\fICOMMAND\\fR [\\fIOPTIONS\\fR]",
+        escape(&name),
+        escape(&name)
+    );
+    if let Some(about) = cmd.get_long_about() {
+        let _ = writeln!(page, ".SH DESCRIPTION");
+        paragraphs(&mut page, &about.to_string());
+    }
+    let _ = writeln!(page, ".SH OPTIONS");
+    options(&mut page, cmd);
+
+    let _ = writeln!(page, ".SH COMMANDS");
+    for sub in cmd
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+    {
+        let _ = writeln!(page, ".SS {} {}", escape(&name), escape(sub.get_name()));
+        if let Some(about) = sub.get_about() {
+            paragraphs(&mut page, &about.to_string());
+        }
+        options(&mut page, sub);
+    }
+    if let Some(author) = cmd.get_author() {
+        let _ = writeln!(page, ".SH AUTHORS");
+        paragraphs(&mut page, author);
+    }
+    page
+}
+
+fn options(page: &mut String, cmd: &Command) {
+    for arg in cmd.get_arguments().filter(|arg| !arg.is_hide_set()) {
+        let _ = writeln!(page, ".TP");
+        let _ = writeln!(page, "{}", synopsis(arg));
+        if let Some(help) = arg.get_long_help().or(arg.get_help()) {
+            paragraphs(page, &help.to_string());
+        }
+        let mut notes = Vec::new();
+        let values: Vec<_> = arg
+            .get_possible_values()
+            .iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| value.get_name().to_string())
+            .collect();
+        if !values.is_empty() {
+            notes.push(format!("Possible values: {}.", values.join(", ")));
+        }
+        let defaults: Vec<_> = arg
+            .get_default_values()
+            .iter()
+            .map(|value| value.to_string_lossy())
+            .collect();
+        if !defaults.is_empty() && !arg.is_hide_default_value_set() {
+            notes.push(format!("Default: {}.", defaults.join(", ")));
+        }
+        if let Some(env) = arg.get_env() {
+            notes.push(format!("Environment: {}.", env.to_string_lossy()));
+        }
+        if !notes.is_empty() {
+            let _ = writeln!(page, ".br");
+            let _ = writeln!(page, "{}", escape(&notes.join(" ")));
+        }
+    }
+}
+
+/// Returns the flags of an option and the name of its value, or the name
+/// of a positional argument.
+fn synopsis(arg: &Arg) -> String {
+    let value = arg
+        .get_value_names()
+        .and_then(|names| names.first())
+        .map_or_else(
+            || arg.get_id().as_str().to_uppercase(),
+            |name| name.to_string(),
+        );
+    if arg.is_positional() && arg.is_required_set() {
+        return format!("\\fI{}\\fR", escape(&value));
+    }
+    if arg.is_positional() {
+        return format!("[\\fI{}\\fR]", escape(&value));
+    }
+    let mut flags = Vec::new();
+    if let Some(short) = arg.get_short() {
+        flags.push(format!("\\fB\\-{}\\fR", escape(&short.to_string())));
+    }
+    if let Some(long) = arg.get_long() {
+        flags.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+    }
+    let mut synopsis = flags.join(", ");
+    if arg.get_num_args().is_some_and(|num| num.takes_values()) {
+        let _ = write!(synopsis, " \\fI{}\\fR", escape(&value));
+    }
+    synopsis
+}
+
+/// Writes text, keeping its blank-line separated paragraphs.
+fn paragraphs(page: &mut String, text: &str) {
+    for (i, paragraph) in text.split("\n\n").enumerate() {
+        if i > 0 {
+            let _ = writeln!(page, ".sp");
+        }
+        let _ = writeln!(page, "{}", escape(paragraph.trim()));
+    }
+}
+
+/// Escapes text for roff, also where it starts a line.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('-', "\\-")
+        .lines()
+        .map(|line| {
+            if line.starts_with(['.', '\'']) {
+                format!("\\&{line}")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
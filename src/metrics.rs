@@ -0,0 +1,1806 @@
+use crate::{events::Events, obis::ExtraObis, procfs::ProcessCollector};
+use prometheus_client::{
+    collector::Collector,
+    encoding::{EncodeLabel, EncodeLabelSet, EncodeMetric, LabelSetEncoder, MetricEncoder},
+    metrics::{
+        counter::{ConstCounter, Counter},
+        family::Family,
+        gauge::{ConstGauge, Gauge},
+        histogram::{exponential_buckets, Histogram},
+        MetricType,
+    },
+    registry::{Descriptor, LocalMetric, Metric, Prefix, Registry, Unit},
+    MaybeOwned,
+};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    fmt,
+    hash::Hash,
+    iter,
+    str::FromStr,
+    sync::{atomic::AtomicU64, Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The collector hasn't managed to connect to the reader since startup.
+pub const READER_NEVER_CONNECTED: i64 = 0;
+/// The collector is connected to the reader.
+pub const READER_UP: i64 = 1;
+/// The collector was connected to the reader before, but lost the connection.
+pub const READER_DOWN: i64 = 2;
+
+/// Labels of a series of a single meter: `meter`, when the meter is named,
+/// followed by the labels of the metric itself.
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct MeterLabels<L = ()> {
+    pub meter: Option<String>,
+    pub labels: L,
+}
+
+impl<L: EncodeLabelSet> EncodeLabelSet for MeterLabels<L> {
+    fn encode(&self, mut encoder: LabelSetEncoder) -> Result<(), fmt::Error> {
+        if let Some(meter) = &self.meter {
+            ("meter", meter.as_str()).encode(encoder.encode_label())?;
+        }
+        self.labels.encode(encoder)
+    }
+}
+
+/// The registered metric families, with a series per meter.
+#[derive(Default)]
+pub struct P1Metrics {
+    pub power_consumed: Family<MeterLabels, Gauge<f64, AtomicU64>>,
+    pub power_produced: Family<MeterLabels, Gauge<f64, AtomicU64>>,
+    pub power_net: Family<MeterLabels, Gauge<f64, AtomicU64>>,
+    pub power_consumed_peak: Family<MeterLabels<PeriodLabels>, Gauge<f64, AtomicU64>>,
+    pub power_produced_peak: Family<MeterLabels<PeriodLabels>, Gauge<f64, AtomicU64>>,
+    pub power_direction_conflicts: Family<MeterLabels, Counter>,
+
+    pub voltage: Family<MeterLabels<PhaseLabels>, Gauge<f64, AtomicU64>>,
+    pub current: Family<MeterLabels<PhaseLabels>, Gauge>,
+    pub phase_power_consumed: Family<MeterLabels<PhaseLabels>, Gauge<f64, AtomicU64>>,
+    pub phase_power_produced: Family<MeterLabels<PhaseLabels>, Gauge<f64, AtomicU64>>,
+    pub phase_reactive_power_consumed: Family<MeterLabels<PhaseLabels>, Gauge<f64, AtomicU64>>,
+    pub phase_reactive_power_produced: Family<MeterLabels<PhaseLabels>, Gauge<f64, AtomicU64>>,
+    pub phase_power_factor: Family<MeterLabels<PhaseLabels>, Gauge<f64, AtomicU64>>,
+
+    pub power_consumed_total: Family<MeterLabels<TariffLabels>, Counter<f64, AtomicU64>>,
+    pub power_produced_total: Family<MeterLabels<TariffLabels>, Counter<f64, AtomicU64>>,
+    pub power_net_energy: Family<MeterLabels<TariffLabels>, Gauge<f64, AtomicU64>>,
+
+    pub energy_cost_total: Family<MeterLabels, Counter<f64, AtomicU64>>,
+    pub gas_cost_total: Family<MeterLabels, Counter<f64, AtomicU64>>,
+    pub energy_price: Family<MeterLabels, Gauge<f64, AtomicU64>>,
+
+    pub active_tariff: Family<MeterLabels<TariffLabels>, Gauge>,
+    pub active_tariff_duration: Family<MeterLabels, Gauge<f64, AtomicU64>>,
+
+    pub average_demand: FloatGaugeFamily,
+    pub monthly_peak: FloatGaugeFamily,
+    pub peak_history: Family<MeterLabels<MonthLabels>, Gauge<f64, AtomicU64>>,
+    pub quarter_hour_energy_consumed: FloatGaugeFamily,
+    pub quarter_hour_energy_produced: FloatGaugeFamily,
+    pub quarter_hour_end: FloatGaugeFamily,
+
+    pub gas_consumed_total: Family<MeterLabels<MbusLabels>, Counter<f64, AtomicU64>>,
+    pub gas_flow: Family<MeterLabels<MbusLabels>, Gauge<f64, AtomicU64>>,
+    pub mbus_timestamp: Family<MeterLabels<MbusLabels>, Gauge<f64, AtomicU64>>,
+    pub water_consumed_total: Family<MeterLabels<MbusLabels>, Counter<f64, AtomicU64>>,
+    pub heat_consumed_total: Family<MeterLabels<MbusLabels>, Counter<f64, AtomicU64>>,
+    pub cooling_consumed_total: Family<MeterLabels<MbusLabels>, Counter<f64, AtomicU64>>,
+
+    pub power_failures: Family<MeterLabels, Counter>,
+    pub long_power_failures: Family<MeterLabels, Counter>,
+    pub long_power_failure_end: Family<MeterLabels<EventLabels>, Gauge<f64, AtomicU64>>,
+    pub long_power_failure_length: Family<MeterLabels<EventLabels>, Gauge<f64, AtomicU64>>,
+    pub long_power_failure_duration: Family<MeterLabels, Counter<f64, AtomicU64>>,
+    pub voltage_sags: Family<MeterLabels<PhaseLabels>, Counter>,
+    pub voltage_swells: Family<MeterLabels<PhaseLabels>, Counter>,
+
+    pub mbus_channel_present: Family<MeterLabels<ChannelLabels>, Gauge>,
+    pub mbus_device_info: Family<MeterLabels<MbusDeviceLabels>, Gauge>,
+    pub mbus_readings_ignored: Family<MeterLabels, Counter>,
+
+    pub meter_info: Family<MeterLabels<MeterInfoLabels>, Gauge>,
+    pub meter_message_info: Family<MeterLabels<MessageLabels>, Gauge>,
+    pub info: Family<MeterLabels<InfoLabels>, Gauge>,
+    pub dsmr_version_changes: Family<MeterLabels, Counter>,
+
+    /// One of [`READER_NEVER_CONNECTED`], [`READER_UP`] or [`READER_DOWN`].
+    pub reader_state: Family<MeterLabels, Gauge>,
+    pub reader_backoff: Family<MeterLabels, Gauge<f64, AtomicU64>>,
+    pub parser_synced: Family<MeterLabels, Gauge>,
+    pub reader_connection_up: Family<MeterLabels, Gauge>,
+    pub reader_reconnects: Family<MeterLabels, Counter>,
+    pub reader_endpoint_active: Family<MeterLabels<EndpointLabels>, Gauge>,
+    pub collector_panics: Family<MeterLabels, Counter>,
+
+    pub bytes_read: Family<MeterLabels, Counter>,
+    pub bytes_parsed: Family<MeterLabels, Counter>,
+    pub readouts_skipped: Family<MeterLabels, Counter>,
+    pub telegrams_received: Family<MeterLabels, Counter>,
+    pub telegram_parse_errors: Family<MeterLabels, Counter>,
+    pub counter_regressions: Family<MeterLabels<MetricLabels>, Counter>,
+    pub readings_rejected: Family<MeterLabels<ReasonLabels>, Counter>,
+
+    pub telegram_interval: Family<MeterLabels, Gauge<f64, AtomicU64>>,
+    /// Telegram arrival times of every meter.
+    pub telegram_timings: TelegramTimings,
+    pub last_telegram_timestamp: Family<MeterLabels, Gauge<f64, AtomicU64>>,
+    pub meter_timestamp: Family<MeterLabels, Gauge<f64, AtomicU64>>,
+    pub meter_clock_drift: Family<MeterLabels, Gauge<f64, AtomicU64>>,
+    /// Recent raw telegrams of every meter.
+    pub recent_telegrams: RecentTelegramsByMeter,
+    /// Subscribers to the readings of every meter.
+    pub events: Arc<Events>,
+    /// Age after which the telegrams of a meter are no longer fresh.
+    pub max_telegram_age: Option<Duration>,
+    /// Latest power and energy readings of every meter.
+    pub meter_readings: ReadingsByMeter,
+    /// Whether to export the sums over all meters, with --aggregate-meters.
+    pub aggregate_meters: bool,
+
+    /// Objects exported under the same name share the family.
+    pub extra_obis: Vec<(ExtraObis, ExtraFamily)>,
+    pub obis_value: Family<MeterLabels<CodeLabels>, Gauge<f64, AtomicU64>>,
+
+    pub reader: ReaderMetrics,
+    pub http: HttpMetrics,
+}
+
+type FloatGaugeFamily = Family<MeterLabels, Gauge<f64, AtomicU64>>;
+type TelegramTimings = Arc<Mutex<Vec<(MeterLabels, Arc<Mutex<TelegramTiming>>)>>>;
+type RecentTelegramsByMeter = Arc<Mutex<Vec<(Option<String>, Arc<Mutex<RecentTelegrams>>)>>>;
+type ReadingsByMeter = Arc<Mutex<Vec<Arc<Mutex<MeterReadings>>>>>;
+type TariffLabels = [(&'static str, String); 1];
+type PhaseLabels = [(&'static str, &'static str); 1];
+type ChannelLabels = [(&'static str, u8); 1];
+type MonthLabels = [(&'static str, String); 1];
+type PeriodLabels = [(&'static str, &'static str); 1];
+type MetricLabels = [(&'static str, String); 1];
+type ReasonLabels = [(&'static str, &'static str); 1];
+type CodeLabels = [(&'static str, String); 1];
+type ExtraLabels = Vec<(String, String)>;
+type EndpointLabels = [(&'static str, String); 1];
+type MessageLabels = [(&'static str, String); 1];
+type EventLabels = [(&'static str, usize); 1];
+
+/// The series of a single meter, updated by its collector.
+pub struct MeterMetrics {
+    /// Name of the meter, if any.
+    pub meter: Option<String>,
+
+    pub power_consumed: MeterFamily<(), Gauge<f64, AtomicU64>>,
+    pub power_produced: MeterFamily<(), Gauge<f64, AtomicU64>>,
+    /// Consumed minus produced power.
+    pub power_net: MeterFamily<(), Gauge<f64, AtomicU64>>,
+    /// Highest power of the current day and month.
+    pub power_consumed_peak: MeterFamily<PeriodLabels, Gauge<f64, AtomicU64>>,
+    pub power_produced_peak: MeterFamily<PeriodLabels, Gauge<f64, AtomicU64>>,
+    pub power_direction_conflicts: Counter,
+
+    pub voltage: MeterFamily<PhaseLabels, Gauge<f64, AtomicU64>>,
+    pub current: MeterFamily<PhaseLabels, Gauge>,
+    pub phase_power_consumed: MeterFamily<PhaseLabels, Gauge<f64, AtomicU64>>,
+    pub phase_power_produced: MeterFamily<PhaseLabels, Gauge<f64, AtomicU64>>,
+    pub phase_reactive_power_consumed: MeterFamily<PhaseLabels, Gauge<f64, AtomicU64>>,
+    pub phase_reactive_power_produced: MeterFamily<PhaseLabels, Gauge<f64, AtomicU64>>,
+    pub phase_power_factor: MeterFamily<PhaseLabels, Gauge<f64, AtomicU64>>,
+
+    pub power_consumed_total: MeterFamily<TariffLabels, Counter<f64, AtomicU64>>,
+    pub power_produced_total: MeterFamily<TariffLabels, Counter<f64, AtomicU64>>,
+    /// Consumed minus produced energy, which may decrease, hence a gauge.
+    pub power_net_energy: MeterFamily<TariffLabels, Gauge<f64, AtomicU64>>,
+
+    /// Only created when priced.
+    pub energy_cost: MeterFamily<(), Counter<f64, AtomicU64>>,
+    pub gas_cost: MeterFamily<(), Counter<f64, AtomicU64>>,
+    /// Only created while the current price of a dynamic contract is known.
+    pub energy_price: MeterFamily<(), Gauge<f64, AtomicU64>>,
+
+    pub active_tariff: MeterFamily<TariffLabels, Gauge>,
+    pub active_tariff_duration: Gauge<f64, AtomicU64>,
+
+    /// Only created for the meters reporting them, Belgian ones.
+    pub average_demand: MeterFamily<(), Gauge<f64, AtomicU64>>,
+    pub monthly_peak: MeterFamily<(), Gauge<f64, AtomicU64>>,
+    pub peak_history: MeterFamily<MonthLabels, Gauge<f64, AtomicU64>>,
+    /// Energy of the last completed quarter hour of the clock, only created
+    /// once the exporter saw one from its start to its end.
+    pub quarter_hour_energy_consumed: MeterFamily<(), Gauge<f64, AtomicU64>>,
+    pub quarter_hour_energy_produced: MeterFamily<(), Gauge<f64, AtomicU64>>,
+    pub quarter_hour_end: MeterFamily<(), Gauge<f64, AtomicU64>>,
+
+    pub gas_consumed_total: MeterFamily<MbusLabels, Counter<f64, AtomicU64>>,
+    /// Only created once the gas meter captured two readings.
+    pub gas_flow: MeterFamily<MbusLabels, Gauge<f64, AtomicU64>>,
+    /// Capture time of the last reading of every M-Bus device.
+    pub mbus_timestamp: MeterFamily<MbusLabels, Gauge<f64, AtomicU64>>,
+    pub water_consumed_total: MeterFamily<MbusLabels, Counter<f64, AtomicU64>>,
+    pub heat_consumed_total: MeterFamily<MbusLabels, Counter<f64, AtomicU64>>,
+    pub cooling_consumed_total: MeterFamily<MbusLabels, Counter<f64, AtomicU64>>,
+
+    pub power_failures: Counter,
+    pub long_power_failures: Counter,
+    /// End and length of the most recent long power failures, from the
+    /// meter's event log, with event 1 the most recent.
+    pub long_power_failure_end: MeterFamily<EventLabels, Gauge<f64, AtomicU64>>,
+    pub long_power_failure_length: MeterFamily<EventLabels, Gauge<f64, AtomicU64>>,
+    /// Of the logged long power failures, including those logged before
+    /// the exporter started.
+    pub long_power_failure_duration: Counter<f64, AtomicU64>,
+    pub voltage_sags: MeterFamily<PhaseLabels, Counter>,
+    pub voltage_swells: MeterFamily<PhaseLabels, Counter>,
+
+    pub mbus_channel_present: MeterFamily<ChannelLabels, Gauge>,
+    pub mbus_device_info: MeterFamily<MbusDeviceLabels, Gauge>,
+    pub mbus_readings_ignored: Counter,
+
+    pub meter_info: MeterFamily<MeterInfoLabels, Gauge>,
+    /// Only created while the meter displays a text message.
+    pub meter_message_info: MeterFamily<MessageLabels, Gauge>,
+    pub info: MeterFamily<InfoLabels, Gauge>,
+    pub dsmr_version_changes: Counter,
+
+    /// One of [`READER_NEVER_CONNECTED`], [`READER_UP`] or [`READER_DOWN`].
+    pub reader_state: Gauge,
+    pub reader_backoff: Gauge<f64, AtomicU64>,
+    pub parser_synced: Gauge,
+    pub reader_connection_up: Gauge,
+    pub reader_reconnects: Counter,
+    /// Only created for the meters with backup readers.
+    pub reader_endpoint_active: MeterFamily<EndpointLabels, Gauge>,
+    pub collector_panics: Counter,
+
+    pub bytes_read: Counter,
+    pub bytes_parsed: Counter,
+    pub readouts_skipped: Counter,
+    pub telegrams_received: Counter,
+    pub telegram_parse_errors: Counter,
+    /// Totals ignored for going backwards, by the family.
+    pub counter_regressions: MeterFamily<MetricLabels, Counter>,
+    /// Telegrams skipped for implausible readings, by the reason.
+    pub readings_rejected: MeterFamily<ReasonLabels, Counter>,
+
+    pub telegram_interval: Gauge<f64, AtomicU64>,
+    pub telegram_timing: Arc<Mutex<TelegramTiming>>,
+    pub last_telegram_timestamp: Gauge<f64, AtomicU64>,
+    /// Only created for the meters reporting their time.
+    pub meter_timestamp: MeterFamily<(), Gauge<f64, AtomicU64>>,
+    /// How far the clock of the meter is ahead of the system's.
+    pub meter_clock_drift: MeterFamily<(), Gauge<f64, AtomicU64>>,
+    pub recent_telegrams: Arc<Mutex<RecentTelegrams>>,
+    pub readings: Arc<Mutex<MeterReadings>>,
+    pub events: Arc<Events>,
+
+    pub extra_obis: Vec<(ExtraObis, ExtraSeries)>,
+    /// Every numeric object of the telegrams, with --export-raw-obis.
+    pub obis_value: MeterFamily<CodeLabels, Gauge<f64, AtomicU64>>,
+
+    pub connection_duration: Histogram,
+}
+
+impl MeterMetrics {
+    /// Removes the gauges set from every telegram, until the next one, for
+    /// the readings no longer to be served once the meter went silent.
+    pub fn expire_readings(&self) {
+        self.power_consumed.clear();
+        self.power_produced.clear();
+        self.power_net.clear();
+        self.voltage.clear();
+        self.current.clear();
+        self.phase_power_consumed.clear();
+        self.phase_power_produced.clear();
+        self.phase_reactive_power_consumed.clear();
+        self.phase_reactive_power_produced.clear();
+        self.phase_power_factor.clear();
+        self.power_net_energy.clear();
+        self.energy_price.clear();
+        self.active_tariff.clear();
+        self.average_demand.clear();
+        self.monthly_peak.clear();
+        self.meter_clock_drift.clear();
+        self.obis_value.clear();
+        *self.readings.lock().unwrap() = MeterReadings::default();
+    }
+}
+
+/// The series of a single meter in a family with additional labels.
+pub struct MeterFamily<L, M> {
+    meter: Option<String>,
+    family: Family<MeterLabels<L>, M>,
+    /// Labels of the series created through this handle.
+    created: Mutex<HashSet<L>>,
+}
+
+impl<L, M> MeterFamily<L, M>
+where
+    L: Clone + Hash + Eq,
+    M: Clone + Default,
+{
+    fn new(meter: &Option<String>, family: &Family<MeterLabels<L>, M>) -> Self {
+        MeterFamily {
+            meter: meter.clone(),
+            family: family.clone(),
+            created: Mutex::default(),
+        }
+    }
+
+    fn labels(&self, labels: &L) -> MeterLabels<L> {
+        MeterLabels {
+            meter: self.meter.clone(),
+            labels: labels.clone(),
+        }
+    }
+
+    pub fn get_or_create(&self, labels: &L) -> M {
+        self.created.lock().unwrap().insert(labels.clone());
+        self.family.get_or_create(&self.labels(labels)).clone()
+    }
+
+    /// Removes all series of the meter.
+    pub fn clear(&self) {
+        for labels in self.created.lock().unwrap().drain() {
+            self.family.remove(&self.labels(&labels));
+        }
+    }
+}
+
+/// The family of the `--extra-obis` objects of a name.
+#[derive(Clone, Debug)]
+pub enum ExtraFamily {
+    Gauge(Family<MeterLabels<ExtraLabels>, Gauge<f64, AtomicU64>>),
+    Counter(Family<MeterLabels<ExtraLabels>, Counter<f64, AtomicU64>>),
+}
+
+impl ExtraFamily {
+    fn get_or_create(&self, labels: &MeterLabels<ExtraLabels>) -> ExtraSeries {
+        match self {
+            ExtraFamily::Gauge(family) => ExtraSeries::Gauge(family.get_or_create(labels).clone()),
+            ExtraFamily::Counter(family) => {
+                ExtraSeries::Counter(family.get_or_create(labels).clone())
+            }
+        }
+    }
+
+    fn remove(&self, labels: &MeterLabels<ExtraLabels>) {
+        match self {
+            ExtraFamily::Gauge(family) => family.remove(labels),
+            ExtraFamily::Counter(family) => family.remove(labels),
+        };
+    }
+}
+
+/// The series of an `--extra-obis` object of a meter.
+pub enum ExtraSeries {
+    Gauge(Gauge<f64, AtomicU64>),
+    Counter(Counter<f64, AtomicU64>),
+}
+
+/// Metrics of the connections to the P1 reader that need explicit setup.
+#[derive(Default)]
+pub struct ReaderMetrics {
+    pub connection_duration: HistogramFamily,
+}
+
+/// Connection duration histograms by meter.
+///
+/// Unlike a [`Family`], leaves the histogram of an unnamed meter without a
+/// label set, for which the encoder would emit a dangling comma after `le`.
+#[derive(Clone, Debug, Default)]
+pub struct HistogramFamily(Arc<Mutex<Vec<(MeterLabels, Histogram)>>>);
+
+impl HistogramFamily {
+    fn get_or_create(&self, labels: &MeterLabels) -> Histogram {
+        let mut histograms = self.0.lock().unwrap();
+        if let Some((_, histogram)) = histograms.iter().find(|(l, _)| l == labels) {
+            return histogram.clone();
+        }
+        // From a second to several hours.
+        let histogram = Histogram::new(exponential_buckets(1.0, 4.0, 8));
+        histograms.push((labels.clone(), histogram.clone()));
+        histogram
+    }
+
+    fn remove(&self, labels: &MeterLabels) {
+        self.0.lock().unwrap().retain(|(l, _)| l != labels);
+    }
+}
+
+impl EncodeMetric for HistogramFamily {
+    fn encode(&self, mut encoder: MetricEncoder) -> Result<(), fmt::Error> {
+        let histograms = self.0.lock().unwrap();
+        if let [(MeterLabels { meter: None, .. }, histogram)] = histograms.as_slice() {
+            return histogram.encode(encoder);
+        }
+        for (labels, histogram) in histograms.iter() {
+            histogram.encode(encoder.encode_family(labels)?)?;
+        }
+        Ok(())
+    }
+
+    fn metric_type(&self) -> MetricType {
+        MetricType::Histogram
+    }
+}
+
+impl ReaderMetrics {
+    fn register(&self, registry: &mut Enabled) {
+        registry.register_with_unit(
+            "reader_connection_duration",
+            "Lifetime of the connections to the P1 reader",
+            Unit::Seconds,
+            self.connection_duration.clone(),
+        );
+    }
+}
+
+/// Latest readings of a meter, summed over all meters on each scrape.
+#[derive(Debug, Default)]
+pub struct MeterReadings {
+    /// In the power unit.
+    pub power_consumed: Option<f64>,
+    pub power_produced: Option<f64>,
+    /// Over all tariffs, in kWh.
+    pub energy_consumed: Option<f64>,
+    pub energy_produced: Option<f64>,
+}
+
+/// Arrival times of telegrams, shared between the collector and scrapes.
+#[derive(Debug, Default)]
+pub struct TelegramTiming {
+    /// When the last telegram was received.
+    pub last: Option<Instant>,
+    /// Rolling average of the interval between telegrams, in seconds.
+    pub interval: Option<f64>,
+}
+
+impl TelegramTiming {
+    /// Weight of the latest interval in the rolling average.
+    const WEIGHT: f64 = 0.2;
+
+    /// Records a telegram received at `now`, `since_previous` after the
+    /// previous one on the same connection.
+    pub fn record(&mut self, now: Instant, since_previous: Option<Duration>) {
+        self.last = Some(now);
+        if let Some(since_previous) = since_previous {
+            let since_previous = since_previous.as_secs_f64();
+            self.interval = Some(match self.interval {
+                Some(interval) => interval + Self::WEIGHT * (since_previous - interval),
+                None => since_previous,
+            });
+        }
+    }
+}
+
+/// A raw telegram and when it was received.
+#[derive(Clone, Debug)]
+pub struct RawTelegram {
+    pub text: String,
+    pub received: SystemTime,
+    /// Why the telegram was rejected, if it was.
+    pub error: Option<String>,
+}
+
+impl RawTelegram {
+    /// Returns the Unix time the telegram was received at.
+    pub fn received_unix(&self) -> f64 {
+        let received = self.received.duration_since(UNIX_EPOCH).unwrap_or_default();
+        received.as_secs_f64()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RecentTelegrams {
+    /// The last telegram received, as served on `/debug/telegram`.
+    pub last: Option<RawTelegram>,
+    /// The last telegram parsed, as served on `/api/v1/state`.
+    pub last_parsed: Option<RawTelegram>,
+}
+
+/// A constant label of all families, given as `NAME=VALUE`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConstLabel {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for ConstLabel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=VALUE, got '{s}'"))?;
+        let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !name.starts_with("__");
+        if !valid_name {
+            return Err(format!("invalid label name '{name}'"));
+        }
+        Ok(ConstLabel {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+impl ConstLabel {
+    fn pair(&self) -> (Cow<'static, str>, Cow<'static, str>) {
+        (self.name.clone().into(), self.value.clone().into())
+    }
+}
+
+/// A group of metric families, exported or not as a whole.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricGroup {
+    /// Total power.
+    Power,
+    /// Voltage, current and power per phase.
+    Phases,
+    /// Energy totals per tariff.
+    Energy,
+    Tariff,
+    /// Quarter-hour demand and its peaks.
+    Demand,
+    /// Gas, water, heat and cooling meters on the M-Bus channels.
+    Mbus,
+    /// Power failures, voltage sags and swells.
+    Quality,
+    Info,
+    Reader,
+    Telegram,
+    Http,
+    /// Resource usage of the exporter itself.
+    Process,
+    /// Costs of the consumed energy and gas.
+    Cost,
+}
+
+impl MetricGroup {
+    pub const ALL: [MetricGroup; 13] = [
+        MetricGroup::Power,
+        MetricGroup::Phases,
+        MetricGroup::Energy,
+        MetricGroup::Tariff,
+        MetricGroup::Demand,
+        MetricGroup::Mbus,
+        MetricGroup::Quality,
+        MetricGroup::Info,
+        MetricGroup::Reader,
+        MetricGroup::Telegram,
+        MetricGroup::Http,
+        MetricGroup::Process,
+        MetricGroup::Cost,
+    ];
+
+    /// Returns the group of a family given its name without the prefix, if
+    /// it belongs to one.
+    pub fn of(family: &str) -> Option<Self> {
+        Some(match family {
+            "power_consumed_kw"
+            | "power_produced_kw"
+            | "power_net_kw"
+            | "power_consumed_peak_kw"
+            | "power_produced_peak_kw"
+            | "power_consumed_watts"
+            | "power_produced_watts"
+            | "power_net_watts"
+            | "power_consumed_peak_watts"
+            | "power_produced_peak_watts"
+            | "power_direction_conflicts"
+            | "site_power_consumed_kw"
+            | "site_power_produced_kw"
+            | "site_power_consumed_watts"
+            | "site_power_produced_watts" => MetricGroup::Power,
+            "voltage_volts"
+            | "current_amperes"
+            | "phase_power_consumed_kw"
+            | "phase_power_produced_kw"
+            | "phase_power_consumed_watts"
+            | "phase_power_produced_watts"
+            | "phase_reactive_power_consumed_kvar"
+            | "phase_reactive_power_produced_kvar"
+            | "phase_reactive_power_consumed_var"
+            | "phase_reactive_power_produced_var"
+            | "phase_power_factor" => MetricGroup::Phases,
+            "power_consumed_kwh"
+            | "power_produced_kwh"
+            | "power_net_kwh"
+            | "site_power_consumed_kwh"
+            | "site_power_produced_kwh"
+            | "power_consumed_joules"
+            | "power_produced_joules"
+            | "power_net_joules"
+            | "site_power_consumed_joules"
+            | "site_power_produced_joules" => MetricGroup::Energy,
+            "energy_cost" | "gas_cost" | "energy_price" => MetricGroup::Cost,
+            "active_tariff" | "active_tariff_duration_seconds" => MetricGroup::Tariff,
+            "average_demand_kw"
+            | "monthly_peak_kw"
+            | "peak_history_kw"
+            | "average_demand_watts"
+            | "monthly_peak_watts"
+            | "peak_history_watts"
+            | "quarter_hour_energy_consumed_kwh"
+            | "quarter_hour_energy_produced_kwh"
+            | "quarter_hour_energy_consumed_joules"
+            | "quarter_hour_energy_produced_joules"
+            | "quarter_hour_end_timestamp_seconds" => MetricGroup::Demand,
+            "gas_consumed_cubic_meters"
+            | "gas_flow_cubic_meters_per_hour"
+            | "mbus_timestamp_seconds"
+            | "water_consumed_cubic_meters"
+            | "heat_consumed_gj"
+            | "cooling_consumed_gj"
+            | "mbus_channel_present"
+            | "mbus_device_info"
+            | "mbus_readings_ignored" => MetricGroup::Mbus,
+            "power_failures"
+            | "long_power_failures"
+            | "long_power_failure_end_timestamp_seconds"
+            | "long_power_failure_length_seconds"
+            | "long_power_failure_duration_seconds"
+            | "voltage_sags"
+            | "voltage_swells" => MetricGroup::Quality,
+            "meter_info"
+            | "meter_message_info"
+            | "info"
+            | "dsmr_version_changes"
+            | "exporter_build_info" => MetricGroup::Info,
+            "reader_state"
+            | "reader_backoff_seconds"
+            | "parser_synced"
+            | "reader_connection_up"
+            | "reader_reconnects"
+            | "reader_endpoint_active"
+            | "reader_connection_duration_seconds"
+            | "collector_panics" => MetricGroup::Reader,
+            "bytes_read"
+            | "bytes_parsed"
+            | "readouts_skipped"
+            | "telegrams_received"
+            | "telegram_parse_errors"
+            | "counter_regressions"
+            | "readings_rejected"
+            | "telegram_interval_seconds"
+            | "last_telegram_timestamp_seconds"
+            | "meter_timestamp_seconds"
+            | "meter_clock_drift_seconds"
+            | "next_telegram_expected_seconds"
+            | "telegram_fresh" => MetricGroup::Telegram,
+            "http_response_bytes"
+            | "metrics_cache_hits"
+            | "metrics_cache_misses"
+            | "http_requests_rejected" => MetricGroup::Http,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            MetricGroup::Power => "power",
+            MetricGroup::Phases => "phases",
+            MetricGroup::Energy => "energy",
+            MetricGroup::Tariff => "tariff",
+            MetricGroup::Demand => "demand",
+            MetricGroup::Mbus => "mbus",
+            MetricGroup::Quality => "quality",
+            MetricGroup::Info => "info",
+            MetricGroup::Reader => "reader",
+            MetricGroup::Telegram => "telegram",
+            MetricGroup::Http => "http",
+            MetricGroup::Process => "process",
+            MetricGroup::Cost => "cost",
+        }
+    }
+}
+
+impl FromStr for MetricGroup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MetricGroup::ALL
+            .into_iter()
+            .find(|group| group.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = MetricGroup::ALL.iter().map(|group| group.name()).collect();
+                format!(
+                    "unknown metric group '{s}', expected one of {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Unit of the power and energy families, which also ends their names:
+/// kW and kWh as the meter reports them, or the base units watts and joules.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PowerUnit {
+    #[default]
+    Kilowatts,
+    Watts,
+}
+
+impl PowerUnit {
+    /// Returns a power given in kW in this unit.
+    pub fn scale(self, kw: f64) -> f64 {
+        match self {
+            PowerUnit::Kilowatts => kw,
+            PowerUnit::Watts => kw * 1000.0,
+        }
+    }
+
+    /// Returns an energy given in kWh in this unit.
+    pub fn energy(self, kwh: f64) -> f64 {
+        match self {
+            PowerUnit::Kilowatts => kwh,
+            PowerUnit::Watts => kwh * 3_600_000.0,
+        }
+    }
+
+    /// Returns an energy in this unit in kWh, the inverse of [`Self::energy`].
+    pub fn kwh(self, energy: f64) -> f64 {
+        match self {
+            PowerUnit::Kilowatts => energy,
+            PowerUnit::Watts => energy / 3_600_000.0,
+        }
+    }
+
+    /// Returns the name of a power or energy sample given its name in kW or
+    /// kWh.
+    pub fn sample_name(self, kw: &'static str) -> &'static str {
+        match (self, kw) {
+            (PowerUnit::Watts, "power_consumed_kw") => "power_consumed_watts",
+            (PowerUnit::Watts, "power_produced_kw") => "power_produced_watts",
+            (PowerUnit::Watts, "power_net_kw") => "power_net_watts",
+            (PowerUnit::Watts, "power_consumed_peak_kw") => "power_consumed_peak_watts",
+            (PowerUnit::Watts, "power_produced_peak_kw") => "power_produced_peak_watts",
+            (PowerUnit::Watts, "phase_power_consumed_kw") => "phase_power_consumed_watts",
+            (PowerUnit::Watts, "phase_power_produced_kw") => "phase_power_produced_watts",
+            (PowerUnit::Watts, "phase_reactive_power_consumed_kvar") => {
+                "phase_reactive_power_consumed_var"
+            }
+            (PowerUnit::Watts, "phase_reactive_power_produced_kvar") => {
+                "phase_reactive_power_produced_var"
+            }
+            (PowerUnit::Watts, "average_demand_kw") => "average_demand_watts",
+            (PowerUnit::Watts, "monthly_peak_kw") => "monthly_peak_watts",
+            (PowerUnit::Watts, "power_consumed_kwh") => "power_consumed_joules",
+            (PowerUnit::Watts, "power_produced_kwh") => "power_produced_joules",
+            (PowerUnit::Watts, "power_net_kwh") => "power_net_joules",
+            (PowerUnit::Watts, "quarter_hour_energy_consumed_kwh") => {
+                "quarter_hour_energy_consumed_joules"
+            }
+            (PowerUnit::Watts, "quarter_hour_energy_produced_kwh") => {
+                "quarter_hour_energy_produced_joules"
+            }
+            _ => kw,
+        }
+    }
+
+    fn unit(self) -> Unit {
+        // Kilowatts keep the names the families always had.
+        Unit::Other(match self {
+            PowerUnit::Kilowatts => "kw".to_string(),
+            PowerUnit::Watts => "watts".to_string(),
+        })
+    }
+
+    fn energy_unit(self) -> Unit {
+        match self {
+            PowerUnit::Kilowatts => Unit::Other("kwh".to_string()),
+            PowerUnit::Watts => Unit::Joules,
+        }
+    }
+
+    /// The unit of reactive power, in the magnitude of this unit.
+    fn reactive_unit(self) -> Unit {
+        Unit::Other(match self {
+            PowerUnit::Kilowatts => "kvar".to_string(),
+            PowerUnit::Watts => "var".to_string(),
+        })
+    }
+}
+
+impl FromStr for PowerUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kilowatts" | "kw" => Ok(PowerUnit::Kilowatts),
+            "watts" | "w" => Ok(PowerUnit::Watts),
+            _ => Err(format!(
+                "unknown power unit '{s}', expected watts or kilowatts"
+            )),
+        }
+    }
+}
+
+/// Registers the families of the enabled groups only.
+struct Enabled<'a> {
+    registry: &'a mut Registry,
+    groups: &'a [MetricGroup],
+}
+
+impl Enabled<'_> {
+    fn register(&mut self, name: &str, help: &str, metric: impl Metric) {
+        if MetricGroup::of(name).is_none_or(|group| self.groups.contains(&group)) {
+            self.registry.register(name, help, metric);
+        }
+    }
+
+    /// Registers a family whose name ends with its unit, which is added to
+    /// `name`.
+    fn register_with_unit(&mut self, name: &str, help: &str, unit: Unit, metric: impl Metric) {
+        let full = format!("{name}_{}", unit.as_str());
+        if MetricGroup::of(&full).is_none_or(|group| self.groups.contains(&group)) {
+            self.registry.register_with_unit(name, help, unit, metric);
+        }
+    }
+}
+
+/// Computes the time until the next telegram is expected on each scrape.
+#[derive(Debug)]
+struct NextTelegramCollector {
+    timings: TelegramTimings,
+    prefix: Prefix,
+    labels: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+}
+
+impl Collector for NextTelegramCollector {
+    fn collect<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = (Cow<'a, Descriptor>, MaybeOwned<'a, Box<dyn LocalMetric>>)> + 'a>
+    {
+        let family = FloatGaugeFamily::default();
+        let mut empty = true;
+        for (labels, timing) in self.timings.lock().unwrap().iter() {
+            let timing = timing.lock().unwrap();
+            if let (Some(last), Some(interval)) = (timing.last, timing.interval) {
+                family
+                    .get_or_create(labels)
+                    .set(interval - last.elapsed().as_secs_f64());
+                empty = false;
+            }
+        }
+        if empty {
+            return Box::new(iter::empty());
+        }
+        let descriptor = Descriptor::new(
+            "next_telegram_expected",
+            "Seconds until the next telegram is expected, negative when overdue",
+            Some(Unit::Seconds),
+            Some(&self.prefix),
+            self.labels.clone(),
+        );
+        let family: Box<dyn LocalMetric> = Box::new(family);
+        Box::new(iter::once((
+            Cow::Owned(descriptor),
+            MaybeOwned::Owned(family),
+        )))
+    }
+}
+
+/// Computes whether the last telegram is recent enough on each scrape.
+#[derive(Debug)]
+struct FreshTelegramCollector {
+    timings: TelegramTimings,
+    max_age: Duration,
+    prefix: Prefix,
+    labels: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+}
+
+impl Collector for FreshTelegramCollector {
+    fn collect<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = (Cow<'a, Descriptor>, MaybeOwned<'a, Box<dyn LocalMetric>>)> + 'a>
+    {
+        let family = Family::<MeterLabels, Gauge>::default();
+        for (labels, timing) in self.timings.lock().unwrap().iter() {
+            let last = timing.lock().unwrap().last;
+            let fresh = last.is_some_and(|last| last.elapsed() <= self.max_age);
+            family.get_or_create(labels).set(fresh as i64);
+        }
+        let descriptor = Descriptor::new(
+            "telegram_fresh",
+            "Whether a telegram has been received within --max-telegram-age",
+            None,
+            Some(&self.prefix),
+            self.labels.clone(),
+        );
+        let family: Box<dyn LocalMetric> = Box::new(family);
+        Box::new(iter::once((
+            Cow::Owned(descriptor),
+            MaybeOwned::Owned(family),
+        )))
+    }
+}
+
+/// Sums the power and energy readings of all meters on each scrape.
+#[derive(Debug)]
+struct SiteCollector {
+    readings: ReadingsByMeter,
+    power_unit: PowerUnit,
+    /// Whether the power and energy groups are enabled.
+    power: bool,
+    energy: bool,
+    prefix: Prefix,
+    labels: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+}
+
+impl Collector for SiteCollector {
+    fn collect<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = (Cow<'a, Descriptor>, MaybeOwned<'a, Box<dyn LocalMetric>>)> + 'a>
+    {
+        let mut sums = [None::<f64>; 4];
+        for readings in self.readings.lock().unwrap().iter() {
+            let readings = readings.lock().unwrap();
+            let values = [
+                readings.power_consumed,
+                readings.power_produced,
+                readings.energy_consumed,
+                readings.energy_produced,
+            ];
+            for (sum, value) in sums.iter_mut().zip(values) {
+                if let Some(value) = value {
+                    *sum = Some(sum.unwrap_or_default() + value);
+                }
+            }
+        }
+        let [power_consumed, power_produced, energy_consumed, energy_produced] = sums;
+        let mut families: Vec<(Descriptor, Box<dyn LocalMetric>)> = vec![];
+        for (name, help, sum) in [
+            (
+                "site_power_consumed",
+                "Power consumed by all meters",
+                power_consumed,
+            ),
+            (
+                "site_power_produced",
+                "Power produced by all meters",
+                power_produced,
+            ),
+        ] {
+            if let (true, Some(sum)) = (self.power, sum) {
+                let descriptor = Descriptor::new(
+                    name,
+                    help,
+                    Some(self.power_unit.unit()),
+                    Some(&self.prefix),
+                    self.labels.clone(),
+                );
+                families.push((descriptor, Box::new(ConstGauge::new(sum))));
+            }
+        }
+        for (name, help, sum) in [
+            (
+                "site_power_consumed",
+                "Total consumed power of all meters",
+                energy_consumed,
+            ),
+            (
+                "site_power_produced",
+                "Total produced power of all meters",
+                energy_produced,
+            ),
+        ] {
+            if let (true, Some(sum)) = (self.energy, sum) {
+                let descriptor = Descriptor::new(
+                    name,
+                    help,
+                    Some(self.power_unit.energy_unit()),
+                    Some(&self.prefix),
+                    self.labels.clone(),
+                );
+                let sum = self.power_unit.energy(sum);
+                families.push((descriptor, Box::new(ConstCounter::new(sum))));
+            }
+        }
+        Box::new(
+            families
+                .into_iter()
+                .map(|(descriptor, family)| (Cow::Owned(descriptor), MaybeOwned::Owned(family))),
+        )
+    }
+}
+
+/// Labels of the readings of an M-Bus device.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct MbusLabels {
+    pub mbus_channel: u8,
+    pub equipment_id: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct MbusDeviceLabels {
+    pub channel: u8,
+    pub device_type: u64,
+    pub medium: &'static str,
+    pub equipment_id: String,
+}
+
+/// Labels of `p1_meter_info`, identifying a meter and its gas meter, if any.
+/// Identifiers not reported are empty.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct MeterInfoLabels {
+    pub dsmr_version: String,
+    pub equipment_id: String,
+    pub gas_equipment_id: String,
+}
+
+/// Labels of `p1_exporter_build_info`.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct BuildInfoLabels {
+    version: &'static str,
+    revision: &'static str,
+    rustc: &'static str,
+}
+
+/// Labels of `p1_info`, identifying a meter and the exporter reading it.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct InfoLabels {
+    pub version: &'static str,
+    pub dsmr_version: String,
+    pub equipment_id: String,
+    pub source: String,
+}
+
+/// Metrics of the HTTP server itself.
+pub struct HttpMetrics {
+    pub response_bytes: Histogram,
+    pub cache_hits: Counter,
+    pub cache_misses: Counter,
+    /// Requests refused by the public listeners, see [`crate::front`].
+    pub requests_rejected: Family<ReasonLabels, Counter>,
+}
+
+impl Default for HttpMetrics {
+    fn default() -> Self {
+        HttpMetrics {
+            response_bytes: Histogram::new(exponential_buckets(256.0, 2.0, 10)),
+            cache_hits: Counter::default(),
+            cache_misses: Counter::default(),
+            requests_rejected: Family::default(),
+        }
+    }
+}
+
+impl HttpMetrics {
+    fn register(&self, registry: &mut Enabled) {
+        registry.register_with_unit(
+            "http_response",
+            "Size of the metrics responses",
+            Unit::Bytes,
+            self.response_bytes.clone(),
+        );
+        registry.register(
+            "metrics_cache_hits",
+            "Number of scrapes served from the response cache",
+            self.cache_hits.clone(),
+        );
+        registry.register(
+            "metrics_cache_misses",
+            "Number of scrapes encoded afresh while the response cache is enabled",
+            self.cache_misses.clone(),
+        );
+        registry.register(
+            "http_requests_rejected",
+            "Number of HTTP requests refused for breaking the limits of the server, by the reason",
+            self.requests_rejected.clone(),
+        );
+    }
+}
+
+impl P1Metrics {
+    pub fn new(extra_obis: &[ExtraObis], max_telegram_age: Option<Duration>) -> Self {
+        let mut families = BTreeMap::new();
+        P1Metrics {
+            extra_obis: extra_obis
+                .iter()
+                .map(|extra| {
+                    let family = families.entry(&extra.name).or_insert_with(|| {
+                        if extra.counter {
+                            ExtraFamily::Counter(Family::default())
+                        } else {
+                            ExtraFamily::Gauge(Family::default())
+                        }
+                    });
+                    (extra.clone(), family.clone())
+                })
+                .collect(),
+            max_telegram_age,
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether any meter has received a telegram within `max_age`.
+    pub fn telegram_within(&self, max_age: Duration) -> bool {
+        self.telegram_timings
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(_, timing)| {
+                let last = timing.lock().unwrap().last;
+                last.is_some_and(|last| last.elapsed() <= max_age)
+            })
+    }
+
+    /// Creates the series of the meter with the given name.
+    pub fn meter(&self, meter: Option<String>) -> MeterMetrics {
+        let labels = MeterLabels {
+            meter: meter.clone(),
+            labels: (),
+        };
+        let telegram_timing = Arc::<Mutex<TelegramTiming>>::default();
+        let recent_telegrams = Arc::<Mutex<RecentTelegrams>>::default();
+        let readings = Arc::<Mutex<MeterReadings>>::default();
+        self.meter_readings.lock().unwrap().push(readings.clone());
+        self.recent_telegrams
+            .lock()
+            .unwrap()
+            .push((meter.clone(), recent_telegrams.clone()));
+        self.telegram_timings
+            .lock()
+            .unwrap()
+            .push((labels.clone(), telegram_timing.clone()));
+        let metrics = MeterMetrics {
+            power_consumed: MeterFamily::new(&meter, &self.power_consumed),
+            power_produced: MeterFamily::new(&meter, &self.power_produced),
+            power_net: MeterFamily::new(&meter, &self.power_net),
+            power_consumed_peak: MeterFamily::new(&meter, &self.power_consumed_peak),
+            power_produced_peak: MeterFamily::new(&meter, &self.power_produced_peak),
+            power_direction_conflicts: self
+                .power_direction_conflicts
+                .get_or_create(&labels)
+                .clone(),
+            voltage: MeterFamily::new(&meter, &self.voltage),
+            current: MeterFamily::new(&meter, &self.current),
+            phase_power_consumed: MeterFamily::new(&meter, &self.phase_power_consumed),
+            phase_power_produced: MeterFamily::new(&meter, &self.phase_power_produced),
+            phase_reactive_power_consumed: MeterFamily::new(
+                &meter,
+                &self.phase_reactive_power_consumed,
+            ),
+            phase_reactive_power_produced: MeterFamily::new(
+                &meter,
+                &self.phase_reactive_power_produced,
+            ),
+            phase_power_factor: MeterFamily::new(&meter, &self.phase_power_factor),
+            power_consumed_total: MeterFamily::new(&meter, &self.power_consumed_total),
+            power_produced_total: MeterFamily::new(&meter, &self.power_produced_total),
+            power_net_energy: MeterFamily::new(&meter, &self.power_net_energy),
+            energy_cost: MeterFamily::new(&meter, &self.energy_cost_total),
+            gas_cost: MeterFamily::new(&meter, &self.gas_cost_total),
+            energy_price: MeterFamily::new(&meter, &self.energy_price),
+            active_tariff: MeterFamily::new(&meter, &self.active_tariff),
+            active_tariff_duration: self.active_tariff_duration.get_or_create(&labels).clone(),
+            average_demand: MeterFamily::new(&meter, &self.average_demand),
+            monthly_peak: MeterFamily::new(&meter, &self.monthly_peak),
+            peak_history: MeterFamily::new(&meter, &self.peak_history),
+            quarter_hour_energy_consumed: MeterFamily::new(
+                &meter,
+                &self.quarter_hour_energy_consumed,
+            ),
+            quarter_hour_energy_produced: MeterFamily::new(
+                &meter,
+                &self.quarter_hour_energy_produced,
+            ),
+            quarter_hour_end: MeterFamily::new(&meter, &self.quarter_hour_end),
+            gas_consumed_total: MeterFamily::new(&meter, &self.gas_consumed_total),
+            gas_flow: MeterFamily::new(&meter, &self.gas_flow),
+            mbus_timestamp: MeterFamily::new(&meter, &self.mbus_timestamp),
+            water_consumed_total: MeterFamily::new(&meter, &self.water_consumed_total),
+            heat_consumed_total: MeterFamily::new(&meter, &self.heat_consumed_total),
+            cooling_consumed_total: MeterFamily::new(&meter, &self.cooling_consumed_total),
+            power_failures: self.power_failures.get_or_create(&labels).clone(),
+            long_power_failures: self.long_power_failures.get_or_create(&labels).clone(),
+            long_power_failure_end: MeterFamily::new(&meter, &self.long_power_failure_end),
+            long_power_failure_length: MeterFamily::new(&meter, &self.long_power_failure_length),
+            long_power_failure_duration: self
+                .long_power_failure_duration
+                .get_or_create(&labels)
+                .clone(),
+            voltage_sags: MeterFamily::new(&meter, &self.voltage_sags),
+            voltage_swells: MeterFamily::new(&meter, &self.voltage_swells),
+            mbus_channel_present: MeterFamily::new(&meter, &self.mbus_channel_present),
+            mbus_device_info: MeterFamily::new(&meter, &self.mbus_device_info),
+            mbus_readings_ignored: self.mbus_readings_ignored.get_or_create(&labels).clone(),
+            meter_info: MeterFamily::new(&meter, &self.meter_info),
+            meter_message_info: MeterFamily::new(&meter, &self.meter_message_info),
+            info: MeterFamily::new(&meter, &self.info),
+            dsmr_version_changes: self.dsmr_version_changes.get_or_create(&labels).clone(),
+            reader_state: self.reader_state.get_or_create(&labels).clone(),
+            reader_backoff: self.reader_backoff.get_or_create(&labels).clone(),
+            parser_synced: self.parser_synced.get_or_create(&labels).clone(),
+            reader_connection_up: self.reader_connection_up.get_or_create(&labels).clone(),
+            reader_reconnects: self.reader_reconnects.get_or_create(&labels).clone(),
+            reader_endpoint_active: MeterFamily::new(&meter, &self.reader_endpoint_active),
+            collector_panics: self.collector_panics.get_or_create(&labels).clone(),
+            bytes_read: self.bytes_read.get_or_create(&labels).clone(),
+            bytes_parsed: self.bytes_parsed.get_or_create(&labels).clone(),
+            readouts_skipped: self.readouts_skipped.get_or_create(&labels).clone(),
+            telegrams_received: self.telegrams_received.get_or_create(&labels).clone(),
+            telegram_parse_errors: self.telegram_parse_errors.get_or_create(&labels).clone(),
+            counter_regressions: MeterFamily::new(&meter, &self.counter_regressions),
+            readings_rejected: MeterFamily::new(&meter, &self.readings_rejected),
+            telegram_interval: self.telegram_interval.get_or_create(&labels).clone(),
+            telegram_timing,
+            last_telegram_timestamp: self.last_telegram_timestamp.get_or_create(&labels).clone(),
+            meter_timestamp: MeterFamily::new(&meter, &self.meter_timestamp),
+            meter_clock_drift: MeterFamily::new(&meter, &self.meter_clock_drift),
+            recent_telegrams,
+            readings,
+            events: self.events.clone(),
+            extra_obis: self
+                .extra_obis
+                .iter()
+                .map(|(extra, family)| {
+                    let labels = MeterLabels {
+                        meter: meter.clone(),
+                        labels: extra.labels.clone(),
+                    };
+                    (extra.clone(), family.get_or_create(&labels))
+                })
+                .collect(),
+            obis_value: MeterFamily::new(&meter, &self.obis_value),
+            connection_duration: self.reader.connection_duration.get_or_create(&labels),
+            meter,
+        };
+        metrics.reader_state.set(READER_NEVER_CONNECTED);
+        // The power is served as 0 until the first telegram.
+        metrics.power_consumed.get_or_create(&());
+        metrics.power_produced.get_or_create(&());
+        metrics.power_net.get_or_create(&());
+        metrics
+    }
+
+    /// Removes the series of a meter whose collector has stopped.
+    pub fn remove_meter(&self, metrics: &MeterMetrics) {
+        let labels = MeterLabels {
+            meter: metrics.meter.clone(),
+            labels: (),
+        };
+        self.recent_telegrams
+            .lock()
+            .unwrap()
+            .retain(|(_, recent)| !Arc::ptr_eq(recent, &metrics.recent_telegrams));
+        self.telegram_timings
+            .lock()
+            .unwrap()
+            .retain(|(_, timing)| !Arc::ptr_eq(timing, &metrics.telegram_timing));
+        self.meter_readings
+            .lock()
+            .unwrap()
+            .retain(|readings| !Arc::ptr_eq(readings, &metrics.readings));
+        self.power_direction_conflicts.remove(&labels);
+        self.active_tariff_duration.remove(&labels);
+        self.power_failures.remove(&labels);
+        self.long_power_failures.remove(&labels);
+        self.long_power_failure_duration.remove(&labels);
+        self.mbus_readings_ignored.remove(&labels);
+        self.dsmr_version_changes.remove(&labels);
+        self.reader_state.remove(&labels);
+        self.reader_backoff.remove(&labels);
+        self.parser_synced.remove(&labels);
+        self.reader_connection_up.remove(&labels);
+        self.reader_reconnects.remove(&labels);
+        self.collector_panics.remove(&labels);
+        self.bytes_read.remove(&labels);
+        self.bytes_parsed.remove(&labels);
+        self.readouts_skipped.remove(&labels);
+        self.telegrams_received.remove(&labels);
+        self.telegram_parse_errors.remove(&labels);
+        self.telegram_interval.remove(&labels);
+        self.last_telegram_timestamp.remove(&labels);
+        for (extra, family) in &self.extra_obis {
+            family.remove(&MeterLabels {
+                meter: labels.meter.clone(),
+                labels: extra.labels.clone(),
+            });
+        }
+        self.reader.connection_duration.remove(&labels);
+        metrics.power_consumed.clear();
+        metrics.power_produced.clear();
+        metrics.power_net.clear();
+        metrics.voltage.clear();
+        metrics.current.clear();
+        metrics.phase_power_consumed.clear();
+        metrics.phase_power_produced.clear();
+        metrics.phase_reactive_power_consumed.clear();
+        metrics.phase_reactive_power_produced.clear();
+        metrics.phase_power_factor.clear();
+        metrics.power_consumed_total.clear();
+        metrics.power_produced_total.clear();
+        metrics.power_net_energy.clear();
+        metrics.power_consumed_peak.clear();
+        metrics.power_produced_peak.clear();
+        metrics.energy_cost.clear();
+        metrics.gas_cost.clear();
+        metrics.energy_price.clear();
+        metrics.active_tariff.clear();
+        metrics.average_demand.clear();
+        metrics.monthly_peak.clear();
+        metrics.peak_history.clear();
+        metrics.quarter_hour_energy_consumed.clear();
+        metrics.quarter_hour_energy_produced.clear();
+        metrics.quarter_hour_end.clear();
+        metrics.gas_consumed_total.clear();
+        metrics.gas_flow.clear();
+        metrics.mbus_timestamp.clear();
+        metrics.meter_timestamp.clear();
+        metrics.meter_clock_drift.clear();
+        metrics.obis_value.clear();
+        metrics.reader_endpoint_active.clear();
+        metrics.counter_regressions.clear();
+        metrics.readings_rejected.clear();
+        metrics.water_consumed_total.clear();
+        metrics.heat_consumed_total.clear();
+        metrics.cooling_consumed_total.clear();
+        metrics.long_power_failure_end.clear();
+        metrics.long_power_failure_length.clear();
+        metrics.voltage_sags.clear();
+        metrics.voltage_swells.clear();
+        metrics.mbus_channel_present.clear();
+        metrics.mbus_device_info.clear();
+        metrics.meter_info.clear();
+        metrics.meter_message_info.clear();
+        metrics.info.clear();
+    }
+
+    /// Registers the families of the enabled `groups` with the constant
+    /// `labels`, named with `prefix` but for the extra OBIS objects, which are
+    /// named as given. The power families are in `power_unit`.
+    pub fn register(
+        &self,
+        registry: &mut Registry,
+        prefix: &str,
+        labels: &[ConstLabel],
+        groups: &[MetricGroup],
+        power_unit: PowerUnit,
+    ) {
+        let mut labeled = &mut *registry;
+        for label in labels {
+            labeled = labeled.sub_registry_with_label(label.pair());
+        }
+        let families = &mut Enabled {
+            registry: labeled.sub_registry_with_prefix(prefix),
+            groups,
+        };
+        families.register_with_unit(
+            "power_consumed",
+            "Power consumed",
+            power_unit.unit(),
+            self.power_consumed.clone(),
+        );
+        families.register_with_unit(
+            "power_produced",
+            "Power produced",
+            power_unit.unit(),
+            self.power_produced.clone(),
+        );
+        families.register_with_unit(
+            "power_net",
+            "Power consumed minus power produced",
+            power_unit.unit(),
+            self.power_net.clone(),
+        );
+        families.register_with_unit(
+            "power_consumed_peak",
+            "Highest power consumed in the current day or month",
+            power_unit.unit(),
+            self.power_consumed_peak.clone(),
+        );
+        families.register_with_unit(
+            "power_produced_peak",
+            "Highest power produced in the current day or month",
+            power_unit.unit(),
+            self.power_produced_peak.clone(),
+        );
+        families.register(
+            "power_direction_conflicts",
+            "Number of telegrams reporting both consumed and produced power",
+            self.power_direction_conflicts.clone(),
+        );
+        families.register_with_unit(
+            "voltage",
+            "Instantaneous voltage per phase",
+            Unit::Volts,
+            self.voltage.clone(),
+        );
+        families.register_with_unit(
+            "current",
+            "Instantaneous current per phase",
+            Unit::Amperes,
+            self.current.clone(),
+        );
+        families.register_with_unit(
+            "phase_power_consumed",
+            "Power consumed per phase",
+            power_unit.unit(),
+            self.phase_power_consumed.clone(),
+        );
+        families.register_with_unit(
+            "phase_power_produced",
+            "Power produced per phase",
+            power_unit.unit(),
+            self.phase_power_produced.clone(),
+        );
+        families.register_with_unit(
+            "phase_reactive_power_consumed",
+            "Reactive power consumed per phase, i.e. inductive",
+            power_unit.reactive_unit(),
+            self.phase_reactive_power_consumed.clone(),
+        );
+        families.register_with_unit(
+            "phase_reactive_power_produced",
+            "Reactive power produced per phase, i.e. capacitive",
+            power_unit.reactive_unit(),
+            self.phase_reactive_power_produced.clone(),
+        );
+        families.register(
+            "phase_power_factor",
+            "Power factor per phase, from the active and reactive power",
+            self.phase_power_factor.clone(),
+        );
+        families.register_with_unit(
+            "power_consumed",
+            "Total consumed power",
+            power_unit.energy_unit(),
+            self.power_consumed_total.clone(),
+        );
+        families.register_with_unit(
+            "power_produced",
+            "Total produced power",
+            power_unit.energy_unit(),
+            self.power_produced_total.clone(),
+        );
+        families.register_with_unit(
+            "power_net",
+            "Total consumed minus total produced power",
+            power_unit.energy_unit(),
+            self.power_net_energy.clone(),
+        );
+        families.register(
+            "energy_cost",
+            "Cost of the consumed energy, including the daily charges",
+            self.energy_cost_total.clone(),
+        );
+        families.register(
+            "gas_cost",
+            "Cost of the consumed gas, including the daily charges",
+            self.gas_cost_total.clone(),
+        );
+        families.register(
+            "energy_price",
+            "Current price per kWh of the dynamic contract",
+            self.energy_price.clone(),
+        );
+        families.register(
+            "active_tariff",
+            "Currently active tariff",
+            self.active_tariff.clone(),
+        );
+        families.register_with_unit(
+            "active_tariff_duration",
+            "Time since the active tariff last changed",
+            Unit::Seconds,
+            self.active_tariff_duration.clone(),
+        );
+        families.register_with_unit(
+            "average_demand",
+            "Average power demand of the current quarter hour",
+            power_unit.unit(),
+            self.average_demand.clone(),
+        );
+        families.register_with_unit(
+            "monthly_peak",
+            "Highest quarter-hour average demand of the current month",
+            power_unit.unit(),
+            self.monthly_peak.clone(),
+        );
+        families.register_with_unit(
+            "peak_history",
+            "Highest quarter-hour average demand of each of the last 13 months",
+            power_unit.unit(),
+            self.peak_history.clone(),
+        );
+        families.register_with_unit(
+            "quarter_hour_energy_consumed",
+            "Energy consumed in the last completed quarter hour of the clock",
+            power_unit.energy_unit(),
+            self.quarter_hour_energy_consumed.clone(),
+        );
+        families.register_with_unit(
+            "quarter_hour_energy_produced",
+            "Energy produced in the last completed quarter hour of the clock",
+            power_unit.energy_unit(),
+            self.quarter_hour_energy_produced.clone(),
+        );
+        families.register_with_unit(
+            "quarter_hour_end_timestamp",
+            "Unix time of the end of the last completed quarter hour",
+            Unit::Seconds,
+            self.quarter_hour_end.clone(),
+        );
+        families.register_with_unit(
+            "gas_consumed",
+            "Total consumed natural gas",
+            Unit::Other("cubic_meters".to_string()),
+            self.gas_consumed_total.clone(),
+        );
+        families.register_with_unit(
+            "gas_flow",
+            "Gas flow between the last two readings of the gas meter",
+            Unit::Other("cubic_meters_per_hour".to_string()),
+            self.gas_flow.clone(),
+        );
+        families.register_with_unit(
+            "mbus_timestamp",
+            "Unix time the M-Bus device captured its last reading at",
+            Unit::Seconds,
+            self.mbus_timestamp.clone(),
+        );
+        families.register_with_unit(
+            "water_consumed",
+            "Total consumed water",
+            Unit::Other("cubic_meters".to_string()),
+            self.water_consumed_total.clone(),
+        );
+        families.register_with_unit(
+            "heat_consumed",
+            "Total consumed heat",
+            Unit::Other("gj".to_string()),
+            self.heat_consumed_total.clone(),
+        );
+        families.register_with_unit(
+            "cooling_consumed",
+            "Total consumed cooling",
+            Unit::Other("gj".to_string()),
+            self.cooling_consumed_total.clone(),
+        );
+        families.register(
+            "power_failures",
+            "Number of power failures in any phase, as reported by the meter",
+            self.power_failures.clone(),
+        );
+        families.register(
+            "long_power_failures",
+            "Number of long power failures in any phase, as reported by the meter",
+            self.long_power_failures.clone(),
+        );
+        families.register_with_unit(
+            "long_power_failure_end_timestamp",
+            "End of each of the most recent long power failures in the meter's event log, since the Unix epoch",
+            Unit::Seconds,
+            self.long_power_failure_end.clone(),
+        );
+        families.register_with_unit(
+            "long_power_failure_length",
+            "Length of each of the most recent long power failures in the meter's event log",
+            Unit::Seconds,
+            self.long_power_failure_length.clone(),
+        );
+        families.register_with_unit(
+            "long_power_failure_duration",
+            "Total length of the long power failures in the meter's event log and those logged since",
+            Unit::Seconds,
+            self.long_power_failure_duration.clone(),
+        );
+        families.register(
+            "voltage_sags",
+            "Number of voltage sags per phase, as reported by the meter",
+            self.voltage_sags.clone(),
+        );
+        families.register(
+            "voltage_swells",
+            "Number of voltage swells per phase, as reported by the meter",
+            self.voltage_swells.clone(),
+        );
+        families.register(
+            "mbus_channel_present",
+            "Whether a device is present on the M-Bus channel",
+            self.mbus_channel_present.clone(),
+        );
+        families.register(
+            "mbus_device_info",
+            "Information about the devices attached to the M-Bus channels",
+            self.mbus_device_info.clone(),
+        );
+        families.register(
+            "mbus_readings_ignored",
+            "Number of M-Bus channel readings ignored as not listed in --mbus-channels",
+            self.mbus_readings_ignored.clone(),
+        );
+        families.register(
+            "meter_info",
+            "Equipment identifiers of the meter and its gas meter, and the DSMR version",
+            self.meter_info.clone(),
+        );
+        families.register(
+            "meter_message_info",
+            "Text message displayed by the meter, from the grid operator",
+            self.meter_message_info.clone(),
+        );
+        families.register(
+            "info",
+            "Exporter and meter information, one series per meter",
+            self.info.clone(),
+        );
+        families.register(
+            "dsmr_version_changes",
+            "Number of times the reported DSMR version changed",
+            self.dsmr_version_changes.clone(),
+        );
+        let build_info = Family::<BuildInfoLabels, Gauge>::default();
+        build_info
+            .get_or_create(&BuildInfoLabels {
+                version: env!("CARGO_PKG_VERSION"),
+                revision: env!("P1_EXPORTER_REVISION"),
+                rustc: env!("P1_EXPORTER_RUSTC"),
+            })
+            .set(1);
+        families.register(
+            "exporter_build_info",
+            "Version, revision and compiler the exporter was built from",
+            build_info,
+        );
+        families.register(
+            "reader_state",
+            "State of the P1 reader connection (0 - never connected, 1 - up, 2 - down)",
+            self.reader_state.clone(),
+        );
+        families.register_with_unit(
+            "reader_backoff",
+            "Current delay before reconnecting to the P1 reader",
+            Unit::Seconds,
+            self.reader_backoff.clone(),
+        );
+        families.register(
+            "parser_synced",
+            "Whether a telegram has been parsed on the current reader connection",
+            self.parser_synced.clone(),
+        );
+        families.register(
+            "reader_connection_up",
+            "Whether the collector is connected to the P1 reader",
+            self.reader_connection_up.clone(),
+        );
+        families.register(
+            "reader_reconnects",
+            "Number of times the connection to the P1 reader was re-established",
+            self.reader_reconnects.clone(),
+        );
+        families.register(
+            "reader_endpoint_active",
+            "Whether the collector is connected to this P1 reader, of a meter with backup readers",
+            self.reader_endpoint_active.clone(),
+        );
+        families.register(
+            "collector_panics",
+            "Number of times the collector panicked and was recovered",
+            self.collector_panics.clone(),
+        );
+        families.register(
+            "bytes_read",
+            "Total number of bytes read from the P1 reader",
+            self.bytes_read.clone(),
+        );
+        families.register(
+            "bytes_parsed",
+            "Total number of bytes of successfully parsed telegrams",
+            self.bytes_parsed.clone(),
+        );
+        families.register(
+            "readouts_skipped",
+            "Total number of readouts dropped for any reason",
+            self.readouts_skipped.clone(),
+        );
+        families.register(
+            "telegrams_received",
+            "Total number of telegrams received from the P1 reader",
+            self.telegrams_received.clone(),
+        );
+        families.register(
+            "telegram_parse_errors",
+            "Total number of telegrams that failed to parse",
+            self.telegram_parse_errors.clone(),
+        );
+        families.register(
+            "counter_regressions",
+            "Number of totals read from the meter that were ignored for being lower than the last one",
+            self.counter_regressions.clone(),
+        );
+        families.register(
+            "readings_rejected",
+            "Number of telegrams skipped for implausible readings, by the reason",
+            self.readings_rejected.clone(),
+        );
+        families.register_with_unit(
+            "telegram_interval",
+            "Rolling average of the interval between telegrams",
+            Unit::Seconds,
+            self.telegram_interval.clone(),
+        );
+        families.register_with_unit(
+            "last_telegram_timestamp",
+            "Unix time the last telegram was received at",
+            Unit::Seconds,
+            self.last_telegram_timestamp.clone(),
+        );
+        families.register_with_unit(
+            "meter_timestamp",
+            "Unix time of the last telegram, by the clock of the meter",
+            Unit::Seconds,
+            self.meter_timestamp.clone(),
+        );
+        families.register_with_unit(
+            "meter_clock_drift",
+            "Difference between the clock of the meter and that of the system at the last telegram",
+            Unit::Seconds,
+            self.meter_clock_drift.clone(),
+        );
+        self.reader.register(families);
+        self.http.register(families);
+        families.register(
+            "obis_value",
+            "Value of an OBIS object, as reported by the meter",
+            self.obis_value.clone(),
+        );
+        let mut by_name = BTreeMap::new();
+        for (extra, family) in &self.extra_obis {
+            by_name
+                .entry(&extra.name)
+                .or_insert_with(|| (family, vec![]))
+                .1
+                .push(extra.code.as_str());
+        }
+        for (name, (family, codes)) in by_name {
+            let help = match codes.as_slice() {
+                [code] => format!("Value of OBIS object {code}"),
+                codes => format!("Value of OBIS objects {}", codes.join(", ")),
+            };
+            match family {
+                ExtraFamily::Gauge(family) => labeled.register(name, help, family.clone()),
+                ExtraFamily::Counter(family) => labeled.register(name, help, family.clone()),
+            }
+        }
+        // Collectors name and label their families themselves, as
+        // sub-registries would end their help text with a second period.
+        let prefix = Prefix::from(prefix.to_string());
+        let labels: Vec<_> = labels.iter().map(ConstLabel::pair).collect();
+        if groups.contains(&MetricGroup::Process) {
+            registry.register_collector(Box::new(ProcessCollector {
+                labels: labels.clone(),
+            }));
+        }
+        if self.aggregate_meters {
+            registry.register_collector(Box::new(SiteCollector {
+                readings: self.meter_readings.clone(),
+                power_unit,
+                power: groups.contains(&MetricGroup::Power),
+                energy: groups.contains(&MetricGroup::Energy),
+                prefix: prefix.clone(),
+                labels: labels.clone(),
+            }));
+        }
+        if !groups.contains(&MetricGroup::Telegram) {
+            return;
+        }
+        registry.register_collector(Box::new(NextTelegramCollector {
+            timings: self.telegram_timings.clone(),
+            prefix: prefix.clone(),
+            labels: labels.clone(),
+        }));
+        if let Some(max_age) = self.max_telegram_age {
+            registry.register_collector(Box::new(FreshTelegramCollector {
+                timings: self.telegram_timings.clone(),
+                max_age,
+                prefix,
+                labels,
+            }));
+        }
+    }
+}
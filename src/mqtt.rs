@@ -0,0 +1,672 @@
+//! Publishing of the readings of every telegram to an MQTT broker, for home
+//! automation, and receiving of the telegrams published by P1 dongles. Speaks
+//! just enough MQTT 3.1.1 to publish retained messages and subscribe to a
+//! topic.
+
+use crate::{
+    collector,
+    parse::{Format, Value},
+    sink::{Kind, Sample, Sink},
+    telegram::TelegramReader,
+    tls,
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    io::{self, Read, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Timeout for connecting to the broker and for every read and write.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Keep alive interval announced to the broker.
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// Delay before reconnecting to the broker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Address of an MQTT broker, `mqtt://host[:port]` or, with TLS,
+/// `mqtts://host[:port]`.
+#[derive(Clone, Debug)]
+pub struct BrokerAddress {
+    tls: bool,
+    /// `host:port`, the port defaulting to that of the scheme.
+    addr: String,
+}
+
+impl FromStr for BrokerAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tls, host, port) = if let Some(host) = s.strip_prefix("mqtts://") {
+            (true, host, 8883)
+        } else if let Some(host) = s.strip_prefix("mqtt://") {
+            (false, host, 1883)
+        } else {
+            return Err(format!(
+                "invalid broker URL '{s}', expected mqtt:// or mqtts://"
+            ));
+        };
+        let host = host.trim_end_matches('/');
+        if host.is_empty() || host.contains('/') {
+            return Err(format!(
+                "invalid broker URL '{s}', expected a host and port only"
+            ));
+        }
+        let addr = match host.rsplit_once(':') {
+            Some((_, port)) if !port.contains(']') => host.to_string(),
+            _ => format!("{host}:{port}"),
+        };
+        Ok(BrokerAddress { tls, addr })
+    }
+}
+
+impl fmt::Display for BrokerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls { "mqtts" } else { "mqtt" };
+        write!(f, "{scheme}://{}", self.addr)
+    }
+}
+
+/// How to connect to a broker.
+#[derive(Clone, Debug)]
+pub struct Broker {
+    pub address: BrokerAddress,
+    /// CA to verify the broker's certificate against instead of the system's
+    /// trusted CAs.
+    pub tls_ca: Option<PathBuf>,
+    pub client_id: String,
+    /// User name and password.
+    pub credentials: Option<(String, String)>,
+}
+
+impl Broker {
+    /// Loads the TLS configuration if the broker is connected to over TLS.
+    fn tls(&self) -> Result<Option<tls::Client>, io::Error> {
+        if self.address.tls {
+            Ok(Some(tls::Client::load(self.tls_ca.as_deref(), None, None)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+pub struct MqttConfig {
+    pub broker: Broker,
+    /// Topics are `<prefix>/[<meter>/]<reading>[/<label>...]`.
+    pub topic_prefix: String,
+    pub qos: u8,
+    /// Prefix of the Home Assistant discovery topics, if the sensors are to
+    /// be announced to Home Assistant.
+    pub discovery_prefix: Option<String>,
+}
+
+/// Messages waiting to be published, the latest payload by topic.
+#[derive(Default)]
+struct Pending {
+    messages: Mutex<BTreeMap<String, String>>,
+    published: Condvar,
+}
+
+pub struct MqttSink {
+    prefix: String,
+    discovery_prefix: Option<String>,
+    /// Topics announced to Home Assistant.
+    announced: Mutex<HashSet<String>>,
+    pending: Arc<Pending>,
+}
+
+impl MqttSink {
+    pub fn start(config: MqttConfig) -> Result<Self, io::Error> {
+        let tls = config.broker.tls()?;
+        let pending = Arc::new(Pending::default());
+        let queue = pending.clone();
+        let prefix = config.topic_prefix.clone();
+        let discovery_prefix = config.discovery_prefix.clone();
+        thread::spawn(move || {
+            let mut packet_id = 0u16;
+            loop {
+                let broker = &config.broker.address;
+                match Connection::open(&config.broker, tls.as_ref(), KEEP_ALIVE, TIMEOUT) {
+                    Ok(mut conn) => {
+                        tracing::info!("Connected to MQTT broker {broker}");
+                        if let Err(err) = conn.run(&queue, config.qos, &mut packet_id) {
+                            tracing::warn!("MQTT connection to {broker} lost: {err}");
+                        }
+                    }
+                    Err(err) => {
+                        tracing::warn!("Failed to connect to MQTT broker {broker}: {err}");
+                    }
+                }
+                thread::sleep(RECONNECT_DELAY);
+            }
+        });
+        Ok(MqttSink {
+            prefix,
+            discovery_prefix,
+            announced: Mutex::default(),
+            pending,
+        })
+    }
+}
+
+impl Sink for MqttSink {
+    fn publish(&self, samples: &[Sample]) {
+        let mut messages = self.pending.messages.lock().unwrap();
+        let mut announced = self.announced.lock().unwrap();
+        for sample in samples {
+            let (topic, payload) = message(&self.prefix, sample);
+            if let Some(discovery_prefix) = &self.discovery_prefix {
+                if !announced.contains(&topic) {
+                    let (config_topic, config) = discovery(discovery_prefix, &topic, sample);
+                    messages.insert(config_topic, config);
+                    announced.insert(topic.clone());
+                }
+            }
+            messages.insert(topic, payload);
+        }
+        self.pending.published.notify_one();
+    }
+}
+
+/// Returns the topic and payload of a sample.
+fn message(prefix: &str, sample: &Sample) -> (String, String) {
+    let mut topic = prefix.to_string();
+    let label = |name: &str| {
+        sample
+            .labels
+            .iter()
+            .find(|(label, _)| *label == name)
+            .map(|(_, value)| value.as_str())
+    };
+    if let Some(meter) = label("meter") {
+        topic.push('/');
+        topic.push_str(meter);
+    }
+    topic.push('/');
+    topic.push_str(sample.name);
+    // Only one tariff is active at a time, so its name is the payload.
+    if sample.name == "active_tariff" {
+        return (topic, label("tariff").unwrap_or_default().to_string());
+    }
+    for (name, value) in &sample.labels {
+        if !matches!(*name, "meter" | "equipment_id") {
+            topic.push('/');
+            topic.push_str(value);
+        }
+    }
+    (topic, sample.value.to_string())
+}
+
+/// Returns the topic and payload of the Home Assistant discovery message of
+/// the sensor publishing the sample to `topic`.
+fn discovery(prefix: &str, topic: &str, sample: &Sample) -> (String, String) {
+    // The device class and unit, if any.
+    let (name, class) = match sample.name {
+        "power_consumed_kw" | "phase_power_consumed_kw" => {
+            ("Power consumed", Some(("power", "kW")))
+        }
+        "power_produced_kw" | "phase_power_produced_kw" => {
+            ("Power produced", Some(("power", "kW")))
+        }
+        "average_demand_kw" => ("Average demand", Some(("power", "kW"))),
+        "monthly_peak_kw" => ("Monthly peak", Some(("power", "kW"))),
+        "power_consumed_watts" | "phase_power_consumed_watts" => {
+            ("Power consumed", Some(("power", "W")))
+        }
+        "power_produced_watts" | "phase_power_produced_watts" => {
+            ("Power produced", Some(("power", "W")))
+        }
+        "power_net_kw" => ("Net power", Some(("power", "kW"))),
+        "power_consumed_peak_kw" => ("Peak power consumed", Some(("power", "kW"))),
+        "power_produced_peak_kw" => ("Peak power produced", Some(("power", "kW"))),
+        "power_consumed_peak_watts" => ("Peak power consumed", Some(("power", "W"))),
+        "power_produced_peak_watts" => ("Peak power produced", Some(("power", "W"))),
+        "power_net_watts" => ("Net power", Some(("power", "W"))),
+        "average_demand_watts" => ("Average demand", Some(("power", "W"))),
+        "monthly_peak_watts" => ("Monthly peak", Some(("power", "W"))),
+        "power_consumed_kwh" => ("Energy consumed", Some(("energy", "kWh"))),
+        "power_net_kwh" => ("Net energy", Some(("energy", "kWh"))),
+        "power_produced_kwh" => ("Energy produced", Some(("energy", "kWh"))),
+        "power_consumed_joules" => ("Energy consumed", Some(("energy", "J"))),
+        "power_net_joules" => ("Net energy", Some(("energy", "J"))),
+        "power_produced_joules" => ("Energy produced", Some(("energy", "J"))),
+        "voltage_volts" => ("Voltage", Some(("voltage", "V"))),
+        "current_amperes" => ("Current", Some(("current", "A"))),
+        "voltage_sags" => ("Voltage sags", None),
+        "voltage_swells" => ("Voltage swells", None),
+        "power_failures" => ("Power failures", None),
+        "long_power_failures" => ("Long power failures", None),
+        "gas_consumed_cubic_meters" => ("Gas consumed", Some(("gas", "m³"))),
+        "gas_flow_cubic_meters_per_hour" => ("Gas flow", Some(("volume_flow_rate", "m³/h"))),
+        "water_consumed_cubic_meters" => ("Water consumed", Some(("water", "m³"))),
+        "heat_consumed_gj" => ("Heat consumed", Some(("energy", "GJ"))),
+        "cooling_consumed_gj" => ("Cooling consumed", Some(("energy", "GJ"))),
+        "energy_cost" => ("Energy cost", None),
+        "gas_cost" => ("Gas cost", None),
+        "energy_price" => ("Energy price", None),
+        "active_tariff" => ("Active tariff", None),
+        name => (name, None),
+    };
+    // The active tariff is published as text, it has no state class. The net
+    // energy is a total that may decrease.
+    let state_class = match sample.kind {
+        _ if sample.name == "active_tariff" => None,
+        _ if matches!(sample.name, "power_net_kwh" | "power_net_joules") => Some("total"),
+        Kind::Gauge => Some("measurement"),
+        Kind::Counter => Some("total_increasing"),
+    };
+    let mut name = name.to_string();
+    for (label, value) in &sample.labels {
+        match *label {
+            "phase" => name.push_str(&format!(" {}", value.to_uppercase())),
+            "tariff" if sample.name != "active_tariff" => name.push_str(&format!(" {value}")),
+            "period" => name.push_str(&format!(" of the {value}")),
+            _ => {}
+        }
+    }
+    let meter = sample
+        .labels
+        .iter()
+        .find(|(label, _)| *label == "meter")
+        .map(|(_, meter)| meter.as_str());
+    let object_id = topic.replace('/', "_");
+    let mut config = vec![
+        ("name", Value::Text(name)),
+        ("unique_id", Value::Text(format!("p1_exporter_{object_id}"))),
+        ("object_id", Value::Text(object_id.clone())),
+        ("state_topic", Value::Text(topic.to_string())),
+    ];
+    if let Some((device_class, unit)) = class {
+        config.push(("device_class", Value::Text(device_class.to_string())));
+        config.push(("unit_of_measurement", Value::Text(unit.to_string())));
+    }
+    if let Some(state_class) = state_class {
+        config.push(("state_class", Value::Text(state_class.to_string())));
+    }
+    let device_id = format!("p1_exporter_{}", meter.unwrap_or("meter"));
+    config.push((
+        "device",
+        Value::Object(vec![
+            ("identifiers", Value::List(vec![Value::Text(device_id)])),
+            ("name", Value::Text(meter.unwrap_or("P1 meter").to_string())),
+            ("manufacturer", Value::Text("p1-exporter".to_string())),
+            (
+                "sw_version",
+                Value::Text(env!("CARGO_PKG_VERSION").to_string()),
+            ),
+        ]),
+    ));
+    (
+        format!("{prefix}/sensor/{object_id}/config"),
+        Value::Object(config).render(Format::Json),
+    )
+}
+
+/// Subscribes to the telegrams published to `topic`, read as a continuous
+/// stream. The broker isn't asked
+/// to check that the connection is alive, reads fail once nothing has been
+/// received for `read_timeout` instead.
+pub fn subscribe(
+    broker: &Broker,
+    topic: &str,
+    read_timeout: Duration,
+) -> Result<TelegramReader<impl FnMut() -> io::Result<Vec<u8>>>, io::Error> {
+    let tls = broker.tls()?;
+    let mut conn = Connection::open(broker, tls.as_ref(), Duration::ZERO, read_timeout)?;
+    let mut packet = Vec::new();
+    packet.extend_from_slice(&1u16.to_be_bytes());
+    string(&mut packet, topic);
+    packet.push(0); // QoS 0
+    conn.send(0x82, &packet)?;
+    let body = conn.expect(0x90)?;
+    if body.get(2).is_none_or(|&code| code == 0x80) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("subscription to '{topic}' refused"),
+        ));
+    }
+    Ok(TelegramReader::new(move || next_telegram(&mut conn)))
+}
+
+/// Receives the next telegram published to the subscribed topic.
+fn next_telegram(conn: &mut Connection) -> Result<Vec<u8>, io::Error> {
+    loop {
+        let (kind, body) = conn.receive()?;
+        // Retained messages are telegrams from before subscribing.
+        if kind >> 4 != 3 || kind & 0x01 != 0 {
+            continue;
+        }
+        let topic_len = match body.get(..2) {
+            Some(len) => usize::from(u16::from_be_bytes([len[0], len[1]])),
+            None => continue,
+        };
+        let mut start = 2 + topic_len;
+        if kind & 0x06 != 0 {
+            start += 2; // packet identifier
+        }
+        let mut telegram = body.get(start..).unwrap_or_default().to_vec();
+        // Dongles tend to leave out the line end after the CRC.
+        if !telegram.ends_with(b"\n") {
+            telegram.extend_from_slice(b"\r\n");
+        }
+        return Ok(telegram);
+    }
+}
+
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+struct Connection {
+    stream: Box<dyn Stream>,
+}
+
+impl Connection {
+    /// Connects to the broker, announcing the `keep_alive` interval, zero
+    /// to disable it.
+    fn open(
+        broker: &Broker,
+        tls: Option<&tls::Client>,
+        keep_alive: Duration,
+        read_timeout: Duration,
+    ) -> Result<Connection, io::Error> {
+        let addr = &broker.address.addr;
+        let sock = collector::connect(addr, TIMEOUT)?;
+        sock.set_read_timeout(Some(read_timeout))?;
+        sock.set_write_timeout(Some(TIMEOUT))?;
+        let stream: Box<dyn Stream> = match tls {
+            Some(tls) => Box::new(tls.connect(sock, addr)?),
+            None => Box::new(sock),
+        };
+        let mut conn = Connection { stream };
+
+        let mut flags = 0x02; // clean session
+        let mut payload = Vec::new();
+        string(&mut payload, &broker.client_id);
+        if let Some((user, password)) = &broker.credentials {
+            flags |= 0xc0;
+            string(&mut payload, user);
+            string(&mut payload, password);
+        }
+        let mut packet = Vec::new();
+        string(&mut packet, "MQTT");
+        packet.push(4); // protocol level 3.1.1
+        packet.push(flags);
+        packet.extend_from_slice(&(keep_alive.as_secs() as u16).to_be_bytes());
+        packet.extend_from_slice(&payload);
+        conn.send(0x10, &packet)?;
+
+        let (kind, body) = conn.receive()?;
+        match (kind >> 4, body.get(1)) {
+            (2, Some(0)) => Ok(conn),
+            (2, Some(code)) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("connection refused: {}", connack_reason(*code)),
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected CONNACK",
+            )),
+        }
+    }
+
+    /// Publishes the pending messages as they come, pinging the broker when
+    /// idle.
+    fn run(&mut self, pending: &Pending, qos: u8, packet_id: &mut u16) -> Result<(), io::Error> {
+        let mut last_sent = Instant::now();
+        loop {
+            let messages = {
+                let messages = pending.messages.lock().unwrap();
+                let (mut messages, _) = pending
+                    .published
+                    .wait_timeout_while(messages, KEEP_ALIVE / 2, |messages| messages.is_empty())
+                    .unwrap();
+                std::mem::take(&mut *messages)
+            };
+            if messages.is_empty() {
+                if last_sent.elapsed() >= KEEP_ALIVE / 2 {
+                    self.send(0xc0, &[])?;
+                    self.expect(0xd0)?;
+                    last_sent = Instant::now();
+                }
+                continue;
+            }
+            let mut messages = messages.into_iter();
+            let result = messages
+                .try_for_each(|(topic, payload)| self.publish(&topic, &payload, qos, packet_id));
+            if let Err(err) = result {
+                // Newer readings that came meanwhile take precedence.
+                let mut pending = pending.messages.lock().unwrap();
+                for (topic, payload) in messages {
+                    pending.entry(topic).or_insert(payload);
+                }
+                return Err(err);
+            }
+            last_sent = Instant::now();
+        }
+    }
+
+    /// Publishes a retained message and, with QoS 1, waits for it to be
+    /// acknowledged.
+    fn publish(
+        &mut self,
+        topic: &str,
+        payload: &str,
+        qos: u8,
+        packet_id: &mut u16,
+    ) -> Result<(), io::Error> {
+        let mut packet = Vec::new();
+        string(&mut packet, topic);
+        if qos > 0 {
+            *packet_id = packet_id.checked_add(1).unwrap_or(1);
+            packet.extend_from_slice(&packet_id.to_be_bytes());
+        }
+        packet.extend_from_slice(payload.as_bytes());
+        self.send(0x30 | qos << 1 | 1, &packet)?;
+        if qos > 0 {
+            let body = self.expect(0x40)?;
+            if body != packet_id.to_be_bytes() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "PUBACK for an unexpected packet",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, kind: u8, body: &[u8]) -> Result<(), io::Error> {
+        let mut packet = vec![kind];
+        let mut len = body.len();
+        loop {
+            let byte = (len % 128) as u8;
+            len /= 128;
+            packet.push(if len > 0 { byte | 0x80 } else { byte });
+            if len == 0 {
+                break;
+            }
+        }
+        packet.extend_from_slice(body);
+        self.stream.write_all(&packet)?;
+        self.stream.flush()
+    }
+
+    fn receive(&mut self) -> Result<(u8, Vec<u8>), io::Error> {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte)?;
+        let kind = byte[0];
+        let mut len = 0;
+        for shift in (0..28).step_by(7) {
+            self.stream.read_exact(&mut byte)?;
+            len |= usize::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        self.stream.read_exact(&mut body)?;
+        Ok((kind, body))
+    }
+
+    /// Receives the next packet, which has to be of the given type.
+    fn expect(&mut self, kind: u8) -> Result<Vec<u8>, io::Error> {
+        let (received, body) = self.receive()?;
+        if received & 0xf0 != kind {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected packet type {}", received >> 4),
+            ));
+        }
+        Ok(body)
+    }
+}
+
+fn string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn connack_reason(code: u8) -> &'static str {
+    match code {
+        1 => "unacceptable protocol version",
+        2 => "client identifier rejected",
+        3 => "server unavailable",
+        4 => "bad user name or password",
+        5 => "not authorized",
+        _ => "unknown reason",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A broker replaying `input`, keeping what the client sent.
+    struct Replay {
+        input: io::Cursor<Vec<u8>>,
+        sent: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for Replay {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Replay {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn connection(input: &[u8]) -> (Connection, Arc<Mutex<Vec<u8>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let stream = Replay {
+            input: io::Cursor::new(input.to_vec()),
+            sent: sent.clone(),
+        };
+        let conn = Connection {
+            stream: Box::new(stream),
+        };
+        (conn, sent)
+    }
+
+    #[test]
+    fn packet_lengths() {
+        let (mut conn, sent) = connection(&[]);
+        let body = vec![0x55; 200];
+        conn.send(0x30, &body).unwrap();
+        let packet = sent.lock().unwrap().clone();
+        assert_eq!(packet[..3], [0x30, 0xc8, 0x01]);
+
+        let (mut conn, _) = connection(&packet);
+        assert_eq!(conn.receive().unwrap(), (0x30, body));
+        assert_eq!(
+            conn.receive().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn publish_retained() {
+        let (mut conn, sent) = connection(&[0x40, 0x02, 0x00, 0x08]);
+        let mut packet_id = 7;
+        conn.publish("p1/power_consumed_kw", "0.31", 1, &mut packet_id)
+            .unwrap();
+        assert_eq!(packet_id, 8);
+        let mut expected = vec![0x33, 28, 0, 20];
+        expected.extend_from_slice(b"p1/power_consumed_kw");
+        expected.extend_from_slice(&[0, 8]);
+        expected.extend_from_slice(b"0.31");
+        assert_eq!(*sent.lock().unwrap(), expected);
+
+        // Acknowledging another packet.
+        let (mut conn, _) = connection(&[0x40, 0x02, 0x00, 0x01]);
+        assert!(conn.publish("t", "1", 1, &mut packet_id).is_err());
+        let (mut conn, sent) = connection(&[]);
+        conn.publish("t", "1", 0, &mut packet_id).unwrap();
+        assert_eq!(*sent.lock().unwrap(), [0x31, 4, 0, 1, b't', b'1']);
+    }
+
+    #[test]
+    fn receive_telegrams() {
+        let publish = |kind: u8, packet_id: Option<u16>, payload: &[u8]| {
+            let mut body = Vec::new();
+            string(&mut body, "dsmr/raw");
+            if let Some(id) = packet_id {
+                body.extend_from_slice(&id.to_be_bytes());
+            }
+            body.extend_from_slice(payload);
+            [vec![kind, body.len() as u8], body].concat()
+        };
+        let input = [
+            // A retained telegram, from before subscribing.
+            publish(0x31, None, b"/old\r\n!0000\r\n"),
+            vec![0xd0, 0x00],
+            publish(0x32, Some(1), b"/ISK5\r\n!1234"),
+            publish(0x30, None, b"/ISK5\r\n!5678\r\n"),
+        ]
+        .concat();
+        let (mut conn, _) = connection(&input);
+        assert_eq!(next_telegram(&mut conn).unwrap(), b"/ISK5\r\n!1234\r\n");
+        assert_eq!(next_telegram(&mut conn).unwrap(), b"/ISK5\r\n!5678\r\n");
+        assert!(next_telegram(&mut conn).is_err());
+    }
+
+    #[test]
+    fn parse_broker_address() {
+        let addr = "mqtt://broker".parse::<BrokerAddress>().unwrap();
+        assert_eq!((addr.tls, addr.addr.as_str()), (false, "broker:1883"));
+        let addr = "mqtts://broker:1884/".parse::<BrokerAddress>().unwrap();
+        assert_eq!((addr.tls, addr.addr.as_str()), (true, "broker:1884"));
+        let addr = "mqtts://[::1]".parse::<BrokerAddress>().unwrap();
+        assert_eq!(addr.to_string(), "mqtts://[::1]:8883");
+        for invalid in ["broker:1883", "mqtt://", "mqtt://broker/topic"] {
+            assert!(invalid.parse::<BrokerAddress>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn topics() {
+        let sample = Sample::gauge("phase_power_consumed_kw", 0.1)
+            .with_label("meter", "house")
+            .with_label("phase", "l1");
+        let (topic, payload) = message("p1", &sample);
+        assert_eq!(topic, "p1/house/phase_power_consumed_kw/l1");
+        assert_eq!(payload, "0.1");
+        let sample = Sample::gauge("active_tariff", 1.0).with_label("tariff", "low");
+        assert_eq!(
+            message("p1", &sample),
+            ("p1/active_tariff".to_string(), "low".to_string())
+        );
+    }
+}
@@ -0,0 +1,413 @@
+//! Raw access to COSEM objects for the bits `dsmr5` doesn't model (units,
+//! vendor-specific references, ...).
+
+use dsmr5::{
+    state::State,
+    types::{OctetString, UFixedInteger, TST},
+    OBIS,
+};
+use std::{collections::BTreeSet, str::FromStr};
+
+/// An OBIS object to be exported under the given name, given as
+/// `CODE=METRIC[,type=gauge|counter][,scale=FACTOR][,LABEL=VALUE...]`.
+#[derive(Clone)]
+pub struct ExtraObis {
+    pub code: String,
+    pub name: String,
+    /// Whether the object is a total that only increases, like an energy
+    /// register.
+    pub counter: bool,
+    /// Factor the value is multiplied by, e.g. to convert Wh to kWh.
+    pub scale: f64,
+    pub labels: Vec<(String, String)>,
+}
+
+impl FromStr for ExtraObis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let (code, name) = parts
+            .next()
+            .and_then(|mapping| mapping.split_once('='))
+            .ok_or_else(|| format!("expected CODE=METRIC, got '{s}'"))?;
+        if code.is_empty() {
+            return Err("OBIS code must not be empty".to_string());
+        }
+        if !valid_name(name) {
+            return Err(format!("invalid metric name '{name}'"));
+        }
+        let mut extra = ExtraObis {
+            code: code.to_string(),
+            name: name.to_string(),
+            counter: false,
+            scale: 1.0,
+            labels: vec![],
+        };
+        for part in parts {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected KEY=VALUE, got '{part}'"))?;
+            match key {
+                "type" => {
+                    extra.counter = match value {
+                        "gauge" => false,
+                        "counter" => true,
+                        _ => {
+                            return Err(format!(
+                                "unknown type '{value}', expected gauge or counter"
+                            ))
+                        }
+                    }
+                }
+                "scale" => {
+                    extra.scale = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|scale| scale.is_finite())
+                        .ok_or_else(|| format!("invalid scale '{value}'"))?
+                }
+                "meter" => return Err("the meter label is set by the exporter".to_string()),
+                key if valid_name(key) => extra.labels.push((key.to_string(), value.to_string())),
+                key => return Err(format!("invalid label name '{key}'")),
+            }
+        }
+        Ok(extra)
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns an error if objects exported under the same name differ in their
+/// type or can't be told apart by their labels.
+pub fn check_extra_obis(extra_obis: &[ExtraObis]) -> Result<(), String> {
+    for (i, extra) in extra_obis.iter().enumerate() {
+        for other in extra_obis[..i]
+            .iter()
+            .filter(|other| other.name == extra.name)
+        {
+            if other.counter != extra.counter {
+                return Err(format!(
+                    "{} and {} are both exported as {}, but as different types",
+                    other.code, extra.code, extra.name
+                ));
+            }
+            if other.labels == extra.labels {
+                return Err(format!(
+                    "{} and {} are both exported as {} with the same labels",
+                    other.code, extra.code, extra.name
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the telegram text of a readout without the trailing padding.
+pub fn readout_text(readout: &dsmr5::Readout) -> &str {
+    std::str::from_utf8(&readout.buffer)
+        .unwrap_or_default()
+        .trim_end_matches('\0')
+}
+
+/// Returns the value groups (the parts between parentheses) of the object
+/// with the given OBIS reference.
+pub fn find<'a>(telegram: &'a str, reference: &str) -> Option<Vec<&'a str>> {
+    telegram.lines().find_map(|line| {
+        let groups = line.trim_end().strip_prefix(reference)?;
+        groups.starts_with('(').then(|| groups_of(groups))
+    })
+}
+
+fn groups_of(groups: &str) -> Vec<&str> {
+    groups
+        .split(')')
+        .filter_map(|g| g.strip_prefix('('))
+        .collect()
+}
+
+/// Splits a value group like `004836.851*m3` into its number and unit.
+pub fn value(group: &str) -> Option<(f64, Option<&str>)> {
+    let (number, unit) = match group.split_once('*') {
+        Some((number, unit)) => (number, Some(unit)),
+        None => (group, None),
+    };
+    Some((number.parse().ok()?, unit))
+}
+
+/// Returns the unit of the last value group of the given object.
+pub fn unit<'a>(telegram: &'a str, reference: &str) -> Option<&'a str> {
+    let groups = find(telegram, reference)?;
+    value(groups.last()?)?.1
+}
+
+/// Prefixes of the energy registers, as consumed and produced, followed by
+/// the tariff.
+const ENERGY_REGISTERS: [&str; 2] = ["1-0:1.8.", "1-0:2.8."];
+
+/// Returns the consumed and produced energy registers of a tariff.
+pub fn energy_registers(tariff: u16) -> [String; 2] {
+    ENERGY_REGISTERS.map(|prefix| format!("{prefix}{tariff}"))
+}
+
+/// Returns the tariff of the energy register starting `line`, if any. The
+/// totals (`1-0:1.8.0`) aren't registers of a tariff.
+pub fn energy_register_tariff(line: &str) -> Option<u16> {
+    let rest = ENERGY_REGISTERS
+        .iter()
+        .find_map(|prefix| line.strip_prefix(prefix))?;
+    let tariff = rest.split('(').next()?.parse().ok()?;
+    (tariff > 0).then_some(tariff)
+}
+
+/// Returns the tariffs of the energy registers reported in a telegram, in
+/// order.
+pub fn energy_tariffs(telegram: &str) -> BTreeSet<u16> {
+    objects(telegram)
+        .filter_map(energy_register_tariff)
+        .collect()
+}
+
+/// Average power demand of the current quarter hour, on Belgian meters.
+pub const AVERAGE_DEMAND: &str = "1-0:1.4.0";
+/// Highest quarter-hour average demand of the month, with when it occurred.
+pub const MONTHLY_PEAK: &str = "1-0:1.6.0";
+/// Monthly peaks of the last 13 months.
+pub const PEAK_HISTORY: &str = "0-0:98.1.0";
+/// Objects of the Belgian capacity tariff, read with [`find`].
+pub const CAPACITY_TARIFF: [&str; 3] = [AVERAGE_DEMAND, MONTHLY_PEAK, PEAK_HISTORY];
+/// Event log of the long power failures in any phase.
+const POWER_FAILURE_LOG: &str = "1-0:99.97.0";
+
+/// Instantaneous reactive power per phase, consumed (Q+) and produced (Q-).
+const REACTIVE_POWER: [[&str; 2]; 3] = [
+    ["1-0:23.7.0", "1-0:24.7.0"],
+    ["1-0:43.7.0", "1-0:44.7.0"],
+    ["1-0:63.7.0", "1-0:64.7.0"],
+];
+
+/// Returns the reactive power consumed and produced in a phase, numbered
+/// from 0, normalized to kvar.
+pub fn reactive_power(telegram: &str, phase: usize) -> [Option<f64>; 2] {
+    REACTIVE_POWER[phase].map(
+        |reference| match value(find(telegram, reference)?.last()?)? {
+            (value, Some("var")) => Some(value / 1000.0),
+            (value, _) => Some(value),
+        },
+    )
+}
+
+/// Reads a power value group, normalized to kW.
+pub fn power(group: &str) -> Option<f64> {
+    match value(group)? {
+        (value, Some("W")) => Some(value / 1000.0),
+        (value, _) => Some(value),
+    }
+}
+
+/// Returns the monthly peaks of the peak history, in kW, by month as
+/// `YYYY-MM`.
+///
+/// The history is `(count)(reference)(reference)` followed by the start of
+/// the next month, the time of the peak and the peak of every month.
+pub fn peak_history(telegram: &str) -> Option<Vec<(String, f64)>> {
+    let groups = find(telegram, PEAK_HISTORY)?;
+    let months = groups
+        .get(3..)?
+        .chunks_exact(3)
+        .filter_map(|entry| {
+            let year: u16 = entry[0].get(..2)?.parse().ok()?;
+            let month: u8 = entry[0].get(2..4)?.parse().ok()?;
+            let (year, month) = match month {
+                1 => (year.checked_sub(1)?, 12),
+                month => (year, month - 1),
+            };
+            Some((format!("20{year:02}-{month:02}"), power(entry[2])?))
+        })
+        .collect();
+    Some(months)
+}
+
+/// Returns the end and the length in seconds of every long power failure in
+/// the event log, in the order logged.
+///
+/// The log is `(count)(reference)` followed by the end and the length of
+/// every failure.
+pub fn power_failure_log(telegram: &str) -> Option<Vec<(TST, f64)>> {
+    let groups = find(telegram, POWER_FAILURE_LOG)?;
+    let events = groups
+        .get(2..)?
+        .chunks_exact(2)
+        .filter_map(|event| {
+            let end = TST::parse(&format!("({})", event[0])).ok()?;
+            Some((end, value(event[1])?.0))
+        })
+        .collect();
+    Some(events)
+}
+
+/// Returns the lines of the COSEM objects of a telegram.
+pub fn objects(telegram: &str) -> impl Iterator<Item = &str> {
+    telegram
+        .lines()
+        .filter(|line| line.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// Returns the reference of an object line and the number of its last value
+/// group, if it's numeric. Identifiers and text messages are octet strings
+/// that may well consist of digits only, they're skipped, and so are
+/// profiles like the peak history, with more than a time and a value.
+pub fn numeric_value(line: &str) -> Option<(&str, f64)> {
+    let (reference, groups) = line.trim_end().split_at(line.find('(')?);
+    let octet_string = reference
+        .split_once(':')
+        .is_some_and(|(_, id)| id.starts_with("96.1.") || id.starts_with("96.13."));
+    let groups = groups_of(groups);
+    if octet_string || groups.len() > 2 {
+        return None;
+    }
+    let (value, _) = value(groups.last()?)?;
+    value.is_finite().then_some((reference, value))
+}
+
+/// Builds the meter state from the text of a telegram.
+///
+/// Same as `dsmr5::Result::<State>::from`, except that objects not known to
+/// `dsmr5` are skipped instead of failing the whole telegram. Energy
+/// registers `dsmr5` can't parse (it only accepts kWh) are skipped as well,
+/// they are read with [`find`] instead, and so are the objects it mistakes
+/// for M-Bus ones.
+pub fn state(telegram: &str) -> dsmr5::Result<State> {
+    objects(telegram).try_fold(State::default(), |mut state, line| {
+        match OBIS::parse(line) {
+            Ok(obj) => apply(&mut state, obj)?,
+            Err(dsmr5::Error::UnknownObis) => {}
+            Err(_) if is_energy_register(line) || is_misread_as_mbus(line) => {}
+            Err(err) => return Err(err),
+        }
+        Ok(state)
+    })
+}
+
+pub fn is_energy_register(line: &str) -> bool {
+    energy_register_tariff(line).is_some()
+}
+
+/// Whether `dsmr5` fails on the object only because it takes it for an
+/// M-Bus one: it reads the `0-n:` references of that length it doesn't know
+/// as objects of channel n, and rejects channel 0, that of the meter itself,
+/// e.g. of the Belgian peak history.
+pub fn is_misread_as_mbus(line: &str) -> bool {
+    let reference = line.split('(').next().unwrap_or_default();
+    reference.len() == 10
+        && reference.starts_with("0-0:")
+        && !matches!(reference, "0-0:96.1.1" | "0-0:96.7.9")
+}
+
+fn apply(state: &mut State, obj: OBIS) -> dsmr5::Result<()> {
+    match obj {
+        OBIS::DateTime(tst) => state.datetime = Some(tst),
+        OBIS::MeterReadingTo(t, mr) => state.meterreadings[t as usize].to = Some(f64::from(&mr)),
+        OBIS::MeterReadingBy(t, mr) => state.meterreadings[t as usize].by = Some(f64::from(&mr)),
+        OBIS::TariffIndicator(ti) => {
+            let mut octets = ti.as_octets();
+            let mut next = || octets.next().unwrap_or(Err(dsmr5::Error::InvalidFormat));
+            state.tariff_indicator = Some([next()?, next()?]);
+        }
+        OBIS::PowerDelivered(p) => state.power_delivered = Some(f64::from(&p)),
+        OBIS::PowerReceived(p) => state.power_received = Some(f64::from(&p)),
+        OBIS::PowerFailures(UFixedInteger(pf)) => state.power_failures = Some(pf),
+        OBIS::LongPowerFailures(UFixedInteger(lpf)) => state.long_power_failures = Some(lpf),
+        OBIS::VoltageSags(l, UFixedInteger(n)) => state.lines[l as usize].voltage_sags = Some(n),
+        OBIS::VoltageSwells(l, UFixedInteger(n)) => {
+            state.lines[l as usize].voltage_swells = Some(n)
+        }
+        OBIS::InstantaneousVoltage(l, v) => state.lines[l as usize].voltage = Some(f64::from(&v)),
+        OBIS::InstantaneousCurrent(l, UFixedInteger(a)) => {
+            state.lines[l as usize].current = Some(a)
+        }
+        OBIS::InstantaneousActivePowerPlus(l, p) => {
+            state.lines[l as usize].active_power_plus = Some(f64::from(&p))
+        }
+        OBIS::InstantaneousActivePowerNeg(l, p) => {
+            state.lines[l as usize].active_power_neg = Some(f64::from(&p))
+        }
+        OBIS::SlaveDeviceType(s, UFixedInteger(dt)) => {
+            state.slaves[s as usize].device_type = Some(dt)
+        }
+        OBIS::SlaveMeterReading(s, tst, mr) => {
+            state.slaves[s as usize].meter_reading = Some((tst, f64::from(&mr)))
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Identification data of the meter and its M-Bus devices.
+#[derive(Default)]
+pub struct Identification {
+    /// DSMR version, e.g. `50` for DSMR 5.0.
+    pub version: Option<String>,
+    pub equipment_id: Option<String>,
+    pub slave_equipment_ids: [Option<String>; 4],
+}
+
+pub fn identification(telegram: &str) -> Identification {
+    let mut id = Identification::default();
+    for line in objects(telegram) {
+        match OBIS::parse(line) {
+            Ok(OBIS::Version(v)) => {
+                id.version = v
+                    .as_octets()
+                    .map(|b| b.map(|b| format!("{b:02X}")))
+                    .collect::<dsmr5::Result<String>>()
+                    .ok()
+            }
+            Ok(OBIS::EquipmentIdentifier(ei)) => id.equipment_id = octet_string(&ei),
+            Ok(OBIS::SlaveEquipmentIdentifier(s, ei)) => {
+                id.slave_equipment_ids[s as usize] = octet_string(&ei)
+            }
+            _ => {}
+        }
+    }
+    id
+}
+
+/// Returns the text message of the meter, if it displays one. It's an octet
+/// string, but older meters send the text as is.
+pub fn text_message(telegram: &str) -> Option<String> {
+    let groups = find(telegram, "0-0:96.13.0")?;
+    let group = groups.first()?.trim();
+    let bytes = (0..group.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(group.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>();
+    let message = match bytes {
+        Some(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        None => group.to_string(),
+    };
+    let message = message.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+    (!message.is_empty()).then(|| message.to_string())
+}
+
+/// Decodes a hex-encoded octet string into text.
+fn octet_string(s: &OctetString) -> Option<String> {
+    let bytes = s.as_octets().collect::<dsmr5::Result<Vec<u8>>>().ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// CRC16/ARC over the telegram, from `/` up to and including `!`.
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &b| {
+        (0..8).fold(crc ^ u16::from(b), |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
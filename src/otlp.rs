@@ -0,0 +1,261 @@
+//! Periodic export of the registry to an OpenTelemetry collector, with OTLP
+//! over HTTP in its JSON encoding.
+
+use crate::{
+    exposition::{self, families, sample_value, Family, Labels},
+    http::{Client, Url},
+    parse::{Format, Value},
+};
+use prometheus_client::registry::Registry;
+use std::{
+    io,
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Timeout for connecting to the collector and for every read and write.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct OtlpConfig {
+    /// Base URL of the collector's OTLP/HTTP receiver, `v1/metrics` is
+    /// appended to it.
+    pub url: Url,
+    pub interval: Duration,
+}
+
+pub fn start_exporter(config: OtlpConfig, registry: Arc<Registry>) -> Result<(), io::Error> {
+    let client = Client::new(&config.url, TIMEOUT)?;
+    let url = config.url.join("v1/metrics");
+    // The counters and histograms are cumulative since the exporter started.
+    let start = unix_nanos(SystemTime::now());
+    thread::spawn(move || loop {
+        thread::sleep(config.interval);
+        if let Err(err) = export(&client, &url, &registry, &start) {
+            tracing::warn!("Failed to export metrics to {url}: {err}");
+        }
+    });
+    Ok(())
+}
+
+fn export(client: &Client, url: &Url, registry: &Registry, start: &str) -> Result<(), io::Error> {
+    let openmetrics = exposition::encode(registry).map_err(io::Error::other)?;
+    let now = unix_nanos(SystemTime::now());
+    let body = export_request(&openmetrics, start, &now).render(Format::Json);
+    let response = client.send(
+        "POST",
+        url,
+        &[("Content-Type", "application/json")],
+        body.as_bytes(),
+    )?;
+    match response.status {
+        200..=299 => Ok(()),
+        status => Err(io::Error::other(format!(
+            "unexpected status {status}: {}",
+            response.body
+        ))),
+    }
+}
+
+/// Nanoseconds since the Unix epoch, as a string since 64-bit integers are
+/// strings in the JSON encoding.
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// Converts the OpenMetrics encoding of the registry into an OTLP
+/// `ExportMetricsServiceRequest`: gauges, info and unknown metrics into
+/// gauges, counters into monotonic cumulative sums and histograms into
+/// cumulative explicit bucket histograms.
+fn export_request(openmetrics: &str, start: &str, now: &str) -> Value {
+    let metrics = families(openmetrics)
+        .iter()
+        .filter_map(|family| metric(family, start, now))
+        .collect();
+    Value::Object(vec![(
+        "resourceMetrics",
+        Value::List(vec![Value::Object(vec![
+            (
+                "resource",
+                Value::Object(vec![(
+                    "attributes",
+                    attributes(&[
+                        ("service.name".to_string(), "p1-exporter".to_string()),
+                        (
+                            "service.version".to_string(),
+                            env!("CARGO_PKG_VERSION").to_string(),
+                        ),
+                    ]),
+                )]),
+            ),
+            (
+                "scopeMetrics",
+                Value::List(vec![Value::Object(vec![
+                    (
+                        "scope",
+                        Value::Object(vec![
+                            ("name", Value::Text("p1-exporter".to_string())),
+                            (
+                                "version",
+                                Value::Text(env!("CARGO_PKG_VERSION").to_string()),
+                            ),
+                        ]),
+                    ),
+                    ("metrics", Value::List(metrics)),
+                ])]),
+            ),
+        ])]),
+    )])
+}
+
+fn metric(family: &Family, start: &str, now: &str) -> Option<Value> {
+    let data = match family.kind {
+        "counter" => (
+            "sum",
+            Value::Object(vec![
+                (
+                    "dataPoints",
+                    number_points(family, "_total", Some(start), now),
+                ),
+                ("aggregationTemporality", Value::Number(2.0)),
+                ("isMonotonic", Value::Bool(true)),
+            ]),
+        ),
+        "histogram" => (
+            "histogram",
+            Value::Object(vec![
+                ("dataPoints", histogram_points(family, start, now)),
+                ("aggregationTemporality", Value::Number(2.0)),
+            ]),
+        ),
+        "info" => (
+            "gauge",
+            Value::Object(vec![(
+                "dataPoints",
+                number_points(family, "_info", None, now),
+            )]),
+        ),
+        "gauge" | "unknown" => (
+            "gauge",
+            Value::Object(vec![("dataPoints", number_points(family, "", None, now))]),
+        ),
+        _ => return None,
+    };
+    Some(Value::Object(vec![
+        ("name", Value::Text(family.name.to_string())),
+        ("description", Value::Text(family.help.to_string())),
+        ("unit", Value::Text(family.unit.to_string())),
+        data,
+    ]))
+}
+
+/// Returns the data points of the samples named after the family with
+/// `suffix`, skipping e.g. the `_created` samples of counters.
+fn number_points(family: &Family, suffix: &str, start: Option<&str>, now: &str) -> Value {
+    let points = family
+        .samples
+        .iter()
+        .filter(|sample| sample.name.strip_prefix(family.name) == Some(suffix))
+        .map(|sample| {
+            let mut point = vec![("attributes", attributes(&sample.labels))];
+            if let Some(start) = start {
+                point.push(("startTimeUnixNano", Value::Text(start.to_string())));
+            }
+            point.push(("timeUnixNano", Value::Text(now.to_string())));
+            point.push(("asDouble", double(sample.value)));
+            Value::Object(point)
+        })
+        .collect();
+    Value::List(points)
+}
+
+fn histogram_points(family: &Family, start: &str, now: &str) -> Value {
+    /// The series of a histogram: its labels, count, sum and cumulative
+    /// buckets.
+    type Series = (Labels, f64, f64, Vec<(f64, f64)>);
+    let mut series: Vec<Series> = Vec::new();
+    for sample in &family.samples {
+        let mut labels = sample.labels.clone();
+        let le = labels
+            .iter()
+            .position(|(name, _)| name == "le")
+            .map(|i| labels.remove(i).1);
+        let i = match series.iter().position(|series| series.0 == labels) {
+            Some(i) => i,
+            None => {
+                series.push((labels, 0.0, 0.0, Vec::new()));
+                series.len() - 1
+            }
+        };
+        match sample.name.strip_prefix(family.name) {
+            Some("_count") => series[i].1 = sample.value,
+            Some("_sum") => series[i].2 = sample.value,
+            Some("_bucket") => {
+                if let Some(le) = le.and_then(|le| sample_value(&le)) {
+                    series[i].3.push((le, sample.value));
+                }
+            }
+            _ => {}
+        }
+    }
+    let points = series
+        .into_iter()
+        .map(|(labels, count, sum, mut buckets)| {
+            buckets.sort_by(|a, b| a.0.total_cmp(&b.0));
+            // OTLP counts every bucket separately, the +Inf one included,
+            // and leaves the +Inf bound out.
+            let mut counts = Vec::with_capacity(buckets.len());
+            let mut previous = 0.0;
+            for (_, cumulative) in &buckets {
+                counts.push(Value::Text(format!("{}", cumulative - previous)));
+                previous = *cumulative;
+            }
+            let bounds = buckets
+                .iter()
+                .filter(|(le, _)| le.is_finite())
+                .map(|(le, _)| Value::Number(*le))
+                .collect();
+            Value::Object(vec![
+                ("attributes", attributes(&labels)),
+                ("startTimeUnixNano", Value::Text(start.to_string())),
+                ("timeUnixNano", Value::Text(now.to_string())),
+                ("count", Value::Text(format!("{count}"))),
+                ("sum", double(sum)),
+                ("bucketCounts", Value::List(counts)),
+                ("explicitBounds", Value::List(bounds)),
+            ])
+        })
+        .collect();
+    Value::List(points)
+}
+
+/// Encodes a double, non-finite values being strings in the JSON encoding.
+fn double(value: f64) -> Value {
+    if value.is_nan() {
+        Value::Text("NaN".to_string())
+    } else if value.is_infinite() {
+        let sign = if value > 0.0 { "" } else { "-" };
+        Value::Text(format!("{sign}Infinity"))
+    } else {
+        Value::Number(value)
+    }
+}
+
+fn attributes(labels: &[(String, String)]) -> Value {
+    let attributes = labels
+        .iter()
+        .map(|(key, value)| {
+            Value::Object(vec![
+                ("key", Value::Text(key.clone())),
+                (
+                    "value",
+                    Value::Object(vec![("stringValue", Value::Text(value.clone()))]),
+                ),
+            ])
+        })
+        .collect();
+    Value::List(attributes)
+}
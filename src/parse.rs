@@ -0,0 +1,447 @@
+//! Decoding of raw telegrams into a human-readable or JSON description, for
+//! debugging what the exporter makes of a meter's output.
+
+use crate::{collector::mbus_medium, obis};
+use dsmr5::{state::State, types::TST, OBIS};
+use std::{
+    fmt::{self, Write},
+    fs,
+    io::{self, Read},
+    path::Path,
+    str::FromStr,
+};
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown format '{s}', expected text or json")),
+        }
+    }
+}
+
+/// A decoded telegram.
+pub struct Decoded {
+    pub description: Value,
+    /// Whether the exporter would accept the telegram.
+    pub valid: bool,
+}
+
+/// A JSON-like value, which can also be rendered as indented text.
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    /// A number with the unit it was reported in.
+    Quantity(f64, Option<String>),
+    Text(String),
+    List(Vec<Value>),
+    Object(Vec<(&'static str, Value)>),
+}
+
+impl From<Option<f64>> for Value {
+    fn from(value: Option<f64>) -> Self {
+        value.map_or(Value::Null, Value::Number)
+    }
+}
+
+impl From<Option<u64>> for Value {
+    fn from(value: Option<u64>) -> Self {
+        value.map_or(Value::Null, |v| Value::Number(v as f64))
+    }
+}
+
+impl From<Option<String>> for Value {
+    fn from(value: Option<String>) -> Self {
+        value.map_or(Value::Null, Value::Text)
+    }
+}
+
+/// Prints every telegram read from the file, or stdin if it's `None` or
+/// `-`. Fails if there's no telegram or the exporter would reject any.
+pub fn run(path: Option<&Path>, format: Format) -> Result<(), String> {
+    let input = match path {
+        Some(path) if path != Path::new("-") => {
+            fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?
+        }
+        _ => {
+            let mut input = Vec::new();
+            io::stdin()
+                .read_to_end(&mut input)
+                .map_err(|err| format!("stdin: {err}"))?;
+            input
+        }
+    };
+    let input = String::from_utf8_lossy(&input);
+
+    let telegrams = telegrams(&input);
+    if telegrams.is_empty() {
+        return Err("No telegram found, telegrams start with '/'".to_string());
+    }
+    let mut rejected = 0;
+    for (i, telegram) in telegrams.iter().enumerate() {
+        let decoded = decode(telegram);
+        if i > 0 {
+            println!();
+        }
+        print!("{}", decoded.description.render(format));
+        rejected += !decoded.valid as usize;
+    }
+    match rejected {
+        0 => Ok(()),
+        n => Err(format!(
+            "{n} of {} telegrams would be rejected by the exporter",
+            telegrams.len()
+        )),
+    }
+}
+
+/// Splits the input into telegrams, at lines starting with `/`.
+pub fn telegrams(input: &str) -> Vec<&str> {
+    let mut starts: Vec<usize> = input
+        .match_indices('/')
+        .map(|(i, _)| i)
+        .filter(|&i| i == 0 || input[..i].ends_with('\n'))
+        .collect();
+    starts.push(input.len());
+    starts
+        .windows(2)
+        .map(|w| input[w[0]..w[1]].trim_end())
+        .collect()
+}
+
+/// Decodes a telegram, from the leading `/` to the checksum.
+pub fn decode(telegram: &str) -> Decoded {
+    let checksum = checksum(telegram);
+    let mut valid = checksum.is_ok();
+
+    // Objects `dsmr5` fails to parse make the exporter reject the telegram,
+    // they are reported and left out of the state here.
+    let mut unknown = Vec::new();
+    let mut invalid = Vec::new();
+    let mut objects = String::new();
+    for line in obis::objects(telegram) {
+        match OBIS::parse(line) {
+            // Unknown to `dsmr5` but read by the exporter.
+            Err(_) if obis::CAPACITY_TARIFF.contains(&reference(line)) => {}
+            Err(dsmr5::Error::UnknownObis) => {
+                unknown.push(Value::Text(reference(line).to_string()))
+            }
+            Err(_) if obis::is_misread_as_mbus(line) => {
+                unknown.push(Value::Text(reference(line).to_string()))
+            }
+            Err(_) if !obis::is_energy_register(line) => {
+                invalid.push(Value::Text(line.to_string()));
+                continue;
+            }
+            _ => {}
+        }
+        objects.push_str(line);
+        objects.push('\n');
+    }
+    valid &= invalid.is_empty();
+    let state = obis::state(&objects).unwrap_or_default();
+    let id = obis::identification(&objects);
+
+    let description = Value::Object(vec![
+        (
+            "header",
+            Value::Text(telegram.lines().next().unwrap_or_default().to_string()),
+        ),
+        (
+            "checksum",
+            Value::Text(checksum.map_or_else(|err| err, |()| "ok".to_string())),
+        ),
+        ("dsmr_version", id.version.clone().into()),
+        ("equipment_id", id.equipment_id.clone().into()),
+        ("timestamp", state.datetime.as_ref().map(timestamp).into()),
+        (
+            "tariff",
+            state
+                .tariff_indicator
+                .map(|ti| u16::from_be_bytes(ti) as u64)
+                .into(),
+        ),
+        ("power_consumed_kw", state.power_delivered.into()),
+        ("power_produced_kw", state.power_received.into()),
+        ("average_demand", reading(&objects, obis::AVERAGE_DEMAND, 0)),
+        ("monthly_peak", reading(&objects, obis::MONTHLY_PEAK, 1)),
+        ("energy_consumed", energy(&objects, 0)),
+        ("energy_produced", energy(&objects, 1)),
+        ("power_failures", state.power_failures.into()),
+        ("long_power_failures", state.long_power_failures.into()),
+        ("phases", phases(&state)),
+        ("mbus", mbus(&objects, &state, &id)),
+        ("unknown_objects", Value::List(unknown)),
+        ("invalid_objects", Value::List(invalid)),
+    ]);
+    Decoded { description, valid }
+}
+
+/// Fields of a decoded telegram describing the telegram rather than the
+/// readings.
+const NON_READINGS: [&str; 4] = ["header", "checksum", "unknown_objects", "invalid_objects"];
+
+/// Decodes the readings of a telegram, leaving out the fields describing the
+/// telegram itself.
+pub fn readings(telegram: &str) -> Value {
+    let mut description = decode(telegram).description;
+    if let Value::Object(fields) = &mut description {
+        fields.retain(|(key, _)| !NON_READINGS.contains(key));
+    }
+    description
+}
+
+fn checksum(telegram: &str) -> Result<(), String> {
+    let (data, crc) = telegram
+        .split_once('!')
+        .ok_or_else(|| "missing: no '!' at the end of the telegram".to_string())?;
+    let computed = obis::crc16(format!("{data}!").as_bytes());
+    match u16::from_str_radix(crc.trim(), 16) {
+        Ok(given) if given == computed => Ok(()),
+        Ok(given) => Err(format!(
+            "mismatch: telegram says {given:04X}, computed {computed:04X} (line endings must be CRLF)"
+        )),
+        Err(_) => Err(format!("invalid: '{}'", crc.trim())),
+    }
+}
+
+/// Returns the OBIS reference of an object line, e.g. `1-0:1.8.1`.
+fn reference(line: &str) -> &str {
+    line.split('(').next().unwrap_or(line)
+}
+
+fn timestamp(tst: &TST) -> String {
+    format!(
+        "20{:02}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+        tst.year,
+        tst.month,
+        tst.day,
+        tst.hour,
+        tst.minute,
+        tst.second,
+        if tst.dst {
+            "summer time"
+        } else {
+            "winter time"
+        }
+    )
+}
+
+/// Returns the energy registers by tariff, as consumed (`direction` 0) or
+/// produced (1), with the unit they are reported in.
+fn energy(objects: &str, direction: usize) -> Value {
+    const TARIFFS: [&str; 9] = [
+        "tariff_1", "tariff_2", "tariff_3", "tariff_4", "tariff_5", "tariff_6", "tariff_7",
+        "tariff_8", "tariff_9",
+    ];
+    // DSMR 5 meters report the first two tariffs, others may report more.
+    let mut tariffs = obis::energy_tariffs(objects);
+    tariffs.extend([1, 2]);
+    Value::Object(
+        tariffs
+            .into_iter()
+            .filter_map(|tariff| {
+                let name = TARIFFS.get(usize::from(tariff) - 1)?;
+                let register = &obis::energy_registers(tariff)[direction];
+                Some((*name, reading(objects, register, 0)))
+            })
+            .collect(),
+    )
+}
+
+/// Returns a value group of an object with its unit.
+fn reading(objects: &str, reference: &str, group: usize) -> Value {
+    let value = obis::find(objects, reference)
+        .and_then(|groups| obis::value(groups.get(group)?))
+        .map(|(value, unit)| Value::Quantity(value, unit.map(str::to_string)));
+    value.unwrap_or(Value::Null)
+}
+
+fn phases(state: &State) -> Value {
+    let phases = ["l1", "l2", "l3"];
+    Value::Object(
+        phases
+            .into_iter()
+            .zip(&state.lines)
+            .map(|(phase, line)| {
+                let line = Value::Object(vec![
+                    ("voltage_volts", line.voltage.into()),
+                    ("current_amperes", line.current.into()),
+                    ("power_consumed_kw", line.active_power_plus.into()),
+                    ("power_produced_kw", line.active_power_neg.into()),
+                    ("voltage_sags", line.voltage_sags.into()),
+                    ("voltage_swells", line.voltage_swells.into()),
+                ]);
+                (phase, line)
+            })
+            .collect(),
+    )
+}
+
+/// Returns the M-Bus channels with any data.
+fn mbus(objects: &str, state: &State, id: &obis::Identification) -> Value {
+    let channels = ["channel_1", "channel_2", "channel_3", "channel_4"];
+    Value::Object(
+        channels
+            .into_iter()
+            .enumerate()
+            .zip(&state.slaves)
+            .filter(|((i, _), slave)| {
+                slave.device_type.is_some()
+                    || slave.meter_reading.is_some()
+                    || id.slave_equipment_ids[*i].is_some()
+            })
+            .map(|((i, channel), slave)| {
+                let slave = Value::Object(vec![
+                    ("device_type", slave.device_type.into()),
+                    (
+                        "medium",
+                        slave
+                            .device_type
+                            .map(|dt| mbus_medium(dt).to_string())
+                            .into(),
+                    ),
+                    ("equipment_id", id.slave_equipment_ids[i].clone().into()),
+                    (
+                        "reading",
+                        reading(objects, &format!("0-{}:24.2.1", i + 1), 1),
+                    ),
+                    (
+                        "reading_timestamp",
+                        slave
+                            .meter_reading
+                            .as_ref()
+                            .map(|(tst, _)| timestamp(tst))
+                            .into(),
+                    ),
+                ]);
+                (channel, slave)
+            })
+            .collect(),
+    )
+}
+
+impl Value {
+    pub fn render(&self, format: Format) -> String {
+        let mut out = String::new();
+        match format {
+            Format::Text => self.write_text(&mut out, 0),
+            Format::Json => self.write_json(&mut out, 0),
+        }
+        .expect("writing to a String can't fail");
+        if let Format::Json = format {
+            out.push('\n');
+        }
+        out
+    }
+
+    fn write_text(&self, out: &mut String, indent: usize) -> fmt::Result {
+        let Value::Object(fields) = self else {
+            return writeln!(out, "{:indent$}{self}", "");
+        };
+        for (key, value) in fields {
+            match value {
+                Value::Object(nested) if !nested.is_empty() => {
+                    writeln!(out, "{:indent$}{key}:", "")?;
+                    value.write_text(out, indent + 2)?;
+                }
+                _ => writeln!(out, "{:indent$}{key}: {value}", "")?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_json(&self, out: &mut String, indent: usize) -> fmt::Result {
+        let fields: Vec<(Option<&str>, &Value)> = match self {
+            Value::Null => return write!(out, "null"),
+            Value::Bool(b) => return write!(out, "{b}"),
+            Value::Number(n) => return write!(out, "{n}"),
+            Value::Quantity(n, unit) => {
+                write!(out, "{{\"value\": {n}, \"unit\": ")?;
+                match unit {
+                    Some(unit) => write_json_string(out, unit)?,
+                    None => write!(out, "null")?,
+                }
+                return write!(out, "}}");
+            }
+            Value::Text(s) => return write_json_string(out, s),
+            Value::List(items) => items.iter().map(|item| (None, item)).collect(),
+            Value::Object(fields) => fields.iter().map(|(k, v)| (Some(*k), v)).collect(),
+        };
+        let (open, close) = match self {
+            Value::List(_) => ('[', ']'),
+            _ => ('{', '}'),
+        };
+        if fields.is_empty() {
+            return write!(out, "{open}{close}");
+        }
+        writeln!(out, "{open}")?;
+        for (i, (key, value)) in fields.iter().enumerate() {
+            write!(out, "{:1$}", "", indent + 2)?;
+            if let Some(key) = key {
+                write_json_string(out, key)?;
+                write!(out, ": ")?;
+            }
+            value.write_json(out, indent + 2)?;
+            writeln!(out, "{}", if i + 1 < fields.len() { "," } else { "" })?;
+        }
+        write!(out, "{:indent$}{close}", "")
+    }
+}
+
+fn write_json_string(out: &mut String, s: &str) -> fmt::Result {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    Ok(())
+}
+
+/// Renders scalars and lists for the text format.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "missing"),
+            Value::Bool(b) => write!(f, "{}", if *b { "yes" } else { "no" }),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Quantity(n, Some(unit)) => write!(f, "{n} {unit}"),
+            Value::Quantity(n, None) => write!(f, "{n}"),
+            Value::Text(s) => write!(f, "{s}"),
+            Value::List(items) if items.is_empty() => write!(f, "none"),
+            Value::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { ", " };
+                    write!(f, "{sep}{item}")?;
+                }
+                Ok(())
+            }
+            Value::Object(fields) if fields.is_empty() => write!(f, "none"),
+            Value::Object(fields) => {
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { ", " };
+                    write!(f, "{sep}{key}={value}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Day-ahead electricity prices of dynamic contracts, fetched periodically
+//! from ENTSO-E or Tibber, for the price of the consumed energy.
+
+use crate::{
+    collector::{civil_date, unix_days},
+    http::{self, Client, Url},
+};
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const ENTSOE_URL: &str = "https://web-api.tp.entsoe.eu/api";
+const TIBBER_URL: &str = "https://api.tibber.com/v1-beta/gql";
+const TIBBER_QUERY: &str = r#"{"query":"{viewer{homes{currentSubscription{priceInfo{today{total startsAt} tomorrow{total startsAt}}}}}}"}"#;
+
+/// Timeout for connecting to the API and for every read and write.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delay before retrying a failed fetch.
+const RETRY_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Where the prices come from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PriceSource {
+    /// Day-ahead market prices of a bidding zone, from the ENTSO-E
+    /// Transparency Platform.
+    Entsoe,
+    /// Prices of the Tibber contract, taxes included.
+    Tibber,
+}
+
+impl FromStr for PriceSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "entsoe" => Ok(PriceSource::Entsoe),
+            "tibber" => Ok(PriceSource::Tibber),
+            _ => Err(format!(
+                "unknown price source '{s}', expected entsoe or tibber"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PriceSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PriceSource::Entsoe => "ENTSO-E",
+            PriceSource::Tibber => "Tibber",
+        })
+    }
+}
+
+pub struct PriceConfig {
+    pub source: PriceSource,
+    pub token: String,
+    /// EIC code of the bidding zone, for ENTSO-E.
+    pub area: Option<String>,
+    /// Added to every price per kWh, e.g. the taxes and the supplier's
+    /// margin on top of the market price.
+    pub markup: f64,
+    pub refresh_interval: Duration,
+}
+
+/// Prices per kWh by the Unix time their slot starts at, with the time it
+/// ends at.
+type Slots = BTreeMap<i64, (i64, f64)>;
+
+/// The fetched prices, shared with the collectors.
+#[derive(Clone, Debug)]
+pub struct DayAheadPrices {
+    slots: Arc<Mutex<Slots>>,
+    markup: f64,
+}
+
+impl DayAheadPrices {
+    /// Starts fetching the prices in the background.
+    pub fn start(config: PriceConfig) -> Result<Self, io::Error> {
+        let url: Url = match config.source {
+            PriceSource::Entsoe => ENTSOE_URL,
+            PriceSource::Tibber => TIBBER_URL,
+        }
+        .parse()
+        .map_err(io::Error::other)?;
+        let client = Client::new(&url, TIMEOUT)?;
+        let prices = DayAheadPrices {
+            slots: Arc::default(),
+            markup: config.markup,
+        };
+        let slots = prices.slots.clone();
+        thread::spawn(move || loop {
+            let delay = match fetch(&client, &url, &config) {
+                Ok(fetched) => {
+                    tracing::debug!(slots = fetched.len(), "Fetched {} prices", config.source);
+                    let mut slots = slots.lock().unwrap();
+                    let since = now() - 86400;
+                    slots.retain(|_, (end, _)| *end > since);
+                    slots.extend(fetched);
+                    config.refresh_interval
+                }
+                Err(err) => {
+                    tracing::warn!("Failed to fetch {} prices: {err}", config.source);
+                    config.refresh_interval.min(RETRY_INTERVAL)
+                }
+            };
+            thread::sleep(delay);
+        });
+        Ok(prices)
+    }
+
+    /// Returns the current price per kWh, with the markup, if known.
+    pub fn current(&self) -> Option<f64> {
+        let now = now();
+        let slots = self.slots.lock().unwrap();
+        let (_, &(end, price)) = slots.range(..=now).next_back()?;
+        (now < end).then_some(price + self.markup)
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+fn fetch(client: &Client, url: &Url, config: &PriceConfig) -> Result<Slots, io::Error> {
+    let response = match config.source {
+        PriceSource::Entsoe => {
+            // From yesterday to the end of tomorrow, in UTC.
+            let today = now().div_euclid(86400);
+            let area = http::escape(config.area.as_deref().unwrap_or_default());
+            let query = format!(
+                "?securityToken={}&documentType=A44&in_Domain={area}&out_Domain={area}&periodStart={}&periodEnd={}",
+                http::escape(&config.token),
+                period(today - 1),
+                period(today + 2),
+            );
+            let url: Url = format!("{url}{query}").parse().map_err(io::Error::other)?;
+            client.send("GET", &url, &[], &[])?
+        }
+        PriceSource::Tibber => {
+            let authorization = format!("Bearer {}", config.token);
+            client.send(
+                "POST",
+                url,
+                &[
+                    ("Authorization", &authorization),
+                    ("Content-Type", "application/json"),
+                ],
+                TIBBER_QUERY.as_bytes(),
+            )?
+        }
+    };
+    if response.status != 200 {
+        return Err(io::Error::other(format!(
+            "unexpected status {}: {}",
+            response.status, response.body
+        )));
+    }
+    let slots = match config.source {
+        PriceSource::Entsoe => entsoe_slots(&response.body),
+        PriceSource::Tibber => tibber_slots(&response.body),
+    };
+    if slots.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no prices in the response: {}", response.body),
+        ));
+    }
+    Ok(slots)
+}
+
+/// Returns the start of a day since the Unix epoch as an ENTSO-E period
+/// boundary, `yyyyMMddHHmm` in UTC.
+fn period(days: i64) -> String {
+    let (year, month, day) = civil_date(days);
+    format!("{year:04}{month:02}{day:02}0000")
+}
+
+/// Parses the periods of an ENTSO-E day-ahead prices document, in EUR per
+/// MWh.
+fn entsoe_slots(xml: &str) -> Slots {
+    let mut slots = Slots::new();
+    for period in elements(xml, "Period") {
+        let interval = elements(period, "timeInterval").next();
+        let start = interval.and_then(|i| elements(i, "start").next().and_then(parse_time));
+        let end = interval.and_then(|i| elements(i, "end").next().and_then(parse_time));
+        let resolution = elements(period, "resolution").next().and_then(|r| match r {
+            "PT15M" => Some(900),
+            "PT30M" => Some(1800),
+            "PT60M" => Some(3600),
+            _ => None,
+        });
+        let (Some(start), Some(end), Some(resolution)) = (start, end, resolution) else {
+            continue;
+        };
+        let points: Vec<(i64, f64)> = elements(period, "Point")
+            .filter_map(|point| {
+                let position = elements(point, "position").next()?.parse().ok()?;
+                let price = elements(point, "price.amount").next()?.parse().ok()?;
+                Some((position, price))
+            })
+            .collect();
+        // Points equal to the previous one may be left out.
+        for (i, &(position, price)) in points.iter().enumerate() {
+            let next = points
+                .get(i + 1)
+                .map_or((end - start) / resolution + 1, |p| p.0);
+            for position in position..next {
+                let slot = start + (position - 1) * resolution;
+                slots.insert(slot, (slot + resolution, price / 1000.0));
+            }
+        }
+    }
+    slots
+}
+
+/// Parses the prices of a Tibber `priceInfo` response, each lasting until
+/// the next one.
+fn tibber_slots(json: &str) -> Slots {
+    let mut prices = BTreeMap::new();
+    for object in json.split('{') {
+        let total = object
+            .split_once("\"total\":")
+            .and_then(|(_, rest)| rest.split([',', '}']).next()?.trim().parse().ok());
+        let starts_at = object
+            .split_once("\"startsAt\":\"")
+            .and_then(|(_, rest)| parse_time(rest.split('"').next()?));
+        if let (Some(total), Some(starts_at)) = (total, starts_at) {
+            prices.insert(starts_at, total);
+        }
+    }
+    let starts: Vec<i64> = prices.keys().copied().collect();
+    prices
+        .into_iter()
+        .enumerate()
+        .map(|(i, (start, price))| {
+            let end = starts.get(i + 1).copied().unwrap_or(start + 3600);
+            (start, (end, price))
+        })
+        .collect()
+}
+
+/// Returns the contents of the elements named `tag`, which mustn't have
+/// attributes.
+fn elements<'a>(xml: &'a str, tag: &str) -> impl Iterator<Item = &'a str> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+    let mut rest = xml;
+    std::iter::from_fn(move || {
+        let (_, after) = rest.split_once(&open)?;
+        let (content, after) = after.split_once(&close)?;
+        rest = after;
+        Some(content.trim())
+    })
+}
+
+/// Parses an RFC 3339 time, with optional seconds, into a Unix time.
+fn parse_time(s: &str) -> Option<i64> {
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.split('-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, offset) = match time.find(['Z', '+', '-']) {
+        Some(i) => time.split_at(i),
+        None => return None,
+    };
+    let offset = match offset {
+        "Z" => 0,
+        offset => {
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let seconds = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+            if offset.starts_with('-') {
+                -seconds
+            } else {
+                seconds
+            }
+        }
+    };
+    let mut time = time.split(':');
+    let hour: i64 = time.next()?.parse().ok()?;
+    let minute: i64 = time.next()?.parse().ok()?;
+    let second = match time.next() {
+        Some(second) => second.parse::<f64>().ok()? as i64,
+        None => 0,
+    };
+    let days = unix_days(year, u8::try_from(month).ok()?, u8::try_from(day).ok()?);
+    Some(days * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
@@ -0,0 +1,147 @@
+//! Dropping of privileges once the HTTP listener is bound.
+//!
+//! The exporter may be started as root or with `CAP_NET_BIND_SERVICE` to be
+//! able to listen on a privileged port. Neither is needed after the socket is
+//! bound, so the process switches to an unprivileged user (if requested) and
+//! clears all of its capabilities.
+
+use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path, str::FromStr};
+
+/// User (and optionally group) to switch to, given as `USER[:GROUP]`.
+///
+/// Both user and group may be given either by name or numerically.
+#[derive(Clone, Debug)]
+pub struct User {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+impl FromStr for User {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+        let (uid, primary_gid) = lookup_user(user)?;
+        let gid = match group {
+            Some(group) => lookup_group(group)?,
+            None => primary_gid,
+        };
+        Ok(User { uid, gid })
+    }
+}
+
+/// Group given either by name or numerically.
+#[derive(Clone, Debug)]
+pub struct Group(libc::gid_t);
+
+impl Group {
+    pub fn gid(&self) -> libc::gid_t {
+        self.0
+    }
+}
+
+impl FromStr for Group {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        lookup_group(s).map(Group)
+    }
+}
+
+fn lookup_user(user: &str) -> Result<(libc::uid_t, libc::gid_t), String> {
+    let name = CString::new(user).map_err(|_| format!("invalid user name '{user}'"))?;
+    // SAFETY: getpwnam is only called during argument parsing, before any
+    // other thread could call it concurrently.
+    let pw = unsafe { libc::getpwnam(name.as_ptr()) };
+    if !pw.is_null() {
+        // SAFETY: pw is a valid pointer returned by getpwnam.
+        return Ok(unsafe { ((*pw).pw_uid, (*pw).pw_gid) });
+    }
+    let uid = user.parse().map_err(|_| format!("unknown user '{user}'"))?;
+    Ok((uid, uid))
+}
+
+fn lookup_group(group: &str) -> Result<libc::gid_t, String> {
+    let name = CString::new(group).map_err(|_| format!("invalid group name '{group}'"))?;
+    // SAFETY: see lookup_user.
+    let gr = unsafe { libc::getgrnam(name.as_ptr()) };
+    if !gr.is_null() {
+        // SAFETY: gr is a valid pointer returned by getgrnam.
+        return Ok(unsafe { (*gr).gr_gid });
+    }
+    group
+        .parse()
+        .map_err(|_| format!("unknown group '{group}'"))
+}
+
+/// Switches to the given user (if any) and drops all capabilities.
+/// Changes the owner of a file created before dropping privileges to the
+/// user the process is going to switch to.
+pub fn chown(path: &Path, user: &User) -> Result<(), io::Error> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    // SAFETY: path is a valid NUL-terminated string.
+    if unsafe { libc::chown(path.as_ptr(), user.uid, user.gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn drop_privileges(user: Option<&User>) -> Result<(), io::Error> {
+    if let Some(user) = user {
+        // SAFETY: plain syscalls without pointers besides the empty group list.
+        unsafe {
+            if libc::setgroups(0, std::ptr::null()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::setgid(user.gid) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::setuid(user.uid) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    clear_capabilities()
+}
+
+#[cfg(target_os = "linux")]
+fn clear_capabilities() -> Result<(), io::Error> {
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: libc::c_int,
+    }
+    #[repr(C)]
+    #[derive(Default, Clone, Copy)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+    const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+    let header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [CapData::default(); 2];
+    // SAFETY: header and data match the kernel's capset ABI for version 3.
+    if unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Make sure privileges can't be regained via setuid or file capabilities.
+    // SAFETY: prctl with integer arguments only.
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clear_capabilities() -> Result<(), io::Error> {
+    Ok(())
+}
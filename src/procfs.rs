@@ -0,0 +1,139 @@
+//! The standard `process_*` metrics of the exporter itself, read from procfs
+//! on every scrape. Where there is no procfs, none are exported.
+
+use prometheus_client::{
+    collector::Collector,
+    metrics::{counter::ConstCounter, gauge::ConstGauge},
+    registry::{Descriptor, LocalMetric, Unit},
+    MaybeOwned,
+};
+use std::{borrow::Cow, fs, iter};
+
+type Labels = Vec<(Cow<'static, str>, Cow<'static, str>)>;
+type Metrics<'a> = Vec<(Cow<'a, Descriptor>, MaybeOwned<'a, Box<dyn LocalMetric>>)>;
+
+#[derive(Debug)]
+pub struct ProcessCollector {
+    /// Constant labels of all families.
+    pub labels: Labels,
+}
+
+/// Resource usage of the process.
+struct Stats {
+    cpu_seconds: f64,
+    resident_memory: u64,
+    virtual_memory: u64,
+    threads: i64,
+    /// Unix time the process started at.
+    start_time: f64,
+    open_fds: i64,
+    max_fds: Option<i64>,
+}
+
+impl Stats {
+    fn read() -> Option<Self> {
+        let stat = fs::read_to_string("/proc/self/stat").ok()?;
+        // The command name is in parentheses and may contain anything, so
+        // the fields are counted from its end, starting with the third one.
+        let (_, fields) = stat.rsplit_once(')')?;
+        let fields: Vec<&str> = fields.split_whitespace().collect();
+        let field = |n: usize| -> Option<u64> { fields.get(n - 3)?.parse().ok() };
+        // SAFETY: sysconf only reads configuration values.
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let boot_time: f64 = fs::read_to_string("/proc/stat")
+            .ok()?
+            .lines()
+            .find_map(|line| line.strip_prefix("btime "))?
+            .trim()
+            .parse()
+            .ok()?;
+        let open_fds = fs::read_dir("/proc/self/fd").ok()?.count() as i64;
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: `limit` is a valid rlimit to write to.
+        let max_fds = (unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == 0)
+            .then(|| i64::try_from(limit.rlim_cur).unwrap_or(i64::MAX));
+        Some(Stats {
+            cpu_seconds: (field(14)? + field(15)?) as f64 / ticks,
+            resident_memory: field(24)? * page_size,
+            virtual_memory: field(23)?,
+            threads: field(20)? as i64,
+            start_time: boot_time + field(22)? as f64 / ticks,
+            open_fds,
+            max_fds,
+        })
+    }
+}
+
+impl ProcessCollector {
+    fn descriptor(&self, name: &str, help: &str, unit: Option<Unit>) -> Cow<'static, Descriptor> {
+        Cow::Owned(Descriptor::new(name, help, unit, None, self.labels.clone()))
+    }
+}
+
+impl Collector for ProcessCollector {
+    fn collect<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = (Cow<'a, Descriptor>, MaybeOwned<'a, Box<dyn LocalMetric>>)> + 'a>
+    {
+        let Some(stats) = Stats::read() else {
+            return Box::new(iter::empty());
+        };
+        let mut metrics: Metrics = vec![
+            (
+                self.descriptor(
+                    "process_cpu",
+                    "Total user and system CPU time spent",
+                    Some(Unit::Seconds),
+                ),
+                MaybeOwned::Owned(Box::new(ConstCounter::new(stats.cpu_seconds))),
+            ),
+            (
+                self.descriptor(
+                    "process_resident_memory",
+                    "Resident memory size",
+                    Some(Unit::Bytes),
+                ),
+                MaybeOwned::Owned(Box::new(ConstGauge::new(stats.resident_memory as i64))),
+            ),
+            (
+                self.descriptor(
+                    "process_virtual_memory",
+                    "Virtual memory size",
+                    Some(Unit::Bytes),
+                ),
+                MaybeOwned::Owned(Box::new(ConstGauge::new(stats.virtual_memory as i64))),
+            ),
+            (
+                self.descriptor("process_threads", "Number of OS threads", None),
+                MaybeOwned::Owned(Box::new(ConstGauge::new(stats.threads))),
+            ),
+            (
+                self.descriptor(
+                    "process_start_time",
+                    "Start time of the process since the Unix epoch",
+                    Some(Unit::Seconds),
+                ),
+                MaybeOwned::Owned(Box::new(ConstGauge::new(stats.start_time))),
+            ),
+            (
+                self.descriptor("process_open_fds", "Number of open file descriptors", None),
+                MaybeOwned::Owned(Box::new(ConstGauge::new(stats.open_fds))),
+            ),
+        ];
+        if let Some(max_fds) = stats.max_fds {
+            metrics.push((
+                self.descriptor(
+                    "process_max_fds",
+                    "Maximum number of open file descriptors",
+                    None,
+                ),
+                MaybeOwned::Owned(Box::new(ConstGauge::new(max_fds))),
+            ));
+        }
+        Box::new(metrics.into_iter())
+    }
+}
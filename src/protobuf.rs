@@ -0,0 +1,28 @@
+//! Encoding of protobuf messages, field by field, for remote write and the
+//! protobuf exposition.
+
+/// Appends a length-delimited field: a string, bytes or a message.
+pub fn field_bytes(buf: &mut Vec<u8>, field: u8, data: &[u8]) {
+    buf.push(field << 3 | 2);
+    varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+/// Appends a varint field, e.g. an integer or an enum.
+pub fn field_varint(buf: &mut Vec<u8>, field: u8, value: u64) {
+    buf.push(field << 3);
+    varint(buf, value);
+}
+
+pub fn field_double(buf: &mut Vec<u8>, field: u8, value: f64) {
+    buf.push(field << 3 | 1);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
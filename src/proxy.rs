@@ -0,0 +1,48 @@
+//! Fan-out of the raw P1 stream to downstream TCP clients.
+//!
+//! Most P1 readers accept a single client only. The proxy lets other tools
+//! consume the same telegrams the exporter reads.
+
+use std::{
+    io::{self, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Clients not accepting data within this time are disconnected.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+pub struct Proxy {
+    clients: Mutex<Vec<TcpStream>>,
+}
+
+impl Proxy {
+    /// Starts accepting downstream clients on the given address.
+    pub fn start(addr: SocketAddr) -> Result<Arc<Proxy>, io::Error> {
+        let listener = TcpListener::bind(addr)?;
+        let proxy = Arc::new(Proxy::default());
+
+        let acceptor = proxy.clone();
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                match client.and_then(|c| c.set_write_timeout(Some(WRITE_TIMEOUT)).map(|_| c)) {
+                    Ok(client) => acceptor.clients.lock().unwrap().push(client),
+                    Err(err) => tracing::warn!("Failed to accept proxy client: {err}"),
+                }
+            }
+        });
+
+        Ok(proxy)
+    }
+
+    /// Sends the data to all connected clients, dropping the ones that fail.
+    pub fn broadcast(&self, data: &[u8]) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain_mut(|client| client.write_all(data).is_ok());
+    }
+}
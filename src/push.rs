@@ -0,0 +1,72 @@
+//! Periodic push of the registry to a Prometheus Pushgateway, for exporters
+//! that can't be scraped.
+
+use crate::{
+    exposition,
+    http::{self, Client, Url},
+};
+use prometheus_client::registry::Registry;
+use std::{io, sync::Arc, thread, time::Duration};
+
+/// Timeout for connecting to the Pushgateway and for every read and write.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct PushConfig {
+    pub url: Url,
+    pub interval: Duration,
+    /// Values of the `job` and `instance` grouping labels.
+    pub job: String,
+    pub instance: Option<String>,
+}
+
+pub fn start_pusher(config: PushConfig, registry: Arc<Registry>) -> Result<(), io::Error> {
+    let client = Client::new(&config.url, TIMEOUT)?;
+    let mut group = format!("metrics/{}", grouping_label("job", &config.job));
+    if let Some(instance) = &config.instance {
+        group.push_str(&format!("/{}", grouping_label("instance", instance)));
+    }
+    let url = config.url.join(&group);
+    thread::spawn(move || loop {
+        if let Err(err) = push(&client, &url, &registry) {
+            tracing::warn!("Failed to push metrics to {url}: {err}");
+        }
+        thread::sleep(config.interval);
+    });
+    Ok(())
+}
+
+/// Replaces the metrics of the group at `url` with the encoded registry.
+fn push(client: &Client, url: &Url, registry: &Registry) -> Result<(), io::Error> {
+    let body = exposition::encode(registry).map_err(io::Error::other)?;
+    let body = exposition::to_text(&body);
+    let content_type = exposition::Format::Text.content_type();
+    let response = client.send(
+        "PUT",
+        url,
+        &[("Content-Type", content_type)],
+        body.as_bytes(),
+    )?;
+    match response.status {
+        200..=299 => Ok(()),
+        status => Err(io::Error::other(format!(
+            "unexpected status {status}: {}",
+            response.body
+        ))),
+    }
+}
+
+/// Returns the path segments of a grouping label. Values with a `/` or empty
+/// ones can't be put in the path as is, the Pushgateway takes them base64
+/// encoded instead, with `=` for an empty value.
+fn grouping_label(name: &str, value: &str) -> String {
+    if value.is_empty() {
+        format!("{name}@base64/=")
+    } else if value.contains('/') {
+        format!(
+            "{name}@base64/{}",
+            http::base64_encode(value.as_bytes(), true)
+        )
+    } else {
+        format!("{name}/{}", http::escape(value))
+    }
+}
@@ -0,0 +1,77 @@
+//! Recording of the raw telegrams to disk, to be replayed with `--replay`.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+pub struct Recorder {
+    path: PathBuf,
+    /// Size after which the file is rotated to `<path>.1`.
+    max_size: Option<u64>,
+    file: Mutex<(File, u64)>,
+}
+
+impl Recorder {
+    pub fn open(path: PathBuf, max_size: Option<u64>) -> Result<Self, io::Error> {
+        let file = open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Recorder {
+            path,
+            max_size,
+            file: Mutex::new((file, size)),
+        })
+    }
+
+    /// Appends a telegram, rotating the file first if it grew too large.
+    pub fn record(&self, telegram: &str) {
+        if let Err(err) = self.write(telegram) {
+            tracing::warn!(
+                "Failed to record telegram to {}: {err}",
+                self.path.display()
+            );
+        }
+    }
+
+    fn write(&self, telegram: &str) -> Result<(), io::Error> {
+        let mut file = self.file.lock().unwrap();
+        let (current, size) = &mut *file;
+        if self
+            .max_size
+            .is_some_and(|max_size| *size > 0 && *size >= max_size)
+        {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            fs::rename(&self.path, rotated)?;
+            *current = open(&self.path)?;
+            *size = 0;
+        }
+        let mut telegram = telegram.to_string();
+        if !telegram.ends_with('\n') {
+            telegram.push_str("\r\n");
+        }
+        current.write_all(telegram.as_bytes())?;
+        *size += telegram.len() as u64;
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> Result<File, io::Error> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Parses a size like `512K`, `10M` or `1G`. Plain numbers are bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M')) => (&s[..i], 1 << 20),
+        Some((i, 'G')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(number) if number > 0 => Ok(number * multiplier),
+        _ => Err(format!("invalid size '{s}'")),
+    }
+}
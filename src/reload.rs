@@ -0,0 +1,136 @@
+//! Reloading of the configuration file on SIGHUP and `POST /-/reload`.
+
+use crate::{
+    collector::{
+        self, start_metrics_collector, CollectionMode, Collector, CollectorConfig, Inputs, OnDemand,
+    },
+    metrics::P1Metrics,
+};
+use std::{
+    io::Read,
+    os::unix::{io::AsRawFd, net::UnixStream},
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+/// Re-reads the configuration and applies it.
+pub type Reload = Arc<dyn Fn() -> Result<(), String> + Send + Sync>;
+
+/// Write end of the pipe waking up the reload thread, written to by the
+/// signal handler.
+static SIGHUP_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// The collectors of all meters, replaced as a whole on reload.
+pub struct Collectors {
+    metrics: Arc<P1Metrics>,
+    running: Mutex<Vec<Collector>>,
+    /// The meters read on every scrape instead, in the scrape collection
+    /// mode.
+    on_demand: Mutex<Vec<OnDemand>>,
+}
+
+impl Collectors {
+    pub fn start(
+        metrics: Arc<P1Metrics>,
+        inputs: Inputs,
+        config: Arc<CollectorConfig>,
+    ) -> Collectors {
+        let collectors = Collectors {
+            metrics,
+            running: Mutex::default(),
+            on_demand: Mutex::default(),
+        };
+        collectors.replace(inputs, config);
+        collectors
+    }
+
+    /// Stops the running collectors and starts new ones. The series of meters
+    /// that are still read are kept, those of the others are removed.
+    ///
+    /// Collectors stop after the telegram or connection attempt at hand, so
+    /// this may take up to the read or connect timeout.
+    pub fn replace(&self, inputs: Inputs, config: Arc<CollectorConfig>) {
+        let mut running = self.running.lock().unwrap();
+        let mut on_demand = self.on_demand.lock().unwrap();
+        for collector in running.iter() {
+            collector.stop();
+        }
+        let mut stopped: Vec<_> = running
+            .drain(..)
+            .map(Collector::join)
+            .chain(on_demand.drain(..).map(OnDemand::into_metrics))
+            .collect();
+        for (meter, input) in inputs {
+            let metrics = match stopped.iter().position(|metrics| metrics.meter == meter) {
+                Some(i) => stopped.swap_remove(i),
+                None => {
+                    let metrics = self.metrics.meter(meter);
+                    if config.pre_register {
+                        collector::pre_register(&metrics, &config);
+                    }
+                    metrics
+                }
+            };
+            match config.mode {
+                CollectionMode::Background => {
+                    running.push(start_metrics_collector(input, metrics, config.clone()))
+                }
+                CollectionMode::Scrape => {
+                    on_demand.push(OnDemand::new(input, metrics, config.clone()))
+                }
+            }
+        }
+        for metrics in stopped {
+            self.metrics.remove_meter(&metrics);
+        }
+    }
+
+    /// Reads a telegram from every meter in the scrape collection mode, does
+    /// nothing otherwise.
+    pub fn scrape(&self) {
+        let mut on_demand = self.on_demand.lock().unwrap();
+        thread::scope(|scope| {
+            for meter in on_demand.iter_mut() {
+                scope.spawn(|| {
+                    meter.collect();
+                });
+            }
+        });
+    }
+}
+
+/// Calls `reload` on every SIGHUP, instead of terminating.
+pub fn on_sighup(reload: Reload) {
+    let (mut receiver, sender) = match UnixStream::pair() {
+        Ok(pair) => pair,
+        Err(err) => {
+            tracing::warn!("Failed to set up reloading on SIGHUP: {err}");
+            return;
+        }
+    };
+    SIGHUP_PIPE.store(sender.as_raw_fd(), Ordering::SeqCst);
+    // The signal handler writes to it for the lifetime of the process.
+    std::mem::forget(sender);
+    // SAFETY: sighup only makes async-signal-safe calls.
+    unsafe { libc::signal(libc::SIGHUP, sighup as *const () as libc::sighandler_t) };
+
+    thread::spawn(move || {
+        let mut signal = [0];
+        while receiver.read_exact(&mut signal).is_ok() {
+            tracing::info!("Reloading the configuration on SIGHUP");
+            match reload() {
+                Ok(()) => tracing::info!("Reloaded the configuration"),
+                Err(err) => tracing::error!("Failed to reload the configuration: {err}"),
+            }
+        }
+    });
+}
+
+extern "C" fn sighup(_: libc::c_int) {
+    let fd = SIGHUP_PIPE.load(Ordering::SeqCst);
+    // SAFETY: write is async-signal-safe, a failure only loses the reload.
+    unsafe { libc::write(fd, [1u8].as_ptr().cast(), 1) };
+}
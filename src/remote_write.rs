@@ -0,0 +1,204 @@
+//! Shipping of the readings of every telegram with the Prometheus remote
+//! write protocol, keeping their full resolution.
+
+use crate::{
+    http::{Client, Url},
+    metrics::ConstLabel,
+    protobuf::{field_bytes, field_double, field_varint, varint},
+    sink::{Kind, Sample, Sink},
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Timeout for connecting to the endpoint and for every read and write.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Telegrams kept while the endpoint is unreachable, the oldest are dropped
+/// beyond that. About an hour of telegrams every second.
+const MAX_PENDING: usize = 3600;
+
+pub struct RemoteWriteConfig {
+    pub url: Url,
+    /// Interval between requests, the samples received meanwhile are sent
+    /// in one batch.
+    pub interval: Duration,
+    /// Value of the `Authorization` header, if any.
+    pub authorization: Option<String>,
+    /// Prefix of the metric names.
+    pub prefix: String,
+    /// Constant labels of all series.
+    pub labels: Vec<ConstLabel>,
+}
+
+/// A sample with the labels of its series, `__name__` included.
+struct Timestamped {
+    labels: Vec<(String, String)>,
+    value: f64,
+    /// Milliseconds since the Unix epoch.
+    timestamp: i64,
+}
+
+pub struct RemoteWriteSink {
+    /// Samples of the telegrams waiting to be sent, the oldest first.
+    pending: Arc<Mutex<VecDeque<Vec<Timestamped>>>>,
+    prefix: String,
+    labels: Vec<ConstLabel>,
+}
+
+impl RemoteWriteSink {
+    pub fn start(config: RemoteWriteConfig) -> Result<Self, io::Error> {
+        let client = Client::new(&config.url, TIMEOUT)?;
+        let pending = Arc::new(Mutex::new(VecDeque::new()));
+        let queue = pending.clone();
+        let prefix = config.prefix.clone();
+        let labels = config.labels.clone();
+        thread::spawn(move || loop {
+            thread::sleep(config.interval);
+            let telegrams = std::mem::take(&mut *queue.lock().unwrap());
+            if telegrams.is_empty() {
+                continue;
+            }
+            let batch: Vec<&Timestamped> = telegrams.iter().flatten().collect();
+            match send(&client, &config, &batch) {
+                Ok(()) => {}
+                Err(Failure::Retry(err)) => {
+                    tracing::warn!("Failed to send samples to {}, retrying: {err}", config.url);
+                    let mut pending = queue.lock().unwrap();
+                    let newer = std::mem::replace(&mut *pending, telegrams);
+                    pending.extend(newer);
+                    let excess = pending.len().saturating_sub(MAX_PENDING);
+                    pending.drain(..excess);
+                }
+                Err(Failure::Drop(err)) => {
+                    tracing::warn!("Failed to send samples to {}: {err}", config.url);
+                }
+            }
+        });
+        Ok(RemoteWriteSink {
+            pending,
+            prefix,
+            labels,
+        })
+    }
+}
+
+impl Sink for RemoteWriteSink {
+    fn publish(&self, samples: &[Sample]) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        let mut telegram = Vec::with_capacity(samples.len());
+        for sample in samples {
+            let name = match sample.kind {
+                Kind::Gauge => format!("{}_{}", self.prefix, sample.name),
+                Kind::Counter => format!("{}_{}_total", self.prefix, sample.name),
+            };
+            let mut labels = vec![("__name__".to_string(), name)];
+            labels.extend(
+                sample
+                    .labels
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.clone())),
+            );
+            labels.extend(
+                self.labels
+                    .iter()
+                    .map(|label| (label.name.clone(), label.value.clone())),
+            );
+            labels.sort();
+            telegram.push(Timestamped {
+                labels,
+                value: sample.value,
+                timestamp,
+            });
+        }
+        let mut pending = self.pending.lock().unwrap();
+        pending.push_back(telegram);
+        let excess = pending.len().saturating_sub(MAX_PENDING);
+        pending.drain(..excess);
+    }
+}
+
+enum Failure {
+    /// The request may succeed later, e.g. the endpoint is down.
+    Retry(io::Error),
+    /// The endpoint rejected the samples.
+    Drop(io::Error),
+}
+
+fn send(
+    client: &Client,
+    config: &RemoteWriteConfig,
+    batch: &[&Timestamped],
+) -> Result<(), Failure> {
+    let body = snappy_literal(&write_request(batch));
+    let mut headers = vec![
+        ("Content-Type", "application/x-protobuf"),
+        ("Content-Encoding", "snappy"),
+        ("X-Prometheus-Remote-Write-Version", "0.1.0"),
+    ];
+    if let Some(authorization) = &config.authorization {
+        headers.push(("Authorization", authorization));
+    }
+    let response = client
+        .send("POST", &config.url, &headers, &body)
+        .map_err(Failure::Retry)?;
+    let err = || io::Error::other(format!("status {}: {}", response.status, response.body));
+    match response.status {
+        200..=299 => Ok(()),
+        429 | 500..=599 => Err(Failure::Retry(err())),
+        _ => Err(Failure::Drop(err())),
+    }
+}
+
+/// Encodes the samples as a `prometheus.WriteRequest` protobuf message, with
+/// the samples of a series in one `TimeSeries`.
+fn write_request(batch: &[&Timestamped]) -> Vec<u8> {
+    let mut series: BTreeMap<&[(String, String)], Vec<&Timestamped>> = BTreeMap::new();
+    for &sample in batch {
+        series.entry(&sample.labels).or_default().push(sample);
+    }
+    let mut request = Vec::new();
+    for (labels, samples) in series {
+        let mut timeseries = Vec::new();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            field_bytes(&mut label, 1, name.as_bytes());
+            field_bytes(&mut label, 2, value.as_bytes());
+            field_bytes(&mut timeseries, 1, &label);
+        }
+        for sample in samples {
+            let mut encoded = Vec::new();
+            field_double(&mut encoded, 1, sample.value);
+            field_varint(&mut encoded, 2, sample.timestamp as u64);
+            field_bytes(&mut timeseries, 2, &encoded);
+        }
+        field_bytes(&mut request, 1, &timeseries);
+    }
+    request
+}
+
+/// Frames `data` as a snappy block made of literals only. That's valid
+/// snappy any decoder accepts, just without the compression, which matters
+/// little for the few kilobytes sent at a time.
+fn snappy_literal(data: &[u8]) -> Vec<u8> {
+    let mut block = Vec::with_capacity(data.len() + data.len() / 65536 * 3 + 8);
+    varint(&mut block, data.len() as u64);
+    for chunk in data.chunks(65536) {
+        let len = chunk.len() - 1;
+        if len < 60 {
+            block.push((len as u8) << 2);
+        } else {
+            // Tag 61: the length minus one follows in two bytes.
+            block.push(61 << 2);
+            block.extend_from_slice(&(len as u16).to_le_bytes());
+        }
+        block.extend_from_slice(chunk);
+    }
+    block
+}
@@ -0,0 +1,135 @@
+//! Replay of captured raw telegrams through the normal pipeline.
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+
+/// Delay between telegrams without a usable timestamp.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How fast captured telegrams are replayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaySpeed {
+    /// Multiple of the pace the telegrams were captured at.
+    Factor(f64),
+    AsFastAsPossible,
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "as-fast-as-possible" {
+            return Ok(ReplaySpeed::AsFastAsPossible);
+        }
+        match s.strip_suffix('x').unwrap_or(s).parse::<f64>() {
+            Ok(factor) if factor > 0.0 && factor.is_finite() => Ok(ReplaySpeed::Factor(factor)),
+            _ => Err(format!(
+                "invalid replay speed '{s}', expected e.g. 10x or as-fast-as-possible"
+            )),
+        }
+    }
+}
+
+/// Serves the telegrams of a capture file, pausing between them as long as
+/// their timestamps are apart (scaled by the replay speed).
+pub struct ReplayReader {
+    telegrams: Vec<String>,
+    speed: ReplaySpeed,
+    /// Index of the telegram being served and the offset into it.
+    next: usize,
+    offset: usize,
+}
+
+pub fn open(path: &Path, speed: ReplaySpeed) -> Result<ReplayReader, io::Error> {
+    let capture = fs::read_to_string(path)?;
+    Ok(ReplayReader {
+        telegrams: split(&capture),
+        speed,
+        next: 0,
+        offset: 0,
+    })
+}
+
+/// Splits a capture into telegrams, each starting with a `/` line.
+fn split(capture: &str) -> Vec<String> {
+    let mut telegrams: Vec<String> = vec![];
+    for line in capture.split_inclusive('\n') {
+        match telegrams.last_mut() {
+            Some(telegram) if !line.starts_with('/') => telegram.push_str(line),
+            _ => telegrams.push(line.to_string()),
+        }
+    }
+    telegrams
+}
+
+/// Returns the timestamp (`0-0:1.0.0`) of a telegram in seconds.
+fn timestamp(telegram: &str) -> Option<i64> {
+    let line = telegram
+        .lines()
+        .find_map(|line| line.strip_prefix("0-0:1.0.0("))?;
+    let digits = line.get(..12)?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let field = |i: usize| digits[i..i + 2].parse::<i64>().unwrap();
+    let days = days_from_civil(2000 + field(0), field(2), field(4));
+    Some(days * 86400 + field(6) * 3600 + field(8) * 60 + field(10))
+}
+
+/// Returns the number of days since 1970-01-01 of a date in the Gregorian
+/// calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+impl ReplayReader {
+    /// Waits until the telegram at `self.next` is due.
+    fn pause(&self) {
+        let ReplaySpeed::Factor(factor) = self.speed else {
+            return;
+        };
+        if self.next == 0 {
+            return;
+        }
+        let previous = timestamp(&self.telegrams[self.next - 1]);
+        let current = timestamp(&self.telegrams[self.next]);
+        let interval = match (previous, current) {
+            (Some(previous), Some(current)) if current >= previous => {
+                Duration::from_secs((current - previous) as u64)
+            }
+            _ => DEFAULT_INTERVAL,
+        };
+        thread::sleep(interval.div_f64(factor));
+    }
+}
+
+impl Read for ReplayReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(telegram) = self.telegrams.get(self.next) else {
+            return Ok(0);
+        };
+        if self.offset == 0 {
+            self.pause();
+        }
+        let rest = &telegram.as_bytes()[self.offset..];
+        let n = buf.len().min(rest.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        self.offset += n;
+        if self.offset == telegram.len() {
+            self.next += 1;
+            self.offset = 0;
+        }
+        Ok(n)
+    }
+}
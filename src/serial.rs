@@ -0,0 +1,143 @@
+//! Reading from a P1 cable attached to a local serial port.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    mem::MaybeUninit,
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+#[derive(Clone, Debug)]
+pub struct SerialConfig {
+    pub device: PathBuf,
+    pub baud_rate: u32,
+    pub parity: Parity,
+    /// 7 or 8.
+    pub data_bits: u8,
+    pub flow_control: FlowControl,
+}
+
+/// Parity of the serial line. DSMR 4 and 5 meters use none (8N1), older
+/// ones even (7E1), as do Norwegian HAN ports with 8 data bits (8E1).
+#[derive(Clone, Copy, Debug)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+impl FromStr for Parity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Parity::None),
+            "even" => Ok(Parity::Even),
+            "odd" => Ok(Parity::Odd),
+            _ => Err(format!("unknown parity '{s}', expected none, even or odd")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum FlowControl {
+    None,
+    Hardware,
+    Software,
+}
+
+impl FromStr for FlowControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(FlowControl::None),
+            "rtscts" => Ok(FlowControl::Hardware),
+            "xonxoff" => Ok(FlowControl::Software),
+            _ => Err(format!(
+                "unknown flow control '{s}', expected none, rtscts or xonxoff"
+            )),
+        }
+    }
+}
+
+/// Checks that the baud rate is supported by [`open`].
+pub fn parse_baud_rate(s: &str) -> Result<u32, String> {
+    let rate = s.parse().map_err(|_| format!("invalid baud rate '{s}'"))?;
+    speed(rate).map(|_| rate)
+}
+
+fn speed(rate: u32) -> Result<libc::speed_t, String> {
+    Ok(match rate {
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        _ => return Err(format!("unsupported baud rate {rate}")),
+    })
+}
+
+/// Opens the serial port in raw mode. Reads return no data once nothing has
+/// been received for `timeout` (at most 25.5s).
+pub fn open(config: &SerialConfig, timeout: Duration) -> Result<File, io::Error> {
+    let port = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&config.device)?;
+    let fd = port.as_raw_fd();
+
+    let mut tty = MaybeUninit::<libc::termios>::uninit();
+    // SAFETY: fd is a valid descriptor and tty is only read once tcgetattr
+    // has initialized it.
+    let mut tty = unsafe {
+        if libc::tcgetattr(fd, tty.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        tty.assume_init()
+    };
+
+    // SAFETY: tty is a valid termios structure.
+    unsafe { libc::cfmakeraw(&mut tty) };
+    tty.c_cflag |= libc::CLOCAL | libc::CREAD;
+    tty.c_cflag &= !(libc::CSIZE | libc::PARENB | libc::PARODD | libc::CSTOPB | libc::CRTSCTS);
+    tty.c_cflag |= match config.data_bits {
+        7 => libc::CS7,
+        _ => libc::CS8,
+    };
+    tty.c_cflag |= match config.parity {
+        Parity::None => 0,
+        Parity::Even => libc::PARENB,
+        Parity::Odd => libc::PARENB | libc::PARODD,
+    };
+    tty.c_iflag &= !(libc::IXON | libc::IXOFF | libc::IXANY);
+    match config.flow_control {
+        FlowControl::None => {}
+        FlowControl::Hardware => tty.c_cflag |= libc::CRTSCTS,
+        FlowControl::Software => tty.c_iflag |= libc::IXON | libc::IXOFF,
+    }
+    tty.c_cc[libc::VMIN] = 0;
+    tty.c_cc[libc::VTIME] = (timeout.as_millis() / 100).clamp(1, 255) as libc::cc_t;
+
+    let speed = speed(config.baud_rate).map_err(io::Error::other)?;
+    // SAFETY: tty is a valid termios structure and fd a valid descriptor.
+    unsafe {
+        if libc::cfsetispeed(&mut tty, speed) != 0 || libc::cfsetospeed(&mut tty, speed) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::tcsetattr(fd, libc::TCSANOW, &tty) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Drop whatever was buffered before, likely a partial telegram.
+        libc::tcflush(fd, libc::TCIFLUSH);
+    }
+
+    Ok(port)
+}
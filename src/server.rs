@@ -0,0 +1,768 @@
+use crate::{
+    auth::Auth,
+    events, exposition, front,
+    metrics::P1Metrics,
+    parse::{self, Format, Value},
+    privileges::Group,
+    reload::{Collectors, Reload},
+    systemd,
+};
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use prometheus_client::registry::Registry;
+use std::{
+    convert::Infallible,
+    fmt, fs,
+    io::{self, Read, Write},
+    net::{SocketAddr, ToSocketAddrs},
+    os::unix::{
+        self,
+        fs::{FileTypeExt, PermissionsExt},
+        io::RawFd,
+        net::UnixStream,
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use tiny_http::{ConfigListenAddr, Header, Method, Request, Response, Server};
+use tracing::Level;
+
+pub struct ServerConfig {
+    pub no_eof: bool,
+    /// Whether to attach the time the meter took its readings to them.
+    pub sample_timestamps: bool,
+    pub metric_prefix: String,
+    /// How long an encoded response is served to subsequent scrapes.
+    pub cache_ttl: Option<Duration>,
+    /// Whether to serve the last raw telegrams on `/debug/telegram`.
+    pub debug_endpoint: bool,
+    /// Credentials required for the metrics, the API and the debug endpoint.
+    pub auth: Option<Auth>,
+    /// Kept to rebind the listener.
+    pub socket_permissions: SocketPermissions,
+    /// Reloads the configuration file on `POST /-/reload`, if one is used.
+    pub reload: Option<Reload>,
+    /// Read the meters before the metrics are encoded, in the scrape
+    /// collection mode.
+    pub collectors: Arc<Collectors>,
+    /// Level every served request is logged at.
+    pub access_log: Level,
+    /// Number of requests served concurrently.
+    pub workers: usize,
+    /// How long a request may wait for a worker before it's answered with
+    /// 503 Service Unavailable.
+    pub request_timeout: Duration,
+}
+
+/// Address for the HTTP server to listen on.
+///
+/// Values starting with `/` or `unix:` are Unix socket paths, everything else
+/// must be a `host:port` pair.
+#[derive(Clone, Debug)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    /// A socket passed by systemd socket activation.
+    Systemd(RawFd),
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("empty Unix socket path".to_string());
+            }
+            return Ok(ListenAddress::Unix(path.into()));
+        }
+        if s.starts_with('/') {
+            return Ok(ListenAddress::Unix(s.into()));
+        }
+        if let Ok(addr) = s.parse() {
+            return Ok(ListenAddress::Tcp(addr));
+        }
+        if !s.contains(':') {
+            return Err(format!(
+                "'{s}' is neither host:port nor an absolute path, use unix:{s} for a relative Unix socket path"
+            ));
+        }
+        s.to_socket_addrs()
+            .map_err(|err| format!("failed to resolve '{s}': {err}"))?
+            .next()
+            .map(ListenAddress::Tcp)
+            .ok_or_else(|| format!("'{s}' didn't resolve to any address"))
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(addr) => write!(f, "{addr}"),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+            ListenAddress::Systemd(fd) => write!(f, "systemd socket (fd {fd})"),
+        }
+    }
+}
+
+/// Permissions of the Unix socket the HTTP server listens on. The socket is
+/// created according to the umask by default.
+#[derive(Clone, Debug, Default)]
+pub struct SocketPermissions {
+    pub mode: Option<u32>,
+    pub group: Option<Group>,
+}
+
+impl SocketPermissions {
+    pub fn apply(&self, path: &Path) -> Result<(), io::Error> {
+        if let Some(group) = &self.group {
+            unix::fs::chown(path, None, Some(group.gid()))?;
+        }
+        if let Some(mode) = self.mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the octal mode of a Unix socket, e.g. `660` or `0o660`.
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!(
+            "invalid mode '{s}', expected octal permissions like 660"
+        )),
+    }
+}
+
+/// Removes the socket file left behind by a previous process at `path`, so
+/// that it can be bound again. Anything but an unused socket is left alone.
+pub fn remove_stale_socket(path: &Path) -> Result<(), io::Error> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and isn't a socket", path.display()),
+        ));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("another process listens on {}", path.display()),
+        ));
+    }
+    fs::remove_file(path)
+}
+
+/// Binds the HTTP server to the given address.
+pub fn bind(addr: &ListenAddress, permissions: &SocketPermissions) -> Result<Server, io::Error> {
+    let listen = match addr {
+        ListenAddress::Tcp(addr) => ConfigListenAddr::from_socket_addrs(addr)?,
+        ListenAddress::Unix(path) => {
+            remove_stale_socket(path)?;
+            ConfigListenAddr::unix_from_path(path)
+        }
+        ListenAddress::Systemd(fd) => {
+            let listener: tiny_http::Listener = match systemd::listener(*fd)? {
+                systemd::Listener::Tcp(listener) => listener.into(),
+                systemd::Listener::Unix(listener) => listener.into(),
+            };
+            return Server::from_listener(listener, None).map_err(io::Error::other);
+        }
+    };
+    let server = Server::new(tiny_http::ServerConfig {
+        addr: listen,
+        ssl: None,
+    });
+    let server = server.map_err(|err| match err.downcast::<io::Error>() {
+        Ok(err) => bind_error(addr, *err),
+        Err(err) => io::Error::other(err),
+    })?;
+    if let ListenAddress::Unix(path) = addr {
+        permissions.apply(path)?;
+    }
+    Ok(server)
+}
+
+/// Explains how to bind privileged ports if that is what failed.
+pub fn bind_error(addr: &ListenAddress, err: io::Error) -> io::Error {
+    match addr {
+        ListenAddress::Tcp(addr)
+            if err.kind() == io::ErrorKind::PermissionDenied && addr.port() < 1024 =>
+        {
+            io::Error::new(
+                err.kind(),
+                format!(
+                    "binding to privileged port {} requires root or CAP_NET_BIND_SERVICE \
+                     (e.g. `setcap cap_net_bind_service=+ep p1-exporter`): {err}",
+                    addr.port()
+                ),
+            )
+        }
+        _ => err,
+    }
+}
+
+/// Age of the last telegram up to which `/readyz` reports ready and the
+/// systemd watchdog is pinged, unless `--max-telegram-age` is given.
+pub const DEFAULT_READY_AGE: Duration = Duration::from_secs(60);
+
+const LANDING_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>P1 Exporter</title></head>
+<body>
+<h1>P1 Exporter</h1>
+<p><a href="/metrics">Metrics</a></p>
+</body>
+</html>
+"#;
+
+/// Number of consecutive listener failures after which the server gives up.
+const MAX_RESTARTS: u32 = 5;
+/// Delay before rebinding a failed listener.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Serves the metrics on `/metrics`, a landing page on `/`, `/healthz`, which
+/// reports that the exporter is alive, `/readyz`, which reports whether a
+/// telegram has been received recently, and the latest readings as JSON on
+/// `/api/v1/state` and as a stream of Server-Sent Events on `/api/v1/events`.
+/// Reloads the configuration file on `POST /-/reload`.
+///
+/// Requests of all the listeners are handled by [`ServerConfig::workers`]
+/// threads. Rebinds a listener to its address when it fails. Gives up after
+/// `MAX_RESTARTS` failures of a listener without a request served in
+/// between.
+pub fn run_metrics_server(
+    servers: Vec<(Server, ListenAddress)>,
+    registry: Arc<Registry>,
+    metrics: Arc<P1Metrics>,
+    config: ServerConfig,
+) -> Result<(), io::Error> {
+    let (requests, queue) = mpsc::channel::<(Request, Access)>();
+    let queue = Arc::new(Mutex::new(queue));
+    let handler = Arc::new(Handler {
+        registry,
+        metrics,
+        config,
+        cache: Mutex::default(),
+    });
+    for _ in 0..handler.config.workers {
+        let (queue, handler) = (queue.clone(), handler.clone());
+        thread::spawn(move || loop {
+            let next = queue.lock().unwrap().recv();
+            let Ok((req, access)) = next else {
+                return;
+            };
+            handler.handle(req, access);
+        });
+    }
+
+    let (failed, failure) = mpsc::channel();
+    for (server, addr) in servers {
+        let (requests, handler, failed) = (requests.clone(), handler.clone(), failed.clone());
+        thread::spawn(move || {
+            let Err(err) = accept(server, &addr, &requests, &handler);
+            let _ = failed.send(err);
+        });
+    }
+    Err(failure
+        .recv()
+        .expect("listeners to run until one of them fails"))
+}
+
+/// Passes the requests received by `server` to the workers.
+fn accept(
+    mut server: Server,
+    addr: &ListenAddress,
+    requests: &mpsc::Sender<(Request, Access)>,
+    handler: &Handler,
+) -> Result<Infallible, io::Error> {
+    let mut failures = 0;
+    loop {
+        match server.recv() {
+            Ok(req) => {
+                failures = 0;
+                let access = Access::new(&req, handler.config.access_log);
+                requests
+                    .send((req, access))
+                    .expect("workers to run as long as the server");
+            }
+            Err(err) => {
+                tracing::warn!("HTTP listener on {addr} failed: {err}");
+                // The old listener has to be closed before its address can
+                // be bound again.
+                drop(server);
+                server = rebind(addr, &handler.config.socket_permissions, &mut failures)?;
+            }
+        }
+    }
+}
+
+/// State shared by the workers serving requests.
+struct Handler {
+    registry: Arc<Registry>,
+    metrics: Arc<P1Metrics>,
+    config: ServerConfig,
+    /// The last encoded metrics and when they were encoded.
+    cache: Mutex<Option<(Instant, String)>>,
+}
+
+impl Handler {
+    fn handle(&self, req: Request, access: Access) {
+        if access.started.elapsed() > self.config.request_timeout {
+            let response = Response::from_string("server busy\n").with_status_code(503);
+            respond(req, response, access);
+            return;
+        }
+        let html = "Content-Type: text/html; charset=utf-8"
+            .parse::<tiny_http::Header>()
+            .unwrap();
+        let json = "Content-Type: application/json"
+            .parse::<tiny_http::Header>()
+            .unwrap();
+        let allow = "Allow: GET, HEAD".parse::<tiny_http::Header>().unwrap();
+
+        let path = req.url().split('?').next().unwrap_or_default();
+        let head = *req.method() == Method::Head;
+        let protected = matches!(
+            path,
+            "/metrics" | "/api/v1/state" | "/api/v1/events" | "/debug/telegram" | "/-/reload"
+        );
+        if let (true, Some(auth)) = (protected, &self.config.auth) {
+            if !auth.authorized(req.headers()) {
+                let response = Response::from_string("unauthorized\n")
+                    .with_status_code(401)
+                    .with_header(auth.challenge());
+                respond(req, response, access);
+                return;
+            }
+        }
+        if path == "/api/v1/events" && (head || *req.method() == Method::Get) {
+            // The stream stays open, so it's served from its own thread.
+            let events = self.metrics.events.subscribe();
+            thread::spawn(move || {
+                if let Err(err) = events::stream(req.into_writer(), events, head) {
+                    tracing::debug!("Event stream closed: {err}");
+                }
+                access.log(200, None);
+            });
+            return;
+        }
+        if let ("/-/reload", Method::Post, Some(reload)) = (path, req.method(), &self.config.reload)
+        {
+            // Waits for the collectors to stop, so it's done in its own
+            // thread.
+            let reload = reload.clone();
+            thread::spawn(move || {
+                let response = match reload() {
+                    Ok(()) => {
+                        tracing::info!("Reloaded the configuration");
+                        Response::from_string("reloaded\n")
+                    }
+                    Err(err) => {
+                        tracing::error!("Failed to reload the configuration: {err}");
+                        Response::from_string(format!("{err}\n")).with_status_code(500)
+                    }
+                };
+                respond(req, response, access);
+            });
+            return;
+        }
+        let encoding = Encoding::negotiate(req.headers());
+        let response = match (req.method(), path) {
+            (Method::Get | Method::Head, "/metrics") => {
+                let encoded = encode_cached(
+                    &mut self.cache.lock().unwrap(),
+                    &self.registry,
+                    &self.metrics,
+                    &self.config,
+                );
+                match encoded {
+                    Ok(body) => {
+                        let accept = req
+                            .headers()
+                            .iter()
+                            .find(|header| header.field.equiv("Accept"))
+                            .map(|header| header.value.as_str());
+                        let format = exposition::Format::negotiate(accept);
+                        let body = match format {
+                            exposition::Format::OpenMetrics => body.into_bytes(),
+                            exposition::Format::Text => exposition::to_text(&body).into_bytes(),
+                            exposition::Format::Protobuf => exposition::to_protobuf(&body),
+                        };
+                        let response = compressed(body, encoding);
+                        self.metrics
+                            .http
+                            .response_bytes
+                            .observe(response.data_length().unwrap_or_default() as f64);
+                        let content_type = format!("Content-Type: {}", format.content_type());
+                        response
+                            .with_header(content_type.parse::<Header>().unwrap())
+                            .with_header("Vary: Accept".parse::<Header>().unwrap())
+                    }
+                    Err(err) => Response::from_string(format!("{}", err)).with_status_code(500),
+                }
+            }
+            (Method::Get | Method::Head, "/") => {
+                Response::from_string(LANDING_PAGE).with_header(html)
+            }
+            (Method::Get | Method::Head, "/healthz") => Response::from_string("ok\n"),
+            (Method::Get | Method::Head, "/readyz") => {
+                let max_age = self.metrics.max_telegram_age.unwrap_or(DEFAULT_READY_AGE);
+                if self.metrics.telegram_within(max_age) {
+                    Response::from_string("ready\n")
+                } else {
+                    let body = format!("no telegram received within {max_age:?}\n");
+                    Response::from_string(body).with_status_code(503)
+                }
+            }
+            (Method::Get | Method::Head, "/debug/telegram") if self.config.debug_endpoint => {
+                Response::from_string(last_telegrams(&self.metrics))
+            }
+            (Method::Get | Method::Head, "/api/v1/state") => {
+                compressed(state(&self.metrics).into_bytes(), encoding).with_header(json)
+            }
+            (_, "/-/reload") if self.config.reload.is_some() => {
+                Response::from_string("method not allowed\n")
+                    .with_status_code(405)
+                    .with_header("Allow: POST".parse::<Header>().unwrap())
+            }
+            (Method::Get | Method::Head, _) => {
+                Response::from_string("not found\n").with_status_code(404)
+            }
+            _ => Response::from_string("method not allowed\n")
+                .with_status_code(405)
+                .with_header(allow),
+        };
+        respond(req, response, access);
+    }
+}
+
+/// A request as written to the access log once it has been served.
+struct Access {
+    level: Level,
+    /// `None` for clients connected over a Unix socket.
+    remote: Option<SocketAddr>,
+    method: Method,
+    path: String,
+    started: Instant,
+}
+
+impl Access {
+    fn new(req: &Request, level: Level) -> Access {
+        Access {
+            level,
+            remote: req.remote_addr().copied().or_else(|| forwarded(req)),
+            method: req.method().clone(),
+            path: req.url().split('?').next().unwrap_or_default().to_string(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Logs the request with the status and body size of its response.
+    fn log(&self, status: u16, bytes: Option<usize>) {
+        macro_rules! log {
+            ($level:expr) => {
+                tracing::event!(
+                    $level,
+                    remote = self.remote.map(tracing::field::display),
+                    status,
+                    bytes,
+                    duration_seconds = self.started.elapsed().as_secs_f64(),
+                    "{} {}",
+                    self.method,
+                    self.path
+                )
+            };
+        }
+        match self.level {
+            Level::ERROR => log!(Level::ERROR),
+            Level::WARN => log!(Level::WARN),
+            Level::INFO => log!(Level::INFO),
+            Level::DEBUG => log!(Level::DEBUG),
+            Level::TRACE => log!(Level::TRACE),
+        }
+    }
+}
+
+/// Returns the address of the client a request was relayed for by the
+/// public listeners.
+fn forwarded(req: &Request) -> Option<SocketAddr> {
+    let header = req
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(front::FORWARDED))?;
+    let addr = header.value.as_str().strip_prefix("for=\"")?;
+    addr.strip_suffix('"')?.parse().ok()
+}
+
+fn respond<R: Read>(req: Request, response: Response<R>, access: Access) {
+    let status = response.status_code().0;
+    let bytes = response.data_length();
+    if let Err(err) = req.respond(response) {
+        tracing::warn!("Failed to respond: {err}");
+    }
+    access.log(status, bytes);
+}
+
+/// Returns the last raw telegram of every meter, with a header telling when
+/// it was received and whether it was parsed.
+fn last_telegrams(metrics: &P1Metrics) -> String {
+    let mut body = String::new();
+    for (meter, recent) in metrics.recent_telegrams.lock().unwrap().iter() {
+        if let Some(meter) = meter {
+            body.push_str(&format!("# meter: {meter}\n"));
+        }
+        match &recent.lock().unwrap().last {
+            Some(last) => {
+                let age = last.received.elapsed().unwrap_or_default();
+                body.push_str(&format!("# received: {age:.1?} ago\n"));
+                match &last.error {
+                    Some(err) => body.push_str(&format!("# status: rejected: {err}\n")),
+                    None => body.push_str("# status: ok\n"),
+                }
+                body.push_str(&last.text);
+                body.push_str("\n\n");
+            }
+            None => body.push_str("# no telegram received yet\n\n"),
+        }
+    }
+    body
+}
+
+/// Returns the readings of the last parsed telegram of every meter as JSON.
+fn state(metrics: &P1Metrics) -> String {
+    let meters = metrics.recent_telegrams.lock().unwrap();
+    let meters = meters
+        .iter()
+        .map(|(meter, recent)| {
+            let recent = recent.lock().unwrap();
+            let (received, readings) = match &recent.last_parsed {
+                Some(telegram) => (
+                    Value::Number(telegram.received_unix()),
+                    parse::readings(&telegram.text),
+                ),
+                None => (Value::Null, Value::Null),
+            };
+            Value::Object(vec![
+                ("meter", meter.clone().into()),
+                ("received", received),
+                ("readings", readings),
+            ])
+        })
+        .collect();
+    Value::Object(vec![("meters", Value::List(meters))]).render(Format::Json)
+}
+
+/// Content codings responses can be compressed with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// Picks the coding for the `Accept-Encoding` header of a request,
+    /// preferring gzip. Returns `None` if the response is to be sent as is.
+    fn negotiate(headers: &[Header]) -> Option<Encoding> {
+        let accept = headers
+            .iter()
+            .find(|header| header.field.equiv("Accept-Encoding"))?;
+        let accepts = |name: &str| {
+            accept.value.as_str().split(',').any(|coding| {
+                let mut params = coding.split(';');
+                let coding = params.next().unwrap_or_default().trim();
+                let q = params
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.trim().parse::<f64>().ok())
+                    .unwrap_or(1.0);
+                (coding.eq_ignore_ascii_case(name) || coding == "*") && q > 0.0
+            })
+        };
+        if accepts("gzip") {
+            Some(Encoding::Gzip)
+        } else if accepts("deflate") {
+            Some(Encoding::Deflate)
+        } else {
+            None
+        }
+    }
+
+    fn compress(self, body: &[u8]) -> Result<Vec<u8>, io::Error> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            // HTTP's deflate is the zlib format rather than raw deflate.
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Returns a response with the body compressed with the given coding.
+fn compressed(body: Vec<u8>, encoding: Option<Encoding>) -> Response<io::Cursor<Vec<u8>>> {
+    let vary = "Vary: Accept-Encoding".parse::<Header>().unwrap();
+    let Some(encoding) = encoding else {
+        return Response::from_data(body).with_header(vary);
+    };
+    match encoding.compress(&body) {
+        Ok(data) => {
+            let content_encoding = format!("Content-Encoding: {}", encoding.name())
+                .parse::<Header>()
+                .unwrap();
+            Response::from_data(data)
+                .with_header(content_encoding)
+                .with_header(vary)
+        }
+        Err(err) => {
+            tracing::warn!("Failed to compress the response: {err}");
+            Response::from_data(body).with_header(vary)
+        }
+    }
+}
+
+/// Returns the encoded metrics, from the cache while it's fresh.
+fn encode_cached(
+    cache: &mut Option<(Instant, String)>,
+    registry: &Registry,
+    metrics: &P1Metrics,
+    config: &ServerConfig,
+) -> Result<String, fmt::Error> {
+    if let (Some((encoded, body)), Some(ttl)) = (&cache, config.cache_ttl) {
+        if encoded.elapsed() < ttl {
+            metrics.http.cache_hits.inc();
+            return Ok(body.clone());
+        }
+    }
+    if config.cache_ttl.is_some() {
+        metrics.http.cache_misses.inc();
+    }
+    config.collectors.scrape();
+    let body = encode_body(registry, config)?;
+    if config.cache_ttl.is_some() {
+        *cache = Some((Instant::now(), body.clone()));
+    }
+    Ok(body)
+}
+
+fn rebind(
+    addr: &ListenAddress,
+    permissions: &SocketPermissions,
+    failures: &mut u32,
+) -> Result<Server, io::Error> {
+    if let ListenAddress::Unix(path) = addr {
+        // Closing the listener leaves the socket file behind.
+        let _ = fs::remove_file(path);
+    }
+    loop {
+        *failures += 1;
+        tracing::warn!("Rebinding {addr} (attempt {failures}/{MAX_RESTARTS})");
+        thread::sleep(RESTART_DELAY);
+        match bind(addr, permissions) {
+            Ok(server) => return Ok(server),
+            Err(err) if *failures < MAX_RESTARTS => {
+                tracing::warn!("Failed to rebind {addr}: {err}")
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn encode_body(registry: &Registry, config: &ServerConfig) -> Result<String, fmt::Error> {
+    let mut body = exposition::encode(registry)?;
+    if config.sample_timestamps {
+        body = exposition::with_timestamps(&body, &config.metric_prefix);
+    }
+    if config.no_eof && body.ends_with("# EOF\n") {
+        body.truncate(body.len() - "# EOF\n".len());
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept_encoding(value: &str) -> Option<Encoding> {
+        let header = format!("Accept-Encoding: {value}")
+            .parse::<Header>()
+            .unwrap();
+        Encoding::negotiate(&[header])
+    }
+
+    #[test]
+    fn negotiate_encoding() {
+        assert_eq!(Encoding::negotiate(&[]), None);
+        assert_eq!(accept_encoding("identity"), None);
+        assert_eq!(accept_encoding("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(accept_encoding("GZIP;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(
+            accept_encoding("gzip;q=0, deflate"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(accept_encoding("gzip; q=0, deflate;q=0"), None);
+        assert_eq!(accept_encoding("*"), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn compressed_roundtrip() {
+        let body = b"p1_power_consumed_kw 0.3\n".repeat(10);
+        let compressed = Encoding::Gzip.compress(&body).unwrap();
+        let mut decoded = vec![];
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn parse_listen_address() {
+        let addr = "127.0.0.1:4545".parse::<ListenAddress>().unwrap();
+        assert!(matches!(addr, ListenAddress::Tcp(addr) if addr.port() == 4545));
+        let addr = "[::1]:9000".parse::<ListenAddress>().unwrap();
+        assert!(matches!(addr, ListenAddress::Tcp(addr) if addr.is_ipv6()));
+        let addr = "localhost:4545".parse::<ListenAddress>().unwrap();
+        assert!(matches!(addr, ListenAddress::Tcp(addr) if addr.ip().is_loopback()));
+        for (s, path) in [
+            ("/run/p1.sock", "/run/p1.sock"),
+            ("unix:p1.sock", "p1.sock"),
+            ("unix:/run/p1.sock", "/run/p1.sock"),
+        ] {
+            let addr = s.parse::<ListenAddress>().unwrap();
+            assert!(
+                matches!(&addr, ListenAddress::Unix(p) if p == Path::new(path)),
+                "{s}"
+            );
+            assert_eq!(addr.to_string(), format!("unix:{path}"));
+        }
+        assert_eq!(
+            "unix:".parse::<ListenAddress>().unwrap_err(),
+            "empty Unix socket path"
+        );
+        let err = "p1.sock".parse::<ListenAddress>().unwrap_err();
+        assert!(err.contains("use unix:p1.sock"), "{err}");
+    }
+}
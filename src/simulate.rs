@@ -0,0 +1,205 @@
+//! A simulated DSMR5 meter serving synthetic telegrams over TCP, for
+//! developing dashboards and tests without a meter.
+
+use crate::{obis::crc16, proxy::Proxy};
+use std::{
+    collections::hash_map::RandomState,
+    f64::consts::PI,
+    fmt::Write,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::SocketAddr,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub struct SimulatorConfig {
+    pub listen: SocketAddr,
+    pub interval: Duration,
+    /// Average consumption in kW.
+    pub consumption: f64,
+    /// Production in kW at noon, following the sun between 6:00 and 18:00.
+    pub production: f64,
+    /// Gas consumption in m3 per hour.
+    pub gas: f64,
+}
+
+const EQUIPMENT_ID: &str = "SIM0000000000001";
+const GAS_EQUIPMENT_ID: &str = "SIM0000000000002";
+/// Nominal voltage in V.
+const VOLTAGE: f64 = 230.0;
+
+/// Serves a telegram every interval to all clients connected to the listen
+/// address. Returns only if the address can't be bound.
+pub fn run(config: SimulatorConfig) -> Result<(), io::Error> {
+    let clients = Proxy::start(config.listen)?;
+    tracing::info!("Serving simulated telegrams on {}", config.listen);
+
+    let mut meter = Meter {
+        consumed: [1000.0, 800.0],
+        produced: [0.0, 0.0],
+        gas: 300.0,
+        random: Random::new(),
+    };
+    loop {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let telegram = meter.step(now, &config);
+        clients.broadcast(telegram.as_bytes());
+        thread::sleep(config.interval);
+    }
+}
+
+struct Meter {
+    /// Energy registers in kWh by tariff.
+    consumed: [f64; 2],
+    produced: [f64; 2],
+    /// Gas register in m3.
+    gas: f64,
+    random: Random,
+}
+
+impl Meter {
+    /// Advances the meter by one interval ending at `now` (Unix time) and
+    /// returns the telegram for it.
+    fn step(&mut self, now: u64, config: &SimulatorConfig) -> String {
+        let seconds_of_day = now % 86400;
+        let hour = seconds_of_day as f64 / 3600.0;
+
+        let consumption = config.consumption * (0.5 + self.random.next());
+        let daylight = (PI * (hour - 6.0) / 12.0).sin().max(0.0);
+        let production = config.production * daylight * (0.9 + 0.2 * self.random.next());
+        let (delivered, received) = if consumption > production {
+            (consumption - production, 0.0)
+        } else {
+            (0.0, production - consumption)
+        };
+
+        // Low tariff at night, high tariff during the day.
+        let tariff = if (7..23).contains(&(seconds_of_day / 3600)) {
+            1
+        } else {
+            0
+        };
+        let hours = config.interval.as_secs_f64() / 3600.0;
+        self.consumed[tariff] += delivered * hours;
+        self.produced[tariff] += received * hours;
+        self.gas += config.gas * hours;
+
+        let voltages: Vec<f64> = (0..3)
+            .map(|_| VOLTAGE + 4.0 * (self.random.next() - 0.5))
+            .collect();
+        let timestamp = timestamp(now);
+
+        let mut t = String::new();
+        t.push_str("/SIM5\\2P1-EXPORTER-SIMULATOR\r\n\r\n");
+        t.push_str("1-3:0.2.8(50)\r\n");
+        line(&mut t, "0-0:1.0.0", &timestamp);
+        line(&mut t, "0-0:96.1.1", &hex(EQUIPMENT_ID));
+        for (tariff, reference) in [(0, "1-0:1.8.1"), (1, "1-0:1.8.2")] {
+            line(
+                &mut t,
+                reference,
+                &format!("{:010.3}*kWh", self.consumed[tariff]),
+            );
+        }
+        for (tariff, reference) in [(0, "1-0:2.8.1"), (1, "1-0:2.8.2")] {
+            line(
+                &mut t,
+                reference,
+                &format!("{:010.3}*kWh", self.produced[tariff]),
+            );
+        }
+        line(&mut t, "0-0:96.14.0", &format!("{:04}", tariff + 1));
+        line(&mut t, "1-0:1.7.0", &format!("{delivered:06.3}*kW"));
+        line(&mut t, "1-0:2.7.0", &format!("{received:06.3}*kW"));
+        line(&mut t, "0-0:96.7.21", "00000");
+        line(&mut t, "0-0:96.7.9", "00000");
+        for phase in ["32", "52", "72"] {
+            line(&mut t, &format!("1-0:{phase}.32.0"), "00000");
+            line(&mut t, &format!("1-0:{phase}.36.0"), "00000");
+        }
+        t.push_str("0-0:96.13.0()\r\n");
+        for (i, voltage) in voltages.iter().enumerate() {
+            let reference = |base: u8| format!("1-0:{}.7.0", base + 20 * i as u8);
+            let current = (delivered + received) / 3.0 * 1000.0 / voltage;
+            line(&mut t, &reference(32), &format!("{voltage:05.1}*V"));
+            line(
+                &mut t,
+                &reference(31),
+                &format!("{:03.0}*A", current.round()),
+            );
+            line(
+                &mut t,
+                &reference(21),
+                &format!("{:06.3}*kW", delivered / 3.0),
+            );
+            line(
+                &mut t,
+                &reference(22),
+                &format!("{:06.3}*kW", received / 3.0),
+            );
+        }
+        line(&mut t, "0-1:24.1.0", "003");
+        line(&mut t, "0-1:96.1.0", &hex(GAS_EQUIPMENT_ID));
+        let _ = write!(t, "0-1:24.2.1({timestamp})({:09.3}*m3)\r\n", self.gas);
+        t.push('!');
+        let _ = write!(t, "{:04X}\r\n", crc16(t.as_bytes()));
+        t
+    }
+}
+
+fn line(telegram: &mut String, reference: &str, value: &str) {
+    let _ = write!(telegram, "{reference}({value})\r\n");
+}
+
+fn hex(s: &str) -> String {
+    s.bytes().map(|b| format!("{b:02X}")).collect()
+}
+
+/// Formats Unix time as a DSMR timestamp (`YYMMDDhhmmssW`), in UTC.
+fn timestamp(now: u64) -> String {
+    let (year, month, day) = civil_from_days((now / 86400) as i64);
+    let seconds = now % 86400;
+    format!(
+        "{:02}{month:02}{day:02}{:02}{:02}{:02}W",
+        year % 100,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Returns the date of the given number of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// A xorshift generator, good enough for noise.
+struct Random(u64);
+
+impl Random {
+    fn new() -> Self {
+        Random(RandomState::new().build_hasher().finish() | 1)
+    }
+
+    /// Returns a number in `[0, 1)`.
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,53 @@
+//! Outputs that are pushed the readings of every telegram, as opposed to the
+//! registry being scraped.
+
+/// How a sample should be interpreted by a sink.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Gauge,
+    /// A monotonically increasing total.
+    Counter,
+}
+
+/// A single value produced while processing a telegram.
+///
+/// Names match the registry's metric names without their prefix (`p1_`).
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub name: &'static str,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+    pub kind: Kind,
+}
+
+impl Sample {
+    pub fn gauge(name: &'static str, value: f64) -> Self {
+        Sample {
+            name,
+            labels: vec![],
+            value,
+            kind: Kind::Gauge,
+        }
+    }
+
+    pub fn counter(name: &'static str, value: f64) -> Self {
+        Sample {
+            kind: Kind::Counter,
+            ..Sample::gauge(name, value)
+        }
+    }
+
+    pub fn with_label(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.labels.push((name, value.into()));
+        self
+    }
+}
+
+pub trait Sink: Send + Sync {
+    /// Publishes the samples of a single telegram.
+    fn publish(&self, samples: &[Sample]);
+
+    /// Publishes the text of a parsed telegram, before its samples. Sinks
+    /// that only need the samples ignore it.
+    fn publish_telegram(&self, _text: &str) {}
+}
@@ -0,0 +1,319 @@
+//! Reading of the SML (Smart Message Language) files German meters send on
+//! their optical interface, which are converted into DSMR telegrams for the
+//! rest of the exporter.
+
+use crate::{
+    dlms::crc16_x25,
+    telegram::{self, scale, Reading, TelegramReader},
+};
+use std::io::{self, BufReader, Read};
+
+/// Escape sequence of the transport, followed by the start or end of a file.
+const ESCAPE: [u8; 4] = [0x1b; 4];
+const START: [u8; 4] = [0x01; 4];
+/// First byte following the escape sequence at the end of a file.
+const END: u8 = 0x1a;
+/// Files are well below that.
+const MAX_FILE: usize = 64 * 1024;
+/// The reference of the manufacturer, pushed as text.
+const MANUFACTURER: &str = "129-129:199.130.3";
+
+/// Reads the telegrams converted from a stream of SML files, skipping the
+/// files that fail the checksum or to decode.
+pub fn reader<R: Read>(inner: R) -> TelegramReader<impl FnMut() -> io::Result<Vec<u8>>> {
+    let mut files = SmlFiles {
+        inner: BufReader::new(inner),
+    };
+    TelegramReader::new(move || files.next_file())
+}
+
+struct SmlFiles<R> {
+    inner: BufReader<R>,
+}
+
+impl<R: Read> SmlFiles<R> {
+    /// Converts the next file into a telegram, failing with `UnexpectedEof`
+    /// at the end of the stream.
+    fn next_file(&mut self) -> Result<Vec<u8>, io::Error> {
+        loop {
+            let Some(messages) = self.read_file()? else {
+                continue;
+            };
+            match to_telegram(&messages) {
+                Ok(telegram) => return Ok(telegram.into_bytes()),
+                Err(err) => tracing::warn!("Skipping SML file: {err}"),
+            }
+        }
+    }
+
+    /// Reads the messages of the next file, unescaped and without their
+    /// padding. Returns `None` for files cut short or failing the checksum.
+    fn read_file(&mut self) -> Result<Option<Vec<u8>>, io::Error> {
+        // The start sequence, which may follow anything.
+        let mut window = [0; 8];
+        while window[..4] != ESCAPE || window[4..] != START {
+            window.rotate_left(1);
+            window[7] = self.read_u8()?;
+        }
+        // The whole file is checked, escape sequences included.
+        let mut file = window.to_vec();
+        let mut messages = Vec::new();
+        while messages.len() < MAX_FILE {
+            let mut block = [0; 4];
+            self.inner.read_exact(&mut block)?;
+            file.extend_from_slice(&block);
+            if block != ESCAPE {
+                messages.extend_from_slice(&block);
+                continue;
+            }
+            self.inner.read_exact(&mut block)?;
+            if block == ESCAPE {
+                // An escaped escape sequence in the messages.
+                file.extend_from_slice(&block);
+                messages.extend_from_slice(&block);
+                continue;
+            }
+            if block[0] != END {
+                return Ok(None);
+            }
+            file.extend_from_slice(&block[..2]);
+            if crc16_x25(&file) != u16::from_le_bytes([block[2], block[3]]) {
+                tracing::warn!("Skipping SML file with a wrong checksum");
+                return Ok(None);
+            }
+            let padding = usize::from(block[1]);
+            messages.truncate(messages.len().saturating_sub(padding));
+            return Ok(Some(messages));
+        }
+        Ok(None)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, io::Error> {
+        let mut byte = [0];
+        self.inner.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+/// An SML value, as far as needed here.
+enum Value {
+    List(Vec<Value>),
+    Bytes(Vec<u8>),
+    Number(f64),
+    /// Booleans and the end of messages.
+    Other,
+}
+
+/// Decodes the type-length encoded value at the start of `data`, returning
+/// it with the rest of the data.
+fn value(data: &[u8]) -> Result<(Value, &[u8]), String> {
+    let truncated = || "truncated SML message".to_string();
+    let (&first, mut rest) = data.split_first().ok_or_else(truncated)?;
+    if first == 0x00 {
+        return Ok((Value::Other, rest));
+    }
+    // The length continues in the next bytes while their top bit is set,
+    // and includes the type-length bytes for all but lists.
+    let mut len = usize::from(first & 0x0f);
+    let mut tl_len = 1;
+    let mut tl = first;
+    while tl & 0x80 != 0 {
+        (tl, rest) = rest
+            .split_first()
+            .map(|(b, r)| (*b, r))
+            .ok_or_else(truncated)?;
+        len = len << 4 | usize::from(tl & 0x0f);
+        tl_len += 1;
+    }
+    if first & 0x70 == 0x70 {
+        let mut items = Vec::new();
+        for _ in 0..len {
+            let (item, next) = value(rest)?;
+            items.push(item);
+            rest = next;
+        }
+        return Ok((Value::List(items), rest));
+    }
+    let len = len.checked_sub(tl_len).ok_or("invalid SML length")?;
+    if rest.len() < len {
+        return Err(truncated());
+    }
+    let (bytes, rest) = rest.split_at(len);
+    let value = match first & 0x70 {
+        0x00 => Value::Bytes(bytes.to_vec()),
+        0x50 if (1..=8).contains(&len) => {
+            // Sign-extended from the top byte.
+            let init = if bytes[0] & 0x80 != 0 { -1 } else { 0 };
+            let n = bytes.iter().fold(init, |n: i64, b| n << 8 | i64::from(*b));
+            Value::Number(n as f64)
+        }
+        0x60 if (1..=8).contains(&len) => {
+            let n = bytes.iter().fold(0, |n: u64, b| n << 8 | u64::from(*b));
+            Value::Number(n as f64)
+        }
+        0x40 => Value::Other,
+        _ => return Err(format!("unsupported SML type-length {first:#04x}")),
+    };
+    Ok((value, rest))
+}
+
+/// Collects the list entries (`SML_ListEntry`) of the messages, wherever
+/// they appear: the OBIS code, status, time, unit, scaler, value and
+/// signature.
+fn readings(value: &Value, readings: &mut Vec<(String, Reading)>) {
+    let Value::List(items) = value else {
+        return;
+    };
+    if let [Value::Bytes(code), _, _, unit, scaler, value, _] = &items[..] {
+        if code.len() == 6 {
+            let unit = match unit {
+                Value::Number(unit) => Some(*unit as u8),
+                _ => None,
+            };
+            let scaler = match scaler {
+                Value::Number(scaler) => *scaler as i32,
+                _ => 0,
+            };
+            let reading = match value {
+                Value::Number(value) => Some(Reading::Number(scale(*value, scaler), unit)),
+                Value::Bytes(bytes) => Some(Reading::Bytes(bytes.clone())),
+                _ => None,
+            };
+            if let Some(reading) = reading {
+                readings.push((telegram::reference(code), reading));
+            }
+            return;
+        }
+    }
+    for item in items {
+        self::readings(item, readings);
+    }
+}
+
+/// Converts the messages of a file into a DSMR telegram.
+fn to_telegram(mut messages: &[u8]) -> Result<String, String> {
+    let mut values = Vec::new();
+    // The padding may be left when the file was cut short.
+    while messages.first().is_some_and(|b| *b != 0x00) {
+        let (message, rest) = value(messages)?;
+        readings(&message, &mut values);
+        messages = rest;
+    }
+    if values.is_empty() {
+        return Err("no values in the file".to_string());
+    }
+    let manufacturer = values
+        .iter()
+        .find_map(|(code, reading)| match reading {
+            Reading::Bytes(bytes) if code == MANUFACTURER => String::from_utf8(bytes.clone()).ok(),
+            _ => None,
+        })
+        .filter(|m| m.len() == 3 && m.chars().all(|c| c.is_ascii_alphanumeric()))
+        .unwrap_or_else(|| "XXX".to_string());
+    Ok(telegram::build(
+        &format!("{manufacturer}5\\SML"),
+        None,
+        &values,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn octets(bytes: &[u8]) -> Vec<u8> {
+        let mut value = vec![bytes.len() as u8 + 1];
+        value.extend_from_slice(bytes);
+        value
+    }
+
+    fn unsigned(n: u64, len: usize) -> Vec<u8> {
+        let mut value = vec![0x60 | (len as u8 + 1)];
+        value.extend_from_slice(&n.to_be_bytes()[8 - len..]);
+        value
+    }
+
+    fn signed(n: i64, len: usize) -> Vec<u8> {
+        let mut value = vec![0x50 | (len as u8 + 1)];
+        value.extend_from_slice(&n.to_be_bytes()[8 - len..]);
+        value
+    }
+
+    fn list(items: &[Vec<u8>]) -> Vec<u8> {
+        let mut value = vec![0x70 | items.len() as u8];
+        value.extend(items.concat());
+        value
+    }
+
+    /// An `SML_ListEntry` with the OBIS code, unit, scaler and value.
+    fn entry(code: [u8; 6], unit: u64, scaler: i64, value: Vec<u8>) -> Vec<u8> {
+        let none = vec![0x01];
+        list(&[
+            octets(&code),
+            none.clone(),
+            none.clone(),
+            unsigned(unit, 1),
+            signed(scaler, 1),
+            value,
+            none,
+        ])
+    }
+
+    /// A file with the given messages, escaped, padded and checksummed.
+    fn file(messages: &[u8]) -> Vec<u8> {
+        let mut messages = messages.to_vec();
+        let padding = messages.len().next_multiple_of(4) - messages.len();
+        messages.resize(messages.len() + padding, 0);
+        let mut file = [ESCAPE, START].concat();
+        for block in messages.chunks(4) {
+            file.extend_from_slice(block);
+            if block == ESCAPE {
+                file.extend_from_slice(block);
+            }
+        }
+        file.extend_from_slice(&ESCAPE);
+        file.extend_from_slice(&[END, padding as u8]);
+        let crc = crc16_x25(&file);
+        file.extend_from_slice(&crc.to_le_bytes());
+        file
+    }
+
+    fn messages() -> Vec<u8> {
+        let entries = list(&[
+            entry([1, 0, 1, 8, 0, 255], 30, -1, unsigned(123_456_789, 7)),
+            entry([1, 0, 16, 7, 0, 255], 27, 0, signed(-523, 4)),
+            // Escaped in the file, as it starts at a multiple of 4.
+            entry([1, 0, 2, 8, 0, 255], 30, 0, unsigned(0x1b1b_1b1b, 4)),
+        ]);
+        let mut messages = list(&[list(&[unsigned(0x0701, 2), entries])]);
+        messages.push(0x00);
+        messages
+    }
+
+    #[test]
+    fn decode_values() {
+        let telegram = to_telegram(&messages()).unwrap();
+        assert!(telegram.starts_with("/XXX5\\SML"), "{telegram}");
+        for line in [
+            "1-0:1.8.1(012345.679*kWh)",
+            "1-0:2.8.1(454761.243*kWh)",
+            "1-0:1.7.0(00.000*kW)",
+            "1-0:2.7.0(00.523*kW)",
+        ] {
+            assert!(telegram.contains(line), "{line} in {telegram}");
+        }
+        assert!(to_telegram(&[0x72, 0x62]).is_err());
+        assert!(to_telegram(&[0x00]).is_err());
+    }
+
+    #[test]
+    fn read_files() {
+        assert!(file(&messages()).windows(8).any(|block| block == [0x1b; 8]));
+        let mut corrupted = file(&messages());
+        corrupted[12] ^= 1;
+        let stream = [b"noise".to_vec(), corrupted, file(&messages())].concat();
+        let mut telegrams = String::new();
+        reader(&stream[..]).read_to_string(&mut telegrams).unwrap();
+        assert_eq!(telegrams, to_telegram(&messages()).unwrap());
+    }
+}
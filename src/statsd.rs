@@ -0,0 +1,128 @@
+//! Push of the readings to a StatsD server, using DogStatsD tags for labels.
+
+use crate::sink::{Kind, Sample, Sink};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::Mutex,
+};
+
+/// Keeps datagrams below the usual Ethernet MTU.
+const MAX_PACKET: usize = 1432;
+
+pub struct StatsdConfig {
+    pub address: SocketAddr,
+    /// Prefix of the metric names, `p1` to match the registry's `p1_`.
+    pub prefix: String,
+    /// Tags added to every metric, as `name:value` or `name`.
+    pub tags: Vec<String>,
+}
+
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+    tags: Vec<String>,
+    /// The last value of every total, by metric name and tags. StatsD
+    /// counters are increments, so totals are sent as their increase since.
+    totals: Mutex<HashMap<String, f64>>,
+}
+
+impl StatsdSink {
+    pub fn new(config: StatsdConfig) -> Result<Self, io::Error> {
+        let local: SocketAddr = if config.address.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(config.address)?;
+        let prefix = match config.prefix.trim_end_matches('.') {
+            "" => String::new(),
+            prefix => format!("{prefix}."),
+        };
+        let tags = config.tags.iter().map(|tag| sanitize_tag(tag)).collect();
+        Ok(StatsdSink {
+            socket,
+            prefix,
+            tags,
+            totals: Mutex::default(),
+        })
+    }
+
+    fn send(&self, packet: &str) {
+        if let Err(err) = self.socket.send(packet.as_bytes()) {
+            tracing::debug!("Failed to send StatsD packet: {err}");
+        }
+    }
+
+    /// Formats a sample as a DogStatsD line, if there is anything to send:
+    /// the first reading of a total or one lower than the previous, e.g.
+    /// after the meter was replaced, only sets the base of the increments.
+    fn format_sample(&self, sample: &Sample) -> Option<String> {
+        let mut metric = format!("{}{}", self.prefix, sample.name);
+        let tags = sample
+            .labels
+            .iter()
+            .map(|(name, value)| format!("{name}:{}", sanitize_tag(value)))
+            .chain(self.tags.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(",");
+        let tags = if tags.is_empty() {
+            tags
+        } else {
+            format!("|#{tags}")
+        };
+        let (value, kind) = match sample.kind {
+            Kind::Gauge => (sample.value, "g"),
+            Kind::Counter => {
+                let mut totals = self.totals.lock().unwrap();
+                let last = totals.insert(format!("{metric}{tags}"), sample.value);
+                match last {
+                    // Readings have a few decimals at most, rounding drops
+                    // the error of the subtraction.
+                    Some(last) if sample.value > last => {
+                        (((sample.value - last) * 1e6).round() / 1e6, "c")
+                    }
+                    _ => return None,
+                }
+            }
+        };
+        let _ = write!(metric, ":{value}|{kind}{tags}");
+        Some(metric)
+    }
+}
+
+impl Sink for StatsdSink {
+    fn publish(&self, samples: &[Sample]) {
+        let mut packet = String::new();
+        for line in samples
+            .iter()
+            .filter_map(|sample| self.format_sample(sample))
+        {
+            if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET {
+                self.send(&packet);
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(&line);
+        }
+        if !packet.is_empty() {
+            self.send(&packet);
+        }
+    }
+}
+
+/// Replaces the characters that delimit DogStatsD fields and tags.
+fn sanitize_tag(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ',' | '|' | '#' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
@@ -0,0 +1,150 @@
+//! Integration with systemd: listening sockets passed by socket activation
+//! and readiness and watchdog notifications.
+
+use crate::metrics::P1Metrics;
+use std::{
+    env,
+    ffi::OsStr,
+    io::{self, Error},
+    mem,
+    net::TcpListener,
+    os::{
+        fd::{AsRawFd, BorrowedFd, RawFd},
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram, UnixListener},
+        },
+    },
+    process,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+/// The first file descriptor passed, see sd_listen_fds(3).
+const SD_LISTEN_FDS_START: RawFd = 3;
+/// Interval of checking whether the first telegram has been received.
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A listening socket of either family.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// Returns the file descriptor of the single socket passed by systemd.
+pub fn listen_fd() -> Result<RawFd, String> {
+    let pid = env::var("LISTEN_PID")
+        .map_err(|_| "LISTEN_PID isn't set, the exporter wasn't started by a socket unit")?;
+    if pid.parse() != Ok(process::id()) {
+        return Err(format!("the sockets were passed to process {pid}"));
+    }
+    let fds = env::var("LISTEN_FDS").unwrap_or_default();
+    match fds.parse::<u32>() {
+        Ok(1) => {}
+        Ok(0) | Err(_) => return Err("systemd passed no socket".to_string()),
+        Ok(n) => return Err(format!("systemd passed {n} sockets, expected one")),
+    }
+    // SAFETY: fcntl with integer arguments only.
+    if unsafe { libc::fcntl(SD_LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+        return Err(format!(
+            "invalid socket passed by systemd: {}",
+            Error::last_os_error()
+        ));
+    }
+    Ok(SD_LISTEN_FDS_START)
+}
+
+/// Returns a listener on a duplicate of the passed socket `fd`, which stays
+/// open to create the listener again.
+pub fn listener(fd: RawFd) -> Result<Listener, io::Error> {
+    // SAFETY: fd was passed by systemd and is never closed.
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+    // SAFETY: an all-zero sockaddr_storage is valid.
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of_val(&addr) as libc::socklen_t;
+    // SAFETY: addr and len describe a buffer large enough for any address.
+    if unsafe {
+        libc::getsockname(
+            fd.as_raw_fd(),
+            (&mut addr as *mut libc::sockaddr_storage).cast(),
+            &mut len,
+        )
+    } != 0
+    {
+        return Err(Error::last_os_error());
+    }
+    Ok(match i32::from(addr.ss_family) {
+        libc::AF_UNIX => Listener::Unix(fd.into()),
+        _ => Listener::Tcp(fd.into()),
+    })
+}
+
+/// Reports readiness to systemd once the first telegram has been received
+/// and, if the unit sets `WatchdogSec=`, pings the watchdog for as long as
+/// telegrams younger than `max_age` keep arriving. Does nothing unless
+/// started by systemd as a `Type=notify` service.
+pub fn start_notifier(metrics: Arc<P1Metrics>, max_age: Duration) {
+    let Some(socket) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let watchdog = watchdog_interval();
+    thread::spawn(move || {
+        while !metrics.telegram_within(Duration::MAX) {
+            thread::sleep(READY_POLL_INTERVAL);
+        }
+        notify(&socket, "READY=1\nSTATUS=Receiving telegrams");
+        let Some(interval) = watchdog else {
+            return;
+        };
+        let mut stalled = false;
+        loop {
+            let fresh = metrics.telegram_within(max_age);
+            if fresh && stalled {
+                notify(&socket, "WATCHDOG=1\nSTATUS=Receiving telegrams");
+            } else if fresh {
+                notify(&socket, "WATCHDOG=1");
+            } else if !stalled {
+                tracing::warn!(
+                    "No telegram received for {max_age:?}, no longer pinging the watchdog"
+                );
+                notify(&socket, "STATUS=No telegrams received");
+            }
+            stalled = !fresh;
+            thread::sleep(interval / 2);
+        }
+    });
+}
+
+/// Returns the watchdog interval configured for this process, see
+/// sd_watchdog_enabled(3).
+fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(process::id()) {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec)).filter(|interval| !interval.is_zero())
+}
+
+fn notify(socket: &OsStr, state: &str) {
+    if let Err(err) = send(socket, state) {
+        tracing::warn!("Failed to notify systemd: {err}");
+    }
+}
+
+fn send(socket: &OsStr, state: &str) -> Result<(), io::Error> {
+    let addr = match socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name)?
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err(io::ErrorKind::Unsupported.into()),
+        None => SocketAddr::from_pathname(socket)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
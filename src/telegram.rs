@@ -0,0 +1,226 @@
+//! Building of DSMR telegrams from the readings of meters speaking other
+//! protocols (DLMS push frames, SML), which then go through the same parsing
+//! as the telegrams of any other meter, and the reading of the telegrams of
+//! any such input as a stream.
+
+use crate::obis::{self, crc16};
+use std::{
+    fmt::Write as _,
+    io::{self, Read},
+};
+
+/// Reads the telegrams returned by `next` one after the other, as the stream
+/// the parser expects. `next` returns the next telegram, converted,
+/// decrypted or upgraded, and fails with `UnexpectedEof` at the end of the
+/// input, which ends the stream.
+pub struct TelegramReader<F> {
+    next: F,
+    telegram: Vec<u8>,
+    /// How much of `telegram` was read.
+    read: usize,
+}
+
+impl<F: FnMut() -> io::Result<Vec<u8>>> TelegramReader<F> {
+    pub fn new(next: F) -> Self {
+        TelegramReader {
+            next,
+            telegram: Vec::new(),
+            read: 0,
+        }
+    }
+}
+
+impl<F: FnMut() -> io::Result<Vec<u8>>> Read for TelegramReader<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read == self.telegram.len() {
+            match (self.next)() {
+                Ok(telegram) => {
+                    self.telegram = telegram;
+                    self.read = 0;
+                }
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                Err(err) => return Err(err),
+            }
+        }
+        let n = buf.len().min(self.telegram.len() - self.read);
+        buf[..n].copy_from_slice(&self.telegram[self.read..self.read + n]);
+        self.read += n;
+        Ok(n)
+    }
+}
+
+/// Unit codes of DLMS/COSEM, which SML uses as well.
+const UNIT_W: u8 = 27;
+const UNIT_VA: u8 = 28;
+const UNIT_VAR: u8 = 29;
+const UNIT_WH: u8 = 30;
+const UNIT_VAH: u8 = 31;
+const UNIT_VARH: u8 = 32;
+const UNIT_A: u8 = 33;
+const UNIT_V: u8 = 35;
+const UNIT_HZ: u8 = 44;
+
+/// Signed power, positive when consumed, and the objects of the consumed
+/// and produced power it's split into.
+const SIGNED_POWER: [[&str; 3]; 4] = [
+    ["1-0:16.7.0", "1-0:1.7.0", "1-0:2.7.0"],
+    ["1-0:36.7.0", "1-0:21.7.0", "1-0:22.7.0"],
+    ["1-0:56.7.0", "1-0:41.7.0", "1-0:42.7.0"],
+    ["1-0:76.7.0", "1-0:61.7.0", "1-0:62.7.0"],
+];
+
+/// A value read from the meter.
+pub enum Reading {
+    /// A number in the given unit, if known.
+    Number(f64, Option<u8>),
+    Bytes(Vec<u8>),
+}
+
+/// Formats an OBIS code of six bytes as a reference like `1-0:1.8.0`.
+pub fn reference(code: &[u8]) -> String {
+    format!(
+        "{}-{}:{}.{}.{}",
+        code[0], code[1], code[2], code[3], code[4]
+    )
+}
+
+/// Builds a telegram from readings by OBIS reference (`1-0:1.8.0`).
+///
+/// Energy totals are exported as the registers of tariff 1 by meters not
+/// reporting them by tariff, and signed power as consumed and produced
+/// power. The values of references unknown to `dsmr5` are passed on for
+/// `--extra-obis`.
+pub fn build(header: &str, timestamp: Option<&str>, readings: &[(String, Reading)]) -> String {
+    let has = |code: &str| readings.iter().any(|(c, _)| c == code);
+    let by_tariff = readings
+        .iter()
+        .any(|(code, _)| obis::is_energy_register(code));
+    let mut split = Vec::new();
+    for (code, reading) in readings {
+        let Reading::Number(value, unit) = reading else {
+            continue;
+        };
+        if let Some([_, consumed, produced]) = SIGNED_POWER
+            .iter()
+            .find(|[signed, consumed, _]| signed == code && !has(consumed))
+        {
+            split.push((consumed.to_string(), Reading::Number(value.max(0.0), *unit)));
+            split.push((
+                produced.to_string(),
+                Reading::Number((-value).max(0.0), *unit),
+            ));
+        }
+    }
+
+    let mut lines = Vec::new();
+    for (code, reading) in readings.iter().chain(&split) {
+        match reading {
+            Reading::Bytes(bytes) => {
+                if matches!(
+                    code.as_str(),
+                    "0-0:96.1.0" | "0-0:96.1.1" | "1-0:96.1.0" | "1-0:0.0.5"
+                ) {
+                    lines.push(format!("0-0:96.1.1({})", hex(bytes)));
+                }
+            }
+            Reading::Number(value, unit) => {
+                let (value, unit) = normalize(code, *value, *unit);
+                // The fixed-width values of telegrams are unsigned.
+                let unsigned = value.max(0.0);
+                let line = match code.as_str() {
+                    "1-0:1.8.0" | "1-0:2.8.0" if !by_tariff => {
+                        let register = code.replace(".8.0", ".8.1");
+                        format!("{register}({unsigned:010.3}*kWh)")
+                    }
+                    code if obis::is_energy_register(code) => {
+                        format!("{code}({unsigned:010.3}*kWh)")
+                    }
+                    "1-0:1.7.0" | "1-0:2.7.0" | "1-0:21.7.0" | "1-0:22.7.0" | "1-0:41.7.0"
+                    | "1-0:42.7.0" | "1-0:61.7.0" | "1-0:62.7.0" => {
+                        format!("{code}({unsigned:06.3}*kW)")
+                    }
+                    "1-0:32.7.0" | "1-0:52.7.0" | "1-0:72.7.0" => {
+                        format!("{code}({unsigned:05.1}*V)")
+                    }
+                    "1-0:31.7.0" | "1-0:51.7.0" | "1-0:71.7.0" => {
+                        format!("{code}({:03.0}*A)", unsigned.round())
+                    }
+                    "0-0:96.14.0" => format!("{code}({:04.0})", unsigned.round()),
+                    _ => {
+                        let line = match unit {
+                            Some(unit) => format!("{code}({value}*{unit})"),
+                            None => format!("{code}({value})"),
+                        };
+                        if !matches!(dsmr5::OBIS::parse(&line), Err(dsmr5::Error::UnknownObis))
+                            && !obis::is_misread_as_mbus(&line)
+                        {
+                            continue;
+                        }
+                        line
+                    }
+                };
+                lines.push(line);
+            }
+        }
+    }
+    if !by_tariff && !has("0-0:96.14.0") {
+        lines.push("0-0:96.14.0(0001)".to_string());
+    }
+
+    let mut t = format!("/{header}\r\n\r\n");
+    if let Some(timestamp) = timestamp {
+        let _ = write!(t, "0-0:1.0.0({timestamp})\r\n");
+    }
+    for line in lines {
+        let _ = write!(t, "{line}\r\n");
+    }
+    t.push('!');
+    let _ = write!(t, "{:04X}\r\n", crc16(t.as_bytes()));
+    t
+}
+
+/// Converts a value to the units of DSMR telegrams (kWh, kW, ...), taking
+/// the unit from the OBIS reference when not known.
+fn normalize(code: &str, value: f64, unit: Option<u8>) -> (f64, Option<&'static str>) {
+    let unit = unit.or_else(|| {
+        let (_, quantity) = code.split_once(':')?;
+        let mut groups = quantity.split('.');
+        let quantity = groups.next()?;
+        let reactive = matches!(quantity, "3" | "4");
+        match (quantity, groups.next()?, reactive) {
+            ("31" | "51" | "71", "7", _) => Some(UNIT_A),
+            ("32" | "52" | "72", "7", _) => Some(UNIT_V),
+            (_, "8", false) => Some(UNIT_WH),
+            (_, "8", true) => Some(UNIT_VARH),
+            (_, "7", false) => Some(UNIT_W),
+            (_, "7", true) => Some(UNIT_VAR),
+            _ => None,
+        }
+    });
+    match unit {
+        Some(UNIT_W) => (value / 1000.0, Some("kW")),
+        Some(UNIT_VA) => (value / 1000.0, Some("kVA")),
+        Some(UNIT_VAR) => (value / 1000.0, Some("kvar")),
+        Some(UNIT_WH) => (value / 1000.0, Some("kWh")),
+        Some(UNIT_VAH) => (value / 1000.0, Some("kVAh")),
+        Some(UNIT_VARH) => (value / 1000.0, Some("kvarh")),
+        Some(UNIT_A) => (value, Some("A")),
+        Some(UNIT_V) => (value, Some("V")),
+        Some(UNIT_HZ) => (value, Some("Hz")),
+        _ => (value, None),
+    }
+}
+
+/// Applies a decimal scaler, dividing for negative ones to keep e.g. 0.95
+/// exact.
+pub fn scale(value: f64, scaler: i32) -> f64 {
+    if scaler < 0 {
+        value / 10f64.powi(-scaler)
+    } else {
+        value * 10f64.powi(scaler)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
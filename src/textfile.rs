@@ -0,0 +1,36 @@
+//! Periodic export of the registry to a file, e.g. for node_exporter's
+//! textfile collector.
+
+use crate::exposition;
+use prometheus_client::registry::Registry;
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+pub fn start_textfile_exporter(path: PathBuf, interval: Duration, registry: Arc<Registry>) {
+    thread::spawn(move || loop {
+        if let Err(err) = write_textfile(&path, &registry) {
+            tracing::error!("Failed to write metrics to {}: {err}", path.display());
+        }
+        thread::sleep(interval);
+    });
+}
+
+/// Atomically replaces the file at `path` with the registry encoded in the
+/// Prometheus text format, which node_exporter expects.
+pub fn write_textfile(path: &Path, registry: &Registry) -> Result<(), io::Error> {
+    let body = exposition::encode(registry).map_err(io::Error::other)?;
+    let body = exposition::to_text(&body);
+
+    let mut tmp = OsString::from(path.as_os_str());
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    fs::write(&tmp, body)?;
+    fs::rename(&tmp, path)
+}
@@ -0,0 +1,177 @@
+//! TLS for the HTTP server and for the connections to P1 readers.
+//!
+//! HTTPS is terminated by the public listeners of the HTTP server, see
+//! [`crate::front`].
+
+use rustls::{
+    server::AllowAnyAuthenticatedClient, Certificate, ClientConfig, ClientConnection, PrivateKey,
+    RootCertStore, ServerConfig, ServerName, StreamOwned,
+};
+use std::{
+    fs,
+    io::{self, BufReader},
+    net::{IpAddr, TcpStream},
+    path::Path,
+    sync::Arc,
+};
+
+/// Reads the certificate chain and the private key to serve HTTPS with and,
+/// if `client_ca` is given, requires clients to present a certificate issued
+/// by one of the CAs in that file.
+pub fn load(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<Arc<ServerConfig>, io::Error> {
+    let chain = certificates(cert)?;
+    let private_key = private_key(key)?;
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match client_ca {
+        Some(path) => {
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots(path)?))
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(chain, private_key)
+        .map_err(|err| invalid(key, &err.to_string()))?;
+    Ok(Arc::new(config))
+}
+
+/// Settings of TLS connections to P1 readers, e.g. behind stunnel.
+pub struct Client {
+    config: Arc<ClientConfig>,
+    /// Name to verify the certificate of the reader against instead of the
+    /// host it is connected to.
+    server_name: Option<ServerName>,
+}
+
+impl Client {
+    /// Trusts the CAs in `ca` or, if not given, the system's trusted CAs and
+    /// authenticates with the client certificate `cert` if given.
+    pub fn load(
+        ca: Option<&Path>,
+        server_name: Option<&str>,
+        cert: Option<(&Path, &Path)>,
+    ) -> Result<Client, io::Error> {
+        let roots = match ca {
+            Some(path) => roots(path)?,
+            None => {
+                let mut roots = RootCertStore::empty();
+                for cert in rustls_native_certs::load_native_certs()? {
+                    // Unparsable system certificates are skipped like other
+                    // TLS clients do.
+                    let _ = roots.add(&Certificate(cert.0));
+                }
+                roots
+            }
+        };
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let config = match cert {
+            Some((cert, key)) => builder
+                .with_single_cert(certificates(cert)?, private_key(key)?)
+                .map_err(|err| invalid(key, &err.to_string()))?,
+            None => builder.with_no_client_auth(),
+        };
+        let server_name = server_name
+            .map(|name| {
+                ServerName::try_from(name).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid TLS server name '{name}'"),
+                    )
+                })
+            })
+            .transpose()?;
+        Ok(Client {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+
+    /// Performs the TLS handshake over `sock`, connected to `addr`.
+    pub fn connect(
+        &self,
+        mut sock: TcpStream,
+        addr: &str,
+    ) -> Result<StreamOwned<ClientConnection, TcpStream>, io::Error> {
+        let server_name = match &self.server_name {
+            Some(name) => name.clone(),
+            None => {
+                let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                // Certificates can only be verified against DNS names.
+                ServerName::try_from(host)
+                    .ok()
+                    .filter(|_| host.parse::<IpAddr>().is_err())
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!(
+                                "'{host}' isn't a DNS name to verify the certificate against, \
+                                 use --p1-tls-server-name"
+                            ),
+                        )
+                    })?
+            }
+        };
+        let mut conn =
+            ClientConnection::new(self.config.clone(), server_name).map_err(io::Error::other)?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut sock)?;
+        }
+        Ok(StreamOwned::new(conn, sock))
+    }
+}
+
+fn certificates(path: &Path) -> Result<Vec<Certificate>, io::Error> {
+    let certs: Vec<_> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certs.is_empty() {
+        return Err(invalid(path, "no certificate found"));
+    }
+    Ok(certs)
+}
+
+fn private_key(path: &Path) -> Result<PrivateKey, io::Error> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid(path, "expected an unencrypted private key"))
+}
+
+fn roots(path: &Path) -> Result<RootCertStore, io::Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in certificates(path)? {
+        roots
+            .add(&cert)
+            .map_err(|err| invalid(path, &format!("{err:?}")))?;
+    }
+    Ok(roots)
+}
+
+fn read_pem(path: &Path) -> Result<Vec<rustls_pemfile::Item>, io::Error> {
+    let file = fs::File::open(path)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+}
+
+fn invalid(path: &Path, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {msg}", path.display()),
+    )
+}
@@ -0,0 +1,177 @@
+//! Live view of the readings of a P1 reader in the terminal, for on-site
+//! debugging without a dashboard at hand.
+
+use crate::{collector, obis};
+use dsmr5::{state::State, types::TST};
+use std::{
+    fmt::Write as _,
+    io::{self, BufReader, Read, Write},
+    thread,
+    time::Duration,
+};
+
+/// Delay before reconnecting to the P1 reader.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Moves the cursor home and clears the screen.
+const CLEAR: &str = "\x1b[H\x1b[2J";
+
+/// Readings at the first telegram of the day, for the consumption since.
+struct Day {
+    date: (u8, u8, u8),
+    since: String,
+    consumed: f64,
+    produced: f64,
+    gas: Option<f64>,
+}
+
+/// Connects to the P1 reader at `addr` and redraws its readings with every
+/// telegram until interrupted, reconnecting whenever no data arrives for
+/// `timeout`.
+pub fn run(addr: &str, timeout: Duration) -> Result<(), String> {
+    let mut day = None;
+    let mut telegrams = 0;
+    loop {
+        let err = match collector::connect(addr, timeout).and_then(|sock| {
+            sock.set_read_timeout(Some(timeout))?;
+            Ok(sock)
+        }) {
+            Ok(sock) => {
+                let mut last_err = None;
+                let bytes = BufReader::new(sock)
+                    .bytes()
+                    .map_while(|b| b.map_err(|err| last_err = Some(err)).ok());
+                for readout in dsmr5::Reader::new(bytes) {
+                    let text = obis::readout_text(&readout);
+                    let Ok(state) = readout.to_telegram().and_then(|_| obis::state(text)) else {
+                        continue;
+                    };
+                    telegrams += 1;
+                    let screen = render(addr, text, &state, &mut day, telegrams);
+                    write_screen(&screen)?;
+                }
+                last_err.map_or_else(|| "connection closed".to_string(), |err| err.to_string())
+            }
+            Err(err) => err.to_string(),
+        };
+        let status = format!(
+            "\nLost the connection to {addr}: {err}, reconnecting in {RECONNECT_INTERVAL:?}\n"
+        );
+        write_screen(&status)?;
+        thread::sleep(RECONNECT_INTERVAL);
+    }
+}
+
+fn write_screen(screen: &str) -> Result<(), String> {
+    let mut stdout = io::stdout().lock();
+    stdout
+        .write_all(screen.as_bytes())
+        .and_then(|_| stdout.flush())
+        .map_err(|err| format!("Failed to write to the terminal: {err}"))
+}
+
+fn render(addr: &str, text: &str, state: &State, day: &mut Option<Day>, telegrams: u64) -> String {
+    let mut screen = String::from(CLEAR);
+    let time = state.datetime.as_ref().map_or("unknown".to_string(), clock);
+    let _ = writeln!(screen, "P1 reader {addr}, telegram {telegrams} of {time}\n");
+
+    let consumed = state.power_delivered.unwrap_or_default();
+    let produced = state.power_received.unwrap_or_default();
+    let _ = writeln!(
+        screen,
+        "Power      consumed {consumed:7.3} kW   produced {produced:7.3} kW   net {:7.3} kW",
+        consumed - produced
+    );
+    if let Some(tariff) = state.tariff_indicator {
+        let _ = writeln!(screen, "Tariff     {}", u16::from_be_bytes(tariff));
+    }
+
+    let _ = writeln!(
+        screen,
+        "\n{:<10} {:>9} {:>9} {:>10} {:>11}",
+        "Phase", "voltage", "current", "consumed", "produced"
+    );
+    for (line, phase) in state.lines.iter().zip(["L1", "L2", "L3"]) {
+        if line.voltage.is_none() && line.current.is_none() && line.active_power_plus.is_none() {
+            continue;
+        }
+        let _ = writeln!(
+            screen,
+            "{phase:<10} {:>9} {:>9} {:>10} {:>11}",
+            optional(line.voltage.map(|v| format!("{v:.1} V"))),
+            optional(line.current.map(|c| format!("{c} A"))),
+            optional(line.active_power_plus.map(|p| format!("{p:.3} kW"))),
+            optional(line.active_power_neg.map(|p| format!("{p:.3} kW"))),
+        );
+    }
+
+    let _ = writeln!(
+        screen,
+        "\n{:<12} {:>15} {:>15}",
+        "Energy", "consumed", "produced"
+    );
+    let (mut total_consumed, mut total_produced) = (0.0, 0.0);
+    for tariff in obis::energy_tariffs(text) {
+        let [consumed, produced] = obis::energy_registers(tariff);
+        let consumed = collector::energy(text, &consumed, None);
+        let produced = collector::energy(text, &produced, None);
+        total_consumed += consumed.unwrap_or_default();
+        total_produced += produced.unwrap_or_default();
+        let _ = writeln!(
+            screen,
+            "Tariff {tariff:<5} {:>15} {:>15}",
+            optional(consumed.map(|e| format!("{e:.3} kWh"))),
+            optional(produced.map(|e| format!("{e:.3} kWh"))),
+        );
+    }
+    let gas = state
+        .slaves
+        .iter()
+        .find(|slave| slave.device_type == Some(0x03))
+        .and_then(|slave| slave.meter_reading.as_ref())
+        .map(|(_, reading)| *reading);
+
+    // Days follow the meter's clock, starting over on the first telegram
+    // of a new one.
+    let date = state
+        .datetime
+        .as_ref()
+        .map(|tst| (tst.year, tst.month, tst.day));
+    if day.as_ref().is_none_or(|day| Some(day.date) != date) {
+        *day = date.map(|date| Day {
+            date,
+            since: state.datetime.as_ref().map_or(String::new(), clock),
+            consumed: total_consumed,
+            produced: total_produced,
+            gas,
+        });
+    }
+    if let Some(day) = day {
+        let _ = writeln!(
+            screen,
+            "Today        {:>15} {:>15}   since {}",
+            format!("{:.3} kWh", total_consumed - day.consumed),
+            format!("{:.3} kWh", total_produced - day.produced),
+            day.since
+        );
+    }
+
+    if let Some(gas) = gas {
+        let _ = write!(screen, "\nGas        {gas:.3} m³");
+        if let Some(today) = day.as_ref().and_then(|day| day.gas) {
+            let _ = write!(screen, "   today {:.3} m³", gas - today);
+        }
+        let _ = writeln!(screen);
+    }
+    let _ = writeln!(screen, "\nPress Ctrl-C to quit.");
+    screen
+}
+
+fn optional(value: Option<String>) -> String {
+    value.unwrap_or_else(|| "-".to_string())
+}
+
+/// Formats the time of day of a timestamp of the meter.
+fn clock(tst: &TST) -> String {
+    format!("{:02}:{:02}:{:02}", tst.hour, tst.minute, tst.second)
+}
@@ -0,0 +1,46 @@
+//! Receiving telegrams sent as UDP datagrams, e.g. by ESP-based P1 bridges.
+
+use std::{
+    io::{self, Read},
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+/// Largest possible UDP payload.
+const MAX_DATAGRAM: usize = 65507;
+
+/// Reads the datagrams received on a UDP socket as a continuous stream, so
+/// telegrams split across several datagrams are reassembled by the parser.
+pub struct UdpReader {
+    socket: UdpSocket,
+    datagram: Vec<u8>,
+    /// Range of `datagram` not read yet.
+    start: usize,
+    end: usize,
+}
+
+/// Binds a UDP socket on `addr`. Reads fail once nothing has been received
+/// for `timeout`.
+pub fn bind(addr: SocketAddr, timeout: Duration) -> Result<UdpReader, io::Error> {
+    let socket = UdpSocket::bind(addr)?;
+    socket.set_read_timeout(Some(timeout))?;
+    Ok(UdpReader {
+        socket,
+        datagram: vec![0; MAX_DATAGRAM],
+        start: 0,
+        end: 0,
+    })
+}
+
+impl Read for UdpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.start == self.end {
+            self.end = self.socket.recv(&mut self.datagram)?;
+            self.start = 0;
+        }
+        let n = buf.len().min(self.end - self.start);
+        buf[..n].copy_from_slice(&self.datagram[self.start..self.start + n]);
+        self.start += n;
+        Ok(n)
+    }
+}
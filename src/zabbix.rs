@@ -0,0 +1,180 @@
+//! Periodic push of the latest readings to a Zabbix server or proxy, with
+//! the Zabbix sender protocol, for trapper items.
+
+use crate::{
+    collector,
+    parse::{Format, Value},
+    sink::{Sample, Sink},
+};
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Write},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Timeout for connecting to Zabbix and for every read and write.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Header of the messages of the protocol, without compression.
+const HEADER: &[u8; 5] = b"ZBXD\x01";
+
+/// Largest response accepted, which only carries a summary.
+const MAX_RESPONSE: u64 = 64 * 1024;
+
+pub struct ZabbixConfig {
+    /// `host:port` of the Zabbix server or proxy, resolved on every
+    /// connection.
+    pub address: String,
+    /// Name of the host in Zabbix the items belong to.
+    pub host: String,
+    /// First component of the item keys.
+    pub key_prefix: String,
+    pub interval: Duration,
+}
+
+pub struct ZabbixSink {
+    key_prefix: String,
+    /// The latest value of every item key.
+    latest: Arc<Mutex<BTreeMap<String, f64>>>,
+}
+
+impl ZabbixSink {
+    pub fn start(config: ZabbixConfig) -> ZabbixSink {
+        let latest = Arc::new(Mutex::new(BTreeMap::<String, f64>::new()));
+        let readings = latest.clone();
+        let key_prefix = config.key_prefix.clone();
+        thread::spawn(move || loop {
+            thread::sleep(config.interval);
+            let clock = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as f64;
+            let data: Vec<Value> = readings
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(key, value)| {
+                    Value::Object(vec![
+                        ("host", Value::Text(config.host.clone())),
+                        ("key", Value::Text(key.clone())),
+                        ("value", Value::Text(value.to_string())),
+                        ("clock", Value::Number(clock)),
+                    ])
+                })
+                .collect();
+            if data.is_empty() {
+                continue;
+            }
+            let request = Value::Object(vec![
+                ("request", Value::Text("sender data".to_string())),
+                ("data", Value::List(data)),
+                ("clock", Value::Number(clock)),
+            ]);
+            match send(&config.address, &request.render(Format::Json)) {
+                Ok(info) => tracing::debug!("Sent readings to Zabbix: {info}"),
+                Err(err) => {
+                    tracing::warn!("Failed to send readings to {}: {err}", config.address)
+                }
+            }
+        });
+        ZabbixSink { key_prefix, latest }
+    }
+}
+
+impl Sink for ZabbixSink {
+    fn publish(&self, samples: &[Sample]) {
+        let mut latest = self.latest.lock().unwrap();
+        for sample in samples {
+            let key = key(&self.key_prefix, sample);
+            // Only the active tariff is sampled, the others become inactive.
+            if sample.name == "active_tariff" {
+                if let Some(end) = key.rfind([',', '[']) {
+                    let tariffs = key[..=end].to_string();
+                    for (other, value) in latest.range_mut(tariffs.clone()..) {
+                        if !other.starts_with(&tariffs) {
+                            break;
+                        }
+                        *value = 0.0;
+                    }
+                }
+            }
+            latest.insert(key, sample.value);
+        }
+    }
+}
+
+/// Sends a request and returns the `info` of the response, which tells how
+/// many values were processed.
+fn send(address: &str, request: &str) -> Result<String, io::Error> {
+    let mut sock = collector::connect(address, TIMEOUT)?;
+    sock.set_read_timeout(Some(TIMEOUT))?;
+    sock.set_write_timeout(Some(TIMEOUT))?;
+    let mut message = HEADER.to_vec();
+    message.extend((request.len() as u64).to_le_bytes());
+    message.extend(request.as_bytes());
+    sock.write_all(&message)?;
+
+    let mut header = [0; 13];
+    sock.read_exact(&mut header)?;
+    if &header[..5] != HEADER {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a Zabbix response",
+        ));
+    }
+    let len = u64::from_le_bytes(header[5..].try_into().unwrap());
+    if len > MAX_RESPONSE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("response of {len} bytes is too large"),
+        ));
+    }
+    let mut body = String::new();
+    sock.take(len).read_to_string(&mut body)?;
+    let field = |name: &str| {
+        let (_, rest) = body.split_once(&format!("\"{name}\":"))?;
+        let (_, rest) = rest.trim_start().split_once('"')?;
+        rest.split('"').next()
+    };
+    let info = field("info").unwrap_or_default().to_string();
+    if field("response") != Some("success") {
+        return Err(io::Error::other(format!("rejected: {body}")));
+    }
+    // Values of items that don't exist or aren't trapper items are counted
+    // as failed.
+    if !info.contains("failed: 0;") {
+        return Err(io::Error::other(format!(
+            "not all values processed: {info}"
+        )));
+    }
+    Ok(info)
+}
+
+/// Returns the item key of a sample: `prefix.name[meter,label values]`,
+/// leaving out the equipment identifier of M-Bus devices.
+fn key(prefix: &str, sample: &Sample) -> String {
+    let mut key = format!("{prefix}.{}", sample.name);
+    let meter = sample.labels.iter().filter(|(name, _)| *name == "meter");
+    let others = sample
+        .labels
+        .iter()
+        .filter(|(name, _)| !matches!(*name, "meter" | "equipment_id"));
+    let params: Vec<String> = meter.chain(others).map(|(_, value)| param(value)).collect();
+    if !params.is_empty() {
+        key.push('[');
+        key.push_str(&params.join(","));
+        key.push(']');
+    }
+    key
+}
+
+/// Quotes a key parameter if it contains characters that end it.
+fn param(value: &str) -> String {
+    if value.contains([',', ']', '[', '"', ' ']) {
+        format!("\"{}\"", value.replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}